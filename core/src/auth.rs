@@ -47,6 +47,7 @@ impl AccessLevel {
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "pull" => Some(AccessLevel::Pull),
@@ -287,6 +288,7 @@ pub async fn change_permission(
 // ─── Tests ───────────────────────────────────────────────────────────────────
 
 #[cfg(test)]
+#[allow(unused_variables)]
 mod tests {
     use super::*;
 
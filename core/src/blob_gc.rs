@@ -0,0 +1,243 @@
+//! Blob garbage collection and cache quotas.
+//!
+//! Every blob put in the local iroh store is recorded in
+//! `blob_store_entries` with its size, whether we stored it ourselves, and
+//! when it was last used. What references a blob is read from the
+//! `blob_refs` view over messages, profiles, orgs and image thumbnails, so
+//! deleting a message, room or org needs no bookkeeping of its own.
+//!
+//! [`run_blob_gc`] periodically:
+//!
+//! 1. untags blobs nothing references any more (after a grace period, since
+//!    an upload is stored before the message that attaches it), and forgets
+//!    their keys. Our own uploads may be drafts waiting to be sent, so they
+//!    get a much longer grace unless discarded with [`discard`];
+//! 2. evicts, least recently used first, blobs that aren't our own until
+//!    every org and the store as a whole fit their quotas. Evicted blobs are
+//!    still referenced and are fetched from peers again when next opened.
+//!
+//! Removing a blob's tags is all iroh needs: the store's own GC deletes
+//! untagged data. Collection is skipped while the read model is being
+//! rebuilt, when nothing looks referenced.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use iroh_blobs::store::fs::FsStore;
+use iroh_blobs::Hash;
+use sqlx::SqlitePool;
+
+use crate::blobs::BlobError;
+use crate::{db, network, projector, store};
+
+/// Time between collection passes.
+const GC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Unreferenced blobs younger than this are kept: the message attaching an
+/// upload, or a fetched blob's message, may not be projected yet.
+const UNREFERENCED_GRACE_MICROS: i64 = 60 * 60 * 1_000_000;
+
+/// Grace for our own unreferenced uploads, e.g. the attachment of a draft
+/// left in the composer (30 days).
+const OWN_UNREFERENCED_GRACE_MICROS: i64 = 30 * 24 * 60 * 60 * 1_000_000;
+
+/// Quota key of the limit on the whole store.
+const GLOBAL_QUOTA: &str = "";
+
+/// What one collection pass removed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GcReport {
+    pub unreferenced: usize,
+    pub evicted: usize,
+}
+
+/// Background task: collect garbage every [`GC_INTERVAL`].
+pub async fn run_blob_gc(read_pool: SqlitePool) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + GC_INTERVAL, GC_INTERVAL);
+    loop {
+        ticker.tick().await;
+        match collect(&read_pool).await {
+            Ok(report) if report.unreferenced + report.evicted > 0 => {
+                log::info!("[blob_gc] removed {} unreferenced and evicted {} cached blobs", report.unreferenced, report.evicted);
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("[blob_gc] collection failed: {}", e),
+        }
+    }
+}
+
+/// Run one collection pass now.
+pub async fn collect(pool: &SqlitePool) -> Result<GcReport, BlobError> {
+    let core = store::get_core().ok_or(BlobError::NotInitialized)?;
+    if projector::replaying() || db::rebuild_pending(pool).await.map_err(db_error)? {
+        return Ok(GcReport::default());
+    }
+    let store = blob_store().await?;
+    adopt_untracked(&store, pool, &core.public_key_hex).await?;
+
+    let now = crate::now_micros();
+    let unreferenced =
+        db::list_unreferenced_blobs(pool, now - UNREFERENCED_GRACE_MICROS, now - OWN_UNREFERENCED_GRACE_MICROS)
+            .await
+            .map_err(db_error)?;
+    untag(&store, &unreferenced).await?;
+    db::delete_blob_entries(pool, &unreferenced, true).await.map_err(db_error)?;
+
+    let mut evicted = HashSet::new();
+    for (org_id, max_bytes) in db::list_blob_quotas(pool).await.map_err(db_error)? {
+        let scope = (org_id != GLOBAL_QUOTA).then_some(org_id.as_str());
+        let used = db::blob_bytes_used(pool, scope).await.map_err(db_error)?;
+        let candidates = db::list_evictable_blobs(pool, scope, &core.public_key_hex)
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .filter(|(hash, _)| !evicted.contains(hash));
+        let picked = pick_evictions(used, max_bytes, candidates);
+        untag(&store, &picked).await?;
+        db::delete_blob_entries(pool, &picked, false).await.map_err(db_error)?;
+        evicted.extend(picked);
+    }
+
+    Ok(GcReport { unreferenced: unreferenced.len(), evicted: evicted.len() })
+}
+
+/// Least recently used `candidates` to drop until `used` fits `max_bytes`.
+fn pick_evictions(mut used: i64, max_bytes: i64, candidates: impl IntoIterator<Item = (String, i64)>) -> Vec<String> {
+    let mut picked = Vec::new();
+    for (hash, size) in candidates {
+        if used <= max_bytes {
+            break;
+        }
+        used -= size;
+        picked.push(hash);
+    }
+    picked
+}
+
+// ─── Store bookkeeping ───────────────────────────────────────────────────────
+
+/// Record a blob just put in the store; `is_own` for blobs we uploaded.
+/// Failures are logged: at worst the blob is never collected.
+pub(crate) async fn note_stored(hash: &Hash, size: u64, is_own: bool) {
+    let Some(core) = store::get_core() else { return };
+    if let Err(e) = db::record_blob_stored(&core.read_pool, &hash.to_hex(), size as i64, is_own, crate::now_micros()).await {
+        log::warn!("[blob_gc] could not record blob {}: {}", hash.to_hex(), e);
+    }
+}
+
+/// Give up an upload of ours that won't be sent, such as a discarded draft
+/// attachment: unless a message references it, the next passes collect it
+/// after the usual grace.
+pub async fn discard(pool: &SqlitePool, hash: &str) -> Result<(), db::DbError> {
+    db::discard_own_blob(pool, hash).await
+}
+
+/// Mark a blob as just read, keeping it out of the next evictions.
+pub(crate) async fn note_used(hash: &Hash) {
+    let Some(core) = store::get_core() else { return };
+    if let Err(e) = db::touch_blob(&core.read_pool, &hash.to_hex(), crate::now_micros()).await {
+        log::debug!("[blob_gc] could not touch blob {}: {}", hash.to_hex(), e);
+    }
+}
+
+/// Record tagged blobs stored before tracking existed. Blobs whose
+/// metadata names us as the sender count as our own.
+async fn adopt_untracked(store: &FsStore, pool: &SqlitePool, local_key: &str) -> Result<(), BlobError> {
+    let tracked: HashSet<String> = db::list_tracked_blobs(pool).await.map_err(db_error)?.into_iter().collect();
+    let mut untracked = HashSet::new();
+    for (_, hash) in list_tags(store).await? {
+        if !tracked.contains(&hash.to_hex()) {
+            untracked.insert(hash);
+        }
+    }
+
+    for hash in untracked {
+        let size = crate::blobs::stored_size(store, hash).await?;
+        let meta = db::get_blob_meta(pool, &hash.to_hex()).await.map_err(db_error)?;
+        let is_own = meta.and_then(|m| m.sender_key).is_some_and(|k| k == local_key);
+        db::record_blob_stored(pool, &hash.to_hex(), size as i64, is_own, crate::now_micros())
+            .await
+            .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+/// Every tag in the store as (name, hash).
+async fn list_tags(store: &FsStore) -> Result<Vec<(iroh_blobs::api::Tag, Hash)>, BlobError> {
+    let mut tags = store.tags().list().await.map_err(store_error)?;
+    let mut out = Vec::new();
+    while let Some(tag) = tags.next().await {
+        let tag = tag.map_err(store_error)?;
+        out.push((tag.name, tag.hash));
+    }
+    Ok(out)
+}
+
+/// Remove every tag pointing at one of `hashes`, leaving the data to the
+/// store's GC.
+async fn untag(store: &FsStore, hashes: &[String]) -> Result<(), BlobError> {
+    if hashes.is_empty() {
+        return Ok(());
+    }
+    let doomed: HashSet<&str> = hashes.iter().map(String::as_str).collect();
+    for (name, hash) in list_tags(store).await? {
+        if doomed.contains(hash.to_hex().as_str()) {
+            store.tags().delete(name).await.map_err(store_error)?;
+        }
+    }
+    Ok(())
+}
+
+async fn blob_store() -> Result<std::sync::Arc<FsStore>, BlobError> {
+    let network = network::get_network().await.ok_or(BlobError::NetworkNotInitialized)?;
+    let net = network.lock().await;
+    Ok(net.blob_store.clone())
+}
+
+fn db_error(e: db::DbError) -> BlobError {
+    BlobError::StoreError(e.to_string())
+}
+
+fn store_error(e: impl std::fmt::Display) -> BlobError {
+    BlobError::StoreError(e.to_string())
+}
+
+// ─── Quotas and usage ────────────────────────────────────────────────────────
+
+/// Limit the blobs cached for `org_id` (or, without one, the whole store)
+/// to `max_bytes`; `None` removes the limit. Applied by the next pass.
+pub async fn set_quota(pool: &SqlitePool, org_id: Option<&str>, max_bytes: Option<u64>) -> Result<(), db::DbError> {
+    let max = max_bytes.map(|b| b.min(i64::MAX as u64) as i64);
+    db::set_blob_quota(pool, org_id.unwrap_or(GLOBAL_QUOTA), max).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evictions_stop_once_under_quota() {
+        let candidates = vec![("a".to_string(), 4), ("b".to_string(), 4), ("c".to_string(), 4)];
+        assert_eq!(pick_evictions(12, 8, candidates.clone()), vec!["a"]);
+        assert_eq!(pick_evictions(12, 3, candidates.clone()), vec!["a", "b", "c"]);
+        assert!(pick_evictions(8, 8, candidates.clone()).is_empty());
+        // Own blobs aren't candidates, so a quota may stay exceeded
+        assert_eq!(pick_evictions(100, 1, candidates).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn own_blobs_are_kept_and_recently_used_ones_go_last() {
+        let core = crate::store::test_core().await;
+        let pool = &core.read_pool;
+        db::record_blob_stored(pool, "old", 4, false, 1).await.unwrap();
+        db::record_blob_stored(pool, "recent", 4, false, 2).await.unwrap();
+        db::record_blob_stored(pool, "own", 4, true, 1).await.unwrap();
+        db::touch_blob(pool, "old", 3).await.unwrap();
+
+        let candidates = db::list_evictable_blobs(pool, None, &core.public_key_hex).await.unwrap();
+        assert_eq!(candidates, vec![("recent".to_string(), 4), ("old".to_string(), 4)]);
+        assert_eq!(pick_evictions(12, 8, candidates.clone()), vec!["recent"]);
+        assert_eq!(pick_evictions(12, 1, candidates), vec!["recent", "old"]);
+    }
+}
@@ -1,19 +1,67 @@
-//! Blob storage using iroh-blobs for efficient P2P transfer.
+//! Blob storage using iroh-blobs, with peer transfer over [`BLOB_ALPN`].
 //!
-//! All blobs are encrypted with room-specific keys before storage.
-//! iroh-blobs handles content-addressing, chunking, and efficient P2P sync
-//! over the Iroh QUIC connections.
+//! Room and DM blobs are sealed with a random per-blob content key before
+//! storage; the key travels in the encrypted message op that references the
+//! blob and is only adopted once it opens the blob. Blobs uploaded without a
+//! room or thread (profile and org images) are public and stored in the
+//! clear. iroh-blobs handles content-addressing and local storage.
+//!
+//! # Peer transfer
+//!
+//! One bi-directional stream per request, opened by the requester, carrying
+//! length-prefixed CBOR [`BlobRequest`] / [`BlobResponse`] frames followed by
+//! the raw blob bytes:
+//!
+//! - `Get { hash }` → `Found { size }` and the bytes, or `NotFound` / `Denied`.
+//! - `Offer { hash, size }` → `Want` and the offerer sends the bytes, or
+//!   `Have` / `Denied`.
+//!
+//! Served blobs are checked against [`db::can_peer_fetch_blob`]: the peer must
+//! be a member of the org the blob was posted in, a participant of its DM
+//! thread, or allowed to see the org or profile it is the picture of.
+//! Offers are only taken from contacts. Blob bytes are streamed in chunks
+//! straight between the store and the connection, and received blobs are
+//! only kept if they hash to what was asked for. Every read and write on the
+//! stream is bounded by [`TRANSFER_TIMEOUT`], so a stalled peer can't hold a
+//! transfer open.
 
-use std::path::PathBuf;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 
+use bytes::Bytes;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use futures_util::Stream;
+use iroh::endpoint::{Connection, RecvStream, SendStream};
+use iroh::{Endpoint, EndpointAddr};
 use iroh_blobs::store::fs::FsStore;
+use iroh_blobs::api::proto::BlobStatus;
 use iroh_blobs::{BlobFormat, Hash};
-use tokio::io::AsyncReadExt;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 use tokio::time::Duration;
-use sqlx::Row;
+use sqlx::{Row, SqlitePool};
+
+use crate::network::{NetworkError, BLOB_ALPN, DEFAULT_TIMEOUT};
+use crate::ops::{decode_cbor, encode_cbor, ImagePreview};
+use crate::{blob_gc, db, encryption, media, network, store};
+
+/// Largest blob sent or accepted over [`BLOB_ALPN`]. Transfers are streamed,
+/// so this only bounds what a peer can make us write to disk.
+pub const MAX_PEER_BLOB_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Largest control frame; requests and responses are tiny.
+const MAX_BLOB_FRAME_SIZE: usize = 1024;
 
-use crate::{db, encryption, network, store};
+/// Bytes moved per read when streaming a blob over [`BLOB_ALPN`].
+const TRANSFER_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Longest a peer transfer may go without progress.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, thiserror::Error)]
 pub enum BlobError {
@@ -31,6 +79,16 @@ pub enum BlobError {
     EncryptionError(String),
     #[error("Connection failed: {0}")]
     ConnectionFailed(String),
+    #[error("Access denied")]
+    Denied,
+    #[error("Image rejected: {0}")]
+    ImageRejected(#[from] media::MediaError),
+}
+
+impl From<NetworkError> for BlobError {
+    fn from(e: NetworkError) -> Self {
+        BlobError::ConnectionFailed(e.to_string())
+    }
 }
 
 /// Get the blob store path for a given database directory.
@@ -49,36 +107,49 @@ async fn get_blob_store() -> Result<Arc<FsStore>, BlobError> {
 
 /// Upload a blob and return its content-hash (hex).
 /// 
-/// If `room_id` is provided, the blob is encrypted with the room's group key
-/// before being stored. The hash is computed on the encrypted data.
+/// If `room_id` (a room or DM thread id) is provided, the blob is sealed
+/// with a fresh random content key (see [`seal_blob`]) before being stored,
+/// and the key is kept in `blob_meta` until `send_message` copies it into
+/// the message op that references the blob. The hash is computed on the
+/// encrypted data.
+///
+/// Images lose their GPS metadata before they are stored; those we can't
+/// strip are refused with [`BlobError::ImageRejected`]. Decodable ones get
+/// a thumbnail blob (sealed the same way, with its own key) and a blurhash,
+/// recorded in `blob_meta` for `send_message` to pass on.
 pub async fn upload_blob(
     bytes: Vec<u8>,
     mime_type: String,
     room_id: Option<String>,
 ) -> Result<String, BlobError> {
     let store = get_blob_store().await?;
-    
-    // Encrypt if room_id provided
-    let data_to_store = if let Some(rid) = room_id {
-        // Ensure group state exists
-        if encryption::encrypt_for_room(&rid, &[]).await.is_err() {
-            // Need to init group - get current members
-            init_room_group(&rid).await?;
-        }
-        
-        encryption::encrypt_for_room(&rid, &bytes)
+
+    // Decoding is CPU-bound; keep it off the async workers
+    let (bytes, summary) = if mime_type.starts_with("image/") {
+        tokio::task::spawn_blocking(move || media::prepare_image(bytes))
             .await
-            .map_err(|e| BlobError::EncryptionError(e.to_string()))?
+            .map_err(|e| BlobError::StoreError(e.to_string()))??
     } else {
-        bytes
+        (bytes, None)
     };
     
-    // Import to blob store (content-addressed)
-    let tag = store
-        .add_bytes_with_opts((data_to_store, BlobFormat::Raw))
-        .await
-        .map_err(|e| BlobError::StoreError(e.to_string()))?;
-    let hash = tag.hash;
+    let seal = room_id.is_some();
+    let (hash, content_key) = store_bytes(&store, bytes, seal).await?;
+    let mut meta = new_meta(&hash, &mime_type, room_id.clone(), content_key.as_ref());
+
+    if let Some(summary) = summary {
+        let (thumb_hash, thumb_key) = store_bytes(&store, summary.thumbnail, seal).await?;
+        if thumb_key.is_some() {
+            remember_blob(new_meta(&thumb_hash, "image/jpeg", room_id, thumb_key.as_ref())).await?;
+        }
+        meta.thumbnail_hash = Some(thumb_hash.to_hex());
+        meta.width = Some(summary.width);
+        meta.height = Some(summary.height);
+        meta.blurhash = Some(summary.blurhash);
+    }
+    if content_key.is_some() || meta.blurhash.is_some() {
+        remember_blob(meta).await?;
+    }
     
     log::info!("[blobs] Uploaded blob {} (type: {})", hash.to_hex(), mime_type);
     
@@ -87,23 +158,15 @@ pub async fn upload_blob(
 
 /// Retrieve a blob by its hash, decrypting if necessary.
 /// 
-/// The `room_id` must match the room used during upload for proper decryption.
+/// Blobs with a content key in `blob_meta` are opened with it. Otherwise
+/// `room_id` selects the room group used for blobs uploaded before content
+/// keys; it must match the room used during upload.
 pub async fn get_blob(hash_str: &str, room_id: Option<String>) -> Result<Vec<u8>, BlobError> {
     let store = get_blob_store().await?;
     
     // Parse hash
     let hash = hash_from_hex(hash_str)?;
-    
-    // Check if we have the blob locally
-    let has = store
-        .has(hash)
-        .await
-        .map_err(|e| BlobError::StoreError(e.to_string()))?;
-    if !has {
-        // Try to fetch from network peers
-        log::info!("[blobs] Blob {} not found locally, attempting P2P fetch", hash_str);
-        fetch_blob_from_peers(&hash, room_id.as_deref()).await?;
-    }
+    let content_key = prepare_read(&store, hash, room_id.as_deref()).await?;
     
     // Read from store
     let mut reader = store.reader(hash);
@@ -113,7 +176,10 @@ pub async fn get_blob(hash_str: &str, room_id: Option<String>) -> Result<Vec<u8>
         .await
         .map_err(|e| BlobError::StoreError(e.to_string()))?;
     
-    // Decrypt if room_id provided
+    if let Some(key) = content_key {
+        return open_blob(&key, &encrypted_data);
+    }
+    // Legacy blobs were encrypted through the room group
     if let Some(rid) = room_id {
         encryption::decrypt_for_room(&rid, &encrypted_data)
             .await
@@ -123,6 +189,157 @@ pub async fn get_blob(hash_str: &str, room_id: Option<String>) -> Result<Vec<u8>
     }
 }
 
+/// Import the file at `path` without loading it into memory and return its
+/// content-hash (hex). Room blobs are sealed chunk by chunk on their way
+/// into the store, exactly as [`upload_blob`] seals them. `progress` hears
+/// about the file bytes read.
+pub async fn import_blob_from_path(
+    path: &Path,
+    mime_type: String,
+    room_id: Option<String>,
+    progress: Option<Box<dyn BlobProgressListener>>,
+) -> Result<String, BlobError> {
+    let store = get_blob_store().await?;
+    let file = tokio::fs::File::open(path).await?;
+    let total = file.metadata().await?.len();
+
+    if mime_type.starts_with("image/") {
+        // Images are stripped and previewed in memory, as upload_blob does
+        if total > media::MAX_IMAGE_SIZE {
+            return Err(media::MediaError::TooLarge.into());
+        }
+        drop(file);
+        let hash = upload_blob(tokio::fs::read(path).await?, mime_type, room_id).await?;
+        let mut progress = Progress::new(progress, total);
+        progress.advance(total);
+        progress.finish();
+        return Ok(hash);
+    }
+
+    let content_key = room_id.as_ref().map(|_| BlobKey::generate());
+    let chunks = ImportChunks {
+        reader: file,
+        cipher: content_key.as_ref().map(ChunkCipher::new),
+        progress: Progress::new(progress, total),
+        pending: None,
+        finished: false,
+    };
+    let tag = store
+        .add_stream(chunks.into_stream())
+        .await
+        .await
+        .map_err(|e| BlobError::StoreError(e.to_string()))?;
+    let hash = tag.hash;
+    blob_gc::note_stored(&hash, stored_size(&store, hash).await?, true).await;
+
+    if let Some(key) = content_key {
+        remember_blob(new_meta(&hash, &mime_type, room_id, Some(&key))).await?;
+    }
+
+    log::info!("[blobs] Imported blob {} from file (type: {}, {} bytes)", hash.to_hex(), mime_type, total);
+
+    Ok(hash.to_hex())
+}
+
+/// Write blob `hash_str` to `dest_path`, opening it chunk by chunk so
+/// neither the sealed nor the opened blob is held in memory. The file only
+/// appears under `dest_path` once it is complete. `progress` hears about the
+/// stored bytes read.
+pub async fn export_blob_to_path(
+    hash_str: &str,
+    room_id: Option<String>,
+    dest_path: &Path,
+    progress: Option<Box<dyn BlobProgressListener>>,
+) -> Result<(), BlobError> {
+    let store = get_blob_store().await?;
+    let hash = hash_from_hex(hash_str)?;
+    let content_key = prepare_read(&store, hash, room_id.as_deref()).await?;
+
+    let partial = partial_path(dest_path);
+    let written = async {
+        let mut file = tokio::fs::File::create(&partial).await?;
+        if content_key.is_none() && room_id.is_some() {
+            // Legacy room blobs can only be opened whole
+            let data = get_blob(hash_str, room_id).await?;
+            file.write_all(&data).await?;
+            file.sync_all().await?;
+            return Ok(());
+        }
+
+        let stored = stored_size(&store, hash).await?;
+        let mut reader = store.reader(hash);
+        copy_out(
+            &mut reader,
+            &mut file,
+            stored,
+            content_key.as_ref().map(ChunkCipher::new),
+            Progress::new(progress, stored),
+        )
+        .await?;
+        file.sync_all().await?;
+        Ok::<_, BlobError>(())
+    }
+    .await;
+
+    match written {
+        Ok(()) => Ok(tokio::fs::rename(&partial, dest_path).await?),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            Err(e)
+        }
+    }
+}
+
+/// Read up to `len` bytes of the opened blob starting at `offset`, for media
+/// players that seek or resume. Only the chunks covering the range are read
+/// and opened. Fewer bytes come back at the end of the blob, none past it.
+pub async fn read_blob_range(
+    hash_str: &str,
+    room_id: Option<String>,
+    offset: u64,
+    len: u64,
+) -> Result<Vec<u8>, BlobError> {
+    let store = get_blob_store().await?;
+    let hash = hash_from_hex(hash_str)?;
+    let content_key = prepare_read(&store, hash, room_id.as_deref()).await?;
+    let len = len.min(MAX_RANGE_LEN);
+
+    match content_key {
+        Some(key) => {
+            let stored = stored_size(&store, hash).await?;
+            let mut reader = store.reader(hash);
+            read_sealed_range(&mut reader, stored, ChunkCipher::new(&key), offset, len).await
+        }
+        None if room_id.is_some() => {
+            let data = get_blob(hash_str, room_id).await?;
+            let start = offset.min(data.len() as u64) as usize;
+            let end = offset.saturating_add(len).min(data.len() as u64) as usize;
+            Ok(data[start..end].to_vec())
+        }
+        None => {
+            let mut reader = store.reader(hash);
+            reader.seek(SeekFrom::Start(offset)).await?;
+            let mut out = Vec::new();
+            (&mut reader).take(len).read_to_end(&mut out).await?;
+            Ok(out)
+        }
+    }
+}
+
+/// Size in bytes of the opened blob, e.g. the content length a media player
+/// asks for before reading ranges.
+pub async fn get_blob_size(hash_str: &str, room_id: Option<String>) -> Result<u64, BlobError> {
+    let store = get_blob_store().await?;
+    let hash = hash_from_hex(hash_str)?;
+    let sealed = prepare_read(&store, hash, room_id.as_deref()).await?.is_some();
+
+    if !sealed && room_id.is_some() {
+        return Ok(get_blob(hash_str, room_id).await?.len() as u64);
+    }
+    let stored = stored_size(&store, hash).await?;
+    Ok(if sealed { opened_len(stored) } else { stored })
+}
+
 /// Check if we have a blob locally.
 pub async fn has_blob(hash_str: &str) -> Result<bool, BlobError> {
     let store = get_blob_store().await?;
@@ -134,120 +351,182 @@ pub async fn has_blob(hash_str: &str) -> Result<bool, BlobError> {
         .map_err(|e| BlobError::StoreError(e.to_string()))
 }
 
-/// Fetch a blob from network peers if not available locally.
-async fn fetch_blob_from_peers(hash: &Hash, context_id: Option<&str>) -> Result<(), BlobError> {
-    let network = network::get_network().await
-        .ok_or(BlobError::NetworkNotInitialized)?;
-    
-    let net = network.lock().await;
+/// Store `bytes`, sealed with a fresh content key if `seal` is set.
+async fn store_bytes(store: &FsStore, bytes: Vec<u8>, seal: bool) -> Result<(Hash, Option<BlobKey>), BlobError> {
+    let (data, key) = if seal {
+        let key = BlobKey::generate();
+        (seal_blob(&key, &bytes)?, Some(key))
+    } else {
+        (bytes, None)
+    };
 
-    let mut providers = discover_peers(context_id).await?;
-    // Remove ourselves if present
-    let self_id = net.endpoint.id();
-    providers.retain(|p| *p != self_id);
+    // Import to blob store (content-addressed)
+    let size = data.len() as u64;
+    let tag = store
+        .add_bytes_with_opts((data, BlobFormat::Raw))
+        .await
+        .map_err(|e| BlobError::StoreError(e.to_string()))?;
+    blob_gc::note_stored(&tag.hash, size, true).await;
+    Ok((tag.hash, key))
+}
 
-    if providers.is_empty() {
-        return Err(BlobError::NotFound);
+/// Size of a blob in the local store.
+pub(crate) async fn stored_size(store: &FsStore, hash: Hash) -> Result<u64, BlobError> {
+    // Blob readers can't seek from the end, so ask the store
+    match store.status(hash).await.map_err(|e| BlobError::StoreError(e.to_string()))? {
+        BlobStatus::Complete { size } => Ok(size),
+        _ => Err(BlobError::NotFound),
     }
+}
 
-    let downloader = net.blob_store.downloader(&net.endpoint);
-    let request = iroh_blobs::HashAndFormat {
-        hash: *hash,
-        format: BlobFormat::Raw,
-    };
+fn new_meta(hash: &Hash, mime_type: &str, room_id: Option<String>, key: Option<&BlobKey>) -> db::BlobMeta {
+    db::BlobMeta {
+        blob_hash: hash.to_hex(),
+        mime_type: mime_type.to_string(),
+        room_id,
+        secret_id: key.map(|k| k.key.to_vec()),
+        nonce: key.map(|k| k.nonce.to_vec()),
+        ..Default::default()
+    }
+}
 
-    // Best-effort download with a timeout to avoid hanging.
-    let download = downloader.download(request, providers);
-    tokio::time::timeout(Duration::from_secs(20), download)
+/// Record a blob we just stored. Its content key and preview are kept until
+/// `send_message` copies them into the message op that references the blob.
+async fn remember_blob(mut meta: db::BlobMeta) -> Result<(), BlobError> {
+    let core = store::get_core().ok_or(BlobError::NotInitialized)?;
+    meta.sender_key = Some(core.public_key_hex.clone());
+    db::insert_blob_meta(&core.read_pool, &meta)
         .await
-        .map_err(|_| BlobError::NotFound)?
-        .map_err(|e| BlobError::StoreError(e.to_string()))?;
-
-    Ok(())
+        .map_err(|e| BlobError::StoreError(e.to_string()))
 }
 
-/// Try to download a blob from a specific peer.
-async fn try_download_blob(
-    endpoint: &iroh::Endpoint,
-    node_id: iroh::EndpointId,
-    hash: &Hash,
-) -> Result<(), BlobError> {
-    // This is a simplified version - in production, you'd use iroh-blobs' 
-    // proper P2P download mechanism with the downloader
-    
-    // For now, we just check if we can connect
-    let _conn = endpoint.connect(node_id, network::BLOB_ALPN).await
-        .map_err(|e| BlobError::ConnectionFailed(format!("Connection failed: {}", e)))?;
-    
-    // The actual download would happen here using iroh-blobs downloader
-    // This requires setting up a proper download request
-    
-    log::debug!("[blobs] Connection established to {} for blob {}", node_id, hash.to_hex());
-    
-    // Placeholder - in production, use the proper download mechanism
-    Ok(())
+/// The preview `send_message` puts in the op of a message attaching the
+/// blob described by `meta`, if it is an image we summarized.
+pub(crate) async fn image_preview(
+    pool: &SqlitePool,
+    meta: &db::BlobMeta,
+) -> Result<Option<ImagePreview>, db::DbError> {
+    let (Some(width), Some(height), Some(blurhash)) = (meta.width, meta.height, meta.blurhash.clone()) else {
+        return Ok(None);
+    };
+    let thumbnail = match meta.thumbnail_hash.as_deref() {
+        Some(hash) => db::get_blob_meta(pool, hash).await?,
+        None => None,
+    };
+    Ok(Some(ImagePreview {
+        width,
+        height,
+        blurhash,
+        thumbnail_id: meta.thumbnail_hash.clone(),
+        thumbnail_key: thumbnail.as_ref().and_then(|t| t.secret_id.clone()),
+        thumbnail_nonce: thumbnail.and_then(|t| t.nonce),
+    }))
 }
 
-/// Initialize room encryption group if needed.
-async fn init_room_group(room_id: &str) -> Result<(), BlobError> {
-    use crate::db;
-    use sqlx::Row;
-    
+/// Make sure `hash` is stored locally, fetching it from peers of `room_id`,
+/// or of the room it was posted in, and return its content key if sealed.
+async fn prepare_read(
+    store: &FsStore,
+    hash: Hash,
+    room_id: Option<&str>,
+) -> Result<Option<BlobKey>, BlobError> {
     let core = store::get_core().ok_or(BlobError::NotInitialized)?;
-    
-    // Get room info
-    let room = db::get_room(&core.read_pool, room_id).await
-        .map_err(|e| BlobError::StoreError(e.to_string()))?
-        .ok_or_else(|| BlobError::StoreError("Room not found".into()))?;
-    
-    // Get all org members
-    let mut members: Vec<p2panda_core::PublicKey> = vec![];
-    let rows = sqlx::query("SELECT member_key FROM memberships WHERE org_id = ?")
-        .bind(&room.org_id)
-        .fetch_all(&core.read_pool)
+    let meta = db::get_blob_meta(&core.read_pool, &hash.to_hex())
         .await
         .map_err(|e| BlobError::StoreError(e.to_string()))?;
-    
-    for row in rows {
-        let key_hex: String = row.get("member_key");
-        if let Ok(bytes) = hex::decode(&key_hex) {
-            if let Ok(arr) = bytes.try_into() {
-                if let Ok(pk) = p2panda_core::PublicKey::from_bytes(&arr) {
-                    members.push(pk);
-                }
-            }
+
+    let has = store
+        .has(hash)
+        .await
+        .map_err(|e| BlobError::StoreError(e.to_string()))?;
+    if !has {
+        // Try to fetch from network peers
+        log::info!("[blobs] Blob {} not found locally, attempting P2P fetch", hash.to_hex());
+        let context = room_id.map(str::to_string).or_else(|| meta.as_ref().and_then(|m| m.room_id.clone()));
+        fetch_blob_from_peers(&hash, context.as_deref()).await?;
+    }
+    blob_gc::note_used(&hash).await;
+    match meta.as_ref().and_then(BlobKey::from_meta) {
+        Some(key) => Ok(Some(key)),
+        None => verified_key(store, &core.read_pool, hash).await,
+    }
+}
+
+/// The first key a message claims for `hash` that opens it, adopted as the
+/// blob's key from then on. Anyone can post a message naming a blob, so a
+/// claimed key is only trusted once it has authenticated the first chunk.
+async fn verified_key(store: &FsStore, pool: &SqlitePool, hash: Hash) -> Result<Option<BlobKey>, BlobError> {
+    let hash_hex = hash.to_hex();
+    let claimed = db::list_blob_keys(pool, &hash_hex)
+        .await
+        .map_err(|e| BlobError::StoreError(e.to_string()))?;
+    if claimed.is_empty() {
+        return Ok(None);
+    }
+
+    let stored = stored_size(store, hash).await?;
+    let mut reader = store.reader(hash);
+    let mut first = vec![0u8; stored.min(SEALED_CHUNK_SIZE as u64) as usize];
+    reader.read_exact(&mut first).await?;
+    let last = stored <= SEALED_CHUNK_SIZE as u64;
+
+    for (secret_id, nonce) in claimed {
+        let Some(key) = BlobKey::from_parts(&secret_id, &nonce) else { continue };
+        if ChunkCipher::new(&key).open(&first, last).is_ok() {
+            db::set_blob_key(pool, &hash_hex, &key.key, &key.nonce)
+                .await
+                .map_err(|e| BlobError::StoreError(e.to_string()))?;
+            return Ok(Some(key));
         }
     }
-    
-    // Add ourselves
-    members.push(core.private_key.public_key());
-    
-    // Initialize group
-    encryption::init_room_group(room_id, members).await
-        .map_err(|e| BlobError::EncryptionError(e.to_string()))?;
-    
-    Ok(())
+    Err(BlobError::EncryptionError("no key claimed for the blob opens it".into()))
 }
 
-/// Provide a blob to the network (make it available for P2P sharing).
-pub async fn provide_blob(hash_str: &str) -> Result<(), BlobError> {
+/// Fetch a blob from network peers if not available locally.
+async fn fetch_blob_from_peers(hash: &Hash, context_id: Option<&str>) -> Result<(), BlobError> {
+    let endpoint = get_endpoint().await?;
     let store = get_blob_store().await?;
-    
+
+    let mut providers = discover_peers(context_id).await?;
+    // Remove ourselves if present
+    let self_id = endpoint.id();
+    providers.retain(|p| *p != self_id);
+
+    for peer in providers {
+        match fetch_from_peer(&endpoint, &store, peer, hash).await {
+            Ok(()) => return Ok(()),
+            Err(e) => log::debug!("[blobs] {} could not provide {}: {}", peer, hash.to_hex(), e),
+        }
+    }
+    Err(BlobError::NotFound)
+}
+
+async fn get_endpoint() -> Result<Endpoint, BlobError> {
+    let network = network::get_network().await
+        .ok_or(BlobError::NetworkNotInitialized)?;
+    let net = network.lock().await;
+    Ok(net.endpoint.clone())
+}
+
+/// Offer a blob we hold to `peer_node_id`, which pulls it if it lacks it.
+///
+/// The peer must be allowed to fetch the blob from us anyway; this only
+/// saves it the round trip of asking.
+pub async fn provide_blob(hash_str: &str, peer_node_id: &str) -> Result<(), BlobError> {
+    let core = store::get_core().ok_or(BlobError::NotInitialized)?;
     let hash = hash_from_hex(hash_str)?;
-    
-    let has = store
-        .has(hash)
+    let peer = endpoint_id_from_hex(peer_node_id)?;
+
+    let allowed = db::can_peer_fetch_blob(&core.read_pool, &hash.to_hex(), &hex::encode(peer.as_bytes()), &core.public_key_hex)
         .await
         .map_err(|e| BlobError::StoreError(e.to_string()))?;
-    if !has {
-        return Err(BlobError::NotFound);
+    if !allowed {
+        return Err(BlobError::Denied);
     }
-    
-    // The blob is now available for P2P transfer through iroh-blobs
-    // The network handler will serve requests for this hash
-    log::info!("[blobs] Blob {} is now available for P2P sharing", hash_str);
-    
-    Ok(())
+
+    let store = get_blob_store().await?;
+    let endpoint = get_endpoint().await?;
+    offer_to_peer(&endpoint, &store, peer, hash).await
 }
 
 /// Request a blob from a specific peer.
@@ -273,14 +552,8 @@ pub async fn request_blob_from_peer(
     }
     
     // Try to download from the specified peer
-    let network = network::get_network().await
-        .ok_or(BlobError::NetworkNotInitialized)?;
-    
-    let net = network.lock().await;
-    
-    try_download_blob(&net.endpoint, peer, &hash).await?;
-    
-    Ok(())
+    let endpoint = get_endpoint().await?;
+    fetch_from_peer(&endpoint, &store, peer, &hash).await
 }
 
 fn hash_from_hex(hash_str: &str) -> Result<Hash, BlobError> {
@@ -344,6 +617,606 @@ fn endpoint_id_from_hex(hex_str: &str) -> Result<iroh::EndpointId, BlobError> {
         .map_err(|e| BlobError::StoreError(format!("Invalid public key: {e}")))
 }
 
+// ─── Content keys ────────────────────────────────────────────────────────────
+
+/// Plaintext bytes sealed per chunk.
+const BLOB_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Poly1305 tag appended to every chunk.
+const BLOB_TAG_SIZE: usize = 16;
+
+/// Random part of each chunk nonce; the remaining 5 bytes of the 24-byte
+/// XChaCha20 nonce are the chunk counter and the last-chunk flag.
+pub const BLOB_NONCE_PREFIX_LEN: usize = 19;
+
+/// Random key a single blob is sealed with.
+///
+/// Sent to readers inside the encrypted message op that references the
+/// blob, so an attachment is readable by anyone who can read its message,
+/// whatever group secret was current when it was uploaded.
+pub struct BlobKey {
+    pub key: [u8; 32],
+    pub nonce: [u8; BLOB_NONCE_PREFIX_LEN],
+}
+
+impl BlobKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        let mut nonce = [0u8; BLOB_NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut key);
+        OsRng.fill_bytes(&mut nonce);
+        Self { key, nonce }
+    }
+
+    pub fn from_parts(key: &[u8], nonce: &[u8]) -> Option<Self> {
+        Some(Self { key: key.try_into().ok()?, nonce: nonce.try_into().ok()? })
+    }
+
+    fn from_meta(meta: &db::BlobMeta) -> Option<Self> {
+        Self::from_parts(meta.secret_id.as_deref()?, meta.nonce.as_deref()?)
+    }
+}
+
+/// Sealed bytes per chunk.
+const SEALED_CHUNK_SIZE: usize = BLOB_CHUNK_SIZE + BLOB_TAG_SIZE;
+
+/// Seals or opens one blob chunk by chunk. Each chunk's nonce is
+/// prefix ‖ counter (u32 BE) ‖ last flag, as in the STREAM construction, so
+/// chunks cannot be reordered and the flag stops a truncated blob from
+/// opening.
+struct ChunkCipher {
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; BLOB_NONCE_PREFIX_LEN],
+    counter: u32,
+}
+
+impl ChunkCipher {
+    fn new(key: &BlobKey) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new((&key.key).into()),
+            prefix: key.nonce,
+            counter: 0,
+        }
+    }
+
+    /// Continue at chunk `index`, for reads that start mid-blob.
+    fn seek(&mut self, index: u64) -> Result<(), BlobError> {
+        self.counter = u32::try_from(index).map_err(|_| BlobError::EncryptionError("blob too large".into()))?;
+        Ok(())
+    }
+
+    fn next_nonce(&mut self, last: bool) -> Result<XNonce, BlobError> {
+        let mut nonce = [0u8; 24];
+        nonce[..BLOB_NONCE_PREFIX_LEN].copy_from_slice(&self.prefix);
+        nonce[BLOB_NONCE_PREFIX_LEN..23].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[23] = last as u8;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| BlobError::EncryptionError("blob too large".into()))?;
+        Ok(XNonce::from(nonce))
+    }
+
+    fn seal(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>, BlobError> {
+        let nonce = self.next_nonce(last)?;
+        self.cipher
+            .encrypt(&nonce, chunk)
+            .map_err(|_| BlobError::EncryptionError("failed to seal blob".into()))
+    }
+
+    fn open(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>, BlobError> {
+        let nonce = self.next_nonce(last)?;
+        self.cipher
+            .decrypt(&nonce, chunk)
+            .map_err(|_| BlobError::EncryptionError("blob failed authentication".into()))
+    }
+}
+
+/// Number of chunks in a sealed blob of `sealed_len` bytes.
+fn sealed_chunk_count(sealed_len: u64) -> u64 {
+    sealed_len.div_ceil(SEALED_CHUNK_SIZE as u64).max(1)
+}
+
+/// Plaintext size of a sealed blob of `sealed_len` bytes.
+fn opened_len(sealed_len: u64) -> u64 {
+    sealed_len.saturating_sub(sealed_chunk_count(sealed_len) * BLOB_TAG_SIZE as u64)
+}
+
+/// Seal `plaintext` with streaming XChaCha20-Poly1305: 64 KiB chunks, each
+/// with its own tag, so readers can check a blob piece by piece. An empty
+/// blob is still one (empty) final chunk.
+pub fn seal_blob(key: &BlobKey, plaintext: &[u8]) -> Result<Vec<u8>, BlobError> {
+    let mut cipher = ChunkCipher::new(key);
+    let chunks: Vec<&[u8]> = if plaintext.is_empty() {
+        vec![plaintext]
+    } else {
+        plaintext.chunks(BLOB_CHUNK_SIZE).collect()
+    };
+
+    let mut out = Vec::with_capacity(plaintext.len() + chunks.len() * BLOB_TAG_SIZE);
+    for (i, chunk) in chunks.iter().enumerate() {
+        out.extend_from_slice(&cipher.seal(chunk, i + 1 == chunks.len())?);
+    }
+    Ok(out)
+}
+
+/// Open a blob sealed by [`seal_blob`]. Fails if any chunk was altered,
+/// reordered or dropped.
+pub fn open_blob(key: &BlobKey, sealed: &[u8]) -> Result<Vec<u8>, BlobError> {
+    if sealed.len() < BLOB_TAG_SIZE {
+        return Err(BlobError::EncryptionError("sealed blob too short".into()));
+    }
+    let mut cipher = ChunkCipher::new(key);
+    let chunks: Vec<&[u8]> = sealed.chunks(SEALED_CHUNK_SIZE).collect();
+
+    let mut out = Vec::with_capacity(sealed.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        out.extend_from_slice(&cipher.open(chunk, i + 1 == chunks.len())?);
+    }
+    Ok(out)
+}
+
+// ─── Streaming ───────────────────────────────────────────────────────────────
+
+/// Largest range [`read_blob_range`] returns in one call.
+pub const MAX_RANGE_LEN: u64 = 4 * 1024 * 1024;
+
+/// Bytes between two progress callbacks.
+const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// Implemented by the app to follow long blob imports and exports.
+///
+/// Called on a core task; implementations should return quickly.
+pub trait BlobProgressListener: Send + Sync {
+    fn on_progress(&self, bytes_done: u64, bytes_total: u64);
+}
+
+/// Reports to a listener at most once per [`PROGRESS_INTERVAL`] bytes,
+/// plus once when the transfer is done.
+struct Progress {
+    listener: Option<Box<dyn BlobProgressListener>>,
+    done: u64,
+    reported: u64,
+    total: u64,
+}
+
+impl Progress {
+    fn new(listener: Option<Box<dyn BlobProgressListener>>, total: u64) -> Self {
+        Self { listener, done: 0, reported: 0, total }
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        if self.done - self.reported >= PROGRESS_INTERVAL {
+            self.report();
+        }
+    }
+
+    fn finish(&mut self) {
+        self.report();
+    }
+
+    fn report(&mut self) {
+        if let Some(listener) = &self.listener {
+            listener.on_progress(self.done, self.total.max(self.done));
+        }
+        self.reported = self.done;
+    }
+}
+
+/// Read up to `size` bytes, stopping early only at the end of the input.
+async fn read_chunk<R: AsyncRead + Unpin>(reader: &mut R, size: usize) -> std::io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    (&mut *reader).take(size as u64).read_to_end(&mut chunk).await?;
+    Ok(chunk)
+}
+
+/// Where an export is written before it is renamed into place.
+fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// The chunks of a file being imported, sealed if `cipher` is set. Reads one
+/// chunk ahead to know which chunk is the last.
+struct ImportChunks<R> {
+    reader: R,
+    cipher: Option<ChunkCipher>,
+    progress: Progress,
+    pending: Option<Vec<u8>>,
+    finished: bool,
+}
+
+impl<R: AsyncRead + Unpin> ImportChunks<R> {
+    async fn next_chunk(&mut self) -> Option<std::io::Result<Bytes>> {
+        if self.finished {
+            return None;
+        }
+        let chunk = self.read_next().await;
+        if chunk.is_err() {
+            self.finished = true;
+        }
+        Some(chunk)
+    }
+
+    async fn read_next(&mut self) -> std::io::Result<Bytes> {
+        let chunk = match self.pending.take() {
+            Some(chunk) => chunk,
+            None => read_chunk(&mut self.reader, BLOB_CHUNK_SIZE).await?,
+        };
+        let next = read_chunk(&mut self.reader, BLOB_CHUNK_SIZE).await?;
+        let last = next.is_empty();
+
+        self.progress.advance(chunk.len() as u64);
+        if last {
+            self.finished = true;
+            self.progress.finish();
+        } else {
+            self.pending = Some(next);
+        }
+
+        match &mut self.cipher {
+            Some(cipher) => Ok(Bytes::from(cipher.seal(&chunk, last).map_err(std::io::Error::other)?)),
+            None => Ok(Bytes::from(chunk)),
+        }
+    }
+
+    fn into_stream(self) -> impl Stream<Item = std::io::Result<Bytes>> {
+        futures_util::stream::unfold(self, |mut chunks| async move {
+            chunks.next_chunk().await.map(|chunk| (chunk, chunks))
+        })
+    }
+}
+
+/// Copy a stored blob of `stored_len` bytes from `reader` to `writer`,
+/// opening it chunk by chunk if `cipher` is set.
+async fn copy_out<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    stored_len: u64,
+    cipher: Option<ChunkCipher>,
+    mut progress: Progress,
+) -> Result<(), BlobError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    match cipher {
+        Some(mut cipher) => {
+            let count = sealed_chunk_count(stored_len);
+            for index in 0..count {
+                let sealed = read_chunk(reader, SEALED_CHUNK_SIZE).await?;
+                progress.advance(sealed.len() as u64);
+                writer.write_all(&cipher.open(&sealed, index + 1 == count)?).await?;
+            }
+        }
+        None => loop {
+            let chunk = read_chunk(reader, BLOB_CHUNK_SIZE).await?;
+            if chunk.is_empty() {
+                break;
+            }
+            progress.advance(chunk.len() as u64);
+            writer.write_all(&chunk).await?;
+        },
+    }
+    writer.flush().await?;
+    progress.finish();
+    Ok(())
+}
+
+/// Open `len` bytes at `offset` of a sealed blob of `sealed_len` bytes,
+/// reading only the chunks that cover them.
+async fn read_sealed_range<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut R,
+    sealed_len: u64,
+    mut cipher: ChunkCipher,
+    offset: u64,
+    len: u64,
+) -> Result<Vec<u8>, BlobError> {
+    let end = offset.saturating_add(len).min(opened_len(sealed_len));
+    if offset >= end {
+        return Ok(Vec::new());
+    }
+    let chunk_size = BLOB_CHUNK_SIZE as u64;
+    let first = offset / chunk_size;
+    let last = (end - 1) / chunk_size;
+    let count = sealed_chunk_count(sealed_len);
+
+    reader.seek(SeekFrom::Start(first * SEALED_CHUNK_SIZE as u64)).await?;
+    cipher.seek(first)?;
+    let mut opened = Vec::with_capacity(((last - first + 1) * chunk_size) as usize);
+    for index in first..=last {
+        let sealed = read_chunk(reader, SEALED_CHUNK_SIZE).await?;
+        opened.extend_from_slice(&cipher.open(&sealed, index + 1 == count)?);
+    }
+
+    let start = (offset - first * chunk_size) as usize;
+    Ok(opened[start..start + (end - offset) as usize].to_vec())
+}
+
+// ─── Peer transfer ───────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BlobRequest {
+    /// Send me this blob.
+    Get { hash: String },
+    /// I have this blob for you.
+    Offer { hash: String, size: u64 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BlobResponse {
+    /// `size` bytes of blob follow.
+    Found { size: u64 },
+    NotFound,
+    Denied,
+    /// Send the offered blob.
+    Want,
+    /// Already have the offered blob.
+    Have,
+}
+
+/// Download `hash` from `peer` into `store`.
+async fn fetch_from_peer(
+    endpoint: &Endpoint,
+    store: &FsStore,
+    peer: impl Into<EndpointAddr>,
+    hash: &Hash,
+) -> Result<(), BlobError> {
+    let (conn, mut send, mut recv) = open_blob_stream(endpoint, peer).await?;
+    write_frame(&mut send, &BlobRequest::Get { hash: hash.to_hex() }).await?;
+    send.finish().map_err(|e| NetworkError::StreamError(e.to_string()))?;
+
+    let result = match read_frame::<BlobResponse>(&mut recv).await? {
+        BlobResponse::Found { size } => receive_blob(store, &mut recv, hash, size).await,
+        BlobResponse::NotFound => Err(BlobError::NotFound),
+        BlobResponse::Denied => Err(BlobError::Denied),
+        other => Err(BlobError::ConnectionFailed(format!("unexpected response {:?}", other))),
+    };
+    conn.close(0u32.into(), b"done");
+    result
+}
+
+/// Offer `hash` from `store` to `peer`, sending it if the peer wants it.
+async fn offer_to_peer(
+    endpoint: &Endpoint,
+    store: &FsStore,
+    peer: impl Into<EndpointAddr>,
+    hash: Hash,
+) -> Result<(), BlobError> {
+    let size = local_size(store, &hash).await?.ok_or(BlobError::NotFound)?;
+    let (conn, mut send, mut recv) = open_blob_stream(endpoint, peer).await?;
+    write_frame(&mut send, &BlobRequest::Offer { hash: hash.to_hex(), size }).await?;
+
+    let result = match read_frame::<BlobResponse>(&mut recv).await? {
+        BlobResponse::Want => {
+            send_blob(store, &mut send, &hash).await?;
+            send.finish().map_err(|e| BlobError::ConnectionFailed(e.to_string()))?;
+            // Wait for the peer to finish reading before closing.
+            let _ = tokio::time::timeout(TRANSFER_TIMEOUT, recv.read_to_end(MAX_BLOB_FRAME_SIZE)).await;
+            Ok(())
+        }
+        BlobResponse::Have => Ok(()),
+        BlobResponse::Denied => Err(BlobError::Denied),
+        other => Err(BlobError::ConnectionFailed(format!("unexpected response {:?}", other))),
+    };
+    conn.close(0u32.into(), b"done");
+    result
+}
+
+async fn open_blob_stream(
+    endpoint: &Endpoint,
+    peer: impl Into<EndpointAddr>,
+) -> Result<(Connection, SendStream, RecvStream), NetworkError> {
+    let conn = tokio::time::timeout(DEFAULT_TIMEOUT, endpoint.connect(peer, BLOB_ALPN))
+        .await
+        .map_err(|_| NetworkError::ConnectionFailed("blob connect timed out".to_string()))?
+        .map_err(|e| NetworkError::ConnectionFailed(e.to_string()))?;
+    let (send, recv) = tokio::time::timeout(DEFAULT_TIMEOUT, conn.open_bi())
+        .await
+        .map_err(|_| NetworkError::StreamError("blob stream open timed out".to_string()))?
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    Ok((conn, send, recv))
+}
+
+/// Serve one blob request from a peer.
+pub async fn handle_blob_connection(conn: Connection) -> Result<(), NetworkError> {
+    let core = store::get_core().ok_or(NetworkError::NotInitialized)?;
+    let store = get_blob_store().await.map_err(|_| NetworkError::NotInitialized)?;
+    serve_blob_connection(&core.read_pool, &core.public_key_hex, &store, conn).await
+}
+
+async fn serve_blob_connection(
+    pool: &SqlitePool,
+    local_key: &str,
+    store: &FsStore,
+    conn: Connection,
+) -> Result<(), NetworkError> {
+    let peer = conn
+        .remote_id()
+        .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    let peer_hex = hex::encode(peer.as_bytes());
+    let (mut send, mut recv) = tokio::time::timeout(DEFAULT_TIMEOUT, conn.accept_bi())
+        .await
+        .map_err(|_| NetworkError::StreamError("no blob request".to_string()))?
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+
+    match read_frame::<BlobRequest>(&mut recv).await? {
+        BlobRequest::Get { hash } => {
+            let allowed = db::can_peer_fetch_blob(pool, &hash, &peer_hex, local_key)
+                .await
+                .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+            let local = match hash_from_hex(&hash) {
+                Ok(h) if allowed => local_size(store, &h).await.ok().flatten().map(|size| (h, size)),
+                _ => None,
+            };
+            match local {
+                _ if !allowed => {
+                    log::warn!("[blobs] refused blob {} to {}", hash, peer);
+                    write_frame(&mut send, &BlobResponse::Denied).await?
+                }
+                None => write_frame(&mut send, &BlobResponse::NotFound).await?,
+                Some((h, size)) => {
+                    write_frame(&mut send, &BlobResponse::Found { size }).await?;
+                    send_blob(store, &mut send, &h)
+                        .await
+                        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+                }
+            }
+        }
+        BlobRequest::Offer { hash, size } => {
+            let known = db::is_known_sender(pool, &peer_hex, local_key)
+                .await
+                .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+            let parsed = hash_from_hex(&hash).map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+            if !known || size > MAX_PEER_BLOB_SIZE {
+                write_frame(&mut send, &BlobResponse::Denied).await?;
+            } else if local_size(store, &parsed).await.ok().flatten().is_some() {
+                write_frame(&mut send, &BlobResponse::Have).await?;
+            } else {
+                write_frame(&mut send, &BlobResponse::Want).await?;
+                receive_blob(store, &mut recv, &parsed, size)
+                    .await
+                    .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+                log::info!("[blobs] Received offered blob {} from {}", hash, peer);
+            }
+        }
+    }
+
+    send.finish().map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    // Let the requester close once it has read everything.
+    let _ = tokio::time::timeout(TRANSFER_TIMEOUT, conn.closed()).await;
+    Ok(())
+}
+
+/// Size of `hash` if `store` holds all of it.
+async fn local_size(store: &FsStore, hash: &Hash) -> Result<Option<u64>, BlobError> {
+    match store.status(*hash).await.map_err(|e| BlobError::StoreError(e.to_string()))? {
+        BlobStatus::Complete { size } => Ok(Some(size)),
+        _ => Ok(None),
+    }
+}
+
+/// Stream `hash` from `store` onto `send`, a chunk at a time.
+async fn send_blob(store: &FsStore, send: &mut SendStream, hash: &Hash) -> Result<(), BlobError> {
+    let mut reader = store.reader(*hash);
+    let mut buf = vec![0u8; TRANSFER_CHUNK_SIZE];
+    loop {
+        let n = tokio::time::timeout(TRANSFER_TIMEOUT, reader.read(&mut buf))
+            .await
+            .map_err(|_| BlobError::StoreError("blob read timed out".to_string()))?
+            .map_err(|e| BlobError::StoreError(e.to_string()))?;
+        if n == 0 {
+            return Ok(());
+        }
+        tokio::time::timeout(TRANSFER_TIMEOUT, send.write_all(&buf[..n]))
+            .await
+            .map_err(|_| BlobError::ConnectionFailed("blob send timed out".to_string()))?
+            .map_err(|e| BlobError::ConnectionFailed(e.to_string()))?;
+    }
+}
+
+/// Stream the `size` bytes a peer sends for `hash` into `store`, keeping
+/// them only if they hash to `hash`.
+async fn receive_blob(
+    store: &FsStore,
+    recv: &mut RecvStream,
+    hash: &Hash,
+    size: u64,
+) -> Result<(), BlobError> {
+    if size > MAX_PEER_BLOB_SIZE {
+        return Err(BlobError::StoreError(format!("blob too large: {} bytes", size)));
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::channel(8);
+    let pump = async move {
+        let mut left = size;
+        while left > 0 {
+            let want = left.min(TRANSFER_CHUNK_SIZE as u64) as usize;
+            let next = match tokio::time::timeout(TRANSFER_TIMEOUT, recv.read_chunk(want, true)).await {
+                Ok(Ok(Some(chunk))) => Ok(chunk.bytes),
+                Ok(Ok(None)) => Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "blob cut short")),
+                Ok(Err(e)) => Err(std::io::Error::other(e.to_string())),
+                Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "blob receive timed out")),
+            };
+            let failed = next.as_ref().err().map(|e| e.to_string());
+            if let Ok(chunk) = &next {
+                left -= chunk.len() as u64;
+            }
+            // Errors go to the import too, so it stops waiting for more.
+            if tx.send(next).await.is_err() {
+                break;
+            }
+            if let Some(e) = failed {
+                return Err(e);
+            }
+        }
+        Ok(())
+    };
+    let add = async { store.add_stream(ChunkStream(rx)).await.temp_tag().await };
+    let (pumped, added) = tokio::join!(pump, add);
+    pumped.map_err(BlobError::ConnectionFailed)?;
+    let tag = added.map_err(|e| BlobError::StoreError(e.to_string()))?;
+
+    // Dropping the temp tag leaves wrong data to the store's GC.
+    if tag.hash() != *hash {
+        return Err(BlobError::StoreError(format!("peer sent wrong data for {}", hash.to_hex())));
+    }
+    store
+        .tags()
+        .create(tag.hash_and_format())
+        .await
+        .map_err(|e| BlobError::StoreError(e.to_string()))?;
+    blob_gc::note_stored(hash, size, false).await;
+    Ok(())
+}
+
+/// Chunks received from a peer, as the stream `FsStore::add_stream` takes.
+struct ChunkStream(tokio::sync::mpsc::Receiver<std::io::Result<Bytes>>);
+
+impl Stream for ChunkStream {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+async fn write_frame<T: Serialize>(send: &mut SendStream, frame: &T) -> Result<(), NetworkError> {
+    let bytes = encode_cbor(frame).map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    send.write_all(&(bytes.len() as u32).to_be_bytes())
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    send.write_all(&bytes)
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    Ok(())
+}
+
+async fn read_frame<T: for<'de> Deserialize<'de>>(recv: &mut RecvStream) -> Result<T, NetworkError> {
+    tokio::time::timeout(TRANSFER_TIMEOUT, read_frame_inner(recv))
+        .await
+        .map_err(|_| NetworkError::StreamError("blob frame timed out".to_string()))?
+}
+
+async fn read_frame_inner<T: for<'de> Deserialize<'de>>(recv: &mut RecvStream) -> Result<T, NetworkError> {
+    let mut size_buf = [0u8; 4];
+    recv.read_exact(&mut size_buf)
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    let size = u32::from_be_bytes(size_buf) as usize;
+    if size > MAX_BLOB_FRAME_SIZE {
+        return Err(NetworkError::ProtocolError(format!("Frame too large: {} bytes", size)));
+    }
+
+    let mut bytes = vec![0u8; size];
+    recv.read_exact(&mut bytes)
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    decode_cbor(&bytes).map_err(|e| NetworkError::ProtocolError(e.to_string()))
+}
+
 #[derive(Debug, Clone)]
 pub struct BlobInfo {
     pub hash: String,
@@ -369,4 +1242,229 @@ mod tests {
         let h2 = Hash::new(data).to_hex();
         assert_eq!(h1, h2);
     }
+
+    #[test]
+    fn transfer_frames_roundtrip_through_cbor() {
+        let req = BlobRequest::Offer { hash: "ab".repeat(32), size: 42 };
+        match decode_cbor::<BlobRequest>(&encode_cbor(&req).unwrap()).unwrap() {
+            BlobRequest::Offer { hash, size } => {
+                assert_eq!(hash, "ab".repeat(32));
+                assert_eq!(size, 42);
+            }
+            _ => panic!("expected offer"),
+        }
+        let resp = encode_cbor(&BlobResponse::Found { size: 7 }).unwrap();
+        assert!(resp.len() <= MAX_BLOB_FRAME_SIZE);
+        assert!(matches!(decode_cbor::<BlobResponse>(&resp).unwrap(), BlobResponse::Found { size: 7 }));
+    }
+
+    #[test]
+    fn sealed_blobs_roundtrip_at_chunk_boundaries() {
+        let key = BlobKey::generate();
+        for len in [0, 1, BLOB_CHUNK_SIZE - 1, BLOB_CHUNK_SIZE, 2 * BLOB_CHUNK_SIZE + 5] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let sealed = seal_blob(&key, &data).unwrap();
+            let chunks = len.max(1).div_ceil(BLOB_CHUNK_SIZE);
+            assert_eq!(sealed.len(), len + chunks * BLOB_TAG_SIZE);
+            assert_eq!(open_blob(&key, &sealed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn sealed_blobs_reject_tampering_truncation_and_wrong_keys() {
+        let key = BlobKey::generate();
+        let data = vec![7u8; 2 * BLOB_CHUNK_SIZE + 100];
+        let sealed = seal_blob(&key, &data).unwrap();
+
+        let mut flipped = sealed.clone();
+        flipped[BLOB_CHUNK_SIZE + 3] ^= 1;
+        assert!(open_blob(&key, &flipped).is_err());
+
+        // Dropping whole chunks leaves a valid-looking but non-final tail
+        let truncated = &sealed[..2 * (BLOB_CHUNK_SIZE + BLOB_TAG_SIZE)];
+        assert!(open_blob(&key, truncated).is_err());
+
+        assert!(open_blob(&BlobKey::generate(), &sealed).is_err());
+        assert!(BlobKey::from_parts(&key.key, &key.nonce[..10]).is_none());
+    }
+
+    #[tokio::test]
+    async fn imported_chunks_open_like_sealed_blobs() {
+        let key = BlobKey::generate();
+        let data: Vec<u8> = (0..2 * BLOB_CHUNK_SIZE + 5).map(|i| (i % 251) as u8).collect();
+        let mut chunks = ImportChunks {
+            reader: std::io::Cursor::new(data.clone()),
+            cipher: Some(ChunkCipher::new(&key)),
+            progress: Progress::new(None, data.len() as u64),
+            pending: None,
+            finished: false,
+        };
+        let mut sealed = Vec::new();
+        while let Some(chunk) = chunks.next_chunk().await {
+            sealed.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(sealed, seal_blob(&key, &data).unwrap());
+        assert_eq!(open_blob(&key, &sealed).unwrap(), data);
+
+        let mut exported = Vec::new();
+        let mut reader = std::io::Cursor::new(sealed.clone());
+        copy_out(&mut reader, &mut exported, sealed.len() as u64, Some(ChunkCipher::new(&key)), Progress::new(None, 0))
+            .await
+            .unwrap();
+        assert_eq!(exported, data);
+    }
+
+    #[tokio::test]
+    async fn sealed_ranges_match_plaintext_slices() {
+        let key = BlobKey::generate();
+        let data: Vec<u8> = (0..3 * BLOB_CHUNK_SIZE + 7).map(|i| (i % 241) as u8).collect();
+        let sealed = seal_blob(&key, &data).unwrap();
+        assert_eq!(opened_len(sealed.len() as u64), data.len() as u64);
+
+        let chunk = BLOB_CHUNK_SIZE as u64;
+        for (offset, len) in [(0, 10), (chunk - 3, 10), (2 * chunk, chunk + 100), (3 * chunk, 100), (chunk, 0)] {
+            let mut reader = std::io::Cursor::new(sealed.clone());
+            let range = read_sealed_range(&mut reader, sealed.len() as u64, ChunkCipher::new(&key), offset, len)
+                .await
+                .unwrap();
+            let end = (offset + len).min(data.len() as u64) as usize;
+            assert_eq!(range, data[offset as usize..end]);
+        }
+
+        let mut reader = std::io::Cursor::new(sealed.clone());
+        let past_end = read_sealed_range(&mut reader, sealed.len() as u64, ChunkCipher::new(&key), data.len() as u64 + 5, 10)
+            .await
+            .unwrap();
+        assert!(past_end.is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_a_claimed_key_that_opens_the_blob_is_adopted() {
+        let dir = std::env::temp_dir().join(format!("gardens-blobs-{:016x}", rand::random::<u64>()));
+        let store = FsStore::load(&dir).await.unwrap();
+        let pool = sqlx::sqlite::SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let key = BlobKey::generate();
+        let hash = store.add_bytes(seal_blob(&key, b"holiday photo").unwrap()).await.unwrap().hash;
+        let hash_hex = hash.to_hex();
+        let meta = db::BlobMeta { blob_hash: hash_hex.clone(), mime_type: "image/jpeg".into(), ..Default::default() };
+        db::insert_blob_meta(&pool, &meta).await.unwrap();
+
+        // Someone else's message names the blob first, with a bogus key
+        let bogus = BlobKey::generate();
+        db::insert_blob_key(&pool, &hash_hex, "m1", &bogus.key, &bogus.nonce).await.unwrap();
+        assert!(verified_key(&store, &pool, hash).await.is_err());
+
+        db::insert_blob_key(&pool, &hash_hex, "m2", &key.key, &key.nonce).await.unwrap();
+        let adopted = verified_key(&store, &pool, hash).await.unwrap().unwrap();
+        assert_eq!(adopted.key, key.key);
+        let meta = db::get_blob_meta(&pool, &hash_hex).await.unwrap().unwrap();
+        assert_eq!(meta.secret_id.as_deref(), Some(&key.key[..]));
+    }
+
+    // ── Two endpoints in one process ──
+
+    async fn bind(key: &p2panda_core::PrivateKey) -> Endpoint {
+        Endpoint::builder()
+            .secret_key(iroh::SecretKey::from_bytes(key.as_bytes()))
+            .alpns(vec![BLOB_ALPN.to_vec()])
+            .relay_mode(iroh::RelayMode::Disabled)
+            .clear_discovery()
+            .bind()
+            .await
+            .unwrap()
+    }
+
+    fn loopback_addr(endpoint: &Endpoint) -> EndpointAddr {
+        let port = endpoint.bound_sockets().into_iter().find(|a| a.is_ipv4()).unwrap().port();
+        EndpointAddr::new(endpoint.id()).with_ip_addr(([127, 0, 0, 1], port).into())
+    }
+
+    async fn temp_store() -> FsStore {
+        let dir = std::env::temp_dir().join(format!("gardens-blobs-{:016x}", rand::random::<u64>()));
+        FsStore::load(dir).await.unwrap()
+    }
+
+    /// Serve `count` blob connections on `endpoint` from `core` and `store`.
+    fn serve(
+        endpoint: &Endpoint,
+        core: &'static store::GardensCore,
+        store: FsStore,
+        count: usize,
+    ) -> tokio::task::JoinHandle<()> {
+        let endpoint = endpoint.clone();
+        tokio::spawn(async move {
+            for _ in 0..count {
+                let conn = endpoint.accept().await.unwrap().await.unwrap();
+                let _ = serve_blob_connection(&core.read_pool, &core.public_key_hex, &store, conn).await;
+            }
+        })
+    }
+
+    /// A core for `key` where `members` may fetch blobs posted in room1.
+    async fn core_sharing_room(key: &p2panda_core::PrivateKey, members: &[&p2panda_core::PrivateKey]) -> &'static store::GardensCore {
+        let mut core = store::test_core().await;
+        core.private_key = key.clone();
+        core.public_key_hex = key.public_key().to_hex();
+        for member in members {
+            db::upsert_membership(&core.read_pool, "org1", &member.public_key().to_hex(), "write", 1)
+                .await
+                .unwrap();
+        }
+        sqlx::query("INSERT INTO rooms (room_id, org_id, name, created_by, created_at) VALUES ('room1', 'org1', 'general', 'x', 1)")
+            .execute(&core.read_pool)
+            .await
+            .unwrap();
+        Box::leak(Box::new(core))
+    }
+
+    #[tokio::test]
+    async fn blobs_stream_between_peers_in_chunks() {
+        let (alice, bob) = (p2panda_core::PrivateKey::new(), p2panda_core::PrivateKey::new());
+        let a = core_sharing_room(&alice, &[&alice, &bob]).await;
+        let (a_store, b_store) = (temp_store().await, temp_store().await);
+
+        let data: Vec<u8> = (0..3 * TRANSFER_CHUNK_SIZE + 17).map(|i| (i % 251) as u8).collect();
+        let hash = a_store.add_slice(&data).await.unwrap().hash;
+        sqlx::query("INSERT INTO blob_meta (blob_hash, mime_type, room_id) VALUES (?, 'application/octet-stream', 'room1')")
+            .bind(hash.to_hex())
+            .execute(&a.read_pool)
+            .await
+            .unwrap();
+
+        let (a_ep, b_ep) = (bind(&alice).await, bind(&bob).await);
+        let server = serve(&a_ep, a, a_store, 1);
+        fetch_from_peer(&b_ep, &b_store, loopback_addr(&a_ep), &hash).await.unwrap();
+        server.await.unwrap();
+
+        assert_eq!(local_size(&b_store, &hash).await.unwrap(), Some(data.len() as u64));
+        assert_eq!(b_store.get_bytes(hash).await.unwrap().as_ref(), &data[..]);
+    }
+
+    #[tokio::test]
+    async fn peers_outside_the_room_are_denied() {
+        let (alice, carol) = (p2panda_core::PrivateKey::new(), p2panda_core::PrivateKey::new());
+        let a = core_sharing_room(&alice, &[&alice]).await;
+        let (a_store, c_store) = (temp_store().await, temp_store().await);
+
+        let hash = a_store.add_slice(b"members only").await.unwrap().hash;
+        sqlx::query("INSERT INTO blob_meta (blob_hash, mime_type, room_id) VALUES (?, 'text/plain', 'room1')")
+            .bind(hash.to_hex())
+            .execute(&a.read_pool)
+            .await
+            .unwrap();
+        let junk = c_store.add_slice(b"unsolicited").await.unwrap().hash;
+
+        let (a_ep, c_ep) = (bind(&alice).await, bind(&carol).await);
+        let server = serve(&a_ep, a, a_store.clone(), 2);
+        let fetched = fetch_from_peer(&c_ep, &c_store, loopback_addr(&a_ep), &hash).await;
+        assert!(matches!(fetched, Err(BlobError::Denied)), "got {:?}", fetched.err());
+        let offered = offer_to_peer(&c_ep, &c_store, loopback_addr(&a_ep), junk).await;
+        assert!(matches!(offered, Err(BlobError::Denied)), "got {:?}", offered.err());
+        server.await.unwrap();
+
+        assert_eq!(local_size(&c_store, &hash).await.unwrap(), None);
+        assert_eq!(local_size(&a_store, &junk).await.unwrap(), None);
+    }
 }
@@ -0,0 +1,74 @@
+//! Cover traffic for onion routing.
+//!
+//! Padded cells hide how big a packet is, but not when it is sent: a relay
+//! watching a client can still line its packets up with the user's
+//! messages. When enabled, [`set_rate`] runs a task that sends dummy packets
+//! (`OnionPayload::Drop`) through the configured relay hops with
+//! exponentially distributed gaps, i.e. as a Poisson process. Dummies are
+//! indistinguishable from real packets until the exit peels them.
+
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::task::JoinHandle;
+
+use crate::{onion, outbox, store};
+
+/// Ceiling on the configured rate, so a bad setting cannot flood the relays.
+const MAX_PACKETS_PER_MINUTE: f64 = 60.0;
+
+static COVER_TASK: StdMutex<Option<JoinHandle<()>>> = StdMutex::new(None);
+
+/// Start, retune or stop (`packets_per_minute <= 0`) the cover-traffic task.
+pub fn set_rate(packets_per_minute: f64) {
+    let mut task = COVER_TASK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(handle) = task.take() {
+        handle.abort();
+    }
+    if !packets_per_minute.is_finite() || packets_per_minute <= 0.0 {
+        return;
+    }
+
+    let per_second = packets_per_minute.min(MAX_PACKETS_PER_MINUTE) / 60.0;
+    *task = Some(store::spawn(run_cover_traffic(per_second)));
+}
+
+async fn run_cover_traffic(per_second: f64) {
+    loop {
+        // Draw before awaiting: the thread-local rng is not Send
+        let delay = next_delay(per_second, rand::thread_rng().gen());
+        tokio::time::sleep(delay).await;
+        if let Err(e) = send_cover_packet().await {
+            log::debug!("[cover] dummy packet not sent: {}", e);
+        }
+    }
+}
+
+async fn send_cover_packet() -> Result<(), String> {
+    let hops = outbox::relay_hops()?;
+    let packet = onion::build_cover_packet(&hops).map_err(|e| e.to_string())?;
+    outbox::post_onion_packet(&hops[0].next_url, packet).await
+}
+
+/// Gap before the next packet: an exponential draw with mean `1 / per_second`
+/// from a uniform sample `u` in [0, 1).
+fn next_delay(per_second: f64, u: f64) -> Duration {
+    Duration::from_secs_f64(-(1.0 - u).ln() / per_second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_are_exponential_with_the_configured_mean() {
+        assert_eq!(next_delay(1.0, 0.0), Duration::ZERO);
+        let median = next_delay(0.5, 0.5).as_secs_f64();
+        assert!((median - 2.0 * std::f64::consts::LN_2).abs() < 1e-9);
+
+        let n = 10_000;
+        let mean = (0..n).map(|i| next_delay(2.0, i as f64 / n as f64).as_secs_f64()).sum::<f64>() / n as f64;
+        assert!((mean - 0.5).abs() < 0.01, "mean {mean}");
+    }
+}
@@ -23,7 +23,7 @@ pub fn ed25519_pubkey_to_x25519(pubkey_bytes: &[u8; 32]) -> X25519Public {
     let compressed = CompressedEdwardsY(*pubkey_bytes);
     let point = compressed
         .decompress()
-        .unwrap_or(curve25519_dalek::EdwardsPoint::default());
+        .unwrap_or_default();
     X25519Public::from(point.to_montgomery().to_bytes())
 }
 
@@ -13,16 +13,33 @@ use thiserror::Error;
 pub enum DbError {
     #[error("database error: {0}")]
     Sqlx(#[from] sqlx::Error),
+    #[error("migration {version} ({name}) failed: {source}")]
+    Migration {
+        version: i64,
+        name: &'static str,
+        source: sqlx::Error,
+    },
 }
 
 // ─── Schema ──────────────────────────────────────────────────────────────────
 
-/// Create all read-model tables if they don't already exist.
-pub async fn run_migrations(pool: &SqlitePool) -> Result<(), DbError> {
-    sqlx::query(
-        r#"
-        PRAGMA journal_mode=WAL;
+/// A numbered schema change. Migrations run in order, each in its own
+/// transaction, and are recorded in `schema_version` once applied.
+struct Migration {
+    version: i64,
+    name: &'static str,
+    sql: &'static str,
+    /// Set when the change alters what the projector writes, so existing
+    /// read models must be re-projected (see `projector::rebuild_read_model`).
+    rebuilds_projection: bool,
+}
 
+/// Ordered schema history. Append new migrations; never edit applied ones.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline",
+        sql: r#"
         CREATE TABLE IF NOT EXISTS profiles (
             public_key      TEXT PRIMARY KEY,
             username        TEXT NOT NULL,
@@ -146,6 +163,16 @@ pub async fn run_migrations(pool: &SqlitePool) -> Result<(), DbError> {
             is_deleted      INTEGER NOT NULL DEFAULT 0
         );
 
+        CREATE TABLE IF NOT EXISTS message_edits (
+            edit_id         TEXT PRIMARY KEY,
+            message_id      TEXT NOT NULL,
+            author_key      TEXT NOT NULL,
+            text_content    TEXT,
+            mentions        TEXT,
+            edited_at       INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_message_edits_message ON message_edits(message_id, edited_at);
+
         CREATE TABLE IF NOT EXISTS reactions (
             message_id      TEXT NOT NULL,
             emoji           TEXT NOT NULL,
@@ -186,6 +213,36 @@ pub async fn run_migrations(pool: &SqlitePool) -> Result<(), DbError> {
             state_data  BLOB NOT NULL
         );
 
+        -- Encrypted room ops the projector could not decrypt yet (e.g. the
+        -- group welcome has not arrived). Retried with backoff on later ticks.
+        CREATE TABLE IF NOT EXISTS pending_decrypt (
+            op_hash         TEXT PRIMARY KEY,
+            log_id          TEXT NOT NULL,
+            author_key      TEXT NOT NULL,
+            group_id        TEXT NOT NULL,
+            group_type      TEXT NOT NULL,
+            encrypted       BLOB NOT NULL,
+            timestamp       INTEGER NOT NULL,
+            attempts        INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL DEFAULT 0,
+            last_error      TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_pending_decrypt_next ON pending_decrypt(next_attempt_at);
+        CREATE INDEX IF NOT EXISTS idx_pending_decrypt_group ON pending_decrypt(group_id);
+
+        -- Ops the projector refused to apply (e.g. author lacked the access
+        -- level the op requires). Kept for diagnostics; never retried.
+        CREATE TABLE IF NOT EXISTS rejected_ops (
+            op_hash         TEXT PRIMARY KEY,
+            log_id          TEXT NOT NULL,
+            author_key      TEXT NOT NULL,
+            op_type         TEXT NOT NULL,
+            org_id          TEXT,
+            reason          TEXT NOT NULL,
+            rejected_at     INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_rejected_ops_org ON rejected_ops(org_id, rejected_at);
+
         CREATE TABLE IF NOT EXISTS blob_meta (
             blob_hash   TEXT PRIMARY KEY,
             mime_type   TEXT NOT NULL,
@@ -250,51 +307,427 @@ pub async fn run_migrations(pool: &SqlitePool) -> Result<(), DbError> {
             claimed_by      TEXT,
             claimed_at      INTEGER
         );
+
+        -- Full-text index over message text; the message helpers keep it in sync.
+        CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+            message_id UNINDEXED,
+            text_content,
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+        INSERT INTO messages_fts (message_id, text_content)
+            SELECT message_id, text_content FROM messages
+            WHERE is_deleted = 0 AND text_content IS NOT NULL
+              AND message_id NOT IN (SELECT message_id FROM messages_fts);
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 2,
+        name: "authorize_org_room_event_ops",
+        // No schema change: org, room and event ops are now authorized at
+        // projection time, so read models built before that must be replayed.
+        sql: "",
+        rebuilds_projection: true,
+    },
+    Migration {
+        version: 3,
+        name: "causal_dependencies",
+        // Replayed so projected_ops covers ops projected before it existed.
+        sql: r#"
+        -- Every op the projector has handled, so later ops can name it in
+        -- their header's `previous` field.
+        CREATE TABLE IF NOT EXISTS projected_ops (
+            op_hash         TEXT PRIMARY KEY
+        );
+
+        -- Ops held back until everything in their `previous` is projected.
+        CREATE TABLE IF NOT EXISTS pending_deps (
+            op_hash         TEXT PRIMARY KEY,
+            log_id          TEXT NOT NULL,
+            author_key      TEXT NOT NULL,
+            previous        TEXT NOT NULL,  -- JSON array of op hashes
+            body            BLOB NOT NULL,
+            timestamp       INTEGER NOT NULL,
+            received_at     INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_pending_deps_timestamp ON pending_deps(timestamp);
+        "#,
+        rebuilds_projection: true,
+    },
+    Migration {
+        version: 4,
+        name: "fork_evidence",
+        sql: r#"
+        -- Two different ops signed by one author at the same seq_num. The
+        -- conflicting signed header is kept as proof.
+        CREATE TABLE IF NOT EXISTS fork_evidence (
+            author_key       TEXT NOT NULL,
+            log_id           TEXT NOT NULL,
+            seq_num          INTEGER NOT NULL,
+            existing_hash    TEXT NOT NULL,
+            conflicting_hash TEXT NOT NULL,
+            signed_header    BLOB NOT NULL,
+            detected_at      INTEGER NOT NULL,
+            PRIMARY KEY (author_key, log_id, seq_num, conflicting_hash)
+        );
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 5,
+        name: "outbox",
+        sql: r#"
+        -- Locally authored ops and their delivery state, one row per
+        -- transport. Not part of the projection: survives rebuilds.
+        CREATE TABLE IF NOT EXISTS outbox (
+            op_hash         TEXT NOT NULL,
+            transport       TEXT NOT NULL,  -- 'gossip' | 'onion' | 'sync'
+            log_id          TEXT NOT NULL,
+            room_id         TEXT,
+            dm_thread_id    TEXT,
+            recipient_key   TEXT,
+            topic_hex       TEXT NOT NULL,
+            envelope        BLOB NOT NULL,
+            state           TEXT NOT NULL DEFAULT 'pending',
+            attempts        INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL,
+            last_error      TEXT,
+            created_at      INTEGER NOT NULL,
+            delivered_at    INTEGER,
+            PRIMARY KEY (op_hash, transport)
+        );
+        CREATE INDEX IF NOT EXISTS idx_outbox_due ON outbox(state, next_attempt_at);
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 6,
+        name: "blob_previews",
+        // Older messages carry no previews, so replaying adds nothing.
+        sql: r#"
+        -- Image placeholder: dimensions, blurhash and the thumbnail blob.
+        ALTER TABLE blob_meta ADD COLUMN thumbnail_hash TEXT;
+        ALTER TABLE blob_meta ADD COLUMN width INTEGER;
+        ALTER TABLE blob_meta ADD COLUMN height INTEGER;
+        ALTER TABLE blob_meta ADD COLUMN blurhash TEXT;
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 7,
+        name: "blob_storage",
+        sql: r#"
+        -- Blobs held in the local iroh store. Not part of the projection:
+        -- survives rebuilds.
+        CREATE TABLE IF NOT EXISTS blob_store_entries (
+            blob_hash       TEXT PRIMARY KEY,
+            size            INTEGER NOT NULL,
+            is_own          INTEGER NOT NULL DEFAULT 0,  -- stored by us; never evicted
+            stored_at       INTEGER NOT NULL,
+            last_used_at    INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_blob_store_entries_lru ON blob_store_entries(is_own, last_used_at);
+
+        -- Cache limits; org_id '' is the global limit.
+        CREATE TABLE IF NOT EXISTS blob_quotas (
+            org_id          TEXT PRIMARY KEY,
+            max_bytes       INTEGER NOT NULL
+        );
+
+        -- Everything in the read model that points at a blob, with the org
+        -- and room it belongs to and the key of whoever posted it.
+        CREATE VIEW IF NOT EXISTS blob_refs_direct AS
+            SELECT m.blob_id AS blob_hash, r.org_id AS org_id, m.room_id AS room_id, m.author_key AS owner_key
+            FROM messages m LEFT JOIN rooms r ON r.room_id = m.room_id
+            WHERE m.blob_id IS NOT NULL AND m.is_deleted = 0
+            UNION ALL
+            SELECT avatar_blob_id, NULL, NULL, public_key FROM profiles WHERE avatar_blob_id IS NOT NULL
+            UNION ALL
+            SELECT avatar_blob_id, org_id, NULL, creator_key FROM organizations WHERE avatar_blob_id IS NOT NULL
+            UNION ALL
+            SELECT cover_blob_id, org_id, NULL, creator_key FROM organizations WHERE cover_blob_id IS NOT NULL;
+
+        -- ... plus the thumbnails of those blobs.
+        CREATE VIEW IF NOT EXISTS blob_refs AS
+            SELECT blob_hash, org_id, room_id, owner_key FROM blob_refs_direct
+            UNION ALL
+            SELECT b.thumbnail_hash, d.org_id, d.room_id, d.owner_key
+            FROM blob_meta b JOIN blob_refs_direct d ON d.blob_hash = b.blob_hash
+            WHERE b.thumbnail_hash IS NOT NULL;
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 8,
+        name: "blob_keys",
+        sql: r#"
+        -- Content keys as each message carries them. A key only becomes the
+        -- blob's (blob_meta.secret_id) once it has opened the blob. Not part
+        -- of the projection: survives rebuilds with DM history.
+        CREATE TABLE IF NOT EXISTS blob_keys (
+            blob_hash       TEXT NOT NULL,
+            message_id      TEXT NOT NULL,
+            secret_id       BLOB NOT NULL,
+            nonce           BLOB NOT NULL,
+            PRIMARY KEY (blob_hash, message_id)
+        );
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 9,
+        name: "used_reply_blocks",
+        sql: r#"
+        -- Onion reply-block layers this node has peeled, by replay id, kept
+        -- until the block would be stale anyway. Not part of the projection.
+        CREATE TABLE IF NOT EXISTS used_reply_blocks (
+            replay_id       BLOB PRIMARY KEY,
+            expires_at      INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_used_reply_blocks_expires ON used_reply_blocks(expires_at);
         "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 10,
+        name: "messages_fts_external_content",
+        sql: r#"
+        -- The search index reads its text from `messages` by rowid instead of
+        -- holding a copy, and triggers keep it in step with every write.
+        DROP TABLE IF EXISTS messages_fts;
+        CREATE VIRTUAL TABLE messages_fts USING fts5(
+            text_content,
+            content = 'messages',
+            content_rowid = 'rowid',
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+        INSERT INTO messages_fts (rowid, text_content)
+            SELECT rowid, text_content FROM messages
+            WHERE is_deleted = 0 AND text_content IS NOT NULL;
+
+        CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages
+        WHEN new.is_deleted = 0 AND new.text_content IS NOT NULL
+        BEGIN
+            INSERT INTO messages_fts (rowid, text_content) VALUES (new.rowid, new.text_content);
+        END;
+        CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages
+        WHEN old.is_deleted = 0 AND old.text_content IS NOT NULL
+        BEGIN
+            INSERT INTO messages_fts (messages_fts, rowid, text_content)
+                VALUES ('delete', old.rowid, old.text_content);
+        END;
+        CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF text_content, is_deleted ON messages
+        BEGIN
+            INSERT INTO messages_fts (messages_fts, rowid, text_content)
+                SELECT 'delete', old.rowid, old.text_content
+                WHERE old.is_deleted = 0 AND old.text_content IS NOT NULL;
+            INSERT INTO messages_fts (rowid, text_content)
+                SELECT new.rowid, new.text_content
+                WHERE new.is_deleted = 0 AND new.text_content IS NOT NULL;
+        END;
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 11,
+        name: "onetime_prekey_stock",
+        sql: r#"
+        -- How many one-time pre-keys we have published and how many DM
+        -- threads opened towards us have used up, so top-ups only replace
+        -- what was consumed. Not part of the projection.
+        CREATE TABLE IF NOT EXISTS onetime_prekey_stock (
+            id          INTEGER PRIMARY KEY CHECK (id = 1),
+            published   INTEGER NOT NULL DEFAULT 0,
+            consumed    INTEGER NOT NULL DEFAULT 0
+        );
+        INSERT OR IGNORE INTO onetime_prekey_stock (id) VALUES (1);
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 12,
+        name: "pending_decrypt_queued_at",
+        sql: r#"
+        -- When an op was parked, so ops that never become decryptable
+        -- expire instead of being retried forever. Rows already queued
+        -- start their clock now.
+        ALTER TABLE pending_decrypt ADD COLUMN queued_at INTEGER NOT NULL DEFAULT 0;
+        UPDATE pending_decrypt SET queued_at = CAST(strftime('%s', 'now') AS INTEGER) * 1000000;
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 13,
+        name: "relay_directory_cache",
+        sql: r#"
+        -- The last relay directory resolved via pkarr, one TXT record per
+        -- relay, so routes can be picked before the next refresh after a
+        -- restart. Not part of the projection.
+        CREATE TABLE IF NOT EXISTS relay_directory (
+            position        INTEGER PRIMARY KEY,
+            directory_key   TEXT NOT NULL,
+            record          TEXT NOT NULL,
+            fetched_at      INTEGER NOT NULL
+        );
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 14,
+        name: "pending_deps_expiry",
+        sql: r#"
+        -- Held-back ops expire after a while and are capped per author.
+        CREATE INDEX IF NOT EXISTS idx_pending_deps_received ON pending_deps(received_at);
+        CREATE INDEX IF NOT EXISTS idx_pending_deps_author ON pending_deps(author_key);
+        "#,
+        rebuilds_projection: false,
+    },
+    Migration {
+        version: 15,
+        name: "room_type",
+        // Replayed so rooms created as voice rooms are projected as such.
+        sql: r#"
+        ALTER TABLE rooms ADD COLUMN room_type TEXT NOT NULL DEFAULT 'text';
+        "#,
+        rebuilds_projection: true,
+    },
+];
+
+/// Columns added by ad-hoc `ALTER TABLE`s before schema versioning. Databases
+/// that predate `schema_version` are brought up to the baseline with these.
+const LEGACY_COLUMNS: &[(&str, &str, &str)] = &[
+    ("profiles", "is_public", "INTEGER NOT NULL DEFAULT 0"),
+    ("profiles", "email_enabled", "INTEGER NOT NULL DEFAULT 0"),
+    ("organizations", "org_pubkey", "TEXT"),
+    ("organizations", "org_privkey_enc", "BLOB"),
+    ("organizations", "cover_blob_id", "TEXT"),
+    ("organizations", "welcome_text", "TEXT"),
+    ("organizations", "custom_emoji_json", "TEXT"),
+    ("organizations", "org_cooldown_secs", "INTEGER"),
+    ("organizations", "email_enabled", "INTEGER NOT NULL DEFAULT 0"),
+    ("rooms", "room_cooldown_secs", "INTEGER"),
+    ("dm_threads", "is_request", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// Bring the read-model schema up to date.
+///
+/// Applies every migration newer than the recorded `schema_version`, each in
+/// a transaction; any failure aborts startup with the failing migration
+/// named. Migrations flagged `rebuilds_projection` leave a pending rebuild
+/// that the projector runs when it starts, unless the database was empty.
+pub async fn run_migrations(pool: &SqlitePool) -> Result<(), DbError> {
+    // journal_mode can't change inside a transaction.
+    sqlx::query("PRAGMA journal_mode=WAL").execute(pool).await?;
+    sqlx::query(
+        r#"CREATE TABLE IF NOT EXISTS schema_version (
+            version         INTEGER PRIMARY KEY,
+            name            TEXT NOT NULL,
+            applied_at      INTEGER NOT NULL,
+            rebuild_pending INTEGER NOT NULL DEFAULT 0
+        )"#,
     )
     .execute(pool)
     .await?;
 
-    // ── Additive migrations (safe to re-run: errors are ignored) ──────────────
-    // ALTER TABLE ADD COLUMN fails with "duplicate column name" if already present;
-    // that is harmless, so we swallow the error.
-    for sql in &[
-        "ALTER TABLE profiles ADD COLUMN is_public INTEGER NOT NULL DEFAULT 0",
-        "ALTER TABLE organizations ADD COLUMN org_pubkey TEXT",
-        "ALTER TABLE organizations ADD COLUMN org_privkey_enc BLOB",
-        "ALTER TABLE organizations ADD COLUMN cover_blob_id TEXT",
-        "ALTER TABLE organizations ADD COLUMN welcome_text TEXT",
-        "ALTER TABLE organizations ADD COLUMN custom_emoji_json TEXT",
-        "ALTER TABLE organizations ADD COLUMN org_cooldown_secs INTEGER",
-        "ALTER TABLE organizations ADD COLUMN email_enabled INTEGER NOT NULL DEFAULT 0",
-        "ALTER TABLE rooms ADD COLUMN room_cooldown_secs INTEGER",
-        "ALTER TABLE profiles ADD COLUMN email_enabled INTEGER NOT NULL DEFAULT 0",
-        "ALTER TABLE dm_threads ADD COLUMN is_request INTEGER NOT NULL DEFAULT 0",
-        "ALTER TABLE rooms ADD COLUMN room_type TEXT NOT NULL DEFAULT 'text'",
-    ] {
-        let _ = sqlx::query(sql).execute(pool).await;
-    }
-
-    // Create audit_log table for existing databases
-    let _ = sqlx::query(
-        r#"CREATE TABLE IF NOT EXISTS audit_log (
-            id              INTEGER PRIMARY KEY AUTOINCREMENT,
-            org_id          TEXT NOT NULL,
-            moderator_key   TEXT NOT NULL,
-            target_key      TEXT NOT NULL,
-            action_type     TEXT NOT NULL,
-            details         TEXT,
-            created_at      INTEGER NOT NULL,
-            UNIQUE(org_id, moderator_key, target_key, action_type, created_at)
-        )"#
-    ).execute(pool).await;
-    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_log_org ON audit_log(org_id)").execute(pool).await;
-    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_log_target ON audit_log(target_key)").execute(pool).await;
-    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_log_created ON audit_log(created_at)").execute(pool).await;
+    let current: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    let fresh = current == 0 && !table_exists(pool, "profiles").await?;
+    if current == 0 && !fresh {
+        adopt_legacy_schema(pool).await?;
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        apply_migration(pool, migration, !fresh).await.map_err(|source| {
+            DbError::Migration { version: migration.version, name: migration.name, source }
+        })?;
+    }
+    Ok(())
+}
+
+async fn apply_migration(
+    pool: &SqlitePool,
+    migration: &Migration,
+    has_projection: bool,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if !migration.sql.is_empty() {
+        sqlx::query(migration.sql).execute(&mut *tx).await?;
+    }
+    sqlx::query(
+        "INSERT INTO schema_version (version, name, applied_at, rebuild_pending) VALUES (?, ?, strftime('%s', 'now'), ?)",
+    )
+    .bind(migration.version)
+    .bind(migration.name)
+    .bind((migration.rebuilds_projection && has_projection) as i64)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
 
+/// Patch a pre-versioning database so the baseline migration applies to it:
+/// add the columns older builds added ad hoc. `rooms.room_type` is dropped
+/// so migration 15 can add it; the replay migration 2 schedules restores it.
+async fn adopt_legacy_schema(pool: &SqlitePool) -> Result<(), DbError> {
+    let mut tx = pool.begin().await.map_err(legacy_error)?;
+    if column_exists(&mut tx, "rooms", "room_type").await? {
+        sqlx::query("ALTER TABLE rooms DROP COLUMN room_type")
+            .execute(&mut *tx)
+            .await
+            .map_err(legacy_error)?;
+    }
+    for (table, column, decl) in LEGACY_COLUMNS {
+        let table_present: bool = sqlx::query_scalar(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?",
+        )
+        .bind(table)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(legacy_error)?;
+        if table_present && !column_exists(&mut tx, table, column).await? {
+            sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))
+                .execute(&mut *tx)
+                .await
+                .map_err(legacy_error)?;
+        }
+    }
+    tx.commit().await.map_err(legacy_error)?;
     Ok(())
 }
 
+fn legacy_error(source: sqlx::Error) -> DbError {
+    DbError::Migration { version: 0, name: "adopt_legacy_schema", source }
+}
+
+async fn table_exists(pool: &SqlitePool, table: &str) -> Result<bool, DbError> {
+    Ok(sqlx::query_scalar(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?",
+    )
+    .bind(table)
+    .fetch_one(pool)
+    .await?)
+}
+
+async fn column_exists(
+    conn: &mut sqlx::SqliteConnection,
+    table: &str,
+    column: &str,
+) -> Result<bool, DbError> {
+    sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?")
+        .bind(table)
+        .bind(column)
+        .fetch_one(conn)
+        .await
+        .map_err(legacy_error)
+}
+
+/// Whether a migration has asked for the projection to be rebuilt.
+pub async fn rebuild_pending(pool: &SqlitePool) -> Result<bool, DbError> {
+    Ok(sqlx::query_scalar("SELECT COUNT(*) > 0 FROM schema_version WHERE rebuild_pending = 1")
+        .fetch_one(pool)
+        .await?)
+}
+
 // ─── Encryption state ────────────────────────────────────────────────────────
 
 pub async fn save_enc_key_manager(pool: &SqlitePool, state: &[u8]) -> Result<(), DbError> {
@@ -314,6 +747,29 @@ pub async fn load_enc_key_manager(pool: &SqlitePool) -> Result<Option<Vec<u8>>,
     Ok(row.map(|r| r.get::<Vec<u8>, _>("state_data")))
 }
 
+/// One-time pre-keys we have published that no DM thread has used yet.
+pub async fn unused_onetime_prekeys(pool: &SqlitePool) -> Result<i64, DbError> {
+    Ok(sqlx::query_scalar("SELECT MAX(published - consumed, 0) FROM onetime_prekey_stock WHERE id = 1")
+        .fetch_optional(pool)
+        .await?
+        .unwrap_or(0))
+}
+
+pub async fn note_onetime_prekeys_published(pool: &SqlitePool, count: i64) -> Result<(), DbError> {
+    sqlx::query("UPDATE onetime_prekey_stock SET published = published + ? WHERE id = 1")
+        .bind(count)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn note_onetime_prekey_consumed(pool: &SqlitePool) -> Result<(), DbError> {
+    sqlx::query("UPDATE onetime_prekey_stock SET consumed = MIN(consumed + 1, published) WHERE id = 1")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
 pub async fn save_enc_key_registry(pool: &SqlitePool, state: &[u8]) -> Result<(), DbError> {
     sqlx::query(
         "INSERT INTO enc_key_registry (id, state_data) VALUES (1, ?)\n         ON CONFLICT(id) DO UPDATE SET state_data = excluded.state_data",
@@ -484,14 +940,96 @@ pub struct OrgAdminThreadRow {
     pub is_request: bool,
 }
 
+/// A superseded version of a message, recorded when an edit is applied.
+#[derive(Debug, Clone)]
+pub struct MessageEditRow {
+    pub edit_id: String,           // op hash of the edit op
+    pub message_id: String,
+    pub author_key: String,
+    pub text_content: Option<String>,
+    pub mentions: Vec<String>,     // JSON
+    pub edited_at: i64,            // when this version was replaced
+}
+
+/// An op waiting for the ops in its `previous` field to be projected.
+#[derive(Debug, Clone)]
+pub struct PendingDepRow {
+    pub op_hash: String,
+    pub log_id: String,
+    pub author_key: String,
+    pub previous: Vec<String>, // JSON
+    pub body: Vec<u8>,         // as stored; may still be encrypted
+    pub timestamp: i64,
+}
+
+/// Proof that an author signed two different ops at one position in a log.
+#[derive(Debug, Clone)]
+pub struct ForkEvidenceRow {
+    pub author_key: String,
+    pub log_id: String,
+    pub seq_num: i64,
+    pub existing_hash: String,    // the op we already hold
+    pub conflicting_hash: String, // the op that contradicts it
+    pub signed_header: Vec<u8>,   // received header naming the conflicting op
+    pub detected_at: i64,
+}
+
+/// One delivery of a locally authored op over one transport.
+#[derive(Debug, Clone)]
+pub struct OutboxRow {
+    pub op_hash: String,
+    pub transport: String, // 'gossip' | 'onion' | 'sync'
+    pub log_id: String,
+    pub room_id: Option<String>,
+    pub dm_thread_id: Option<String>,
+    pub recipient_key: Option<String>, // DM inbox the op is sealed to
+    pub topic_hex: String,             // sync worker topic
+    pub envelope: Vec<u8>,             // GossipEnvelope CBOR
+    pub state: String,                 // 'pending' | 'delivered' | 'failed'
+    pub attempts: i64,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub delivered_at: Option<i64>,
+}
+
+/// An op the projector refused to apply, with the reason.
 #[derive(Debug, Clone)]
+pub struct RejectedOpRow {
+    pub op_hash: String,
+    pub log_id: String,
+    pub author_key: String,
+    pub op_type: String,
+    pub org_id: Option<String>,
+    pub reason: String,
+    pub rejected_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingDecryptRow {
+    pub op_hash: String,
+    pub log_id: String,
+    pub author_key: String,
+    pub group_id: String,   // room_id or dm thread_id
+    pub group_type: String, // "room" | "dm"
+    pub encrypted: Vec<u8>, // CBOR EncryptedBody / EncryptedDmBody
+    pub timestamp: i64,
+    pub attempts: i64,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct BlobMeta {
     pub blob_hash: String,
     pub mime_type: String,
     pub room_id: Option<String>,
     pub sender_key: Option<String>,  // hex
-    pub secret_id: Option<Vec<u8>>,  // [u8; 32] GroupSecretId
-    pub nonce: Option<Vec<u8>>,      // [u8; 24] XAeadNonce
+    pub secret_id: Option<Vec<u8>>,  // [u8; 32] per-blob content key
+    pub nonce: Option<Vec<u8>>,      // [u8; 19] STREAM nonce prefix
+    // Image previews (see `media`)
+    pub thumbnail_hash: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub blurhash: Option<String>,
 }
 
 // ─── Profile ─────────────────────────────────────────────────────────────────
@@ -553,8 +1091,8 @@ pub async fn insert_org(pool: &SqlitePool, row: &OrgRow) -> Result<(), DbError>
     .bind(&row.cover_blob_id)
     .bind(&row.welcome_text)
     .bind(&row.custom_emoji_json)
-    .bind(&row.org_cooldown_secs)
-    .bind(row.is_public as i64)
+    .bind(row.org_cooldown_secs)
+    .bind(row.is_public)
     .bind(&row.creator_key)
     .bind(&row.org_pubkey)
     .bind(&row.org_privkey_enc)
@@ -564,6 +1102,7 @@ pub async fn insert_org(pool: &SqlitePool, row: &OrgRow) -> Result<(), DbError>
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn update_org(
     pool: &SqlitePool,
     org_id: &str,
@@ -1165,6 +1704,7 @@ pub async fn insert_event(pool: &SqlitePool, row: &EventRow) -> Result<(), DbErr
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn update_event(
     pool: &SqlitePool,
     event_id: &str,
@@ -1199,6 +1739,30 @@ pub async fn update_event(
     Ok(())
 }
 
+pub async fn get_event(pool: &SqlitePool, event_id: &str) -> Result<Option<EventRow>, DbError> {
+    let row = sqlx::query(
+        "SELECT event_id, org_id, title, description, location_type, location_text, location_room_id, start_at, end_at, created_by, created_at, is_deleted FROM events WHERE event_id = ?"
+    )
+    .bind(event_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| EventRow {
+        event_id: r.get("event_id"),
+        org_id: r.get("org_id"),
+        title: r.get("title"),
+        description: r.get("description"),
+        location_type: r.get("location_type"),
+        location_text: r.get("location_text"),
+        location_room_id: r.get("location_room_id"),
+        start_at: r.get("start_at"),
+        end_at: r.get("end_at"),
+        created_by: r.get("created_by"),
+        created_at: r.get("created_at"),
+        is_deleted: r.get::<i64, _>("is_deleted") != 0,
+    }))
+}
+
 pub async fn delete_event(pool: &SqlitePool, event_id: &str) -> Result<(), DbError> {
     sqlx::query("UPDATE events SET is_deleted = 1 WHERE event_id = ?")
         .bind(event_id)
@@ -1373,24 +1937,209 @@ pub async fn list_messages(
         _ => return Ok(vec![]),
     };
 
+    Ok(rows.iter().map(message_row_from).collect())
+}
+
+fn message_row_from(r: &sqlx::sqlite::SqliteRow) -> MessageRow {
+    let mentions_json: String = r.try_get("mentions").unwrap_or_default();
+    MessageRow {
+        message_id: r.get("message_id"),
+        room_id: r.get("room_id"),
+        dm_thread_id: r.get("dm_thread_id"),
+        author_key: r.get("author_key"),
+        content_type: r.get("content_type"),
+        text_content: r.get("text_content"),
+        blob_id: r.get("blob_id"),
+        embed_url: r.get("embed_url"),
+        mentions: serde_json::from_str(&mentions_json).unwrap_or_default(),
+        reply_to: r.get("reply_to"),
+        timestamp: r.get("timestamp"),
+        edited_at: r.get("edited_at"),
+        is_deleted: r.get::<i64, _>("is_deleted") != 0,
+    }
+}
+
+/// Turn free text into an FTS5 query: every word must match, the last one as
+/// a prefix so results update while typing. Operators in the input are quoted.
+fn fts_match_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .filter(|t| t.chars().any(char::is_alphanumeric))
+        .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return None;
+    }
+    Some(format!("{}*", terms.join(" ")))
+}
+
+/// Full-text search over message text, best match first. `snippet` holds the
+/// matching fragment with hits wrapped in `<mark>…</mark>`.
+pub async fn search_messages(
+    pool: &SqlitePool,
+    query: &str,
+    room_id: Option<&str>,
+    dm_thread_id: Option<&str>,
+    author_key: Option<&str>,
+    before_timestamp: Option<i64>,
+    limit: u32,
+) -> Result<Vec<(MessageRow, String)>, DbError> {
+    let Some(match_query) = fts_match_query(query) else {
+        return Ok(vec![]);
+    };
+    let rows = sqlx::query(
+        r#"SELECT m.*, snippet(messages_fts, 0, '<mark>', '</mark>', '…', 16) AS snippet
+           FROM messages_fts
+           JOIN messages m ON m.rowid = messages_fts.rowid
+           WHERE messages_fts MATCH ?
+             AND m.is_deleted = 0
+             AND m.author_key NOT IN (SELECT public_key FROM ignored_keys)
+             AND (? IS NULL OR m.room_id = ?)
+             AND (? IS NULL OR m.dm_thread_id = ?)
+             AND (? IS NULL OR m.author_key = ?)
+             AND (? IS NULL OR m.timestamp < ?)
+           ORDER BY bm25(messages_fts), m.timestamp DESC
+           LIMIT ?"#,
+    )
+    .bind(&match_query)
+    .bind(room_id).bind(room_id)
+    .bind(dm_thread_id).bind(dm_thread_id)
+    .bind(author_key).bind(author_key)
+    .bind(before_timestamp).bind(before_timestamp)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| (message_row_from(r), r.get("snippet")))
+        .collect())
+}
+
+/// Apply an edit to a message, keeping the replaced version in `message_edits`.
+///
+/// Only the original author may edit, and deleted messages stay deleted.
+/// Returns `false` when the edit was rejected or has already been applied.
+/// An edit older than the current version is only recorded in the history.
+pub async fn apply_message_edit(
+    pool: &SqlitePool,
+    edit_id: &str,
+    message_id: &str,
+    author_key: &str,
+    text_content: Option<&str>,
+    mentions: &[String],
+    edited_at: i64,
+) -> Result<bool, DbError> {
+    let mut tx = pool.begin().await?;
+
+    let Some(current) = sqlx::query(
+        "SELECT author_key, text_content, mentions, timestamp, edited_at, is_deleted FROM messages WHERE message_id = ?",
+    )
+    .bind(message_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(false);
+    };
+    let original_author: String = current.get("author_key");
+    if original_author != author_key || current.get::<i64, _>("is_deleted") != 0 {
+        return Ok(false);
+    }
+
+    let mentions_json = serde_json::to_string(mentions).unwrap_or_default();
+    let current_version_at: i64 = current
+        .get::<Option<i64>, _>("edited_at")
+        .unwrap_or_else(|| current.get("timestamp"));
+    let is_latest = edited_at >= current_version_at;
+
+    // The history row holds whichever version loses: the current one for a
+    // newer edit, or the stale edit's own text.
+    let (hist_text, hist_mentions): (Option<String>, String) = if is_latest {
+        (current.get("text_content"), current.try_get("mentions").unwrap_or_default())
+    } else {
+        (text_content.map(str::to_string), mentions_json.clone())
+    };
+    let inserted = sqlx::query(
+        r#"INSERT OR IGNORE INTO message_edits
+               (edit_id, message_id, author_key, text_content, mentions, edited_at)
+           VALUES (?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(edit_id)
+    .bind(message_id)
+    .bind(author_key)
+    .bind(&hist_text)
+    .bind(&hist_mentions)
+    .bind(edited_at)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if inserted == 0 {
+        return Ok(false);
+    }
+
+    if is_latest {
+        sqlx::query("UPDATE messages SET text_content = ?, mentions = ?, edited_at = ? WHERE message_id = ?")
+            .bind(text_content)
+            .bind(&mentions_json)
+            .bind(edited_at)
+            .bind(message_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Room a message was posted in, or None for DMs and unknown messages.
+pub async fn get_message_room_id(pool: &SqlitePool, message_id: &str) -> Result<Option<String>, DbError> {
+    Ok(sqlx::query_scalar::<_, Option<String>>("SELECT room_id FROM messages WHERE message_id = ?")
+        .bind(message_id)
+        .fetch_optional(pool)
+        .await?
+        .flatten())
+}
+
+/// Mark a message deleted if `author_key` wrote it, or whoever wrote it when
+/// `author_key` is None (a moderator delete, checked by the caller). Returns
+/// whether it matched.
+pub async fn mark_message_deleted(
+    pool: &SqlitePool,
+    message_id: &str,
+    author_key: Option<&str>,
+) -> Result<bool, DbError> {
+    let result = sqlx::query(
+        "UPDATE messages SET is_deleted = 1 WHERE message_id = ?1 AND (?2 IS NULL OR author_key = ?2)",
+    )
+    .bind(message_id)
+    .bind(author_key)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Prior versions of a message, oldest first.
+pub async fn list_message_edits(
+    pool: &SqlitePool,
+    message_id: &str,
+) -> Result<Vec<MessageEditRow>, DbError> {
+    let rows = sqlx::query(
+        r#"SELECT edit_id, message_id, author_key, text_content, mentions, edited_at
+           FROM message_edits WHERE message_id = ? ORDER BY edited_at ASC"#,
+    )
+    .bind(message_id)
+    .fetch_all(pool)
+    .await?;
     Ok(rows
         .into_iter()
         .map(|r| {
             let mentions_json: String = r.try_get("mentions").unwrap_or_default();
-            MessageRow {
+            MessageEditRow {
+                edit_id: r.get("edit_id"),
                 message_id: r.get("message_id"),
-                room_id: r.get("room_id"),
-                dm_thread_id: r.get("dm_thread_id"),
                 author_key: r.get("author_key"),
-                content_type: r.get("content_type"),
                 text_content: r.get("text_content"),
-                blob_id: r.get("blob_id"),
-                embed_url: r.get("embed_url"),
                 mentions: serde_json::from_str(&mentions_json).unwrap_or_default(),
-                reply_to: r.get("reply_to"),
-                timestamp: r.get("timestamp"),
                 edited_at: r.get("edited_at"),
-                is_deleted: r.get::<i64, _>("is_deleted") != 0,
             }
         })
         .collect())
@@ -1794,18 +2543,62 @@ pub async fn is_known_sender(pool: &SqlitePool, initiator_key: &str, local_key:
     Ok(shared_org.is_some())
 }
 
+/// Authors whose logs `local_key` and `peer_key` exchange during peer sync:
+/// both of them plus every member of an org they share. Empty when the two
+/// have no DM thread or shared org.
+pub async fn list_sync_authors(pool: &SqlitePool, local_key: &str, peer_key: &str) -> Result<Vec<String>, DbError> {
+    if !is_known_sender(pool, peer_key, local_key).await? {
+        return Ok(vec![]);
+    }
+    let rows = sqlx::query(
+        "SELECT DISTINCT m.member_key FROM memberships m
+         JOIN memberships a ON a.org_id = m.org_id AND a.member_key = ?
+         JOIN memberships b ON b.org_id = m.org_id AND b.member_key = ?",
+    )
+    .bind(local_key)
+    .bind(peer_key)
+    .fetch_all(pool)
+    .await?;
+
+    let mut authors: Vec<String> = rows.into_iter().map(|r| r.get("member_key")).collect();
+    for key in [local_key, peer_key] {
+        if !authors.iter().any(|a| a == key) {
+            authors.push(key.to_string());
+        }
+    }
+    Ok(authors)
+}
+
+/// Rooms in every org `member_key` belongs to: the rooms whose messages peer
+/// sync will hand to them.
+pub async fn list_member_room_ids(pool: &SqlitePool, member_key: &str) -> Result<Vec<String>, DbError> {
+    let rows = sqlx::query(
+        "SELECT r.room_id FROM rooms r
+         JOIN memberships m ON m.org_id = r.org_id AND m.member_key = ?",
+    )
+    .bind(member_key)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.get("room_id")).collect())
+}
+
 // ─── Blob meta ───────────────────────────────────────────────────────────────
 
 pub async fn insert_blob_meta(pool: &SqlitePool, meta: &BlobMeta) -> Result<(), DbError> {
     sqlx::query(
-        "INSERT INTO blob_meta (blob_hash, mime_type, room_id, sender_key, secret_id, nonce)
-         VALUES (?, ?, ?, ?, ?, ?)
+        "INSERT INTO blob_meta (blob_hash, mime_type, room_id, sender_key, secret_id, nonce,
+                                thumbnail_hash, width, height, blurhash)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
          ON CONFLICT(blob_hash) DO UPDATE SET
-             mime_type  = excluded.mime_type,
-             room_id    = excluded.room_id,
-             sender_key = excluded.sender_key,
-             secret_id  = excluded.secret_id,
-             nonce      = excluded.nonce",
+             mime_type      = excluded.mime_type,
+             room_id        = excluded.room_id,
+             sender_key     = excluded.sender_key,
+             secret_id      = excluded.secret_id,
+             nonce          = excluded.nonce,
+             thumbnail_hash = excluded.thumbnail_hash,
+             width          = excluded.width,
+             height         = excluded.height,
+             blurhash       = excluded.blurhash",
     )
     .bind(&meta.blob_hash)
     .bind(&meta.mime_type)
@@ -1813,6 +2606,10 @@ pub async fn insert_blob_meta(pool: &SqlitePool, meta: &BlobMeta) -> Result<(),
     .bind(&meta.sender_key)
     .bind(&meta.secret_id)
     .bind(&meta.nonce)
+    .bind(&meta.thumbnail_hash)
+    .bind(meta.width)
+    .bind(meta.height)
+    .bind(&meta.blurhash)
     .execute(pool)
     .await?;
     Ok(())
@@ -1823,7 +2620,8 @@ pub async fn get_blob_meta(
     hash: &str,
 ) -> Result<Option<BlobMeta>, DbError> {
     let row = sqlx::query(
-        "SELECT blob_hash, mime_type, room_id, sender_key, secret_id, nonce
+        "SELECT blob_hash, mime_type, room_id, sender_key, secret_id, nonce,
+                thumbnail_hash, width, height, blurhash
          FROM blob_meta WHERE blob_hash = ?",
     )
     .bind(hash)
@@ -1839,90 +2637,1033 @@ pub async fn get_blob_meta(
         sender_key: row.try_get("sender_key")?,
         secret_id: row.try_get("secret_id")?,
         nonce: row.try_get("nonce")?,
+        thumbnail_hash: row.try_get("thumbnail_hash")?,
+        width: row.try_get("width")?,
+        height: row.try_get("height")?,
+        blurhash: row.try_get("blurhash")?,
     }))
 }
 
-// ─── Projector cursor ────────────────────────────────────────────────────────
-
-pub async fn get_cursor(
+/// Record the content key `message_id` claims for `blob_hash`.
+pub async fn insert_blob_key(
     pool: &SqlitePool,
-    log_id: &str,
-    public_key: &str,
-) -> Result<u64, DbError> {
-    let row = sqlx::query(
-        "SELECT last_seq_num FROM projector_cursors WHERE log_id = ? AND public_key = ?",
+    blob_hash: &str,
+    message_id: &str,
+    secret_id: &[u8],
+    nonce: &[u8],
+) -> Result<(), DbError> {
+    sqlx::query(
+        "INSERT OR IGNORE INTO blob_keys (blob_hash, message_id, secret_id, nonce) VALUES (?, ?, ?, ?)",
+    )
+    .bind(blob_hash)
+    .bind(message_id)
+    .bind(secret_id)
+    .bind(nonce)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Distinct `(secret_id, nonce)` pairs messages claim for `blob_hash`.
+pub async fn list_blob_keys(pool: &SqlitePool, blob_hash: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>, DbError> {
+    let rows = sqlx::query("SELECT DISTINCT secret_id, nonce FROM blob_keys WHERE blob_hash = ?")
+        .bind(blob_hash)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|r| (r.get("secret_id"), r.get("nonce"))).collect())
+}
+
+/// Adopt a key that has opened `blob_hash` as the blob's content key.
+pub async fn set_blob_key(pool: &SqlitePool, blob_hash: &str, secret_id: &[u8], nonce: &[u8]) -> Result<(), DbError> {
+    sqlx::query("UPDATE blob_meta SET secret_id = ?, nonce = ? WHERE blob_hash = ?")
+        .bind(secret_id)
+        .bind(nonce)
+        .bind(blob_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Whether `peer_key` may fetch blob `blob_hash` from us: it is attached to a
+/// message in a room of an org the peer belongs to or in a DM thread the peer
+/// is part of, it is the avatar or cover of an org that is public or has the
+/// peer as a member, or it is a profile avatar and the peer is a contact
+/// (`is_known_sender`) or the profile is public.
+pub async fn can_peer_fetch_blob(
+    pool: &SqlitePool,
+    blob_hash: &str,
+    peer_key: &str,
+    local_key: &str,
+) -> Result<bool, DbError> {
+    let granted = sqlx::query(
+        "SELECT 1 FROM messages m
+             JOIN rooms r ON r.room_id = m.room_id
+             JOIN memberships ms ON ms.org_id = r.org_id
+          WHERE m.blob_id = ? AND ms.member_key = ?
+         UNION ALL
+         SELECT 1 FROM blob_meta b
+             JOIN rooms r ON r.room_id = b.room_id
+             JOIN memberships ms ON ms.org_id = r.org_id
+          WHERE b.blob_hash = ? AND ms.member_key = ?
+         UNION ALL
+         SELECT 1 FROM messages m
+             JOIN dm_threads d ON d.thread_id = m.dm_thread_id
+          WHERE m.blob_id = ? AND (d.initiator_key = ? OR d.recipient_key = ?)
+         UNION ALL
+         SELECT 1 FROM organizations o
+          WHERE (o.avatar_blob_id = ? OR o.cover_blob_id = ?)
+            AND (o.is_public = 1 OR EXISTS (
+                SELECT 1 FROM memberships ms WHERE ms.org_id = o.org_id AND ms.member_key = ?))
+         LIMIT 1",
+    )
+    .bind(blob_hash).bind(peer_key)
+    .bind(blob_hash).bind(peer_key)
+    .bind(blob_hash).bind(peer_key).bind(peer_key)
+    .bind(blob_hash).bind(blob_hash).bind(peer_key)
+    .fetch_optional(pool)
+    .await?;
+    if granted.is_some() {
+        return Ok(true);
+    }
+
+    let avatar = sqlx::query("SELECT is_public FROM profiles WHERE avatar_blob_id = ?")
+        .bind(blob_hash)
+        .fetch_all(pool)
+        .await?;
+    if avatar.is_empty() {
+        return Ok(false);
+    }
+    if avatar.iter().any(|r| r.get::<i64, _>("is_public") != 0) {
+        return Ok(true);
+    }
+    is_known_sender(pool, peer_key, local_key).await
+}
+
+// ─── Blob storage ────────────────────────────────────────────────────────────
+
+/// Bytes held in the local blob store, overall and per org and room. A blob
+/// posted in several rooms counts towards each of them.
+#[derive(Debug, Clone, Default)]
+pub struct BlobStorageUsage {
+    pub total_bytes: i64,
+    pub own_bytes: i64,
+    pub orgs: Vec<(String, i64)>,          // (org_id, bytes)
+    pub rooms: Vec<(String, String, i64)>, // (org_id, room_id, bytes)
+}
+
+/// Record that `blob_hash` is in the local store. Storing it again refreshes
+/// its LRU position; once own, a blob stays own.
+pub async fn record_blob_stored(
+    pool: &SqlitePool,
+    blob_hash: &str,
+    size: i64,
+    is_own: bool,
+    now: i64,
+) -> Result<(), DbError> {
+    sqlx::query(
+        "INSERT INTO blob_store_entries (blob_hash, size, is_own, stored_at, last_used_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(blob_hash) DO UPDATE SET
+             size         = excluded.size,
+             is_own       = MAX(is_own, excluded.is_own),
+             last_used_at = excluded.last_used_at",
+    )
+    .bind(blob_hash)
+    .bind(size)
+    .bind(is_own as i64)
+    .bind(now)
+    .bind(now)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Move `blob_hash` to the back of the eviction queue.
+pub async fn touch_blob(pool: &SqlitePool, blob_hash: &str, now: i64) -> Result<(), DbError> {
+    sqlx::query("UPDATE blob_store_entries SET last_used_at = ? WHERE blob_hash = ?")
+        .bind(now)
+        .bind(blob_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_tracked_blobs(pool: &SqlitePool) -> Result<Vec<String>, DbError> {
+    Ok(sqlx::query_scalar("SELECT blob_hash FROM blob_store_entries")
+        .fetch_all(pool)
+        .await?)
+}
+
+/// Blobs stored before `stored_before` that nothing in the read model
+/// references any more.
+pub async fn list_unreferenced_blobs(
+    pool: &SqlitePool,
+    stored_before: i64,
+    own_stored_before: i64,
+) -> Result<Vec<String>, DbError> {
+    Ok(sqlx::query_scalar(
+        "SELECT blob_hash FROM blob_store_entries e
+         WHERE stored_at < CASE WHEN is_own = 1 THEN ?2 ELSE ?1 END
+           AND NOT EXISTS (SELECT 1 FROM blob_refs r WHERE r.blob_hash = e.blob_hash)",
+    )
+    .bind(stored_before)
+    .bind(own_stored_before)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Stop treating `blob_hash` as our own upload, e.g. a discarded draft
+/// attachment, so it is collected like any other blob.
+pub async fn discard_own_blob(pool: &SqlitePool, blob_hash: &str) -> Result<(), DbError> {
+    sqlx::query("UPDATE blob_store_entries SET is_own = 0 WHERE blob_hash = ?")
+        .bind(blob_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Blobs that may be evicted to meet a quota, least recently used first:
+/// not stored by us, not posted by `local_key`, and, given `org_id`, used in
+/// that org. Returns (blob_hash, size).
+pub async fn list_evictable_blobs(
+    pool: &SqlitePool,
+    org_id: Option<&str>,
+    local_key: &str,
+) -> Result<Vec<(String, i64)>, DbError> {
+    let rows = sqlx::query(
+        "SELECT blob_hash, size FROM blob_store_entries e
+         WHERE is_own = 0
+           AND NOT EXISTS (SELECT 1 FROM blob_refs r WHERE r.blob_hash = e.blob_hash AND r.owner_key = ?1)
+           AND (?2 IS NULL OR EXISTS (SELECT 1 FROM blob_refs r WHERE r.blob_hash = e.blob_hash AND r.org_id = ?2))
+         ORDER BY last_used_at ASC",
+    )
+    .bind(local_key)
+    .bind(org_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.get("blob_hash"), r.get("size"))).collect())
+}
+
+/// Forget stored blobs; with `drop_meta`, their keys and previews too.
+pub async fn delete_blob_entries(pool: &SqlitePool, blob_hashes: &[String], drop_meta: bool) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    for hash in blob_hashes {
+        sqlx::query("DELETE FROM blob_store_entries WHERE blob_hash = ?")
+            .bind(hash)
+            .execute(&mut *tx)
+            .await?;
+        if drop_meta {
+            sqlx::query("DELETE FROM blob_meta WHERE blob_hash = ?")
+                .bind(hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Set (or with `None`, clear) the cache limit of `org_id`; `""` is the
+/// global limit.
+pub async fn set_blob_quota(pool: &SqlitePool, org_id: &str, max_bytes: Option<i64>) -> Result<(), DbError> {
+    match max_bytes {
+        Some(max) => {
+            sqlx::query(
+                "INSERT INTO blob_quotas (org_id, max_bytes) VALUES (?, ?)
+                 ON CONFLICT(org_id) DO UPDATE SET max_bytes = excluded.max_bytes",
+            )
+            .bind(org_id)
+            .bind(max)
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM blob_quotas WHERE org_id = ?")
+                .bind(org_id)
+                .execute(pool)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// All cache limits as (org_id, max_bytes); `""` is the global limit.
+pub async fn list_blob_quotas(pool: &SqlitePool) -> Result<Vec<(String, i64)>, DbError> {
+    let rows = sqlx::query("SELECT org_id, max_bytes FROM blob_quotas")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|r| (r.get("org_id"), r.get("max_bytes"))).collect())
+}
+
+/// Bytes held for `org_id`, or for everything.
+pub async fn blob_bytes_used(pool: &SqlitePool, org_id: Option<&str>) -> Result<i64, DbError> {
+    Ok(sqlx::query_scalar(
+        "SELECT COALESCE(SUM(size), 0) FROM blob_store_entries e
+         WHERE ?1 IS NULL OR EXISTS (SELECT 1 FROM blob_refs r WHERE r.blob_hash = e.blob_hash AND r.org_id = ?1)",
+    )
+    .bind(org_id)
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn blob_storage_usage(pool: &SqlitePool) -> Result<BlobStorageUsage, DbError> {
+    let (total_bytes, own_bytes): (i64, i64) = sqlx::query_as(
+        "SELECT COALESCE(SUM(size), 0), COALESCE(SUM(CASE WHEN is_own = 1 THEN size ELSE 0 END), 0)
+         FROM blob_store_entries",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let orgs = sqlx::query(
+        "SELECT org_id, SUM(size) AS bytes FROM (
+             SELECT DISTINCT e.blob_hash, e.size, r.org_id
+             FROM blob_store_entries e JOIN blob_refs r ON r.blob_hash = e.blob_hash
+             WHERE r.org_id IS NOT NULL)
+         GROUP BY org_id ORDER BY bytes DESC",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| (r.get("org_id"), r.get("bytes")))
+    .collect();
+
+    let rooms = sqlx::query(
+        "SELECT org_id, room_id, SUM(size) AS bytes FROM (
+             SELECT DISTINCT e.blob_hash, e.size, r.org_id, r.room_id
+             FROM blob_store_entries e JOIN blob_refs r ON r.blob_hash = e.blob_hash
+             WHERE r.org_id IS NOT NULL AND r.room_id IS NOT NULL)
+         GROUP BY org_id, room_id ORDER BY bytes DESC",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| (r.get("org_id"), r.get("room_id"), r.get("bytes")))
+    .collect();
+
+    Ok(BlobStorageUsage { total_bytes, own_bytes, orgs, rooms })
+}
+
+// ─── Projector cursor ────────────────────────────────────────────────────────
+
+pub async fn get_cursor(
+    pool: &SqlitePool,
+    log_id: &str,
+    public_key: &str,
+) -> Result<u64, DbError> {
+    let row = sqlx::query(
+        "SELECT last_seq_num FROM projector_cursors WHERE log_id = ? AND public_key = ?",
+    )
+    .bind(log_id)
+    .bind(public_key)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| r.get::<i64, _>("last_seq_num") as u64).unwrap_or(0))
+}
+
+pub async fn set_cursor(
+    pool: &SqlitePool,
+    log_id: &str,
+    public_key: &str,
+    seq_num: u64,
+) -> Result<(), DbError> {
+    sqlx::query(
+        r#"INSERT INTO projector_cursors (log_id, public_key, last_seq_num)
+           VALUES (?, ?, ?)
+           ON CONFLICT(log_id, public_key) DO UPDATE SET last_seq_num = excluded.last_seq_num"#,
+    )
+    .bind(log_id)
+    .bind(public_key)
+    .bind(seq_num as i64)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// ─── Read-model rebuild ──────────────────────────────────────────────────────
+
+/// Tables the projector materialises from ops. A rebuild empties and replays
+/// them; everything else in read.db (encryption state, pending decrypts,
+/// blob metadata, local settings) is left alone.
+const PROJECTION_TABLES: &[&str] = &[
+    "profiles",
+    "organizations",
+    "memberships",
+    "rooms",
+    "events",
+    "event_rsvps",
+    "org_admin_threads",
+    "org_user_cooldowns",
+    "org_ice",
+    "org_bans",
+    "org_mutes",
+    "audit_log",
+    "messages",
+    "message_edits",
+    "reactions",
+    "dm_threads",
+    "rejected_ops",
+    "projected_ops",
+    "pending_deps",
+];
+
+/// Start a rebuild: stash what replay can't reproduce, empty the projection
+/// tables and clear the projector cursors. Tables are emptied rather than
+/// dropped so the schema stays at the version `schema_version` records.
+///
+/// DM messages (with their edits and reactions) are carried over: the
+/// forward-secure ratchet has already discarded their keys, and our own DM
+/// ops were never projected. Org keys and email flags are set locally and
+/// restored by [`finish_read_model_rebuild`]. If a previous rebuild was
+/// interrupted its stash is kept rather than overwritten.
+///
+/// Runs in one transaction, so a crash part way leaves the read model as
+/// it was rather than half emptied.
+pub async fn begin_read_model_rebuild(pool: &SqlitePool) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS rebuild_stash_messages AS
+            SELECT message_id, room_id, dm_thread_id, author_key, content_type, text_content,
+                   blob_id, embed_url, mentions, reply_to, timestamp, edited_at, is_deleted
+            FROM messages WHERE dm_thread_id IS NOT NULL;
+        CREATE TABLE IF NOT EXISTS rebuild_stash_message_edits AS
+            SELECT edit_id, message_id, author_key, text_content, mentions, edited_at
+            FROM message_edits
+            WHERE message_id IN (SELECT message_id FROM messages WHERE dm_thread_id IS NOT NULL);
+        CREATE TABLE IF NOT EXISTS rebuild_stash_reactions AS
+            SELECT message_id, emoji, reactor_key
+            FROM reactions
+            WHERE message_id IN (SELECT message_id FROM messages WHERE dm_thread_id IS NOT NULL);
+        CREATE TABLE IF NOT EXISTS rebuild_stash_orgs AS
+            SELECT org_id, org_pubkey, org_privkey_enc, email_enabled FROM organizations;
+        CREATE TABLE IF NOT EXISTS rebuild_stash_profiles AS
+            SELECT public_key, email_enabled FROM profiles;
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for table in PROJECTION_TABLES {
+        sqlx::query(&format!("DELETE FROM {table}"))
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query("DELETE FROM projector_cursors").execute(&mut *tx).await?;
+
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO messages
+            (message_id, room_id, dm_thread_id, author_key, content_type, text_content,
+             blob_id, embed_url, mentions, reply_to, timestamp, edited_at, is_deleted)
+            SELECT message_id, room_id, dm_thread_id, author_key, content_type, text_content,
+                   blob_id, embed_url, mentions, reply_to, timestamp, edited_at, is_deleted
+            FROM rebuild_stash_messages;
+        INSERT OR IGNORE INTO message_edits (edit_id, message_id, author_key, text_content, mentions, edited_at)
+            SELECT edit_id, message_id, author_key, text_content, mentions, edited_at
+            FROM rebuild_stash_message_edits;
+        INSERT OR IGNORE INTO reactions (message_id, emoji, reactor_key)
+            SELECT message_id, emoji, reactor_key FROM rebuild_stash_reactions;
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Finish a rebuild once every log has been replayed: restore the locally
+/// set org and profile columns and drop the stash.
+pub async fn finish_read_model_rebuild(pool: &SqlitePool) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        UPDATE organizations SET
+            org_pubkey      = COALESCE(organizations.org_pubkey, s.org_pubkey),
+            org_privkey_enc = COALESCE(organizations.org_privkey_enc, s.org_privkey_enc),
+            email_enabled   = s.email_enabled
+        FROM rebuild_stash_orgs AS s WHERE organizations.org_id = s.org_id;
+        UPDATE profiles SET email_enabled = s.email_enabled
+        FROM rebuild_stash_profiles AS s WHERE profiles.public_key = s.public_key;
+        UPDATE dm_threads SET last_message_at = (
+            SELECT MAX(timestamp) FROM messages WHERE messages.dm_thread_id = dm_threads.thread_id
+        )
+        WHERE thread_id IN (SELECT dm_thread_id FROM rebuild_stash_messages);
+
+        DROP TABLE rebuild_stash_messages;
+        DROP TABLE rebuild_stash_message_edits;
+        DROP TABLE rebuild_stash_reactions;
+        DROP TABLE rebuild_stash_orgs;
+        DROP TABLE rebuild_stash_profiles;
+
+        UPDATE schema_version SET rebuild_pending = 0;
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// ─── Relay directory ─────────────────────────────────────────────────────────
+
+/// Replace the cached relay directory with `records` resolved from `directory_key`.
+pub async fn save_relay_directory(
+    pool: &SqlitePool,
+    directory_key: &str,
+    records: &[String],
+    now: i64,
+) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM relay_directory").execute(&mut *tx).await?;
+    for (position, record) in records.iter().enumerate() {
+        sqlx::query("INSERT INTO relay_directory (position, directory_key, record, fetched_at) VALUES (?, ?, ?, ?)")
+            .bind(position as i64)
+            .bind(directory_key)
+            .bind(record)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The cached relay directory's records, in the order they were listed.
+pub async fn load_relay_directory(pool: &SqlitePool) -> Result<Vec<String>, DbError> {
+    Ok(sqlx::query_scalar("SELECT record FROM relay_directory ORDER BY position")
+        .fetch_all(pool)
+        .await?)
+}
+
+// ─── Pending decrypt ─────────────────────────────────────────────────────────
+
+/// Park an encrypted room or DM op until its group state can decrypt it.
+/// Re-queueing the same op is a no-op.
+pub async fn insert_pending_decrypt(
+    pool: &SqlitePool,
+    row: &PendingDecryptRow,
+    error: &str,
+    now: i64,
+    next_attempt_at: i64,
+) -> Result<(), DbError> {
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO pending_decrypt
+               (op_hash, log_id, author_key, group_id, group_type, encrypted, timestamp, attempts, next_attempt_at, last_error, queued_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(&row.op_hash)
+    .bind(&row.log_id)
+    .bind(&row.author_key)
+    .bind(&row.group_id)
+    .bind(&row.group_type)
+    .bind(&row.encrypted)
+    .bind(row.timestamp)
+    .bind(row.attempts)
+    .bind(next_attempt_at)
+    .bind(error)
+    .bind(now)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Pending ops whose backoff has elapsed, oldest first.
+pub async fn list_due_pending_decrypts(
+    pool: &SqlitePool,
+    now: i64,
+    limit: u32,
+) -> Result<Vec<PendingDecryptRow>, DbError> {
+    let rows = sqlx::query(
+        r#"SELECT op_hash, log_id, author_key, group_id, group_type, encrypted, timestamp, attempts
+           FROM pending_decrypt WHERE next_attempt_at <= ?
+           ORDER BY timestamp ASC LIMIT ?"#,
+    )
+    .bind(now)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(pending_decrypt_from_row).collect())
+}
+
+/// Pending ops that have failed `max_attempts` times or were queued before
+/// `queued_before`; they are not going to become decryptable.
+pub async fn list_expired_pending_decrypts(
+    pool: &SqlitePool,
+    max_attempts: i64,
+    queued_before: i64,
+    limit: u32,
+) -> Result<Vec<PendingDecryptRow>, DbError> {
+    let rows = sqlx::query(
+        r#"SELECT op_hash, log_id, author_key, group_id, group_type, encrypted, timestamp, attempts
+           FROM pending_decrypt WHERE attempts >= ? OR queued_at < ?
+           LIMIT ?"#,
+    )
+    .bind(max_attempts)
+    .bind(queued_before)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(pending_decrypt_from_row).collect())
+}
+
+fn pending_decrypt_from_row(r: &sqlx::sqlite::SqliteRow) -> PendingDecryptRow {
+    PendingDecryptRow {
+        op_hash: r.get("op_hash"),
+        log_id: r.get("log_id"),
+        author_key: r.get("author_key"),
+        group_id: r.get("group_id"),
+        group_type: r.get("group_type"),
+        encrypted: r.get("encrypted"),
+        timestamp: r.get("timestamp"),
+        attempts: r.get("attempts"),
+    }
+}
+
+pub async fn reschedule_pending_decrypt(
+    pool: &SqlitePool,
+    op_hash: &str,
+    error: &str,
+    next_attempt_at: i64,
+) -> Result<(), DbError> {
+    sqlx::query(
+        "UPDATE pending_decrypt SET attempts = attempts + 1, last_error = ?, next_attempt_at = ? WHERE op_hash = ?",
+    )
+    .bind(error)
+    .bind(next_attempt_at)
+    .bind(op_hash)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Make every op parked for `group_id` due immediately, e.g. after a key
+/// exchange for that group has been processed.
+pub async fn wake_pending_decrypts(pool: &SqlitePool, group_id: &str) -> Result<(), DbError> {
+    sqlx::query("UPDATE pending_decrypt SET next_attempt_at = 0 WHERE group_id = ?")
+        .bind(group_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_pending_decrypt(pool: &SqlitePool, op_hash: &str) -> Result<(), DbError> {
+    sqlx::query("DELETE FROM pending_decrypt WHERE op_hash = ?")
+        .bind(op_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_dm_thread(
+    pool: &SqlitePool,
+    thread_id: &str,
+) -> Result<Option<DmThreadRow>, DbError> {
+    let row = sqlx::query(
+        r#"SELECT thread_id, initiator_key, recipient_key, created_at, last_message_at, is_request
+           FROM dm_threads WHERE thread_id = ?"#,
+    )
+    .bind(thread_id)
+    .fetch_optional(pool)
+    .await?
+    .map(|r| DmThreadRow {
+        thread_id: r.get("thread_id"),
+        initiator_key: r.get("initiator_key"),
+        recipient_key: r.get("recipient_key"),
+        created_at: r.get("created_at"),
+        last_message_at: r.get("last_message_at"),
+        is_request: r.get::<i64, _>("is_request") != 0,
+    });
+    Ok(row)
+}
+
+pub async fn get_org_admin_thread(
+    pool: &SqlitePool,
+    thread_id: &str,
+) -> Result<Option<OrgAdminThreadRow>, DbError> {
+    let row = sqlx::query(
+        r#"SELECT thread_id, org_id, initiator_key, participant_key, admin_key, created_at, last_message_at, is_request
+           FROM org_admin_threads WHERE thread_id = ?"#,
+    )
+    .bind(thread_id)
+    .fetch_optional(pool)
+    .await?
+    .map(|r| OrgAdminThreadRow {
+        thread_id: r.get("thread_id"),
+        org_id: r.get("org_id"),
+        initiator_key: r.get("initiator_key"),
+        participant_key: r.get("participant_key"),
+        admin_key: r.get("admin_key"),
+        created_at: r.get("created_at"),
+        last_message_at: r.get("last_message_at"),
+        is_request: r.get::<i64, _>("is_request") != 0,
+    });
+    Ok(row)
+}
+
+// ─── Causal dependencies ─────────────────────────────────────────────────────
+
+pub async fn mark_op_projected(pool: &SqlitePool, op_hash: &str) -> Result<(), DbError> {
+    sqlx::query("INSERT OR IGNORE INTO projected_ops (op_hash) VALUES (?)")
+        .bind(op_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// The hashes in `previous` that haven't been projected yet.
+pub async fn unprojected_ops(
+    pool: &SqlitePool,
+    previous: &[String],
+) -> Result<Vec<String>, DbError> {
+    let mut missing = Vec::new();
+    for op_hash in previous {
+        let projected = sqlx::query("SELECT 1 FROM projected_ops WHERE op_hash = ?")
+            .bind(op_hash)
+            .fetch_optional(pool)
+            .await?
+            .is_some();
+        if !projected {
+            missing.push(op_hash.clone());
+        }
+    }
+    Ok(missing)
+}
+
+/// Hold an op back until its dependencies are projected. Re-queueing the
+/// same op is a no-op.
+pub async fn insert_pending_dep(
+    pool: &SqlitePool,
+    row: &PendingDepRow,
+    received_at: i64,
+) -> Result<(), DbError> {
+    let previous_json = serde_json::to_string(&row.previous).unwrap_or_default();
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO pending_deps
+               (op_hash, log_id, author_key, previous, body, timestamp, received_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(&row.op_hash)
+    .bind(&row.log_id)
+    .bind(&row.author_key)
+    .bind(&previous_json)
+    .bind(&row.body)
+    .bind(row.timestamp)
+    .bind(received_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Hold a released op back again, now waiting on `row.previous`.
+pub async fn requeue_pending_dep(
+    pool: &SqlitePool,
+    row: &PendingDepRow,
+    received_at: i64,
+) -> Result<(), DbError> {
+    sqlx::query("DELETE FROM pending_deps WHERE op_hash = ?")
+        .bind(&row.op_hash)
+        .execute(pool)
+        .await?;
+    insert_pending_dep(pool, row, received_at).await
+}
+
+/// Held-back ops whose dependencies have all been projected, oldest first.
+pub async fn list_ready_pending_deps(
+    pool: &SqlitePool,
+    limit: u32,
+) -> Result<Vec<PendingDepRow>, DbError> {
+    let rows = sqlx::query(
+        r#"SELECT op_hash, log_id, author_key, previous, body, timestamp
+           FROM pending_deps p
+           WHERE NOT EXISTS (
+               SELECT 1 FROM json_each(p.previous) AS dep
+               WHERE dep.value NOT IN (SELECT op_hash FROM projected_ops)
+           )
+           ORDER BY timestamp ASC LIMIT ?"#,
+    )
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(pending_dep_from_row).collect())
+}
+
+/// Held-back ops received before `received_before`, oldest first.
+pub async fn list_expired_pending_deps(
+    pool: &SqlitePool,
+    received_before: i64,
+    limit: u32,
+) -> Result<Vec<PendingDepRow>, DbError> {
+    let rows = sqlx::query(
+        r#"SELECT op_hash, log_id, author_key, previous, body, timestamp
+           FROM pending_deps WHERE received_at < ?
+           ORDER BY received_at ASC LIMIT ?"#,
+    )
+    .bind(received_before)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(pending_dep_from_row).collect())
+}
+
+/// How many ops `author_key` has held back, other than `op_hash`.
+pub async fn count_pending_deps_by_author(
+    pool: &SqlitePool,
+    author_key: &str,
+    op_hash: &str,
+) -> Result<i64, DbError> {
+    Ok(sqlx::query_scalar("SELECT COUNT(*) FROM pending_deps WHERE author_key = ? AND op_hash != ?")
+        .bind(author_key)
+        .bind(op_hash)
+        .fetch_one(pool)
+        .await?)
+}
+
+fn pending_dep_from_row(r: &sqlx::sqlite::SqliteRow) -> PendingDepRow {
+    let previous_json: String = r.get("previous");
+    PendingDepRow {
+        op_hash: r.get("op_hash"),
+        log_id: r.get("log_id"),
+        author_key: r.get("author_key"),
+        previous: serde_json::from_str(&previous_json).unwrap_or_default(),
+        body: r.get("body"),
+        timestamp: r.get("timestamp"),
+    }
+}
+
+pub async fn delete_pending_dep(pool: &SqlitePool, op_hash: &str) -> Result<(), DbError> {
+    sqlx::query("DELETE FROM pending_deps WHERE op_hash = ?")
+        .bind(op_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── Fork evidence ───────────────────────────────────────────────────────────
+
+pub async fn insert_fork_evidence(pool: &SqlitePool, row: &ForkEvidenceRow) -> Result<(), DbError> {
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO fork_evidence
+               (author_key, log_id, seq_num, existing_hash, conflicting_hash, signed_header, detected_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(&row.author_key)
+    .bind(&row.log_id)
+    .bind(row.seq_num)
+    .bind(&row.existing_hash)
+    .bind(&row.conflicting_hash)
+    .bind(&row.signed_header)
+    .bind(row.detected_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn list_fork_evidence(
+    pool: &SqlitePool,
+    author_key: &str,
+) -> Result<Vec<ForkEvidenceRow>, DbError> {
+    let rows = sqlx::query(
+        r#"SELECT author_key, log_id, seq_num, existing_hash, conflicting_hash, signed_header, detected_at
+           FROM fork_evidence WHERE author_key = ? ORDER BY detected_at ASC"#,
+    )
+    .bind(author_key)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| ForkEvidenceRow {
+            author_key: r.get("author_key"),
+            log_id: r.get("log_id"),
+            seq_num: r.get("seq_num"),
+            existing_hash: r.get("existing_hash"),
+            conflicting_hash: r.get("conflicting_hash"),
+            signed_header: r.get("signed_header"),
+            detected_at: r.get("detected_at"),
+        })
+        .collect())
+}
+
+// ─── Outbox ──────────────────────────────────────────────────────────────────
+
+/// Queue an op for delivery. Queuing the same op and transport twice keeps
+/// the first row.
+pub async fn insert_outbox(pool: &SqlitePool, row: &OutboxRow) -> Result<(), DbError> {
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO outbox
+               (op_hash, transport, log_id, room_id, dm_thread_id, recipient_key, topic_hex,
+                envelope, state, attempts, next_attempt_at, last_error, created_at, delivered_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(&row.op_hash)
+    .bind(&row.transport)
+    .bind(&row.log_id)
+    .bind(&row.room_id)
+    .bind(&row.dm_thread_id)
+    .bind(&row.recipient_key)
+    .bind(&row.topic_hex)
+    .bind(&row.envelope)
+    .bind(&row.state)
+    .bind(row.attempts)
+    .bind(row.next_attempt_at)
+    .bind(&row.last_error)
+    .bind(row.created_at)
+    .bind(row.delivered_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+fn outbox_from_row(r: sqlx::sqlite::SqliteRow) -> OutboxRow {
+    OutboxRow {
+        op_hash: r.get("op_hash"),
+        transport: r.get("transport"),
+        log_id: r.get("log_id"),
+        room_id: r.get("room_id"),
+        dm_thread_id: r.get("dm_thread_id"),
+        recipient_key: r.get("recipient_key"),
+        topic_hex: r.get("topic_hex"),
+        envelope: r.get("envelope"),
+        state: r.get("state"),
+        attempts: r.get("attempts"),
+        next_attempt_at: r.get("next_attempt_at"),
+        last_error: r.get("last_error"),
+        created_at: r.get("created_at"),
+        delivered_at: r.get("delivered_at"),
+    }
+}
+
+/// Pending deliveries whose next attempt is due at `now`, oldest first.
+pub async fn list_due_outbox(pool: &SqlitePool, now: i64, limit: i64) -> Result<Vec<OutboxRow>, DbError> {
+    let rows = sqlx::query(
+        r#"SELECT * FROM outbox
+           WHERE state = 'pending' AND next_attempt_at <= ?
+           ORDER BY created_at ASC LIMIT ?"#,
     )
-    .bind(log_id)
-    .bind(public_key)
-    .fetch_optional(pool)
+    .bind(now)
+    .bind(limit)
+    .fetch_all(pool)
     .await?;
+    Ok(rows.into_iter().map(outbox_from_row).collect())
+}
 
-    Ok(row.map(|r| r.get::<i64, _>("last_seq_num") as u64).unwrap_or(0))
+/// Every delivery not yet made, pending or given up on, oldest first.
+pub async fn list_undelivered_outbox(pool: &SqlitePool) -> Result<Vec<OutboxRow>, DbError> {
+    let rows = sqlx::query("SELECT * FROM outbox WHERE state != 'delivered' ORDER BY created_at ASC")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(outbox_from_row).collect())
 }
 
-pub async fn set_cursor(
+pub async fn mark_outbox_delivered(
     pool: &SqlitePool,
-    log_id: &str,
-    public_key: &str,
-    seq_num: u64,
+    op_hash: &str,
+    transport: &str,
+    delivered_at: i64,
 ) -> Result<(), DbError> {
     sqlx::query(
-        r#"INSERT INTO projector_cursors (log_id, public_key, last_seq_num)
-           VALUES (?, ?, ?)
-           ON CONFLICT(log_id, public_key) DO UPDATE SET last_seq_num = excluded.last_seq_num"#,
+        r#"UPDATE outbox SET state = 'delivered', attempts = attempts + 1,
+               last_error = NULL, delivered_at = ?
+           WHERE op_hash = ? AND transport = ?"#,
     )
-    .bind(log_id)
-    .bind(public_key)
-    .bind(seq_num as i64)
+    .bind(delivered_at)
+    .bind(op_hash)
+    .bind(transport)
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn get_dm_thread(
+/// Record a failed attempt. `next_attempt_at` of `None` gives up on the
+/// delivery and marks it failed.
+pub async fn mark_outbox_attempt_failed(
     pool: &SqlitePool,
-    thread_id: &str,
-) -> Result<Option<DmThreadRow>, DbError> {
-    let row = sqlx::query(
-        r#"SELECT thread_id, initiator_key, recipient_key, created_at, last_message_at, is_request
-           FROM dm_threads WHERE thread_id = ?"#,
+    op_hash: &str,
+    transport: &str,
+    error: &str,
+    next_attempt_at: Option<i64>,
+) -> Result<(), DbError> {
+    sqlx::query(
+        r#"UPDATE outbox SET attempts = attempts + 1, last_error = ?,
+               state = CASE WHEN ? IS NULL THEN 'failed' ELSE state END,
+               next_attempt_at = COALESCE(?, next_attempt_at)
+           WHERE op_hash = ? AND transport = ?"#,
     )
-    .bind(thread_id)
-    .fetch_optional(pool)
-    .await?
-    .map(|r| DmThreadRow {
-        thread_id: r.get("thread_id"),
-        initiator_key: r.get("initiator_key"),
-        recipient_key: r.get("recipient_key"),
-        created_at: r.get("created_at"),
-        last_message_at: r.get("last_message_at"),
-        is_request: r.get::<i64, _>("is_request") != 0,
-    });
-    Ok(row)
+    .bind(error)
+    .bind(next_attempt_at)
+    .bind(next_attempt_at)
+    .bind(op_hash)
+    .bind(transport)
+    .execute(pool)
+    .await?;
+    Ok(())
 }
 
-pub async fn get_org_admin_thread(
+// ─── Rejected ops ────────────────────────────────────────────────────────────
+
+/// Record an op the projector refused to apply. Replays of the same op keep
+/// the first rejection.
+pub async fn insert_rejected_op(
     pool: &SqlitePool,
-    thread_id: &str,
-) -> Result<Option<OrgAdminThreadRow>, DbError> {
-    let row = sqlx::query(
-        r#"SELECT thread_id, org_id, initiator_key, participant_key, admin_key, created_at, last_message_at, is_request
-           FROM org_admin_threads WHERE thread_id = ?"#,
+    row: &RejectedOpRow,
+) -> Result<(), DbError> {
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO rejected_ops (op_hash, log_id, author_key, op_type, org_id, reason, rejected_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?)"#,
     )
-    .bind(thread_id)
-    .fetch_optional(pool)
-    .await?
-    .map(|r| OrgAdminThreadRow {
-        thread_id: r.get("thread_id"),
-        org_id: r.get("org_id"),
-        initiator_key: r.get("initiator_key"),
-        participant_key: r.get("participant_key"),
-        admin_key: r.get("admin_key"),
-        created_at: r.get("created_at"),
-        last_message_at: r.get("last_message_at"),
-        is_request: r.get::<i64, _>("is_request") != 0,
-    });
-    Ok(row)
+    .bind(&row.op_hash)
+    .bind(&row.log_id)
+    .bind(&row.author_key)
+    .bind(&row.op_type)
+    .bind(&row.org_id)
+    .bind(&row.reason)
+    .bind(row.rejected_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn list_rejected_ops(
+    pool: &SqlitePool,
+    org_id: &str,
+) -> Result<Vec<RejectedOpRow>, DbError> {
+    let rows = sqlx::query(
+        r#"SELECT op_hash, log_id, author_key, op_type, org_id, reason, rejected_at
+           FROM rejected_ops WHERE org_id = ? ORDER BY rejected_at ASC"#,
+    )
+    .bind(org_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| RejectedOpRow {
+            op_hash: r.get("op_hash"),
+            log_id: r.get("log_id"),
+            author_key: r.get("author_key"),
+            op_type: r.get("op_type"),
+            org_id: r.get("org_id"),
+            reason: r.get("reason"),
+            rejected_at: r.get("rejected_at"),
+        })
+        .collect())
+}
+
+// ─── Reply blocks ────────────────────────────────────────────────────────────
+
+/// Record a peeled reply-block layer until `expires_at` (unix seconds),
+/// forgetting expired ones. Returns false if it was already recorded.
+pub async fn claim_reply_block(pool: &SqlitePool, replay_id: &[u8], now: i64, expires_at: i64) -> Result<bool, DbError> {
+    sqlx::query("DELETE FROM used_reply_blocks WHERE expires_at < ?")
+        .bind(now)
+        .execute(pool)
+        .await?;
+    let inserted = sqlx::query("INSERT OR IGNORE INTO used_reply_blocks (replay_id, expires_at) VALUES (?, ?)")
+        .bind(replay_id)
+        .bind(expires_at)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    Ok(inserted == 1)
 }
 
 // ─── Topic seq ───────────────────────────────────────────────────────────────
@@ -1954,15 +3695,143 @@ mod enc_db_tests {
     use super::*;
     use sqlx::sqlite::SqlitePoolOptions;
 
-    async fn test_pool() -> SqlitePool {
-        let pool = SqlitePoolOptions::new()
+    async fn empty_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
             .connect("sqlite::memory:")
             .await
-            .unwrap();
+            .unwrap()
+    }
+
+    async fn test_pool() -> SqlitePool {
+        let pool = empty_pool().await;
         run_migrations(&pool).await.unwrap();
         pool
     }
 
+    async fn schema_version(pool: &SqlitePool) -> i64 {
+        sqlx::query_scalar("SELECT MAX(version) FROM schema_version")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    fn message(id: &str, room_id: Option<&str>, dm_thread_id: Option<&str>) -> MessageRow {
+        MessageRow {
+            message_id: id.to_string(),
+            room_id: room_id.map(str::to_string),
+            dm_thread_id: dm_thread_id.map(str::to_string),
+            author_key: "alice".to_string(),
+            content_type: "text".to_string(),
+            text_content: Some(format!("hello from {id}")),
+            blob_id: None,
+            embed_url: None,
+            mentions: vec![],
+            reply_to: None,
+            timestamp: 100,
+            edited_at: None,
+            is_deleted: false,
+        }
+    }
+
+    /// `msg1` in `room1`, posted by `author`.
+    fn text_message(author: &str, text: &str) -> MessageRow {
+        MessageRow {
+            author_key: author.to_string(),
+            text_content: Some(text.to_string()),
+            ..message("msg1", Some("room1"), None)
+        }
+    }
+
+    fn image_message(id: &str, room_id: Option<&str>, dm_thread_id: Option<&str>, blob_id: &str) -> MessageRow {
+        MessageRow {
+            content_type: "image".to_string(),
+            text_content: None,
+            blob_id: Some(blob_id.to_string()),
+            ..message(id, room_id, dm_thread_id)
+        }
+    }
+
+    async fn current_text(pool: &SqlitePool) -> Option<String> {
+        sqlx::query_scalar("SELECT text_content FROM messages WHERE message_id = 'msg1'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    fn room(room_id: &str, org_id: &str) -> RoomRow {
+        RoomRow {
+            room_id: room_id.to_string(),
+            org_id: org_id.to_string(),
+            name: "general".to_string(),
+            created_by: "alice".to_string(),
+            created_at: 1,
+            enc_key_epoch: 0,
+            is_archived: false,
+            archived_at: None,
+            room_cooldown_secs: None,
+            room_type: crate::RoomType::Text,
+        }
+    }
+
+    fn org(org_pubkey: Option<&str>) -> OrgRow {
+        OrgRow {
+            org_id: "org1".to_string(),
+            name: "Garden".to_string(),
+            type_label: "club".to_string(),
+            description: None,
+            avatar_blob_id: None,
+            cover_blob_id: None,
+            welcome_text: None,
+            custom_emoji_json: None,
+            org_cooldown_secs: None,
+            is_public: 0,
+            creator_key: "alice".to_string(),
+            org_pubkey: org_pubkey.map(str::to_string),
+            org_privkey_enc: None,
+            created_at: 100,
+            email_enabled: 0,
+        }
+    }
+
+    async fn stash_tables(pool: &SqlitePool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name LIKE 'rebuild_stash_%'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    fn rejection(op_hash: &str, reason: &str, at: i64) -> RejectedOpRow {
+        RejectedOpRow {
+            op_hash: op_hash.to_string(),
+            log_id: "org".to_string(),
+            author_key: "mallory".to_string(),
+            op_type: "delete_org".to_string(),
+            org_id: Some("org1".to_string()),
+            reason: reason.to_string(),
+            rejected_at: at,
+        }
+    }
+
+    fn queued(op_hash: &str, transport: &str, created_at: i64) -> OutboxRow {
+        OutboxRow {
+            op_hash: op_hash.to_string(),
+            transport: transport.to_string(),
+            log_id: "message".to_string(),
+            room_id: Some("room1".to_string()),
+            dm_thread_id: None,
+            recipient_key: None,
+            topic_hex: "room1".to_string(),
+            envelope: vec![1, 2, 3],
+            state: "pending".to_string(),
+            attempts: 0,
+            next_attempt_at: created_at,
+            last_error: None,
+            created_at,
+            delivered_at: None,
+        }
+    }
+
     #[tokio::test]
     async fn save_and_load_enc_key_manager() {
         let pool = test_pool().await;
@@ -2001,6 +3870,10 @@ mod enc_db_tests {
             sender_key: Some("deadbeef".to_string()),
             secret_id: Some(vec![1u8; 32]),
             nonce: Some(vec![2u8; 24]),
+            width: Some(640),
+            height: Some(480),
+            blurhash: Some("LEHV6nWB2yk8pyo0adR*.7kCMdnj".to_string()),
+            ..Default::default()
         };
         insert_blob_meta(&pool, &meta).await.unwrap();
 
@@ -2011,6 +3884,8 @@ mod enc_db_tests {
         assert_eq!(got.sender_key.as_deref(), Some("deadbeef"));
         assert_eq!(got.secret_id.as_deref(), Some(&[1u8; 32][..]));
         assert_eq!(got.nonce.as_deref(), Some(&[2u8; 24][..]));
+        assert_eq!((got.width, got.height), (Some(640), Some(480)));
+        assert_eq!(got.thumbnail_hash, None);
     }
 
     #[tokio::test]
@@ -2026,6 +3901,405 @@ mod enc_db_tests {
         let missing = load_enc_group_state(&pool, "nonexistent").await.unwrap();
         assert!(missing.is_none());
     }
+
+    #[tokio::test]
+    async fn pending_decrypt_backoff_and_delete() {
+        let pool = test_pool().await;
+
+        let row = PendingDecryptRow {
+            op_hash: "op1".to_string(),
+            log_id: "message".to_string(),
+            author_key: "deadbeef".to_string(),
+            group_id: "room1".to_string(),
+            group_type: "room".to_string(),
+            encrypted: vec![7u8; 16],
+            timestamp: 100,
+            attempts: 1,
+        };
+        insert_pending_decrypt(&pool, &row, "no group state", 1, 10).await.unwrap();
+        // Re-queueing the same op is ignored.
+        insert_pending_decrypt(&pool, &row, "no group state", 1, 10).await.unwrap();
+
+        assert!(list_due_pending_decrypts(&pool, 5, 10).await.unwrap().is_empty());
+        let due = list_due_pending_decrypts(&pool, 10, 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].group_id, "room1");
+        assert_eq!(due[0].group_type, "room");
+        assert_eq!(due[0].encrypted, vec![7u8; 16]);
+
+        reschedule_pending_decrypt(&pool, "op1", "still missing", 50).await.unwrap();
+        assert!(list_due_pending_decrypts(&pool, 10, 10).await.unwrap().is_empty());
+        let due = list_due_pending_decrypts(&pool, 50, 10).await.unwrap();
+        assert_eq!(due[0].attempts, 2);
+
+        wake_pending_decrypts(&pool, "room1").await.unwrap();
+        assert_eq!(list_due_pending_decrypts(&pool, 0, 10).await.unwrap().len(), 1);
+
+        // Expired by attempts, or by how long ago it was queued.
+        assert!(list_expired_pending_decrypts(&pool, 3, 1, 10).await.unwrap().is_empty());
+        assert_eq!(list_expired_pending_decrypts(&pool, 2, 1, 10).await.unwrap().len(), 1);
+        assert_eq!(list_expired_pending_decrypts(&pool, 3, 2, 10).await.unwrap().len(), 1);
+
+        delete_pending_decrypt(&pool, "op1").await.unwrap();
+        assert!(list_due_pending_decrypts(&pool, i64::MAX, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn edit_keeps_history_and_rejects_other_authors() {
+        let pool = test_pool().await;
+        insert_message(&pool, &text_message("alice", "v1")).await.unwrap();
+
+        assert!(!apply_message_edit(&pool, "e0", "msg1", "mallory", Some("hacked"), &[], 150).await.unwrap());
+        assert!(apply_message_edit(&pool, "e1", "msg1", "alice", Some("v2"), &[], 200).await.unwrap());
+        // Re-projecting the same edit is a no-op.
+        assert!(!apply_message_edit(&pool, "e1", "msg1", "alice", Some("v2"), &[], 200).await.unwrap());
+        assert!(apply_message_edit(&pool, "e2", "msg1", "alice", Some("v3"), &["bob".to_string()], 300).await.unwrap());
+        assert_eq!(current_text(&pool).await.as_deref(), Some("v3"));
+
+        let history = list_message_edits(&pool, "msg1").await.unwrap();
+        let texts: Vec<_> = history.iter().map(|h| h.text_content.as_deref()).collect();
+        assert_eq!(texts, vec![Some("v1"), Some("v2")]);
+        assert!(history.iter().all(|h| h.author_key == "alice"));
+    }
+
+    #[tokio::test]
+    async fn stale_edit_is_only_recorded() {
+        let pool = test_pool().await;
+        insert_message(&pool, &text_message("alice", "v1")).await.unwrap();
+
+        apply_message_edit(&pool, "e2", "msg1", "alice", Some("v3"), &[], 300).await.unwrap();
+        apply_message_edit(&pool, "e1", "msg1", "alice", Some("v2"), &[], 200).await.unwrap();
+        assert_eq!(current_text(&pool).await.as_deref(), Some("v3"));
+        assert_eq!(list_message_edits(&pool, "msg1").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn search_follows_inserts_edits_and_deletes() {
+        let pool = test_pool().await;
+        insert_message(&pool, &text_message("alice", "the garden party is on friday")).await.unwrap();
+        let mut other = text_message("bob", "bring snacks to the party");
+        other.message_id = "msg2".to_string();
+        other.room_id = Some("room2".to_string());
+        insert_message(&pool, &other).await.unwrap();
+
+        let hits = search_messages(&pool, "part", None, None, None, None, 10).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().any(|(_, snip)| snip.contains("<mark>party</mark>")));
+
+        let hits = search_messages(&pool, "party", Some("room2"), None, None, None, 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].0.author_key, "bob");
+
+        apply_message_edit(&pool, "e1", "msg1", "alice", Some("picnic moved to saturday"), &[], 200)
+            .await
+            .unwrap();
+        assert!(search_messages(&pool, "friday", None, None, None, None, 10).await.unwrap().is_empty());
+        assert_eq!(search_messages(&pool, "picnic", None, None, None, None, 10).await.unwrap().len(), 1);
+
+        mark_message_deleted(&pool, "msg1", Some("alice")).await.unwrap();
+        assert!(search_messages(&pool, "picnic", None, None, None, None, 10).await.unwrap().is_empty());
+
+        // Re-inserts and row deletes reach the index through the triggers.
+        insert_message(&pool, &other).await.unwrap();
+        assert_eq!(search_messages(&pool, "snacks", None, None, None, None, 10).await.unwrap().len(), 1);
+        sqlx::query("DELETE FROM messages WHERE room_id = 'room2'").execute(&pool).await.unwrap();
+        assert!(search_messages(&pool, "snacks", None, None, None, None, 10).await.unwrap().is_empty());
+        sqlx::query("INSERT INTO messages_fts (messages_fts) VALUES ('integrity-check')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // Query syntax in user input is treated as plain text.
+        assert!(search_messages(&pool, "\"snacks OR (", None, None, None, None, 10).await.is_ok());
+        assert!(search_messages(&pool, "   ", None, None, None, None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deleted_messages_cannot_be_edited() {
+        let pool = test_pool().await;
+        insert_message(&pool, &text_message("alice", "v1")).await.unwrap();
+
+        assert!(!mark_message_deleted(&pool, "msg1", Some("mallory")).await.unwrap());
+        assert!(mark_message_deleted(&pool, "msg1", Some("alice")).await.unwrap());
+        assert!(!apply_message_edit(&pool, "e1", "msg1", "alice", Some("v2"), &[], 200).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn moderator_deletes_ignore_the_author() {
+        let pool = test_pool().await;
+        insert_message(&pool, &text_message("alice", "v1")).await.unwrap();
+
+        assert_eq!(get_message_room_id(&pool, "msg1").await.unwrap().as_deref(), Some("room1"));
+        assert!(mark_message_deleted(&pool, "msg1", None).await.unwrap());
+        assert!(!mark_message_deleted(&pool, "missing", None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn fresh_database_applies_every_migration_once() {
+        let pool = empty_pool().await;
+        run_migrations(&pool).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        assert_eq!(schema_version(&pool).await, MIGRATIONS.last().unwrap().version);
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM schema_version")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+        // Nothing to re-project in an empty read model.
+        assert!(!rebuild_pending(&pool).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn legacy_database_is_adopted_and_rebuilt() {
+        let pool = empty_pool().await;
+        sqlx::query(
+            r#"
+            CREATE TABLE profiles (
+                public_key TEXT PRIMARY KEY, username TEXT NOT NULL, avatar_blob_id TEXT,
+                bio TEXT, available_for TEXT, created_at INTEGER NOT NULL, updated_at INTEGER NOT NULL
+            );
+            CREATE TABLE rooms (
+                room_id TEXT PRIMARY KEY, org_id TEXT NOT NULL, name TEXT NOT NULL,
+                created_by TEXT NOT NULL, created_at INTEGER NOT NULL,
+                room_type TEXT NOT NULL DEFAULT 'text'
+            );
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        run_migrations(&pool).await.unwrap();
+
+        assert_eq!(schema_version(&pool).await, MIGRATIONS.last().unwrap().version);
+        let mut conn = pool.acquire().await.unwrap();
+        assert!(column_exists(&mut conn, "profiles", "email_enabled").await.unwrap());
+        assert!(column_exists(&mut conn, "rooms", "room_type").await.unwrap());
+        drop(conn);
+        assert!(rebuild_pending(&pool).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn rebuild_keeps_dm_history_and_local_org_keys() {
+        let pool = test_pool().await;
+
+        insert_org(&pool, &org(Some("orgz32"))).await.unwrap();
+        insert_message(&pool, &message("room-msg", Some("room1"), None)).await.unwrap();
+        insert_message(&pool, &message("dm-msg", None, Some("thread1"))).await.unwrap();
+        set_cursor(&pool, "message", "alice", 7).await.unwrap();
+
+        begin_read_model_rebuild(&pool).await.unwrap();
+
+        assert!(get_org(&pool, "org1").await.unwrap().is_none());
+        assert_eq!(get_cursor(&pool, "message", "alice").await.unwrap(), 0);
+        let ids: Vec<String> = sqlx::query_scalar("SELECT message_id FROM messages")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["dm-msg".to_string()]);
+        let hits = search_messages(&pool, "hello", None, None, None, None, 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+
+        // Replay re-creates the org without its locally held key.
+        insert_org(&pool, &org(None)).await.unwrap();
+        finish_read_model_rebuild(&pool).await.unwrap();
+
+        let restored = get_org(&pool, "org1").await.unwrap().unwrap();
+        assert_eq!(restored.org_pubkey.as_deref(), Some("orgz32"));
+        assert_eq!(stash_tables(&pool).await, 0);
+    }
+
+    #[tokio::test]
+    async fn a_failed_rebuild_start_leaves_the_read_model_alone() {
+        let pool = test_pool().await;
+
+        insert_org(&pool, &org(Some("orgz32"))).await.unwrap();
+        set_cursor(&pool, "message", "alice", 7).await.unwrap();
+        sqlx::query(
+            "CREATE TRIGGER cursors_stuck BEFORE DELETE ON projector_cursors BEGIN SELECT RAISE(ABORT, 'stuck'); END",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert!(begin_read_model_rebuild(&pool).await.is_err());
+
+        assert!(get_org(&pool, "org1").await.unwrap().is_some());
+        assert_eq!(get_cursor(&pool, "message", "alice").await.unwrap(), 7);
+        assert_eq!(stash_tables(&pool).await, 0);
+    }
+
+    #[tokio::test]
+    async fn a_failed_rebuild_finish_keeps_the_stash() {
+        let pool = test_pool().await;
+
+        insert_org(&pool, &org(Some("orgz32"))).await.unwrap();
+        begin_read_model_rebuild(&pool).await.unwrap();
+        sqlx::query(
+            "CREATE TRIGGER version_stuck BEFORE UPDATE ON schema_version BEGIN SELECT RAISE(ABORT, 'stuck'); END",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert!(finish_read_model_rebuild(&pool).await.is_err());
+
+        assert_eq!(stash_tables(&pool).await, 5);
+    }
+
+    #[tokio::test]
+    async fn ops_are_released_once_all_dependencies_are_projected() {
+        let pool = test_pool().await;
+
+        let reaction = PendingDepRow {
+            op_hash: "reaction1".to_string(),
+            log_id: "reaction".to_string(),
+            author_key: "bob".to_string(),
+            previous: vec!["room1".to_string(), "msg1".to_string()],
+            body: vec![1, 2, 3],
+            timestamp: 200,
+        };
+        mark_op_projected(&pool, "room1").await.unwrap();
+        assert_eq!(unprojected_ops(&pool, &reaction.previous).await.unwrap(), vec!["msg1".to_string()]);
+
+        insert_pending_dep(&pool, &reaction, 300).await.unwrap();
+        assert!(list_ready_pending_deps(&pool, 10).await.unwrap().is_empty());
+
+        mark_op_projected(&pool, "msg1").await.unwrap();
+        let ready = list_ready_pending_deps(&pool, 10).await.unwrap();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].previous, reaction.previous);
+        assert_eq!(ready[0].body, vec![1, 2, 3]);
+
+        assert!(list_expired_pending_deps(&pool, 300, 10).await.unwrap().is_empty());
+        assert_eq!(list_expired_pending_deps(&pool, 301, 10).await.unwrap().len(), 1);
+        assert_eq!(count_pending_deps_by_author(&pool, "bob", "other").await.unwrap(), 1);
+        assert_eq!(count_pending_deps_by_author(&pool, "bob", "reaction1").await.unwrap(), 0);
+
+        delete_pending_dep(&pool, "reaction1").await.unwrap();
+        assert!(list_ready_pending_deps(&pool, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejections_are_recorded_once_per_op() {
+        let pool = test_pool().await;
+
+        insert_rejected_op(&pool, &rejection("op1", "author lacks manage access in org", 100)).await.unwrap();
+        // A replayed op keeps its first rejection.
+        insert_rejected_op(&pool, &rejection("op1", "unknown org", 200)).await.unwrap();
+        insert_rejected_op(&pool, &rejection("op2", "unknown org", 300)).await.unwrap();
+
+        let rows = list_rejected_ops(&pool, "org1").await.unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].op_hash, "op1");
+        assert_eq!(rows[0].reason, "author lacks manage access in org");
+        assert_eq!(rows[1].rejected_at, 300);
+        assert!(list_rejected_ops(&pool, "org2").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deliveries_move_from_pending_to_delivered_or_failed() {
+        let pool = test_pool().await;
+
+        insert_outbox(&pool, &queued("op1", "gossip", 100)).await.unwrap();
+        insert_outbox(&pool, &queued("op1", "sync", 100)).await.unwrap();
+        insert_outbox(&pool, &queued("op2", "gossip", 200)).await.unwrap();
+        assert_eq!(list_due_outbox(&pool, 150, 10).await.unwrap().len(), 2);
+
+        mark_outbox_delivered(&pool, "op1", "gossip", 160).await.unwrap();
+        mark_outbox_attempt_failed(&pool, "op1", "sync", "connection refused", Some(1_000)).await.unwrap();
+        mark_outbox_attempt_failed(&pool, "op2", "gossip", "room not found", None).await.unwrap();
+
+        // The failed sync delivery waits for its backoff; op2 was given up on.
+        assert!(list_due_outbox(&pool, 500, 10).await.unwrap().is_empty());
+        let due = list_due_outbox(&pool, 1_000, 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 1);
+        assert_eq!(due[0].last_error.as_deref(), Some("connection refused"));
+
+        let undelivered = list_undelivered_outbox(&pool).await.unwrap();
+        assert_eq!(undelivered.len(), 2);
+        assert_eq!(undelivered[1].state, "failed");
+    }
+
+    #[tokio::test]
+    async fn only_shared_org_members_are_exchanged() {
+        let pool = test_pool().await;
+
+        upsert_membership(&pool, "org1", "alice", "manage", 1).await.unwrap();
+        upsert_membership(&pool, "org1", "bob", "write", 1).await.unwrap();
+        upsert_membership(&pool, "org1", "carol", "read", 1).await.unwrap();
+        upsert_membership(&pool, "org2", "alice", "manage", 1).await.unwrap();
+        upsert_membership(&pool, "org2", "dave", "write", 1).await.unwrap();
+
+        let mut authors = list_sync_authors(&pool, "alice", "bob").await.unwrap();
+        authors.sort();
+        assert_eq!(authors, vec!["alice", "bob", "carol"]);
+
+        // dave shares org2 with alice but nothing with bob.
+        assert!(list_sync_authors(&pool, "bob", "dave").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn blobs_are_served_to_room_members_and_dm_participants_only() {
+        let pool = test_pool().await;
+
+        upsert_membership(&pool, "org1", "alice", "manage", 1).await.unwrap();
+        upsert_membership(&pool, "org1", "bob", "write", 1).await.unwrap();
+        insert_room(&pool, &room("room1", "org1")).await.unwrap();
+        insert_message(&pool, &image_message("m1", Some("room1"), None, "roomblob")).await.unwrap();
+
+        insert_dm_thread(&pool, &DmThreadRow {
+            thread_id: "dm1".to_string(),
+            initiator_key: "alice".to_string(),
+            recipient_key: "carol".to_string(),
+            created_at: 1,
+            last_message_at: None,
+            is_request: false,
+        }).await.unwrap();
+        insert_message(&pool, &image_message("m2", None, Some("dm1"), "dmblob")).await.unwrap();
+
+        assert!(can_peer_fetch_blob(&pool, "roomblob", "bob", "alice").await.unwrap());
+        assert!(!can_peer_fetch_blob(&pool, "roomblob", "carol", "alice").await.unwrap());
+        assert!(can_peer_fetch_blob(&pool, "dmblob", "carol", "alice").await.unwrap());
+        assert!(!can_peer_fetch_blob(&pool, "dmblob", "bob", "alice").await.unwrap());
+        assert!(!can_peer_fetch_blob(&pool, "unknown", "bob", "alice").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn storage_accounting_follows_blob_references() {
+        let pool = test_pool().await;
+        insert_room(&pool, &room("room1", "org1")).await.unwrap();
+        insert_message(&pool, &image_message("m1", Some("room1"), None, "roomblob")).await.unwrap();
+
+        record_blob_stored(&pool, "roomblob", 10, false, 1).await.unwrap();
+        record_blob_stored(&pool, "orphan", 5, false, 1).await.unwrap();
+        record_blob_stored(&pool, "upload", 7, true, 1).await.unwrap();
+        record_blob_stored(&pool, "fresh", 3, false, 100).await.unwrap();
+
+        // Our own uploads get the longer grace until discarded.
+        assert_eq!(list_unreferenced_blobs(&pool, 50, 0).await.unwrap(), vec!["orphan"]);
+        let mut unreferenced = list_unreferenced_blobs(&pool, 50, 50).await.unwrap();
+        unreferenced.sort();
+        assert_eq!(unreferenced, vec!["orphan", "upload"]);
+        discard_own_blob(&pool, "upload").await.unwrap();
+        assert_eq!(list_unreferenced_blobs(&pool, 50, 0).await.unwrap().len(), 2);
+        record_blob_stored(&pool, "upload", 7, true, 1).await.unwrap();
+
+        let evictable = list_evictable_blobs(&pool, Some("org1"), "bob").await.unwrap();
+        assert_eq!(evictable, vec![("roomblob".to_string(), 10)]);
+        assert!(list_evictable_blobs(&pool, Some("org1"), "alice").await.unwrap().is_empty());
+        assert_eq!(blob_bytes_used(&pool, Some("org1")).await.unwrap(), 10);
+        assert_eq!(blob_bytes_used(&pool, None).await.unwrap(), 25);
+
+        let usage = blob_storage_usage(&pool).await.unwrap();
+        assert_eq!((usage.total_bytes, usage.own_bytes), (25, 7));
+        assert_eq!(usage.rooms, vec![("org1".to_string(), "room1".to_string(), 10)]);
+
+        mark_message_deleted(&pool, "m1", Some("alice")).await.unwrap();
+        assert!(list_unreferenced_blobs(&pool, 50, 50).await.unwrap().contains(&"roomblob".to_string()));
+    }
 }
 
 // ─── One-time Invite Codes ─────────────────────────────────────────────────────
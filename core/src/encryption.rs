@@ -245,7 +245,7 @@ impl Ordering<Id, OpId, GardensDgm> for GardensOrdering {
         directs: &[DataDirectMessage<Id, OpId, GardensDgm>],
     ) -> Result<(Self::State, Self::Message), Self::Error> {
         let seq_bytes = y.next_seq.to_be_bytes();
-        let id = OpId(Hash::new(&seq_bytes));
+        let id = OpId(Hash::new(seq_bytes));
         y.next_seq += 1;
         let msg = GardensMessage {
             id,
@@ -262,7 +262,7 @@ impl Ordering<Id, OpId, GardensDgm> for GardensOrdering {
         ciphertext: Vec<u8>,
     ) -> Result<(Self::State, Self::Message), Self::Error> {
         let seq_bytes = y.next_seq.to_be_bytes();
-        let id = OpId(Hash::new(&seq_bytes));
+        let id = OpId(Hash::new(seq_bytes));
         y.next_seq += 1;
         let msg = GardensMessage {
             id,
@@ -302,6 +302,7 @@ use p2panda_encryption::traits::{
     ForwardSecureGroupMessage, ForwardSecureMessageContent, ForwardSecureOrdering,
 };
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum GardensFsMessageContent {
     Control {
@@ -348,7 +349,7 @@ pub struct GardensFsOrderingState {
     welcomed: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct GardensFsOrdering;
 
 impl GardensFsOrdering {
@@ -367,7 +368,7 @@ impl ForwardSecureOrdering<Id, OpId, GardensAckedDgm> for GardensFsOrdering {
         ctrl: &MsgControlMessage<Id, OpId>,
         directs: &[MsgDirectMessage<Id, OpId, GardensAckedDgm>],
     ) -> Result<(Self::State, Self::Message), Self::Error> {
-        let id = OpId(Hash::new(&y.next_seq.to_be_bytes()));
+        let id = OpId(Hash::new(y.next_seq.to_be_bytes()));
         y.next_seq += 1;
         let msg = GardensFsMessage { id, sender: y.my_id, content: GardensFsMessageContent::Control { ctrl: ctrl.clone(), directs: directs.to_vec() } };
         Ok((y, msg))
@@ -378,7 +379,7 @@ impl ForwardSecureOrdering<Id, OpId, GardensAckedDgm> for GardensFsOrdering {
         generation: Generation,
         ciphertext: Vec<u8>,
     ) -> Result<(Self::State, Self::Message), Self::Error> {
-        let id = OpId(Hash::new(&y.next_seq.to_be_bytes()));
+        let id = OpId(Hash::new(y.next_seq.to_be_bytes()));
         y.next_seq += 1;
         let msg = GardensFsMessage { id, sender: y.my_id, content: GardensFsMessageContent::Application { generation, ciphertext } };
         Ok((y, msg))
@@ -496,7 +497,9 @@ use sqlx::SqlitePool;
 use tokio::sync::Mutex;
 use p2panda_encryption::key_manager::{KeyManager, KeyManagerState};
 use p2panda_encryption::key_registry::{KeyRegistry, KeyRegistryState};
-use p2panda_encryption::key_bundle::Lifetime;
+use p2panda_encryption::key_bundle::{Lifetime, OneTimeKeyBundle, OneTimePreKeyId, PreKeyId};
+use p2panda_encryption::crypto::x25519::PublicKey as X25519PublicKey;
+use p2panda_encryption::traits::{IdentityManager, IdentityRegistry, PreKeyRegistry};
 use p2panda_encryption::data_scheme::{GroupState};
 use p2panda_encryption::message_scheme::{GroupState as MsgGroupState};
 use p2panda_encryption::crypto::{Rng, x25519::SecretKey as X25519SecretKey};
@@ -513,12 +516,73 @@ pub type GardensGroupState = GroupState<
 
 pub type GardensMsgGroupState = MsgGroupState<
     Id, OpId,
-    KeyRegistry<Id>,
+    GardensKeyRegistry,
     GardensAckedDgm,
-    KeyManager,
+    GardensKeyManager,
     GardensFsOrdering,
 >;
 
+// The message scheme's `GroupState` derives serde with a bound on every
+// marker type, and p2panda's `KeyRegistry` / `KeyManager` markers don't
+// implement it. These forward to them so DM group state can be stored as is.
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GardensKeyRegistry;
+
+impl IdentityRegistry<Id, KeyRegistryState<Id>> for GardensKeyRegistry {
+    type Error = <KeyRegistry<Id> as IdentityRegistry<Id, KeyRegistryState<Id>>>::Error;
+
+    fn identity_key(y: &KeyRegistryState<Id>, id: &Id) -> Result<Option<X25519PublicKey>, Self::Error> {
+        KeyRegistry::<Id>::identity_key(y, id)
+    }
+}
+
+impl PreKeyRegistry<Id, OneTimeKeyBundle> for GardensKeyRegistry {
+    type State = KeyRegistryState<Id>;
+    type Error = <KeyRegistry<Id> as PreKeyRegistry<Id, OneTimeKeyBundle>>::Error;
+
+    fn key_bundle(y: Self::State, id: &Id) -> Result<(Self::State, Option<OneTimeKeyBundle>), Self::Error> {
+        <KeyRegistry<Id> as PreKeyRegistry<Id, OneTimeKeyBundle>>::key_bundle(y, id)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GardensKeyManager;
+
+impl IdentityManager<KeyManagerState> for GardensKeyManager {
+    fn identity_secret(y: &KeyManagerState) -> &X25519SecretKey {
+        KeyManager::identity_secret(y)
+    }
+}
+
+impl PreKeyManager for GardensKeyManager {
+    type State = KeyManagerState;
+    type Error = <KeyManager as PreKeyManager>::Error;
+
+    fn prekey_secret<'a>(y: &'a Self::State, id: &'a PreKeyId) -> Result<&'a X25519SecretKey, Self::Error> {
+        KeyManager::prekey_secret(y, id)
+    }
+
+    fn rotate_prekey(y: Self::State, lifetime: Lifetime, rng: &Rng) -> Result<Self::State, Self::Error> {
+        KeyManager::rotate_prekey(y, lifetime, rng)
+    }
+
+    fn prekey_bundle(y: &Self::State) -> Result<LongTermKeyBundle, Self::Error> {
+        KeyManager::prekey_bundle(y)
+    }
+
+    fn generate_onetime_bundle(y: Self::State, rng: &Rng) -> Result<(Self::State, OneTimeKeyBundle), Self::Error> {
+        KeyManager::generate_onetime_bundle(y, rng)
+    }
+
+    fn use_onetime_secret(
+        y: Self::State,
+        id: OneTimePreKeyId,
+    ) -> Result<(Self::State, Option<X25519SecretKey>), Self::Error> {
+        KeyManager::use_onetime_secret(y, id)
+    }
+}
+
 pub struct EncryptionCore {
     pub key_manager:  Mutex<KeyManagerState>,
     pub key_registry: Mutex<KeyRegistryState<Id>>,
@@ -1100,7 +1164,7 @@ pub async fn remove_member_from_org_groups(
 
     // Get all rooms in the org
     let rooms = crate::db::list_rooms(pool, org_id, true).await
-        .map_err(|e| EncryptionError::Db(e))?;
+        .map_err(EncryptionError::Db)?;
 
     let mut ctrl_messages = Vec::new();
 
@@ -1221,7 +1285,7 @@ pub async fn add_member_to_org_groups(
 
     // Get all rooms in the org
     let rooms = crate::db::list_rooms(pool, org_id, true).await
-        .map_err(|e| EncryptionError::Db(e))?;
+        .map_err(EncryptionError::Db)?;
 
     let mut ctrl_messages = Vec::new();
 
@@ -1268,10 +1332,34 @@ pub async fn publish_enc_ctrl_op(
     
     log::info!("[encryption] published ENC_CTRL op for group: {} ({})", group_id, op_hash);
     
+    // DM groups: seal the ctrl message to the other participant's inbox.
+    if let Ok(Some(_)) = crate::db::get_dm_thread(&core.read_pool, group_id).await {
+        if let Ok((topic_id, peers, recipient_hex)) = super::dm_gossip_context(core, group_id).await {
+            let sender_pk = core.private_key.public_key();
+            match super::hex_to_bytes_32(&recipient_hex)
+                .ok()
+                .and_then(|r| crate::sealed_sender::seal(&gossip_bytes, sender_pk.as_bytes(), &r).ok())
+            {
+                Some(sealed) => {
+                    if let Err(e) = crate::network::gossip_publish(
+                        topic_id,
+                        crate::network::GossipTopicKind::DmInbox,
+                        peers,
+                        sealed,
+                    ).await {
+                        log::warn!("[encryption] failed to gossip DM ENC_CTRL: {}", e);
+                    }
+                }
+                None => log::warn!("[encryption] failed to seal DM ENC_CTRL for {}", group_id),
+            }
+        }
+        return Ok(gossip_bytes);
+    }
+
     // Also gossip in real-time via Iroh to connected room members
-    if let Some(room_id) = group_id.strip_prefix("room:") {
+    if let Some(_room_id) = group_id.strip_prefix("room:") {
         // Check if it's a room
-        if let Ok((topic_id, peers)) = super::room_gossip_context(&core, group_id).await {
+        if let Ok((topic_id, peers)) = super::room_gossip_context(core, group_id).await {
             let peer_count = peers.len();
             if let Err(e) = crate::network::gossip_publish(
                 topic_id,
@@ -1286,7 +1374,7 @@ pub async fn publish_enc_ctrl_op(
         }
     } else {
         // Try as room_id directly
-        if let Ok((topic_id, peers)) = super::room_gossip_context(&core, group_id).await {
+        if let Ok((topic_id, peers)) = super::room_gossip_context(core, group_id).await {
             let peer_count = peers.len();
             if let Err(e) = crate::network::gossip_publish(
                 topic_id,
@@ -1304,7 +1392,297 @@ pub async fn publish_enc_ctrl_op(
     Ok(gossip_bytes)
 }
 
+// ─── DM encryption — message scheme two-party ratchet ────────────────────────
+
+use p2panda_encryption::message_scheme::group::GroupConfig;
+use p2panda_encryption::message_scheme::{GroupEvent, MessageGroup};
+
+/// Number of unused one-time pre-keys we keep published. Each DM thread
+/// opened towards us consumes one of them; top-ups replace only those.
+pub const ONE_TIME_BUNDLE_TARGET: i64 = 5;
+
+/// Envelope written as the op body for encrypted DM payloads.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncryptedDmBody {
+    pub generation: Generation,
+    pub ciphertext: Vec<u8>,
+    pub sender_key: [u8; 32],      // sender's Ed25519 public key bytes
+}
+
+async fn load_dm_group(
+    pool: &SqlitePool,
+    thread_id: &str,
+) -> Result<Option<GardensMsgGroupState>, EncryptionError> {
+    let Some(bytes) = crate::db::load_enc_group_state(pool, thread_id).await? else {
+        return Ok(None);
+    };
+    let y: GardensMsgGroupState = ciborium::from_reader(bytes.as_slice())
+        .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+    Ok(Some(y))
+}
+
+/// Persist a DM group state.
+///
+/// Unlike room groups, the key material inside is not written back to the
+/// singleton: the message scheme keeps it private, and the copy a DM group
+/// took at creation is older than the singleton's, so writing it back would
+/// drop one-time pre-keys published since.
+async fn save_dm_group(
+    pool: &SqlitePool,
+    thread_id: &str,
+    y: GardensMsgGroupState,
+) -> Result<(), EncryptionError> {
+    let mut state_bytes = Vec::new();
+    ciborium::into_writer(&y, &mut state_bytes)
+        .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+    crate::db::save_enc_group_state(pool, thread_id, "dm", &state_bytes).await?;
+    Ok(())
+}
+
+/// Fresh, not-yet-welcomed DM group state built from the singleton's key material.
+async fn empty_dm_group() -> Result<GardensMsgGroupState, EncryptionError> {
+    let enc = get_encryption().ok_or(EncryptionError::NotInitialised)?;
+    let km_state = enc.key_manager.lock().await.clone();
+    let mut kr_state = enc.key_registry.lock().await.clone();
+    let my_id = Id(enc.my_public_key);
+    // The ratchet looks up every member's identity key, our own included.
+    let own_identity = KeyRegistry::<Id>::identity_key(&kr_state, &my_id)
+        .map_err(|e| EncryptionError::Init(e.to_string()))?;
+    if own_identity.is_none() {
+        let own_bundle = KeyManager::prekey_bundle(&km_state)
+            .map_err(|e| EncryptionError::Init(e.to_string()))?;
+        kr_state = KeyRegistry::add_longterm_bundle(kr_state, my_id, own_bundle)
+            .map_err(|e| EncryptionError::Init(e.to_string()))?;
+    }
+    let dgm_state = GardensAckedDgm::create(my_id, &[])
+        .map_err(|e| EncryptionError::Init(e.to_string()))?;
+    let ord_state = GardensFsOrdering::init(enc.my_public_key);
+    Ok(MessageGroup::init(my_id, km_state, kr_state, dgm_state, ord_state, GroupConfig::default()))
+}
+
+/// Whether a DM thread already has forward-secure group state.
+pub(crate) async fn has_dm_group(pool: &SqlitePool, thread_id: &str) -> Result<bool, EncryptionError> {
+    Ok(crate::db::load_enc_group_state(pool, thread_id).await?.is_some())
+}
+
+/// How many one-time pre-key bundles to publish to get back to
+/// [`ONE_TIME_BUNDLE_TARGET`] unused ones.
+pub(crate) async fn onetime_bundles_missing(pool: &SqlitePool) -> Result<usize, EncryptionError> {
+    let unused = crate::db::unused_onetime_prekeys(pool).await?;
+    Ok((ONE_TIME_BUNDLE_TARGET - unused).max(0) as usize)
+}
+
+/// Generate `count` one-time pre-key bundles and return them CBOR-encoded,
+/// ready to be published as `KeyBundleOp { bundle_type: "one_time" }`.
+pub(crate) async fn generate_onetime_bundles_with_pool(
+    count: usize,
+    pool: &SqlitePool,
+) -> Result<Vec<Vec<u8>>, EncryptionError> {
+    let enc = get_encryption().ok_or(EncryptionError::NotInitialised)?;
+    let rng = Rng::default();
+    let mut km = enc.key_manager.lock().await;
+    let mut state = km.clone();
+    let mut out = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (next, bundle) = KeyManager::generate_onetime_bundle(state, &rng)
+            .map_err(|e| EncryptionError::Init(e.to_string()))?;
+        state = next;
+        let mut buf = Vec::new();
+        ciborium::into_writer(&bundle, &mut buf)
+            .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+        out.push(buf);
+    }
+    let mut km_buf = Vec::new();
+    ciborium::into_writer(&state, &mut km_buf)
+        .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+    crate::db::save_enc_key_manager(pool, &km_buf).await?;
+    *km = state;
+    Ok(out)
+}
+
+/// Register a peer's published key bundle (`KeyBundleOp`) in our KeyRegistry.
+pub(crate) async fn register_key_bundle_with_pool(
+    author: PublicKey,
+    bundle_type: &str,
+    bundle_data: &[u8],
+    pool: &SqlitePool,
+) -> Result<(), EncryptionError> {
+    let enc = get_encryption().ok_or(EncryptionError::NotInitialised)?;
+    let mut kr = enc.key_registry.lock().await;
+    let new_kr = match bundle_type {
+        "long_term" => {
+            let bundle: LongTermKeyBundle = ciborium::from_reader(bundle_data)
+                .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+            KeyRegistry::add_longterm_bundle(kr.clone(), Id(author), bundle)
+                .map_err(|e| EncryptionError::Init(e.to_string()))?
+        }
+        "one_time" => {
+            let bundle: OneTimeKeyBundle = ciborium::from_reader(bundle_data)
+                .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+            KeyRegistry::add_onetime_bundle(kr.clone(), Id(author), bundle)
+                .map_err(|e| EncryptionError::Init(e.to_string()))?
+        }
+        other => return Err(EncryptionError::Init(format!("unknown bundle type '{}'", other))),
+    };
+    let mut buf = Vec::new();
+    ciborium::into_writer(&new_kr, &mut buf)
+        .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+    crate::db::save_enc_key_registry(pool, &buf).await?;
+    *kr = new_kr;
+    Ok(())
+}
+
+/// Inner implementation that takes an explicit pool (needed for tests with in-memory DBs).
+pub(crate) async fn init_dm_group_with_pool(
+    thread_id: &str,
+    recipient: PublicKey,
+    pool: &SqlitePool,
+) -> Result<Vec<u8>, EncryptionError> {
+    let enc = get_encryption().ok_or(EncryptionError::NotInitialised)?;
+    let members = vec![Id(enc.my_public_key), Id(recipient)];
+
+    // Consumes one of the recipient's one-time pre-keys for the 2SM welcome.
+    let rng = Rng::default();
+    let (new_group_state, ctrl_msg) = MessageGroup::create(empty_dm_group().await?, members, &rng)
+        .map_err(|e| EncryptionError::Init(format!("{:?}", e)))?;
+    save_dm_group(pool, thread_id, new_group_state).await?;
+
+    let mut ctrl_bytes = Vec::new();
+    ciborium::into_writer(&ctrl_msg, &mut ctrl_bytes)
+        .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+    Ok(ctrl_bytes)
+}
+
+/// Create the forward-secure group for a DM thread with `recipient`.
+/// Returns the CBOR-encoded control message to publish as an `EncCtrlOp`.
+pub async fn init_dm_group(thread_id: &str, recipient: PublicKey) -> Result<Vec<u8>, EncryptionError> {
+    let core = crate::store::get_core().ok_or(EncryptionError::NotInitialised)?;
+    init_dm_group_with_pool(thread_id, recipient, &core.read_pool).await
+}
+
+/// Process a DM control message from the other participant. A missing group
+/// state is created on the fly, so the first message received is the welcome.
+/// Returns control messages (acks) that must be published back.
+pub(crate) async fn receive_dm_ctrl_with_pool(
+    thread_id: &str,
+    ctrl_bytes: &[u8],
+    pool: &SqlitePool,
+) -> Result<Vec<Vec<u8>>, EncryptionError> {
+    let existing = load_dm_group(pool, thread_id).await?;
+    let welcomed = existing.is_none();
+    let group_state = match existing {
+        Some(y) => y,
+        None => empty_dm_group().await?,
+    };
+    let msg: GardensFsMessage = ciborium::from_reader(ctrl_bytes)
+        .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+
+    let rng = Rng::default();
+    let (new_group_state, output) = MessageGroup::receive(group_state, &msg, &rng)
+        .map_err(|e| EncryptionError::Init(format!("{:?}", e)))?;
+    save_dm_group(pool, thread_id, new_group_state).await?;
+    if welcomed {
+        // The welcome used up one of our one-time pre-keys.
+        crate::db::note_onetime_prekey_consumed(pool).await?;
+    }
+
+    let mut replies = Vec::new();
+    for event in output.map(|o| o.events).unwrap_or_default() {
+        if let GroupEvent::Control(reply) = event {
+            let mut buf = Vec::new();
+            ciborium::into_writer(&reply, &mut buf)
+                .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+            replies.push(buf);
+        }
+    }
+    Ok(replies)
+}
+
+/// Inner implementation that takes an explicit pool (needed for tests with in-memory DBs).
+pub(crate) async fn encrypt_for_dm_with_pool(
+    thread_id: &str,
+    plaintext: &[u8],
+    pool: &SqlitePool,
+) -> Result<Vec<u8>, EncryptionError> {
+    let enc = get_encryption().ok_or(EncryptionError::NotInitialised)?;
+    let group_state = load_dm_group(pool, thread_id)
+        .await?
+        .ok_or_else(|| EncryptionError::Init(format!("no group state for dm thread '{}'", thread_id)))?;
+
+    let (new_group_state, msg) = MessageGroup::send(group_state, plaintext)
+        .map_err(|e| EncryptionError::Init(format!("{:?}", e)))?;
+    let (generation, ciphertext) = match msg.content {
+        GardensFsMessageContent::Application { generation, ciphertext } => (generation, ciphertext),
+        _ => return Err(EncryptionError::Init("send produced non-application message".into())),
+    };
+    save_dm_group(pool, thread_id, new_group_state).await?;
+
+    let body = EncryptedDmBody {
+        generation,
+        ciphertext,
+        sender_key: *enc.my_public_key.as_bytes(),
+    };
+    let mut body_bytes = Vec::new();
+    ciborium::into_writer(&body, &mut body_bytes)
+        .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+    Ok(body_bytes)
+}
+
+/// Encrypt `plaintext` for a DM thread. Returns CBOR-encoded `EncryptedDmBody`.
+pub async fn encrypt_for_dm(thread_id: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+    let core = crate::store::get_core().ok_or(EncryptionError::NotInitialised)?;
+    encrypt_for_dm_with_pool(thread_id, plaintext, &core.read_pool).await
+}
+
+/// Inner implementation that takes an explicit pool (needed for tests with in-memory DBs).
+///
+/// Each message key is used once and then deleted, so a given body can only be
+/// decrypted a single time.
+pub(crate) async fn decrypt_for_dm_with_pool(
+    thread_id: &str,
+    body_bytes: &[u8],
+    pool: &SqlitePool,
+) -> Result<Vec<u8>, EncryptionError> {
+    let group_state = load_dm_group(pool, thread_id)
+        .await?
+        .ok_or_else(|| EncryptionError::Init(format!("no group state for dm thread '{}'", thread_id)))?;
+
+    let body: EncryptedDmBody = ciborium::from_reader(body_bytes)
+        .map_err(|e| EncryptionError::Cbor(e.to_string()))?;
+    let sender_pk = p2panda_core::PublicKey::try_from(body.sender_key.as_slice())
+        .map_err(|e| EncryptionError::Init(format!("invalid sender key: {:?}", e)))?;
+
+    let msg = GardensFsMessage {
+        id: OpId(p2panda_core::Hash::new(&body.ciphertext)),
+        sender: Id(sender_pk),
+        content: GardensFsMessageContent::Application {
+            generation: body.generation,
+            ciphertext: body.ciphertext,
+        },
+    };
+
+    let rng = Rng::default();
+    let (new_group_state, output) = MessageGroup::receive(group_state, &msg, &rng)
+        .map_err(|e| EncryptionError::Init(format!("{:?}", e)))?;
+    save_dm_group(pool, thread_id, new_group_state).await?;
+
+    for event in output.map(|o| o.events).unwrap_or_default() {
+        if let GroupEvent::Application { plaintext, .. } = event {
+            return Ok(plaintext);
+        }
+    }
+
+    Err(EncryptionError::Init("no application output from receive — message may not have been decryptable".into()))
+}
+
+/// Decrypt a CBOR-encoded `EncryptedDmBody` for a DM thread. Returns plaintext.
+pub async fn decrypt_for_dm(thread_id: &str, body_bytes: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+    let core = crate::store::get_core().ok_or(EncryptionError::NotInitialised)?;
+    decrypt_for_dm_with_pool(thread_id, body_bytes, &core.read_pool).await
+}
+
 #[cfg(test)]
+#[allow(dropping_references)]
 mod room_encrypt_tests {
     use super::*;
 
@@ -1397,4 +1775,197 @@ mod room_encrypt_tests {
 
         assert_eq!(recovered, plaintext, "decrypted plaintext should match original");
     }
+
+    #[tokio::test]
+    async fn encrypted_room_op_roundtrip() {
+        use crate::ops::{decode_cbor, encode_cbor, EncryptedRoomOp, MessageOp};
+        use sqlx::sqlite::SqlitePoolOptions;
+
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        crate::db::run_migrations(&pool).await.unwrap();
+        let privkey = p2panda_core::PrivateKey::new();
+        let _ = init_encryption(privkey.to_hex(), pool.clone()).await;
+        let my_pk = get_encryption().expect("EncryptionCore must be initialized").my_public_key;
+
+        init_room_group_with_pool("test-room-msg", vec![my_pk], &pool)
+            .await
+            .expect("init_room_group should succeed");
+
+        // Wrap a MessageOp the same way send_message does.
+        let plaintext = encode_cbor(&MessageOp {
+            op_type: "send".into(),
+            room_id: Some("test-room-msg".into()),
+            dm_thread_id: None,
+            content_type: "text".into(),
+            text_content: Some("secret hello".into()),
+            blob_id: None,
+            blob_key: None,
+            blob_nonce: None,
+            preview: None,
+            embed_url: None,
+            mentions: vec![],
+            reply_to: None,
+            target_id: None,
+        })
+        .unwrap();
+        let encrypted = encrypt_for_room_with_pool("test-room-msg", &plaintext, &pool)
+            .await
+            .expect("encrypt should succeed");
+        let body = encode_cbor(&EncryptedRoomOp {
+            op_type: "encrypted".into(),
+            room_id: "test-room-msg".into(),
+            encrypted,
+        })
+        .unwrap();
+
+        // The stored op body must not contain the message text.
+        assert!(!body.windows(12).any(|w| w == b"secret hello"));
+
+        let env: EncryptedRoomOp = decode_cbor(&body).unwrap();
+        let recovered = decrypt_for_room_with_pool(&env.room_id, &env.encrypted, &pool)
+            .await
+            .expect("decrypt should succeed");
+        let op: MessageOp = decode_cbor(&recovered).unwrap();
+        assert_eq!(op.text_content.as_deref(), Some("secret hello"));
+
+        // Without group state the body can't be opened (the projector queues it).
+        assert!(decrypt_for_room_with_pool("unknown-room", &env.encrypted, &pool).await.is_err());
+    }
+}
+
+#[cfg(test)]
+mod dm_encrypt_tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[tokio::test]
+    async fn init_dm_group_requires_onetime_bundle() {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        crate::db::run_migrations(&pool).await.unwrap();
+        let privkey = p2panda_core::PrivateKey::new();
+        let _ = init_encryption(privkey.to_hex(), pool.clone()).await;
+
+        let peer = p2panda_core::PrivateKey::new().public_key();
+        assert!(init_dm_group_with_pool("dm-no-bundle", peer, &pool).await.is_err());
+        assert!(!has_dm_group(&pool, "dm-no-bundle").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn init_dm_group_persists_dm_state_and_encrypts() {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        crate::db::run_migrations(&pool).await.unwrap();
+        let privkey = p2panda_core::PrivateKey::new();
+        let _ = init_encryption(privkey.to_hex(), pool.clone()).await;
+
+        // Stand in for the peer's published KEY_BUNDLE ops.
+        let peer = p2panda_core::PrivateKey::new().public_key();
+        let enc = get_encryption().expect("EncryptionCore must be initialized");
+        let longterm = KeyManager::prekey_bundle(&*enc.key_manager.lock().await).unwrap();
+        let mut longterm_bytes = Vec::new();
+        ciborium::into_writer(&longterm, &mut longterm_bytes).unwrap();
+        register_key_bundle_with_pool(peer, "long_term", &longterm_bytes, &pool).await.unwrap();
+        let bundles = generate_onetime_bundles_with_pool(1, &pool).await.unwrap();
+        register_key_bundle_with_pool(peer, "one_time", &bundles[0], &pool).await.unwrap();
+
+        let ctrl = init_dm_group_with_pool("dm-thread-001", peer, &pool)
+            .await
+            .expect("init_dm_group should succeed");
+        let _: GardensFsMessage = ciborium::from_reader(ctrl.as_slice())
+            .expect("ctrl message must decode");
+
+        let group_type: String =
+            sqlx::query_scalar("SELECT group_type FROM enc_group_state WHERE group_id = ?")
+                .bind("dm-thread-001")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(group_type, "dm");
+
+        let body = encrypt_for_dm_with_pool("dm-thread-001", b"hello dm", &pool)
+            .await
+            .expect("encrypt should succeed");
+        assert!(!body.windows(8).any(|w| w == b"hello dm"));
+        let _: EncryptedDmBody = ciborium::from_reader(body.as_slice()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn peers_open_a_dm_session_with_a_published_onetime_bundle() {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        crate::db::run_migrations(&pool).await.unwrap();
+        let _ = init_encryption(p2panda_core::PrivateKey::new().to_hex(), pool.clone()).await;
+        let enc = get_encryption().expect("EncryptionCore must be initialized");
+        let rng = Rng::default();
+
+        // We are Bob and publish a full stock of one-time bundles.
+        let bob = enc.my_public_key;
+        assert_eq!(onetime_bundles_missing(&pool).await.unwrap(), ONE_TIME_BUNDLE_TARGET as usize);
+        let published = generate_onetime_bundles_with_pool(ONE_TIME_BUNDLE_TARGET as usize, &pool).await.unwrap();
+        crate::db::note_onetime_prekeys_published(&pool, published.len() as i64).await.unwrap();
+        assert_eq!(onetime_bundles_missing(&pool).await.unwrap(), 0);
+
+        // Alice is a separate device with her own keys.
+        let alice = p2panda_core::PrivateKey::new().public_key();
+        let alice_km = KeyManager::init(&X25519SecretKey::from_rng(&rng).unwrap()).unwrap();
+        let alice_km = KeyManager::rotate_prekey(alice_km, Lifetime::default(), &rng).unwrap();
+        let alice_longterm = KeyManager::prekey_bundle(&alice_km).unwrap();
+        let (alice_km, alice_onetime) = KeyManager::generate_onetime_bundle(alice_km, &rng).unwrap();
+        for (bundle_type, bytes) in [("long_term", cbor(&alice_longterm)), ("one_time", cbor(&alice_onetime))] {
+            register_key_bundle_with_pool(alice, bundle_type, &bytes, &pool).await.unwrap();
+        }
+
+        // She reads Bob's bundles off his KEY_BUNDLE log.
+        let op: crate::ops::KeyBundleOp = crate::ops::decode_cbor(
+            &crate::ops::encode_cbor(&crate::ops::KeyBundleOp {
+                bundle_type: "one_time".into(),
+                bundle_data: published[0].clone(),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        let onetime: OneTimeKeyBundle = ciborium::from_reader(op.bundle_data.as_slice()).unwrap();
+        let bob_longterm = KeyManager::prekey_bundle(&*enc.key_manager.lock().await).unwrap();
+        let alice_kr = KeyRegistry::<Id>::init();
+        let alice_kr = KeyRegistry::add_longterm_bundle(alice_kr, Id(alice), alice_longterm).unwrap();
+        let alice_kr = KeyRegistry::add_longterm_bundle(alice_kr, Id(bob), bob_longterm).unwrap();
+        let alice_kr = KeyRegistry::add_onetime_bundle(alice_kr, Id(bob), onetime).unwrap();
+
+        let y: GardensMsgGroupState = MessageGroup::init(
+            Id(alice),
+            alice_km,
+            alice_kr,
+            GardensAckedDgm::create(Id(alice), &[]).unwrap(),
+            GardensFsOrdering::init(alice),
+            GroupConfig::default(),
+        );
+        let (y, welcome) = MessageGroup::create(y, vec![Id(alice), Id(bob)], &rng).unwrap();
+
+        let acks = receive_dm_ctrl_with_pool("dm-two-party", &cbor(&welcome), &pool).await.unwrap();
+        assert!(!acks.is_empty());
+        // The welcome used one bundle; a top-up would replace just that one.
+        assert_eq!(onetime_bundles_missing(&pool).await.unwrap(), 1);
+
+        let (_, msg) = MessageGroup::send(y, b"hello bob").unwrap();
+        let GardensFsMessageContent::Application { generation, ciphertext } = msg.content else {
+            panic!("expected an application message");
+        };
+        let body = cbor(&EncryptedDmBody { generation, ciphertext, sender_key: *alice.as_bytes() });
+        assert_eq!(decrypt_for_dm_with_pool("dm-two-party", &body, &pool).await.unwrap(), b"hello bob");
+    }
+
+    fn cbor<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        ciborium::into_writer(value, &mut buf).unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn unknown_bundle_type_is_rejected() {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        crate::db::run_migrations(&pool).await.unwrap();
+        let privkey = p2panda_core::PrivateKey::new();
+        let _ = init_encryption(privkey.to_hex(), pool.clone()).await;
+
+        let peer = p2panda_core::PrivateKey::new().public_key();
+        assert!(register_key_bundle_with_pool(peer, "bogus", &[], &pool).await.is_err());
+    }
 }
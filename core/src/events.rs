@@ -0,0 +1,144 @@
+//! Change notifications for the app layer.
+//!
+//! The projector emits a [`GardensEvent`] for every read-model change it
+//! applies. The app registers a [`GardensEventListener`] via
+//! `set_event_listener` and updates its state from the events instead of
+//! re-querying after every ingest.
+//!
+//! While the read model is being rebuilt, per-row events are suppressed and
+//! only [`GardensEvent::RebuildProgress`] is delivered; the app reloads its
+//! state once the rebuild reports `finished`.
+//!
+//! Events are queued and delivered in order from a dedicated dispatch
+//! thread, so the listener never runs on the projector (which may hold the
+//! op store lock) and a slow or re-entrant listener can't stall it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+use crate::Message;
+
+/// A change applied to the read model.
+pub enum GardensEvent {
+    MessageInserted { message: Message },
+    MessageEdited { message_id: String },
+    MessageDeleted { message_id: String },
+    ReactionChanged { message_id: String, emoji: String, reactor_key: String, added: bool },
+    MembershipChanged { org_id: String, member_key: String },
+    RoomUpdated { room_id: String },
+    OrgUpdated { org_id: String },
+    DmRequestReceived { thread_id: String, initiator_key: String },
+    RebuildProgress { ops_done: u64, ops_total: u64, finished: bool },
+}
+
+/// Implemented by the app (Kotlin / Swift / JS) to receive change events.
+///
+/// `on_event` runs on the core's event dispatch thread, one event at a time,
+/// so implementations should return quickly to keep later events flowing.
+pub trait GardensEventListener: Send + Sync {
+    fn on_event(&self, event: GardensEvent);
+}
+
+static LISTENER: RwLock<Option<Arc<dyn GardensEventListener>>> = RwLock::new(None);
+
+type Dispatch = (Arc<dyn GardensEventListener>, GardensEvent);
+
+/// Sending half of the queue drained by the dispatch thread.
+static DISPATCH: OnceLock<Mutex<Sender<Dispatch>>> = OnceLock::new();
+
+/// Set while the projector replays every log into a fresh read model.
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_listener(listener: Box<dyn GardensEventListener>) {
+    if let Ok(mut slot) = LISTENER.write() {
+        *slot = Some(Arc::from(listener));
+    }
+}
+
+pub fn clear_listener() {
+    if let Ok(mut slot) = LISTENER.write() {
+        *slot = None;
+    }
+}
+
+/// Suppress (or resume) per-row change events during a read-model rebuild.
+pub(crate) fn set_suspended(suspended: bool) {
+    SUSPENDED.store(suspended, Ordering::SeqCst);
+}
+
+fn dispatcher() -> &'static Mutex<Sender<Dispatch>> {
+    DISPATCH.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<Dispatch>();
+        std::thread::Builder::new()
+            .name("gardens-events".into())
+            .spawn(move || {
+                for (listener, event) in rx {
+                    listener.on_event(event);
+                }
+            })
+            .expect("spawn event dispatch thread");
+        Mutex::new(tx)
+    })
+}
+
+/// Queue `event` for the listener registered now, if any. Returns without
+/// waiting for the listener to run.
+pub(crate) fn emit(event: GardensEvent) {
+    if SUSPENDED.load(Ordering::SeqCst) && !matches!(event, GardensEvent::RebuildProgress { .. }) {
+        return;
+    }
+    let Some(listener) = LISTENER.read().ok().and_then(|slot| slot.clone()) else {
+        return;
+    };
+    let tx = match dispatcher().lock() {
+        Ok(tx) => tx.clone(),
+        Err(_) => return,
+    };
+    let _ = tx.send((listener, event));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+
+    /// Forwards this test's org ids; other tests emit events concurrently.
+    struct Recorder {
+        seen: Mutex<Sender<String>>,
+        gate: Mutex<Receiver<()>>,
+    }
+
+    impl GardensEventListener for Recorder {
+        fn on_event(&self, event: GardensEvent) {
+            if let GardensEvent::OrgUpdated { org_id } = event {
+                if org_id.starts_with("events-test") {
+                    if org_id == "events-test-blocked" {
+                        let _ = self.gate.lock().unwrap().recv();
+                    }
+                    let _ = self.seen.lock().unwrap().send(org_id);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn emit_queues_for_listener_until_cleared() {
+        let (seen_tx, seen) = mpsc::channel();
+        let (release, gate) = mpsc::channel();
+        set_listener(Box::new(Recorder { seen: Mutex::new(seen_tx), gate: Mutex::new(gate) }));
+
+        // A listener stuck in `on_event` doesn't hold up the emitter.
+        emit(GardensEvent::OrgUpdated { org_id: "events-test-blocked".into() });
+        emit(GardensEvent::OrgUpdated { org_id: "events-test-1".into() });
+        clear_listener();
+        emit(GardensEvent::OrgUpdated { org_id: "events-test-2".into() });
+        release.send(()).unwrap();
+
+        let wait = Duration::from_secs(5);
+        assert_eq!(seen.recv_timeout(wait).unwrap(), "events-test-blocked");
+        assert_eq!(seen.recv_timeout(wait).unwrap(), "events-test-1");
+        assert!(seen.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! Validation for ops received from peers.
+//!
+//! Both ingest paths — the sync worker WebSocket (`sync::ingest_op`) and
+//! gossip (`network::ingest_gossip_envelope`) — hand the raw
+//! [`GossipEnvelope`] bytes to [`ingest_envelope`], which checks the op
+//! before it reaches the op store:
+//!
+//!  1. Envelope, header and body are within size limits and the log is known.
+//!  2. The header signature verifies against its author.
+//!  3. `payload_size` / `payload_hash` match the body.
+//!  4. `seq_num` and `backlink` agree with the author's log as we have it:
+//!     the ops we hold at `seq_num - 1`, `seq_num` and `seq_num + 1`. Two
+//!     different ops at one seq_num are a fork; the signed header is kept in
+//!     `fork_evidence` and the op is rejected. (The header codec already
+//!     refuses a backlink that doesn't fit the seq_num.)
+
+use p2panda_core::{Body, Hash, Header, PublicKey};
+use p2panda_store::{LogStore, OperationStore};
+use thiserror::Error;
+
+use crate::db::{self, DbError, ForkEvidenceRow};
+use crate::ops::{decode_cbor, log_ids, GossipEnvelope};
+use crate::store::{GardensCore, GardensStore};
+
+/// Largest encoded header we accept. Headers are small; this leaves room
+/// for a long `previous` list.
+pub const MAX_HEADER_BYTES: usize = 4 * 1024;
+
+/// Largest op body we accept. Media travels as blobs, not in ops.
+pub const MAX_BODY_BYTES: usize = 256 * 1024;
+
+// ─── Error ───────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Error)]
+pub enum IngestError {
+    #[error("malformed op: {0}")]
+    Malformed(String),
+    #[error("unknown log id: {0}")]
+    UnknownLog(String),
+    #[error("op too large: {what} is {size} bytes (max {max})")]
+    TooLarge { what: &'static str, size: usize, max: usize },
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("payload does not match header: {0}")]
+    PayloadMismatch(&'static str),
+    #[error("fork by {author} in log {log_id} at seq {seq_num}")]
+    Fork { author: String, log_id: String, seq_num: u64 },
+    #[error("store error: {0}")]
+    Store(String),
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+/// An op that passed validation and is now in the op store.
+pub struct IngestedOp {
+    pub author: PublicKey,
+    pub log_id: String,
+    pub op_hash: Hash,
+}
+
+// ─── Ingest ──────────────────────────────────────────────────────────────────
+
+/// Validate a received `GossipEnvelope` and insert it into the op store.
+///
+/// Re-ingesting an op we already have succeeds without changes. The caller
+/// notifies the projector.
+pub async fn ingest_envelope(core: &GardensCore, bytes: &[u8]) -> Result<IngestedOp, IngestError> {
+    let env = decode_cbor::<GossipEnvelope>(bytes).map_err(|e| IngestError::Malformed(e.to_string()))?;
+    let (header, body) = validate_op(&env)?;
+    let op_hash = header.hash();
+
+    let mut store = core.op_store.lock().await;
+    match check_log_chain(&store, &header, &env.log_id).await {
+        Ok(true) => return Ok(IngestedOp { author: header.public_key, log_id: env.log_id, op_hash }),
+        Ok(false) => {}
+        Err(e) => {
+            if let Some(evidence) = fork_evidence(&e, &header, &env.header_bytes, &env.log_id) {
+                db::insert_fork_evidence(&core.read_pool, &evidence).await?;
+            }
+            return Err(e.into_error(&header, &env.log_id));
+        }
+    }
+    store
+        .insert_operation(op_hash, &header, Some(&body), &env.header_bytes, &env.log_id)
+        .await
+        .map_err(|e| IngestError::Store(e.to_string()))?;
+
+    Ok(IngestedOp { author: header.public_key, log_id: env.log_id, op_hash })
+}
+
+/// Checks that need nothing but the op itself.
+pub fn validate_op(env: &GossipEnvelope) -> Result<(Header<()>, Body), IngestError> {
+    if env.header_bytes.len() > MAX_HEADER_BYTES {
+        return Err(IngestError::TooLarge {
+            what: "header",
+            size: env.header_bytes.len(),
+            max: MAX_HEADER_BYTES,
+        });
+    }
+    if env.body_bytes.len() > MAX_BODY_BYTES {
+        return Err(IngestError::TooLarge {
+            what: "body",
+            size: env.body_bytes.len(),
+            max: MAX_BODY_BYTES,
+        });
+    }
+    if !log_ids::ALL.contains(&env.log_id.as_str()) {
+        return Err(IngestError::UnknownLog(env.log_id.clone()));
+    }
+
+    let header = Header::<()>::try_from(env.header_bytes.as_slice())
+        .map_err(|e| IngestError::Malformed(e.to_string()))?;
+    if header.version != 1 {
+        return Err(IngestError::Malformed(format!("unsupported header version {}", header.version)));
+    }
+    if !header.verify() {
+        return Err(IngestError::InvalidSignature);
+    }
+
+    let body = Body::new(&env.body_bytes);
+    if header.payload_size != body.size() {
+        return Err(IngestError::PayloadMismatch("payload_size"));
+    }
+    if header.payload_hash != Some(body.hash()) {
+        return Err(IngestError::PayloadMismatch("payload_hash"));
+    }
+
+    Ok((header, body))
+}
+
+/// How an op conflicts with the author's log as we have it.
+enum ChainConflict {
+    /// A different op already sits at this seq_num.
+    SameSeq { existing: Hash },
+    /// The op's backlink names a different predecessor than ours.
+    Backlink { existing: Hash, claimed: Hash },
+    /// The op we hold at the next seq_num names a different predecessor.
+    Successor { claimed: Hash },
+    Store(String),
+}
+
+impl ChainConflict {
+    fn into_error(self, header: &Header<()>, log_id: &str) -> IngestError {
+        match self {
+            ChainConflict::SameSeq { .. } | ChainConflict::Successor { .. } => IngestError::Fork {
+                author: header.public_key.to_hex(),
+                log_id: log_id.to_string(),
+                seq_num: header.seq_num,
+            },
+            ChainConflict::Backlink { .. } => IngestError::Fork {
+                author: header.public_key.to_hex(),
+                log_id: log_id.to_string(),
+                seq_num: header.seq_num - 1,
+            },
+            ChainConflict::Store(e) => IngestError::Store(e),
+        }
+    }
+}
+
+/// Compare the op against what we already hold for its seq_num and the ones
+/// either side of it. Gaps are fine: sync and gossip deliver out of order.
+/// Returns true if we already hold it.
+async fn check_log_chain(
+    store: &GardensStore,
+    header: &Header<()>,
+    log_id: &str,
+) -> Result<bool, ChainConflict> {
+    let seq = header.seq_num;
+    let hashes = store
+        .get_log_hashes(&header.public_key, &log_id.to_string(), Some(seq.saturating_sub(1)))
+        .await
+        .map_err(|e| ChainConflict::Store(e.to_string()))?
+        .unwrap_or_default();
+
+    let hash = header.hash();
+    let mut held = false;
+    for (stored_seq, stored) in hashes.into_iter().take_while(|(s, _)| *s <= seq + 1) {
+        if stored_seq == seq {
+            if stored != hash {
+                return Err(ChainConflict::SameSeq { existing: stored });
+            }
+            held = true;
+        }
+        if let Some(backlink) = header.backlink {
+            if stored_seq + 1 == seq && stored != backlink {
+                return Err(ChainConflict::Backlink { existing: stored, claimed: backlink });
+            }
+        }
+        if stored_seq == seq + 1 {
+            let successor = store
+                .get_operation(stored)
+                .await
+                .map_err(|e| ChainConflict::Store(e.to_string()))?;
+            if let Some(claimed) = successor.and_then(|(h, _)| h.backlink) {
+                if claimed != hash {
+                    return Err(ChainConflict::Successor { claimed });
+                }
+            }
+        }
+    }
+    Ok(held)
+}
+
+fn fork_evidence(
+    conflict: &ChainConflict,
+    header: &Header<()>,
+    header_bytes: &[u8],
+    log_id: &str,
+) -> Option<ForkEvidenceRow> {
+    let (seq_num, existing, conflicting) = match conflict {
+        ChainConflict::SameSeq { existing } => (header.seq_num, *existing, header.hash()),
+        ChainConflict::Backlink { existing, claimed } => (header.seq_num - 1, *existing, *claimed),
+        ChainConflict::Successor { claimed } => (header.seq_num, *claimed, header.hash()),
+        ChainConflict::Store(_) => return None,
+    };
+    Some(ForkEvidenceRow {
+        author_key: header.public_key.to_hex(),
+        log_id: log_id.to_string(),
+        seq_num: seq_num as i64,
+        existing_hash: existing.to_hex(),
+        conflicting_hash: conflicting.to_hex(),
+        signed_header: header_bytes.to_vec(),
+        detected_at: crate::now_micros(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p2panda_core::PrivateKey;
+
+    fn signed_op(key: &PrivateKey, seq_num: u64, backlink: Option<Hash>, body: &[u8]) -> GossipEnvelope {
+        let body = Body::new(body);
+        let mut header: Header<()> = Header {
+            version: 1,
+            public_key: key.public_key(),
+            signature: None,
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            timestamp: 1,
+            seq_num,
+            backlink,
+            previous: vec![],
+            extensions: (),
+        };
+        header.sign(key);
+        GossipEnvelope {
+            log_id: log_ids::MESSAGE.to_string(),
+            header_bytes: header.to_bytes(),
+            body_bytes: body.to_bytes(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_well_formed_op() {
+        let key = PrivateKey::new();
+        let env = signed_op(&key, 0, None, b"hello");
+        let (header, _) = validate_op(&env).unwrap();
+        assert_eq!(header.public_key, key.public_key());
+    }
+
+    #[test]
+    fn rejects_forged_and_tampered_ops() {
+        let key = PrivateKey::new();
+
+        // Body swapped after signing.
+        let mut env = signed_op(&key, 0, None, b"hello");
+        env.body_bytes = b"jello".to_vec();
+        assert!(matches!(validate_op(&env), Err(IngestError::PayloadMismatch("payload_hash"))));
+
+        // Header re-attributed to another author.
+        let mut header = Header::<()>::try_from(signed_op(&key, 0, None, b"hi").header_bytes.as_slice()).unwrap();
+        header.public_key = PrivateKey::new().public_key();
+        let env = GossipEnvelope {
+            log_id: log_ids::MESSAGE.to_string(),
+            header_bytes: header.to_bytes(),
+            body_bytes: b"hi".to_vec(),
+        };
+        assert!(matches!(validate_op(&env), Err(IngestError::InvalidSignature)));
+    }
+
+    #[test]
+    fn rejects_oversized_unknown_and_unchained_ops() {
+        let key = PrivateKey::new();
+
+        let big = vec![0u8; MAX_BODY_BYTES + 1];
+        assert!(matches!(
+            validate_op(&signed_op(&key, 0, None, &big)),
+            Err(IngestError::TooLarge { what: "body", .. })
+        ));
+
+        let mut env = signed_op(&key, 0, None, b"hi");
+        env.log_id = "nonsense".into();
+        assert!(matches!(validate_op(&env), Err(IngestError::UnknownLog(_))));
+
+        // The header codec already refuses a backlink that doesn't match
+        // the seq_num, so these never get as far as the chain check.
+        assert!(matches!(
+            validate_op(&signed_op(&key, 3, None, b"hi")),
+            Err(IngestError::Malformed(_))
+        ));
+        assert!(matches!(
+            validate_op(&signed_op(&key, 0, Some(Hash::new(b"prev")), b"hi")),
+            Err(IngestError::Malformed(_))
+        ));
+    }
+
+    fn hash_of(env: &GossipEnvelope) -> Hash {
+        Header::<()>::try_from(env.header_bytes.as_slice()).unwrap().hash()
+    }
+
+    #[tokio::test]
+    async fn two_ops_at_one_seq_are_a_fork() {
+        let core = crate::store::test_core().await;
+        let key = PrivateKey::new();
+        let first = signed_op(&key, 0, None, b"first");
+        let second = signed_op(&key, 0, None, b"second");
+
+        ingest_envelope(&core, &crate::ops::encode_cbor(&first).unwrap()).await.unwrap();
+        let result = ingest_envelope(&core, &crate::ops::encode_cbor(&second).unwrap()).await;
+        assert!(matches!(result, Err(IngestError::Fork { seq_num: 0, .. })));
+        // Re-ingesting what we hold is fine.
+        ingest_envelope(&core, &crate::ops::encode_cbor(&first).unwrap()).await.unwrap();
+
+        let evidence = db::list_fork_evidence(&core.read_pool, &key.public_key().to_hex()).await.unwrap();
+        assert_eq!(evidence.len(), 1);
+        assert_eq!(evidence[0].existing_hash, hash_of(&first).to_hex());
+        assert_eq!(evidence[0].conflicting_hash, hash_of(&second).to_hex());
+    }
+
+    #[tokio::test]
+    async fn an_op_its_held_successor_does_not_link_to_is_a_fork() {
+        let core = crate::store::test_core().await;
+        let key = PrivateKey::new();
+        let first = signed_op(&key, 0, None, b"first");
+        let next = signed_op(&key, 1, Some(hash_of(&first)), b"next");
+        let rival = signed_op(&key, 0, None, b"rival");
+
+        // The successor arrives first, then a seq 0 it doesn't link to.
+        ingest_envelope(&core, &crate::ops::encode_cbor(&next).unwrap()).await.unwrap();
+        let result = ingest_envelope(&core, &crate::ops::encode_cbor(&rival).unwrap()).await;
+        assert!(matches!(result, Err(IngestError::Fork { seq_num: 0, .. })));
+        ingest_envelope(&core, &crate::ops::encode_cbor(&first).unwrap()).await.unwrap();
+
+        let evidence = db::list_fork_evidence(&core.read_pool, &key.public_key().to_hex()).await.unwrap();
+        assert_eq!(evidence.len(), 1);
+        assert_eq!(evidence[0].existing_hash, hash_of(&first).to_hex());
+        assert_eq!(evidence[0].conflicting_hash, hash_of(&rival).to_hex());
+    }
+}
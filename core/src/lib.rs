@@ -1,26 +1,61 @@
 uniffi::include_scaffolding!("gardens_core");
 
 pub mod auth;
+pub mod blob_gc;
 pub mod blobs;
 pub mod crypto;
 pub mod db;
 pub mod encryption;
+pub mod events;
+pub mod ingest;
 pub mod keys;
+pub mod media;
 pub mod network;
 pub mod ops;
+pub mod peer_sync;
 pub mod pkarr_publish;
 pub mod projector;
+pub mod relay_directory;
+pub mod replay;
 pub mod sealed_sender;
 pub mod store;
 pub mod onion;
+pub mod cover;
+pub mod outbox;
 pub mod sync;
 pub mod sync_config;
 
 // ── Phase 1 re-exports (UniFFI uses these) ────────────────────────────────────
 pub use keys::{generate_keypair, import_from_mnemonic, KeyError, KeyPair};
 
+// ── Change events ─────────────────────────────────────────────────────────────
+pub use events::{GardensEvent, GardensEventListener};
+
+/// Register the listener that receives read-model change events.
+/// Replaces any previously registered listener.
+pub fn set_event_listener(listener: Box<dyn GardensEventListener>) {
+    events::set_listener(listener);
+}
+
+/// Stop delivering change events.
+pub fn clear_event_listener() {
+    events::clear_listener();
+}
+
+/// Drop the read model and re-project it from the op store, e.g. after a
+/// projection bug. Blocks until done; progress is delivered to the event
+/// listener as `GardensEvent::RebuildProgress`.
+pub fn rebuild_read_model() -> Result<(), CoreError> {
+    store::block_on(async move {
+        let core = store::get_core().ok_or(CoreError::NotInitialised)?;
+        projector::rebuild_read_model(&core.read_pool)
+            .await
+            .map_err(|e| CoreError::DbError(e.to_string()))
+    })
+}
+
 // ── Phase 7 re-exports ────────────────────────────────────────────────────────
-pub use blobs::{provide_blob, BlobError};
+pub use blobs::{provide_blob, BlobError, BlobProgressListener};
 
 /// Upload a blob and return its content-hash (hex).
 pub fn upload_blob(data: Vec<u8>, mime_type: String, room_id: Option<String>) -> Result<String, BlobError> {
@@ -29,13 +64,152 @@ pub fn upload_blob(data: Vec<u8>, mime_type: String, room_id: Option<String>) ->
     })
 }
 
-/// Get blob data with optional room decryption.
+/// Get blob data, opened with its content key if one is known; `room_id`
+/// is only needed for blobs uploaded before per-blob keys.
 pub fn get_blob(hash_str: String, room_id: Option<String>) -> Result<Vec<u8>, BlobError> {
     store::block_on(async move {
         blobs::get_blob(&hash_str, room_id).await
     })
 }
 
+/// Import a file into the blob store without passing its bytes over FFI.
+pub fn import_blob_from_path(
+    path: String,
+    mime_type: String,
+    room_id: Option<String>,
+    progress: Option<Box<dyn BlobProgressListener>>,
+) -> Result<String, BlobError> {
+    store::block_on(async move {
+        blobs::import_blob_from_path(std::path::Path::new(&path), mime_type, room_id, progress).await
+    })
+}
+
+/// Write a blob, decrypted, to `dest_path`.
+pub fn export_blob_to_path(
+    hash_str: String,
+    room_id: Option<String>,
+    dest_path: String,
+    progress: Option<Box<dyn BlobProgressListener>>,
+) -> Result<(), BlobError> {
+    store::block_on(async move {
+        blobs::export_blob_to_path(&hash_str, room_id, std::path::Path::new(&dest_path), progress).await
+    })
+}
+
+/// Read part of a decrypted blob, e.g. for a media player.
+pub fn read_blob_range(hash_str: String, room_id: Option<String>, offset: u64, length: u64) -> Result<Vec<u8>, BlobError> {
+    store::block_on(async move {
+        blobs::read_blob_range(&hash_str, room_id, offset, length).await
+    })
+}
+
+/// Size of a decrypted blob in bytes.
+pub fn get_blob_size(hash_str: String, room_id: Option<String>) -> Result<u64, BlobError> {
+    store::block_on(async move {
+        blobs::get_blob_size(&hash_str, room_id).await
+    })
+}
+
+/// Placeholder for an image blob: its dimensions, blurhash and thumbnail.
+pub struct BlobPreview {
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    pub thumbnail_hash: Option<String>,
+}
+
+/// The preview recorded for an image blob, if any. Fetch the thumbnail with
+/// `get_blob` like any other blob.
+pub fn get_blob_preview(hash_str: String) -> Result<Option<BlobPreview>, CoreError> {
+    store::block_on(async move {
+        let core = store::get_core().ok_or(CoreError::NotInitialised)?;
+        let Some(meta) = db::get_blob_meta(&core.read_pool, &hash_str).await? else {
+            return Ok(None);
+        };
+        Ok(match (meta.width, meta.height, meta.blurhash) {
+            (Some(width), Some(height), Some(blurhash)) => Some(BlobPreview {
+                width,
+                height,
+                blurhash,
+                thumbnail_hash: meta.thumbnail_hash,
+            }),
+            _ => None,
+        })
+    })
+}
+
+/// Bytes of blobs kept locally, overall and for one org or room.
+pub struct StorageUsage {
+    pub total_bytes: u64,
+    pub own_bytes: u64,
+    pub orgs: Vec<OrgStorageUsage>,
+    pub rooms: Vec<RoomStorageUsage>,
+}
+
+pub struct OrgStorageUsage {
+    pub org_id: String,
+    pub bytes: u64,
+}
+
+pub struct RoomStorageUsage {
+    pub org_id: String,
+    pub room_id: String,
+    pub bytes: u64,
+}
+
+/// How much of the local blob store each org and room accounts for.
+pub fn get_storage_usage() -> Result<StorageUsage, CoreError> {
+    store::block_on(async move {
+        let core = store::get_core().ok_or(CoreError::NotInitialised)?;
+        let usage = db::blob_storage_usage(&core.read_pool).await?;
+        Ok(StorageUsage {
+            total_bytes: usage.total_bytes.max(0) as u64,
+            own_bytes: usage.own_bytes.max(0) as u64,
+            orgs: usage
+                .orgs
+                .into_iter()
+                .map(|(org_id, bytes)| OrgStorageUsage { org_id, bytes: bytes.max(0) as u64 })
+                .collect(),
+            rooms: usage
+                .rooms
+                .into_iter()
+                .map(|(org_id, room_id, bytes)| RoomStorageUsage { org_id, room_id, bytes: bytes.max(0) as u64 })
+                .collect(),
+        })
+    })
+}
+
+/// Cap the blobs cached for an org, or for the whole store when `org_id` is
+/// None. Blobs we uploaded are never evicted. `max_bytes` None lifts the cap.
+pub fn set_blob_quota(org_id: Option<String>, max_bytes: Option<u64>) -> Result<(), CoreError> {
+    store::block_on(async move {
+        let core = store::get_core().ok_or(CoreError::NotInitialised)?;
+        blob_gc::set_quota(&core.read_pool, org_id.as_deref(), max_bytes).await?;
+        Ok(())
+    })
+}
+
+/// Run a garbage collection pass now rather than waiting for the next one.
+/// Returns how many blobs were removed or evicted.
+pub fn collect_blob_garbage() -> Result<u32, BlobError> {
+    store::block_on(async move {
+        let core = store::get_core().ok_or(BlobError::NotInitialized)?;
+        let report = blob_gc::collect(&core.read_pool).await?;
+        Ok((report.unreferenced + report.evicted) as u32)
+    })
+}
+
+/// Let go of a blob we uploaded but won't send, e.g. a draft attachment the
+/// user removed. Unless a message references it, it is collected soon after
+/// instead of being kept for the long grace own uploads get.
+pub fn discard_blob(hash_str: String) -> Result<(), CoreError> {
+    store::block_on(async move {
+        let core = store::get_core().ok_or(CoreError::NotInitialised)?;
+        blob_gc::discard(&core.read_pool, &hash_str).await?;
+        Ok(())
+    })
+}
+
 /// Check if we have a blob locally (for P2P availability checks).
 pub fn has_blob(hash_str: String) -> Result<bool, BlobError> {
     store::block_on(async move {
@@ -46,7 +220,11 @@ pub fn has_blob(hash_str: String) -> Result<bool, BlobError> {
 /// Request a blob from a specific peer via P2P.
 pub fn request_blob_from_peer(hash_str: String, peer_node_id: String) -> Result<Option<Vec<u8>>, BlobError> {
     store::block_on(async move {
-        blobs::request_blob_from_peer(&hash_str, &peer_node_id).await?;
+        match blobs::request_blob_from_peer(&hash_str, &peer_node_id).await {
+            Ok(()) => {}
+            Err(blobs::BlobError::NotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        }
         // After requesting, try to get the blob
         match blobs::get_blob(&hash_str, None).await {
             Ok(data) => Ok(Some(data)),
@@ -56,10 +234,10 @@ pub fn request_blob_from_peer(hash_str: String, peer_node_id: String) -> Result<
     })
 }
 
-/// Provide (send) a blob to a peer who requested it.
-pub fn provide_blob_to_peer(hash_str: String, _peer_public_key: String) -> Result<(), BlobError> {
+/// Offer a blob to a peer allowed to see it; the peer pulls it if missing.
+pub fn provide_blob_to_peer(hash_str: String, peer_public_key: String) -> Result<(), BlobError> {
     store::block_on(async move {
-        blobs::provide_blob(&hash_str).await
+        blobs::provide_blob(&hash_str, &peer_public_key).await
     })
 }
 
@@ -102,8 +280,17 @@ pub fn build_onion_packet(
     let mut tid = [0u8; 32];
     tid.copy_from_slice(&topic_id);
 
-    let onion_hops: Result<Vec<onion::OnionHop>, OnionError> = hops
-        .into_iter()
+    // No hops: pick a fresh path from the relay directory.
+    let onion_hops = if hops.is_empty() && !sync_config::get_directory().is_empty() {
+        relay_directory::select_path(&sync_config::get_directory(), relay_directory::DEFAULT_PATH_LEN)?
+    } else {
+        onion_hops_from_ffi(hops)?
+    };
+    onion::build_onion_packet(&onion_hops, &tid, &op)
+}
+
+fn onion_hops_from_ffi(hops: Vec<OnionHopFfi>) -> Result<Vec<onion::OnionHop>, OnionError> {
+    hops.into_iter()
         .map(|h| {
             let pk_bytes = hex::decode(&h.pubkey_hex)
                 .map_err(|e| OnionError::InvalidKey(e.to_string()))?;
@@ -116,9 +303,7 @@ pub fn build_onion_packet(
             pk.copy_from_slice(&pk_bytes);
             Ok(onion::OnionHop { pubkey_bytes: pk, next_url: h.next_url })
         })
-        .collect();
-
-    onion::build_onion_packet(&onion_hops?, &tid, &op)
+        .collect()
 }
 
 pub fn peel_onion_layer(
@@ -133,7 +318,12 @@ pub fn peel_onion_layer(
     let mut seed = [0u8; 32];
     seed.copy_from_slice(&seed_bytes);
 
-    match onion::decrypt_layer(&packet, &seed)? {
+    // With a read model, reply blocks stay single-use across restarts
+    let payload = match store::get_core() {
+        Some(core) => store::block_on(onion::peel_layer(&core.read_pool, &packet, &seed))?,
+        None => onion::decrypt_layer(&packet, &seed)?,
+    };
+    match payload {
         onion::OnionPayload::Forward { next_hop_url, inner_packet } => Ok(OnionPeeled {
             peel_type: "forward".to_string(),
             next_hop_url: Some(next_hop_url),
@@ -148,9 +338,65 @@ pub fn peel_onion_layer(
             topic_id: Some(topic_id.to_vec()),
             op: Some(op),
         }),
+        onion::OnionPayload::Drop => Ok(OnionPeeled {
+            peel_type: "drop".to_string(),
+            next_hop_url: None,
+            inner_packet: None,
+            topic_id: None,
+            op: None,
+        }),
+        // decrypt_layer resolves reply-block layers into Forward / Deliver.
+        onion::OnionPayload::ReplyHop { .. } | onion::OnionPayload::ReplyExit { .. } => {
+            Err(OnionError::InvalidPayload)
+        }
     }
 }
 
+/// A reply block and the secret that opens replies sent with it.
+pub struct OnionReplyBlock {
+    /// Give this to the replier (e.g. inside a support/modmail message).
+    pub block: Vec<u8>,
+    /// Keep this; it is needed to open the reply and is single-use.
+    pub secret: Vec<u8>,
+}
+
+/// A sealed reply, ready to post to `next_hop_url`.
+pub struct OnionReply {
+    pub next_hop_url: String,
+    pub packet: Vec<u8>,
+}
+
+/// Build a reply block routed through `hops` that delivers to `topic_id`.
+pub fn build_onion_reply_block(
+    hops: Vec<OnionHopFfi>,
+    topic_id: Vec<u8>,
+) -> Result<OnionReplyBlock, OnionError> {
+    let tid: [u8; 32] = topic_id
+        .as_slice()
+        .try_into()
+        .map_err(|_| OnionError::InvalidKey("topic_id must be 32 bytes".to_string()))?;
+    let (block, secret) = onion::build_reply_block(&onion_hops_from_ffi(hops)?, &tid)?;
+    Ok(OnionReplyBlock { block: block.to_bytes(), secret: secret.to_bytes() })
+}
+
+/// Seal `op` as a reply along someone else's reply block.
+pub fn seal_onion_reply(block: Vec<u8>, op: Vec<u8>) -> Result<OnionReply, OnionError> {
+    let block = onion::ReplyBlock::from_bytes(&block)?;
+    let packet = block.seal_reply(&op)?;
+    Ok(OnionReply { next_hop_url: block.first_hop_url, packet })
+}
+
+/// Open a reply delivered to the block's topic, using the kept secret.
+pub fn open_onion_reply(secret: Vec<u8>, delivered: Vec<u8>) -> Result<Vec<u8>, OnionError> {
+    onion::open_reply(&onion::ReplySecret::from_bytes(&secret)?, &delivered)
+}
+
+/// Send cover traffic: dummy onion packets through the configured relay hops
+/// at an average of `packets_per_minute`, on a Poisson schedule. Zero stops it.
+pub fn set_cover_traffic_rate(packets_per_minute: f64) {
+    cover::set_rate(packets_per_minute);
+}
+
 // ── Sync Configuration ───────────────────────────────────────────────────────
 
 /// FFI-friendly hop descriptor for sync configuration.
@@ -182,6 +428,23 @@ pub fn get_sync_url() -> String {
     sync_config::get_url()
 }
 
+/// Load the relay directory published under `z32_key` via pkarr. Onion
+/// packets the core builds then take a random path through its relays.
+/// Returns the number of relays listed.
+pub fn refresh_relay_directory(z32_key: String) -> Result<u32, CoreError> {
+    store::block_on(async move {
+        let count = relay_directory::refresh_relay_directory(&z32_key)
+            .await
+            .map_err(CoreError::InvalidInput)?;
+        if let Some(core) = store::get_core() {
+            if let Err(e) = relay_directory::cache_relay_directory(&core.read_pool, &z32_key).await {
+                log::warn!("[relays] failed to cache directory {}: {}", z32_key, e);
+            }
+        }
+        Ok(count as u32)
+    })
+}
+
 // ── Sync ──────────────────────────────────────────────────────────────────────
 
 #[derive(Debug, thiserror::Error)]
@@ -214,7 +477,7 @@ use p2panda_encryption::key_manager::KeyManager;
 use p2panda_encryption::traits::PreKeyManager;
 use regex::Regex;
 
-use db::{DmThreadRow, MessageRow, OrgRow, ProfileRow, RoomRow, EventRow, EventRsvpRow};
+use db::{DmThreadRow, MessageEditRow, MessageRow, OrgRow, ProfileRow, RoomRow, EventRow, EventRsvpRow};
 use sqlx::Row;
 
 /// Regex pattern for valid sluggified channel names:
@@ -288,6 +551,11 @@ impl From<ops::OpsError> for CoreError {
         CoreError::OpsError(e.to_string())
     }
 }
+impl From<encryption::EncryptionError> for CoreError {
+    fn from(e: encryption::EncryptionError) -> Self {
+        CoreError::OpsError(format!("encryption: {e}"))
+    }
+}
 
 pub enum ConnectionStatus {
     Online,
@@ -324,7 +592,7 @@ pub struct OrgSummary {
     pub created_at: i64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RoomType {
     Text,
     Voice,
@@ -381,6 +649,20 @@ pub struct Message {
     pub is_deleted: bool,
 }
 
+pub struct MessageSearchResult {
+    pub message: Message,
+    pub snippet: String,
+}
+
+pub struct MessageEdit {
+    pub edit_id: String,
+    pub message_id: String,
+    pub author_key: String,
+    pub text_content: Option<String>,
+    pub mentions: Vec<String>,
+    pub edited_at: i64,
+}
+
 pub struct Reaction {
     pub message_id: String,
     pub emoji: String,
@@ -493,6 +775,17 @@ fn message_from_row(row: MessageRow) -> Message {
     }
 }
 
+fn message_edit_from_row(row: MessageEditRow) -> MessageEdit {
+    MessageEdit {
+        edit_id: row.edit_id,
+        message_id: row.message_id,
+        author_key: row.author_key,
+        text_content: row.text_content,
+        mentions: row.mentions,
+        edited_at: row.edited_at,
+    }
+}
+
 fn dm_from_row(row: DmThreadRow) -> DmThread {
     DmThread {
         thread_id: row.thread_id,
@@ -570,6 +863,11 @@ pub fn create_or_update_profile(
             bytes
         };
 
+        // Top up one-time pre-keys so peers can open forward-secure DM threads with us.
+        if let Err(e) = top_up_onetime_key_bundles(core).await {
+            log::warn!("[encryption] failed to publish one-time key bundles: {}", e);
+        }
+
         let now = now_micros();
         let existing = db::get_profile(pool, &core.public_key_hex).await?;
         let created_at = existing.as_ref().map(|p| p.created_at).unwrap_or(now);
@@ -622,6 +920,29 @@ pub fn create_or_update_profile(
     })
 }
 
+/// Publish one-time pre-key bundles on the KEY_BUNDLE log until
+/// [`encryption::ONE_TIME_BUNDLE_TARGET`] of ours are unused again.
+pub(crate) async fn top_up_onetime_key_bundles(core: &store::GardensCore) -> Result<(), CoreError> {
+    // Held throughout so concurrent top-ups don't both see the same shortfall.
+    let mut op_store = core.op_store.lock().await;
+    let missing = encryption::onetime_bundles_missing(&core.read_pool).await?;
+    if missing == 0 {
+        return Ok(());
+    }
+    let bundles = encryption::generate_onetime_bundles_with_pool(missing, &core.read_pool).await?;
+    for bundle_data in bundles {
+        ops::publish(
+            &mut op_store,
+            &core.private_key,
+            ops::log_ids::KEY_BUNDLE,
+            &ops::KeyBundleOp { bundle_type: "one_time".into(), bundle_data },
+        )
+        .await?;
+        db::note_onetime_prekeys_published(&core.read_pool, 1).await?;
+    }
+    Ok(())
+}
+
 pub fn get_my_profile() -> Option<Profile> {
     store::block_on(async move {
         let core = store::get_core()?;
@@ -687,7 +1008,7 @@ pub fn create_org(
                 },
             )
             .await?.0;
-            let room_hash = ops::publish(
+            let room_hash = ops::publish_with_previous(
                 &mut op_store,
                 &core.private_key,
                 ops::log_ids::ROOM,
@@ -698,6 +1019,7 @@ pub fn create_org(
                     enc_key_epoch: 0,
                     room_type: "text".into(),
                 },
+                vec![org_hash],
             )
             .await?.0;
             (org_hash.to_hex(), room_hash.to_hex())
@@ -787,12 +1109,10 @@ pub fn list_my_orgs() -> Vec<OrgSummary> {
             .unwrap_or_default();
 
         // Backfill missing org_pubkey so clients resolve the correct pkarr URL.
-        let user_signing_key = match ed25519_dalek::SigningKey::from_bytes(
+        let user_signing_key = ed25519_dalek::SigningKey::from_bytes(
             &hex::decode(core.private_key.to_hex()).unwrap_or_default()
                 .try_into().unwrap_or([0u8; 32])
-        ) {
-            key => key,
-        };
+        );
 
         for row in rows.iter_mut() {
             if row.org_pubkey.is_none() {
@@ -826,7 +1146,7 @@ pub fn create_room(org_id: String, name: String, room_type: RoomType) -> Result<
 
         let op_hash = {
             let mut op_store = core.op_store.lock().await;
-            ops::publish(
+            ops::publish_with_previous(
                 &mut op_store,
                 &core.private_key,
                 ops::log_ids::ROOM,
@@ -840,6 +1160,7 @@ pub fn create_room(org_id: String, name: String, room_type: RoomType) -> Result<
                         RoomType::Voice => "voice".into(),
                     },
                 },
+                ops::previous_from_ids([&org_id]),
             )
             .await?.0
         };
@@ -895,7 +1216,7 @@ pub fn create_room(org_id: String, name: String, room_type: RoomType) -> Result<
 
         // Join gossip topic for this room
         if network::is_initialized().await {
-            if let Ok((topic_id, peers)) = room_gossip_context(&core, &room_id).await {
+            if let Ok((topic_id, peers)) = room_gossip_context(core, &room_id).await {
                 let _ = network::gossip_join(topic_id, network::GossipTopicKind::Room, peers).await;
             }
         }
@@ -939,7 +1260,7 @@ pub fn delete_room(org_id: String, room_id: String) -> Result<(), CoreError> {
         {
             let mut store_guard = core.op_store.lock().await;
             ops::sign_and_store_op(
-                &mut *store_guard,
+                &mut store_guard,
                 &core.private_key,
                 ops::log_ids::ROOM,
                 payload,
@@ -992,7 +1313,7 @@ pub fn archive_room(org_id: String, room_id: String) -> Result<(), CoreError> {
         {
             let mut store_guard = core.op_store.lock().await;
             ops::sign_and_store_op(
-                &mut *store_guard,
+                &mut store_guard,
                 &core.private_key,
                 ops::log_ids::ROOM,
                 payload,
@@ -1039,6 +1360,7 @@ pub fn unarchive_room(org_id: String, room_id: String) -> Result<(), CoreError>
 
 /// Update an organization. Requires Manage-level permission.
 /// Signs the operation with the org's key, not the user's key.
+#[allow(clippy::too_many_arguments)]
 pub fn update_org(
     org_id: String,
     name: Option<String>,
@@ -1092,7 +1414,8 @@ pub fn update_org(
         let org_private_key = get_org_private_key(&encrypted_key, &user_signing_key)
             .ok_or_else(|| CoreError::InvalidInput("failed to decrypt org key".into()))?;
 
-        // Publish update operation signed with ORG's key
+        // Publish update operation signed with our own key; peers authorize it
+        // against our Manage membership. The org key only signs pkarr records.
         let update_op = ops::OrgUpdateOp {
             op_type: "update_org".into(),
             org_id: org_id.clone(),
@@ -1113,8 +1436,8 @@ pub fn update_org(
         let gossip_bytes = {
             let mut store_guard = core.op_store.lock().await;
             let (_op_hash, gossip_bytes) = ops::sign_and_store_op(
-                &mut *store_guard,
-                &org_private_key,  // Sign with org's key, not user's key
+                &mut store_guard,
+                &core.private_key,
                 ops::log_ids::ORG,
                 payload,
             )
@@ -1149,24 +1472,23 @@ pub fn update_org(
         }
 
         // Publish to pkarr if public
-        if is_public == Some(true) || (is_public.is_none() && org_row.is_public != 0) {
-            if org_pubkey_z32.is_some() {
-                let pk_hex = hex::encode(org_private_key.as_bytes());
-                let org_name = name.as_deref().unwrap_or(&org_row.name);
-                let org_desc = description.as_deref().or(org_row.description.as_deref());
+        let publish_pkarr = is_public == Some(true) || (is_public.is_none() && org_row.is_public != 0);
+        if publish_pkarr && org_pubkey_z32.is_some() {
+            let pk_hex = hex::encode(org_private_key.as_bytes());
+            let org_name = name.as_deref().unwrap_or(&org_row.name);
+            let org_desc = description.as_deref().or(org_row.description.as_deref());
 
-                let relay_z32_for_publish = sync_config::get_relay_z32();
-                if let Err(e) = pkarr_publish::publish_org_with_key(
-                    &pk_hex,
-                    org_name,
-                    org_desc,
-                    avatar_blob_id.as_deref(),
-                    cover_blob_id.as_deref(),
-                    relay_z32_for_publish.as_deref(),
-                    email_enabled.unwrap_or(false),
-                ).await {
-                    log::error!("[pkarr] failed to publish org update: {}", e);
-                }
+            let relay_z32_for_publish = sync_config::get_relay_z32();
+            if let Err(e) = pkarr_publish::publish_org_with_key(
+                &pk_hex,
+                org_name,
+                org_desc,
+                avatar_blob_id.as_deref(),
+                cover_blob_id.as_deref(),
+                relay_z32_for_publish.as_deref(),
+                email_enabled.unwrap_or(false),
+            ).await {
+                log::error!("[pkarr] failed to publish org update: {}", e);
             }
         }
 
@@ -1189,23 +1511,11 @@ pub fn delete_org(org_id: String) -> Result<(), CoreError> {
             return Err(CoreError::InvalidInput("only Manage-level members can delete organizations".into()));
         }
 
-        // Get the org's encrypted private key from database
         let org_row = db::get_org(pool, &org_id).await?
             .ok_or_else(|| CoreError::InvalidInput("organization not found".into()))?;
-        
-        let encrypted_key = org_row.org_privkey_enc
-            .ok_or_else(|| CoreError::InvalidInput("org key not available".into()))?;
-        
-        // Decrypt the org's private key
-        let user_signing_key = ed25519_dalek::SigningKey::from_bytes(
-            &hex::decode(core.private_key.to_hex()).map_err(|e| CoreError::InvalidInput(e.to_string()))?
-                .try_into().map_err(|_| CoreError::InvalidInput("invalid key length".into()))?
-        );
-        
-        let org_private_key = get_org_private_key(&encrypted_key, &user_signing_key)
-            .ok_or_else(|| CoreError::InvalidInput("failed to decrypt org key".into()))?;
 
-        // Publish delete operation signed with ORG's key
+        // Publish delete operation signed with our own key; peers authorize it
+        // against our Manage membership.
         let delete_op = ops::OrgUpdateOp {
             op_type: "delete_org".into(),
             org_id: org_id.clone(),
@@ -1226,8 +1536,8 @@ pub fn delete_org(org_id: String) -> Result<(), CoreError> {
         {
             let mut store_guard = core.op_store.lock().await;
             ops::sign_and_store_op(
-                &mut *store_guard,
-                &org_private_key,
+                &mut store_guard,
+                &core.private_key,
                 ops::log_ids::ORG,
                 payload,
             )
@@ -1423,7 +1733,7 @@ pub fn update_room(
         {
             let mut store_guard = core.op_store.lock().await;
             ops::sign_and_store_op(
-                &mut *store_guard,
+                &mut store_guard,
                 &core.private_key,
                 ops::log_ids::ROOM,
                 payload,
@@ -1456,6 +1766,7 @@ pub fn list_rooms(org_id: String, include_archived: bool) -> Vec<Room> {
 
 // ── Events ───────────────────────────────────────────────────────────────────
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_event(
     org_id: String,
     title: String,
@@ -1517,6 +1828,7 @@ pub fn create_event(
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_event(
     org_id: String,
     event_id: String,
@@ -1551,7 +1863,7 @@ pub fn update_event(
         let gossip_bytes = {
             let mut store_guard = core.op_store.lock().await;
             let (_op_hash, gossip_bytes) = ops::sign_and_store_op(
-                &mut *store_guard,
+                &mut store_guard,
                 &core.private_key,
                 ops::log_ids::EVENT,
                 payload,
@@ -1595,7 +1907,7 @@ pub fn delete_event(org_id: String, event_id: String) -> Result<Vec<u8>, CoreErr
         let gossip_bytes = {
             let mut store_guard = core.op_store.lock().await;
             let (_op_hash, gossip_bytes) = ops::sign_and_store_op(
-                &mut *store_guard,
+                &mut store_guard,
                 &core.private_key,
                 ops::log_ids::EVENT,
                 payload,
@@ -1643,7 +1955,7 @@ pub fn set_event_rsvp(event_id: String, status: String) -> Result<Vec<u8>, CoreE
         let gossip_bytes = {
             let mut store_guard = core.op_store.lock().await;
             let (_op_hash, gossip_bytes) = ops::sign_and_store_op(
-                &mut *store_guard,
+                &mut store_guard,
                 &core.private_key,
                 ops::log_ids::EVENT_RSVP,
                 payload,
@@ -1675,7 +1987,7 @@ pub fn clear_event_rsvp(event_id: String) -> Result<Vec<u8>, CoreError> {
         let gossip_bytes = {
             let mut store_guard = core.op_store.lock().await;
             let (_op_hash, gossip_bytes) = ops::sign_and_store_op(
-                &mut *store_guard,
+                &mut store_guard,
                 &core.private_key,
                 ops::log_ids::EVENT_RSVP,
                 payload,
@@ -1707,6 +2019,62 @@ pub fn list_event_rsvps(event_id: String) -> Vec<EventRsvp> {
 
 // ── Messages ──────────────────────────────────────────────────────────────────
 
+/// Sign and store a message-log payload (`MessageOp` / `ReactionOp`).
+///
+/// Room payloads are encrypted under the room's group state and published as
+/// an [`ops::EncryptedRoomOp`]; DM payloads go through the thread's
+/// forward-secure group as an [`ops::EncryptedDmOp`]. A payload with neither
+/// is refused rather than published in the clear. `previous` links the ops
+/// this one refers to.
+async fn publish_message_op<T: serde::Serialize>(
+    core: &store::GardensCore,
+    log_id: &str,
+    room_id: Option<&str>,
+    dm_thread_id: Option<&str>,
+    previous: Vec<p2panda_core::Hash>,
+    payload: &T,
+) -> Result<(p2panda_core::Hash, Vec<u8>), CoreError> {
+    let body_bytes = match (room_id, dm_thread_id) {
+        (Some(rid), _) => {
+            let plaintext = ops::encode_cbor(payload)?;
+            let encrypted = encryption::encrypt_for_room(rid, &plaintext).await?;
+            ops::encode_cbor(&ops::EncryptedRoomOp {
+                op_type: "encrypted".into(),
+                room_id: rid.to_string(),
+                encrypted,
+            })?
+        }
+        (None, Some(tid)) => {
+            ensure_dm_group(core, tid).await?;
+            let plaintext = ops::encode_cbor(payload)?;
+            let encrypted = encryption::encrypt_for_dm(tid, &plaintext).await?;
+            ops::encode_cbor(&ops::EncryptedDmOp {
+                op_type: "encrypted_dm".into(),
+                dm_thread_id: tid.to_string(),
+                encrypted,
+            })?
+        }
+        (None, None) => return Err(CoreError::InvalidInput("room_id or dm_thread_id required".into())),
+    };
+    let mut op_store = core.op_store.lock().await;
+    Ok(ops::sign_and_store_op_with_previous(&mut op_store, &core.private_key, log_id, body_bytes, previous).await?)
+}
+
+/// Queue a published op for delivery. Failures are logged: the op is already
+/// stored and the sync paths will still carry it.
+async fn queue_outbound(
+    core: &store::GardensCore,
+    op_hash: p2panda_core::Hash,
+    log_id: &str,
+    dest: outbox::Destination<'_>,
+    envelope: &[u8],
+) {
+    if let Err(e) = outbox::enqueue(core, op_hash, log_id, dest, envelope).await {
+        log::warn!("[outbox] failed to queue {}: {}", op_hash.to_hex(), e);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn send_message(
     room_id: Option<String>,
     dm_thread_id: Option<String>,
@@ -1780,27 +2148,50 @@ pub fn send_message(
             }
         }
 
-        let (op_hash, gossip_bytes) = {
-            let mut op_store = core.op_store.lock().await;
-            ops::publish(
-                &mut op_store,
-                &core.private_key,
-                ops::log_ids::MESSAGE,
-                &ops::MessageOp {
-                    op_type: "send".into(),
-                    room_id: room_id.clone(),
-                    dm_thread_id: dm_thread_id.clone(),
-                    content_type: content_type.clone(),
-                    text_content: text_content.clone(),
-                    blob_id: blob_id.clone(),
-                    embed_url: embed_url.clone(),
-                    mentions: mentions.clone(),
-                    reply_to: reply_to.clone(),
-                },
-            )
-            .await?
+        // Attachments sealed by upload_blob carry their content key and
+        // image preview in the op
+        let blob_meta = match blob_id.as_deref() {
+            Some(hash) => db::get_blob_meta(pool, hash).await?,
+            None => None,
+        };
+        // DM attachments are always sealed: upload them with the thread id
+        if dm_thread_id.is_some()
+            && blob_id.is_some()
+            && blob_meta.as_ref().is_none_or(|m| m.secret_id.is_none())
+        {
+            return Err(CoreError::InvalidInput(
+                "DM attachments must be uploaded with the thread id so they are sealed".into(),
+            ));
+        }
+        let preview = match &blob_meta {
+            Some(meta) => blobs::image_preview(pool, meta).await?,
+            None => None,
         };
 
+        let (op_hash, gossip_bytes) = publish_message_op(
+            core,
+            ops::log_ids::MESSAGE,
+            room_id.as_deref(),
+            dm_thread_id.as_deref(),
+            ops::previous_from_ids(room_id.as_deref()),
+            &ops::MessageOp {
+                op_type: "send".into(),
+                room_id: room_id.clone(),
+                dm_thread_id: dm_thread_id.clone(),
+                content_type: content_type.clone(),
+                text_content: text_content.clone(),
+                blob_id: blob_id.clone(),
+                blob_key: blob_meta.as_ref().and_then(|m| m.secret_id.clone()),
+                blob_nonce: blob_meta.and_then(|m| m.nonce),
+                preview,
+                embed_url: embed_url.clone(),
+                mentions: mentions.clone(),
+                reply_to: reply_to.clone(),
+                target_id: None,
+            },
+        )
+        .await?;
+
         let message_id = op_hash.to_hex();
 
         db::insert_message(
@@ -1823,57 +2214,9 @@ pub fn send_message(
         )
         .await?;
 
-        // Gossip via Iroh (room topic or DM inbox)
-        if network::is_initialized().await {
-            if let Some(room) = &room_id {
-                if let Ok((topic_id, bootstrap)) = room_gossip_context(&core, room).await {
-                    if let Err(e) = network::gossip_publish(
-                        topic_id,
-                        network::GossipTopicKind::Room,
-                        bootstrap,
-                        gossip_bytes.clone(),
-                    )
-                    .await
-                    {
-                        log::warn!("[gossip] failed to publish room message: {}", e);
-                    }
-                }
-            } else if let Some(thread_id) = &dm_thread_id {
-                if let Ok((topic_id, bootstrap, recipient_hex)) =
-                    dm_gossip_context(&core, thread_id).await
-                {
-                    let sender_pk = core.private_key.public_key();
-                    let recipient_bytes = match hex_to_bytes_32(&recipient_hex) {
-                        Ok(b) => b,
-                        Err(e) => {
-                            log::warn!("[gossip] invalid recipient key: {}", e);
-                            return Ok(SendResult { id: message_id, op_bytes: gossip_bytes });
-                        }
-                    };
-                    let sealed = match sealed_sender::seal(
-                        &gossip_bytes,
-                        sender_pk.as_bytes(),
-                        &recipient_bytes,
-                    ) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            log::warn!("[gossip] failed to seal dm message: {}", e);
-                            return Ok(SendResult { id: message_id, op_bytes: gossip_bytes });
-                        }
-                    };
-
-                    if let Err(e) = network::gossip_publish(
-                        topic_id,
-                        network::GossipTopicKind::DmInbox,
-                        bootstrap,
-                        sealed,
-                    )
-                    .await
-                    {
-                        log::warn!("[gossip] failed to publish dm message: {}", e);
-                    }
-                }
-            }
+        // Queue delivery to the room topic or DM inbox (see `outbox`)
+        if let Some(dest) = outbox::Destination::for_message(room_id.as_deref(), dm_thread_id.as_deref()) {
+            queue_outbound(core, op_hash, ops::log_ids::MESSAGE, dest, &gossip_bytes).await;
         }
 
         Ok(SendResult { id: message_id, op_bytes: gossip_bytes })
@@ -1906,84 +2249,71 @@ pub fn list_messages(
     })
 }
 
+/// Full-text search over messages, best match first. Optional filters narrow
+/// the search to a room, DM thread, author, or messages before a timestamp.
+pub fn search_messages(
+    query: String,
+    room_id: Option<String>,
+    dm_thread_id: Option<String>,
+    author_key: Option<String>,
+    before: Option<i64>,
+    limit: u32,
+) -> Vec<MessageSearchResult> {
+    store::block_on(async move {
+        let Some(core) = store::get_core() else {
+            return vec![];
+        };
+        db::search_messages(
+            &core.read_pool,
+            &query,
+            room_id.as_deref(),
+            dm_thread_id.as_deref(),
+            author_key.as_deref(),
+            before,
+            limit,
+        )
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(row, snippet)| MessageSearchResult { message: message_from_row(row), snippet })
+        .collect()
+    })
+}
+
 pub fn add_reaction(message_id: String, emoji: String) -> Result<SendResult, CoreError> {
     store::block_on(async move {
         let core = store::get_core().ok_or(CoreError::NotInitialised)?;
         let pool = &core.read_pool;
 
-        let (op_hash, gossip_bytes) = {
-            let mut op_store = core.op_store.lock().await;
-            ops::publish(
-                &mut op_store,
-                &core.private_key,
-                ops::log_ids::REACTION,
-                &ops::ReactionOp {
-                    op_type: "add_reaction".into(),
-                    message_id: message_id.clone(),
-                    emoji: emoji.clone(),
-                },
-            )
-            .await?
-        };
+        // The reaction is encrypted under the reacted-to message's room or DM thread.
+        let row = sqlx::query("SELECT room_id, dm_thread_id FROM messages WHERE message_id = ?")
+            .bind(&message_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| CoreError::DbError(e.to_string()))?
+            .ok_or_else(|| CoreError::InvalidInput("message not found".into()))?;
+        let msg_room_id: Option<String> = row.get("room_id");
+        let msg_dm_thread_id: Option<String> = row.get("dm_thread_id");
+
+        let (op_hash, gossip_bytes) = publish_message_op(
+            core,
+            ops::log_ids::REACTION,
+            msg_room_id.as_deref(),
+            msg_dm_thread_id.as_deref(),
+            ops::previous_from_ids([&message_id]),
+            &ops::ReactionOp {
+                op_type: "add_reaction".into(),
+                message_id: message_id.clone(),
+                emoji: emoji.clone(),
+            },
+        )
+        .await?;
 
         db::upsert_reaction(pool, &message_id, &emoji, &core.public_key_hex).await?;
 
-        // Gossip via Iroh (room topic or DM inbox)
-        if network::is_initialized().await {
-            let row = sqlx::query("SELECT room_id, dm_thread_id FROM messages WHERE message_id = ?")
-                .bind(&message_id)
-                .fetch_optional(pool)
-                .await
-                .map_err(|e| CoreError::DbError(e.to_string()))?;
-            if let Some(r) = row {
-                let room_id: Option<String> = r.get("room_id");
-                let dm_thread_id: Option<String> = r.get("dm_thread_id");
-                if let Some(room) = room_id {
-                    if let Ok((topic_id, bootstrap)) = room_gossip_context(&core, &room).await {
-                        if let Err(e) = network::gossip_publish(
-                            topic_id,
-                            network::GossipTopicKind::Room,
-                            bootstrap,
-                            gossip_bytes.clone(),
-                        )
-                        .await
-                        {
-                            log::warn!("[gossip] failed to publish reaction: {}", e);
-                        }
-                    }
-                } else if let Some(thread_id) = dm_thread_id {
-                    if let Ok((topic_id, bootstrap, recipient_hex)) =
-                        dm_gossip_context(&core, &thread_id).await
-                    {
-                        let sender_pk = core.private_key.public_key();
-                        let recipient_bytes = match hex_to_bytes_32(&recipient_hex) {
-                            Ok(b) => b,
-                            Err(e) => {
-                                log::warn!("[gossip] invalid recipient key: {}", e);
-                                return Ok(SendResult { id: op_hash.to_hex(), op_bytes: gossip_bytes });
-                            }
-                        };
-                        let sealed = match sealed_sender::seal(
-                            &gossip_bytes,
-                            sender_pk.as_bytes(),
-                            &recipient_bytes,
-                        ) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                log::warn!("[gossip] failed to seal dm reaction: {}", e);
-                                return Ok(SendResult { id: op_hash.to_hex(), op_bytes: gossip_bytes });
-                            }
-                        };
-                        let _ = network::gossip_publish(
-                            topic_id,
-                            network::GossipTopicKind::DmInbox,
-                            bootstrap,
-                            sealed,
-                        )
-                        .await;
-                    }
-                }
-            }
+        // Queue delivery to the room topic or DM inbox (see `outbox`)
+        if let Some(dest) = outbox::Destination::for_message(msg_room_id.as_deref(), msg_dm_thread_id.as_deref()) {
+            queue_outbound(core, op_hash, ops::log_ids::REACTION, dest, &gossip_bytes).await;
         }
 
         Ok(SendResult { id: op_hash.to_hex(), op_bytes: gossip_bytes })
@@ -1995,76 +2325,35 @@ pub fn remove_reaction(message_id: String, emoji: String) -> Result<SendResult,
         let core = store::get_core().ok_or(CoreError::NotInitialised)?;
         let pool = &core.read_pool;
 
-        let (op_hash, gossip_bytes) = {
-            let mut op_store = core.op_store.lock().await;
-            ops::publish(
-                &mut op_store,
-                &core.private_key,
-                ops::log_ids::REACTION,
-                &ops::ReactionOp {
-                    op_type: "remove_reaction".into(),
-                    message_id: message_id.clone(),
-                    emoji: emoji.clone(),
-                },
-            )
-            .await?
-        };
+        // The reaction is encrypted under the reacted-to message's room or DM thread.
+        let row = sqlx::query("SELECT room_id, dm_thread_id FROM messages WHERE message_id = ?")
+            .bind(&message_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| CoreError::DbError(e.to_string()))?
+            .ok_or_else(|| CoreError::InvalidInput("message not found".into()))?;
+        let msg_room_id: Option<String> = row.get("room_id");
+        let msg_dm_thread_id: Option<String> = row.get("dm_thread_id");
+
+        let (op_hash, gossip_bytes) = publish_message_op(
+            core,
+            ops::log_ids::REACTION,
+            msg_room_id.as_deref(),
+            msg_dm_thread_id.as_deref(),
+            ops::previous_from_ids([&message_id]),
+            &ops::ReactionOp {
+                op_type: "remove_reaction".into(),
+                message_id: message_id.clone(),
+                emoji: emoji.clone(),
+            },
+        )
+        .await?;
 
         db::delete_reaction(pool, &message_id, &emoji, &core.public_key_hex).await?;
 
-        // Gossip via Iroh (room topic or DM inbox)
-        if network::is_initialized().await {
-            let row = sqlx::query("SELECT room_id, dm_thread_id FROM messages WHERE message_id = ?")
-                .bind(&message_id)
-                .fetch_optional(pool)
-                .await
-                .map_err(|e| CoreError::DbError(e.to_string()))?;
-            if let Some(r) = row {
-                let room_id: Option<String> = r.get("room_id");
-                let dm_thread_id: Option<String> = r.get("dm_thread_id");
-                if let Some(room) = room_id {
-                    if let Ok((topic_id, bootstrap)) = room_gossip_context(&core, &room).await {
-                        let _ = network::gossip_publish(
-                            topic_id,
-                            network::GossipTopicKind::Room,
-                            bootstrap,
-                            gossip_bytes.clone(),
-                        )
-                        .await;
-                    }
-                } else if let Some(thread_id) = dm_thread_id {
-                    if let Ok((topic_id, bootstrap, recipient_hex)) =
-                        dm_gossip_context(&core, &thread_id).await
-                    {
-                        let sender_pk = core.private_key.public_key();
-                        let recipient_bytes = match hex_to_bytes_32(&recipient_hex) {
-                            Ok(b) => b,
-                            Err(e) => {
-                                log::warn!("[gossip] invalid recipient key: {}", e);
-                                return Ok(SendResult { id: op_hash.to_hex(), op_bytes: gossip_bytes });
-                            }
-                        };
-                        let sealed = match sealed_sender::seal(
-                            &gossip_bytes,
-                            sender_pk.as_bytes(),
-                            &recipient_bytes,
-                        ) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                log::warn!("[gossip] failed to seal dm reaction: {}", e);
-                                return Ok(SendResult { id: op_hash.to_hex(), op_bytes: gossip_bytes });
-                            }
-                        };
-                        let _ = network::gossip_publish(
-                            topic_id,
-                            network::GossipTopicKind::DmInbox,
-                            bootstrap,
-                            sealed,
-                        )
-                        .await;
-                    }
-                }
-            }
+        // Queue delivery to the room topic or DM inbox (see `outbox`)
+        if let Some(dest) = outbox::Destination::for_message(msg_room_id.as_deref(), msg_dm_thread_id.as_deref()) {
+            queue_outbound(core, op_hash, ops::log_ids::REACTION, dest, &gossip_bytes).await;
         }
 
         Ok(SendResult { id: op_hash.to_hex(), op_bytes: gossip_bytes })
@@ -2149,26 +2438,29 @@ pub fn delete_message(
         }
 
         // Create delete operation
-        let (op_hash, gossip_bytes) = {
-            let mut op_store = core.op_store.lock().await;
-            ops::publish(
-                &mut op_store,
-                &core.private_key,
-                ops::log_ids::MESSAGE,
-                &ops::MessageOp {
-                    op_type: "delete".into(),
-                    room_id: msg_room_id.clone(),
-                    dm_thread_id: msg_dm_thread_id.clone(),
-                    content_type: "text".into(),
-                    text_content: None,
-                    blob_id: None,
-                    embed_url: None,
-                    mentions: vec![],
-                    reply_to: None,
-                },
-            )
-            .await?
-        };
+        let (op_hash, gossip_bytes) = publish_message_op(
+            core,
+            ops::log_ids::MESSAGE,
+            msg_room_id.as_deref(),
+            msg_dm_thread_id.as_deref(),
+            ops::previous_from_ids([&message_id]),
+            &ops::MessageOp {
+                op_type: "delete".into(),
+                room_id: msg_room_id.clone(),
+                dm_thread_id: msg_dm_thread_id.clone(),
+                content_type: "text".into(),
+                text_content: None,
+                blob_id: None,
+                blob_key: None,
+                blob_nonce: None,
+                preview: None,
+                embed_url: None,
+                mentions: vec![],
+                reply_to: None,
+                target_id: Some(message_id.clone()),
+            },
+        )
+        .await?;
 
         // Mark message as deleted in database
         sqlx::query("UPDATE messages SET is_deleted = 1 WHERE message_id = ?")
@@ -2177,55 +2469,107 @@ pub fn delete_message(
             .await
             .map_err(|e| CoreError::DbError(e.to_string()))?;
 
-        // Gossip the delete operation
-        if network::is_initialized().await {
-            if let Some(room) = &msg_room_id {
-                if let Ok((topic_id, bootstrap)) = room_gossip_context(&core, room).await {
-                    let _ = network::gossip_publish(
-                        topic_id,
-                        network::GossipTopicKind::Room,
-                        bootstrap,
-                        gossip_bytes.clone(),
-                    )
-                    .await;
-                }
-            } else if let Some(thread_id) = &msg_dm_thread_id {
-                if let Ok((topic_id, bootstrap, recipient_hex)) =
-                    dm_gossip_context(&core, thread_id).await
-                {
-                    let sender_pk = core.private_key.public_key();
-                    let recipient_bytes = match hex_to_bytes_32(&recipient_hex) {
-                        Ok(b) => b,
-                        Err(_) => {
-                            return Ok(SendResult { id: message_id, op_bytes: gossip_bytes });
-                        }
-                    };
-                    let sealed = match sealed_sender::seal(
-                        &gossip_bytes,
-                        sender_pk.as_bytes(),
-                        &recipient_bytes,
-                    ) {
-                        Ok(v) => v,
-                        Err(_) => {
-                            return Ok(SendResult { id: message_id, op_bytes: gossip_bytes });
-                        }
-                    };
-
-                    let _ = network::gossip_publish(
-                        topic_id,
-                        network::GossipTopicKind::DmInbox,
-                        bootstrap,
-                        sealed,
-                    )
-                    .await;
-                }
-            }
+        // Queue delivery to the room topic or DM inbox (see `outbox`)
+        if let Some(dest) = outbox::Destination::for_message(msg_room_id.as_deref(), msg_dm_thread_id.as_deref()) {
+            queue_outbound(core, op_hash, ops::log_ids::MESSAGE, dest, &gossip_bytes).await;
         }
 
         Ok(SendResult { id: message_id, op_bytes: gossip_bytes })
     })
 }
 
+/// Edit the text of one of the user's own messages. The edit op references
+/// the original message; the replaced version is kept in the edit history.
+pub fn edit_message(
+    message_id: String,
+    new_text: String,
+    new_mentions: Vec<String>,
+) -> Result<SendResult, CoreError> {
+    store::block_on(async move {
+        let core = store::get_core().ok_or(CoreError::NotInitialised)?;
+        let pool = &core.read_pool;
+
+        let row = sqlx::query(
+            "SELECT room_id, dm_thread_id, author_key, content_type, is_deleted FROM messages WHERE message_id = ?"
+        )
+        .bind(&message_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| CoreError::DbError(e.to_string()))?
+        .ok_or_else(|| CoreError::InvalidInput("message not found".into()))?;
+
+        let msg_room_id: Option<String> = row.get("room_id");
+        let msg_dm_thread_id: Option<String> = row.get("dm_thread_id");
+        let author_key: String = row.get("author_key");
+        let content_type: String = row.get("content_type");
+        if author_key != core.public_key_hex {
+            return Err(CoreError::InvalidInput("only the message author can edit messages".into()));
+        }
+        if row.get::<i64, _>("is_deleted") != 0 {
+            return Err(CoreError::InvalidInput("message is deleted".into()));
+        }
+
+        let (op_hash, gossip_bytes) = publish_message_op(
+            core,
+            ops::log_ids::MESSAGE,
+            msg_room_id.as_deref(),
+            msg_dm_thread_id.as_deref(),
+            ops::previous_from_ids([&message_id]),
+            &ops::MessageOp {
+                op_type: "edit".into(),
+                room_id: msg_room_id.clone(),
+                dm_thread_id: msg_dm_thread_id.clone(),
+                content_type,
+                text_content: Some(new_text.clone()),
+                blob_id: None,
+                blob_key: None,
+                blob_nonce: None,
+                preview: None,
+                embed_url: None,
+                mentions: new_mentions.clone(),
+                reply_to: None,
+                target_id: Some(message_id.clone()),
+            },
+        )
+        .await?;
+
+        // Stamp the edit with the op's own timestamp, as every other peer will.
+        let edited_at = ops::decode_cbor::<ops::GossipEnvelope>(&gossip_bytes)?.timestamp()?;
+        db::apply_message_edit(
+            pool,
+            &op_hash.to_hex(),
+            &message_id,
+            &core.public_key_hex,
+            Some(&new_text),
+            &new_mentions,
+            edited_at,
+        )
+        .await?;
+
+        // Queue delivery to the room topic or DM inbox (see `outbox`)
+        if let Some(dest) = outbox::Destination::for_message(msg_room_id.as_deref(), msg_dm_thread_id.as_deref()) {
+            queue_outbound(core, op_hash, ops::log_ids::MESSAGE, dest, &gossip_bytes).await;
+        }
+
+        Ok(SendResult { id: op_hash.to_hex(), op_bytes: gossip_bytes })
+    })
+}
+
+/// Prior versions of a message, oldest first.
+pub fn list_message_edits(message_id: String) -> Vec<MessageEdit> {
+    store::block_on(async move {
+        let Some(core) = store::get_core() else {
+            return vec![];
+        };
+        db::list_message_edits(&core.read_pool, &message_id)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(message_edit_from_row)
+            .collect()
+    })
+}
+
 // ── DM Threads ────────────────────────────────────────────────────────────────
 
 pub fn create_dm_thread(recipient_key: String) -> Result<SendResult, CoreError> {
@@ -2263,41 +2607,40 @@ pub fn create_dm_thread(recipient_key: String) -> Result<SendResult, CoreError>
         )
         .await?;
 
-        // Gossip DM thread creation to recipient inbox
-        if network::is_initialized().await {
-            if let Ok(topic_id) = topic_id_from_hex(&recipient_key) {
-                let mut bootstrap = vec![];
-                if let Ok(peer) = endpoint_id_from_hex(&recipient_key) {
-                    bootstrap.push(peer);
-                }
-                if let Ok(peer) = endpoint_id_from_hex(&core.public_key_hex) {
-                    bootstrap.push(peer);
-                }
+        // Queue delivery to the recipient's inbox (see `outbox`)
+        queue_outbound(core, op_hash, ops::log_ids::DM_THREAD, outbox::Destination::Inbox(&recipient_key), &gossip_bytes).await;
 
-                let sender_pk = core.private_key.public_key();
-                if let Ok(recipient_bytes) = hex_to_bytes_32(&recipient_key) {
-                    if let Ok(sealed) =
-                        sealed_sender::seal(&gossip_bytes, sender_pk.as_bytes(), &recipient_bytes)
-                    {
-                        if let Err(e) = network::gossip_publish(
-                            topic_id,
-                            network::GossipTopicKind::DmInbox,
-                            bootstrap,
-                            sealed,
-                        )
-                        .await
-                        {
-                            log::warn!("[gossip] failed to publish dm thread: {}", e);
-                        }
-                    }
-                }
-            }
+        // Set up the forward-secure group now if the recipient's pre-keys are
+        // known; otherwise the first send_message retries.
+        if let Err(e) = ensure_dm_group(core, &thread_id).await {
+            log::warn!("[encryption] dm group for {} not ready: {}", thread_id, e);
         }
 
         Ok(SendResult { id: thread_id, op_bytes: gossip_bytes })
     })
 }
 
+/// Make sure a DM thread has forward-secure group state.
+///
+/// Only the thread initiator creates the group (publishing the welcome as an
+/// ENC_CTRL op); the recipient's state is created when that welcome is projected.
+async fn ensure_dm_group(core: &store::GardensCore, thread_id: &str) -> Result<(), CoreError> {
+    if encryption::has_dm_group(&core.read_pool, thread_id).await? {
+        return Ok(());
+    }
+    let thread = db::get_dm_thread(&core.read_pool, thread_id)
+        .await?
+        .ok_or_else(|| CoreError::InvalidInput("dm thread not found".into()))?;
+    if thread.initiator_key != core.public_key_hex {
+        return Err(CoreError::InvalidInput("dm key exchange pending".into()));
+    }
+    let recipient = p2panda_core::PublicKey::from_bytes(&hex_to_bytes_32(&thread.recipient_key)?)
+        .map_err(|e| CoreError::InvalidInput(format!("invalid recipient key: {e}")))?;
+    let ctrl_bytes = encryption::init_dm_group(thread_id, recipient).await?;
+    encryption::publish_enc_ctrl_op(thread_id, ctrl_bytes).await?;
+    Ok(())
+}
+
 pub fn list_dm_threads() -> Vec<DmThread> {
     store::block_on(async move {
         let core = match store::get_core() {
@@ -2326,7 +2669,7 @@ pub struct OrgAdminThread {
     pub is_request: bool,
 }
 
-pub fn create_org_admin_thread(org_id: String, admin_key: String) -> Result<SendResult, CoreError> {
+pub fn create_org_admin_thread(_org_id: String, admin_key: String) -> Result<SendResult, CoreError> {
     store::block_on(async move {
         let core = store::get_core().ok_or(CoreError::NotInitialised)?;
         let pool = &core.read_pool;
@@ -2378,37 +2721,8 @@ pub fn create_org_admin_thread(org_id: String, admin_key: String) -> Result<Send
         )
         .await?;
 
-        // Gossip DM thread creation to admin inbox with p2panda encryption
-        if network::is_initialized().await {
-            if let Ok(topic_id) = topic_id_from_hex(&admin_key) {
-                let mut bootstrap = vec![];
-                if let Ok(peer) = endpoint_id_from_hex(&admin_key) {
-                    bootstrap.push(peer);
-                }
-                if let Ok(peer) = endpoint_id_from_hex(&core.public_key_hex) {
-                    bootstrap.push(peer);
-                }
-
-                let sender_pk = core.private_key.public_key();
-                if let Ok(recipient_bytes) = hex_to_bytes_32(&admin_key) {
-                    // Use p2panda sealed sender for encryption
-                    if let Ok(sealed) =
-                        sealed_sender::seal(&gossip_bytes, sender_pk.as_bytes(), &recipient_bytes)
-                    {
-                        if let Err(e) = network::gossip_publish(
-                            topic_id,
-                            network::GossipTopicKind::DmInbox,
-                            bootstrap,
-                            sealed,
-                        )
-                        .await
-                        {
-                            log::warn!("[gossip] failed to publish org admin thread: {}", e);
-                        }
-                    }
-                }
-            }
-        }
+        // Queue delivery to the recipient's inbox (see `outbox`)
+        queue_outbound(core, op_hash, ops::log_ids::DM_THREAD, outbox::Destination::Inbox(&admin_key), &gossip_bytes).await;
 
         Ok(SendResult { id: thread_id, op_bytes: gossip_bytes })
     })
@@ -2472,7 +2786,7 @@ pub fn join_public_org(org_id: String, org_pubkey_z32: String, _join_sig_b64: St
 
         let mut store_guard = core.op_store.lock().await;
         let (_op_hash, gossip_bytes) = ops::sign_and_store_op(
-            &mut *store_guard,
+            &mut store_guard,
             &core.private_key,
             ops::log_ids::MEMBERSHIP,
             payload,
@@ -2564,7 +2878,7 @@ pub fn delete_conversation(thread_id: String) -> Result<SendResult, CoreError> {
         let core = store::get_core().ok_or(CoreError::NotInitialised)?;
         let pool = &core.read_pool;
 
-        let (op_hash, gossip_bytes) = {
+        let (_op_hash, gossip_bytes) = {
             let mut op_store = core.op_store.lock().await;
             ops::publish(
                 &mut op_store,
@@ -2750,9 +3064,99 @@ fn endpoint_id_from_hex(hex_str: &str) -> Result<iroh::EndpointId, CoreError> {
 
 // ── Phase 3: Network ──────────────────────────────────────────────────────────
 
+/// How recent a sync ingest must be to count as being online on its own.
+const SYNC_ONLINE_WINDOW_MICROS: i64 = 5 * 60 * 1_000_000;
+
+/// Per-topic gossip state in [`ConnectionDiagnostics`].
+pub struct TopicDiagnostics {
+    pub topic_id: String,
+    pub kind: String,
+    pub neighbor_count: u32,
+}
+
+/// Detail behind [`get_connection_status`], for debugging delivery problems.
+pub struct ConnectionDiagnostics {
+    pub status: ConnectionStatus,
+    pub network_initialized: bool,
+    pub endpoint_bound: bool,
+    pub node_id: Option<String>,
+    pub home_relay: Option<String>,
+    pub topics: Vec<TopicDiagnostics>,
+    pub direct_peers: u32,
+    pub relayed_peers: u32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+    pub last_sync_ingest_at: Option<i64>,
+}
+
+/// Online when we can reach peers — a relay, a gossip neighbour, or a recent
+/// sync ingest; Connecting while the endpoint is up but reaches nobody.
+fn derive_connection_status(
+    net: Option<&network::NetworkSnapshot>,
+    last_sync_ingest_at: Option<i64>,
+    now: i64,
+) -> ConnectionStatus {
+    let recent_sync = last_sync_ingest_at.is_some_and(|at| now - at < SYNC_ONLINE_WINDOW_MICROS);
+    match net {
+        Some(n) if n.bound && (n.home_relay.is_some() || n.topics.iter().any(|t| t.2 > 0)) => {
+            ConnectionStatus::Online
+        }
+        _ if recent_sync => ConnectionStatus::Online,
+        Some(n) if n.bound => ConnectionStatus::Connecting,
+        _ => ConnectionStatus::Offline,
+    }
+}
+
 pub fn get_connection_status() -> ConnectionStatus {
-    // Network status is managed by the RN sync layer
-    ConnectionStatus::Online
+    store::block_on(async move {
+        let net = network::snapshot().await;
+        derive_connection_status(net.as_ref(), sync::last_ingest_at(), now_micros())
+    })
+}
+
+pub fn get_connection_diagnostics() -> ConnectionDiagnostics {
+    store::block_on(async move {
+        let net = network::snapshot().await;
+        let last_sync_ingest_at = sync::last_ingest_at();
+        let status = derive_connection_status(net.as_ref(), last_sync_ingest_at, now_micros());
+        let Some(net) = net else {
+            return ConnectionDiagnostics {
+                status,
+                network_initialized: false,
+                endpoint_bound: false,
+                node_id: None,
+                home_relay: None,
+                topics: vec![],
+                direct_peers: 0,
+                relayed_peers: 0,
+                last_error: None,
+                last_error_at: None,
+                last_sync_ingest_at,
+            };
+        };
+        let (last_error, last_error_at) = net.last_error.map_or((None, None), |(e, at)| (Some(e), Some(at)));
+        ConnectionDiagnostics {
+            status,
+            network_initialized: true,
+            endpoint_bound: net.bound,
+            node_id: Some(net.node_id),
+            home_relay: net.home_relay,
+            topics: net
+                .topics
+                .into_iter()
+                .map(|(topic_id, kind, neighbors)| TopicDiagnostics {
+                    topic_id: hex::encode(topic_id),
+                    kind: format!("{:?}", kind),
+                    neighbor_count: neighbors as u32,
+                })
+                .collect(),
+            direct_peers: net.direct_peers as u32,
+            relayed_peers: net.relayed_peers as u32,
+            last_error,
+            last_error_at,
+            last_sync_ingest_at,
+        }
+    })
 }
 
 pub fn search_public_orgs(_query: String) -> Vec<OrgSummary> {
@@ -2950,7 +3354,7 @@ pub fn add_member_direct(
 
         let mut store_guard = core.op_store.lock().await;
         let (_hash, gossip_bytes) = ops::sign_and_store_op(
-            &mut *store_guard,
+            &mut store_guard,
             &core.private_key,
             ops::log_ids::MEMBERSHIP,
             payload,
@@ -3011,7 +3415,7 @@ pub fn claim_invite_token(token_base64: String) -> Result<SendResult, AuthError>
 
         let mut store_guard = core.op_store.lock().await;
         let (_op_hash, gossip_bytes) = ops::sign_and_store_op(
-            &mut *store_guard,
+            &mut store_guard,
             &core.private_key,
             ops::log_ids::MEMBERSHIP,
             payload,
@@ -3142,7 +3546,7 @@ pub fn claim_one_time_invite_code(code: String) -> Result<SendResult, AuthError>
 
         let mut store_guard = core.op_store.lock().await;
         let (_op_hash, gossip_bytes) = ops::sign_and_store_op(
-            &mut *store_guard,
+            &mut store_guard,
             &core.private_key,
             ops::log_ids::MEMBERSHIP,
             payload,
@@ -3205,7 +3609,7 @@ pub fn remove_member_from_org(
             .map_err(|e| AuthError::Unauthorized(e.to_string()))?;
         let mut store_guard = core.op_store.lock().await;
         let (_hash, gossip_bytes) = ops::sign_and_store_op(
-            &mut *store_guard, &core.private_key, ops::log_ids::MEMBERSHIP, payload,
+            &mut store_guard, &core.private_key, ops::log_ids::MEMBERSHIP, payload,
         ).await.map_err(|e| AuthError::Unauthorized(e.to_string()))?;
         Ok(SendResult { id: org_id, op_bytes: gossip_bytes })
     })
@@ -3270,7 +3674,7 @@ pub fn change_member_permission(
 
         let mut store_guard = core.op_store.lock().await;
         ops::sign_and_store_op(
-            &mut *store_guard,
+            &mut store_guard,
             &core.private_key,
             ops::log_ids::MEMBERSHIP,
             payload,
@@ -3340,7 +3744,7 @@ pub fn set_user_cooldown(
             .map_err(|e| AuthError::Unauthorized(e.to_string()))?;
         let mut store_guard = core.op_store.lock().await;
         ops::sign_and_store_op(
-            &mut *store_guard,
+            &mut store_guard,
             &core.private_key,
             ops::log_ids::MEMBERSHIP,
             payload,
@@ -3384,7 +3788,7 @@ pub fn ice_member(
             .map_err(|e| AuthError::Unauthorized(e.to_string()))?;
         let mut store_guard = core.op_store.lock().await;
         ops::sign_and_store_op(
-            &mut *store_guard,
+            &mut store_guard,
             &core.private_key,
             ops::log_ids::MEMBERSHIP,
             payload,
@@ -3422,7 +3826,7 @@ pub fn unice_member(org_id: String, member_public_key: String) -> Result<(), Aut
             .map_err(|e| AuthError::Unauthorized(e.to_string()))?;
         let mut store_guard = core.op_store.lock().await;
         ops::sign_and_store_op(
-            &mut *store_guard,
+            &mut store_guard,
             &core.private_key,
             ops::log_ids::MEMBERSHIP,
             payload,
@@ -3551,7 +3955,7 @@ pub fn transfer_org_ownership(
         {
             let mut store_guard = core.op_store.lock().await;
             ops::sign_and_store_op(
-                &mut *store_guard,
+                &mut store_guard,
                 &org_private_key,  // Sign with org's key
                 ops::log_ids::ORG,
                 payload,
@@ -3702,6 +4106,49 @@ mod email_tests {
     }
 }
 
+#[cfg(test)]
+mod connection_status_tests {
+    use super::*;
+
+    fn snapshot(bound: bool, home_relay: bool, neighbors: usize) -> network::NetworkSnapshot {
+        network::NetworkSnapshot {
+            node_id: "node".into(),
+            bound,
+            home_relay: home_relay.then(|| "https://relay.example.com".to_string()),
+            topics: vec![([0u8; 32], network::GossipTopicKind::Room, neighbors)],
+            direct_peers: 0,
+            relayed_peers: 0,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn status_follows_reachability() {
+        let now = 10 * SYNC_ONLINE_WINDOW_MICROS;
+        let stale = Some(now - SYNC_ONLINE_WINDOW_MICROS - 1);
+        let fresh = Some(now - 1);
+
+        assert!(matches!(derive_connection_status(None, None, now), ConnectionStatus::Offline));
+        assert!(matches!(derive_connection_status(None, fresh, now), ConnectionStatus::Online));
+        assert!(matches!(
+            derive_connection_status(Some(&snapshot(true, false, 0)), stale, now),
+            ConnectionStatus::Connecting
+        ));
+        assert!(matches!(
+            derive_connection_status(Some(&snapshot(true, false, 2)), None, now),
+            ConnectionStatus::Online
+        ));
+        assert!(matches!(
+            derive_connection_status(Some(&snapshot(true, true, 0)), None, now),
+            ConnectionStatus::Online
+        ));
+        assert!(matches!(
+            derive_connection_status(Some(&snapshot(false, true, 2)), None, now),
+            ConnectionStatus::Offline
+        ));
+    }
+}
+
 // ── Helper: Encrypt/decrypt org private keys ─────────────────────────────────
 
 use base64::{engine::general_purpose, Engine as _};
@@ -3860,7 +4307,7 @@ async fn get_org_membership_state(org_id: &str) -> Result<auth::MembershipState,
 
 pub fn get_pkarr_url(public_key_hex: String) -> Result<String, CoreError> {
     pkarr_publish::get_pkarr_url(&public_key_hex)
-        .map_err(|e| CoreError::InvalidInput(e))
+        .map_err(CoreError::InvalidInput)
 }
 
 /// Get pkarr URL from a z32-encoded public key (for orgs).
@@ -3876,7 +4323,7 @@ pub fn get_pkarr_url_from_z32(z32_key: String) -> Result<String, CoreError> {
 pub fn resolve_pkarr(z32_key: String) -> Result<Option<PkarrResolved>, CoreError> {
     store::block_on(async move {
         let record = pkarr_publish::resolve_pkarr(&z32_key).await
-            .map_err(|e| CoreError::InvalidInput(e))?;
+            .map_err(CoreError::InvalidInput)?;
         
         Ok(record.map(|r| PkarrResolved {
             record_type: r.record_type,
@@ -3908,17 +4355,70 @@ pub struct PkarrResolved {
     pub join_sig: Option<String>,
 }
 
+// ── Outbox ───────────────────────────────────────────────────────────────────
+
+/// One not-yet-delivered send of a locally authored op.
+pub struct PendingOutbound {
+    pub op_hash: String,
+    pub transport: String,
+    pub room_id: Option<String>,
+    pub dm_thread_id: Option<String>,
+    pub state: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub next_attempt_at: i64,
+}
+
+/// Deliveries still pending or given up on, oldest first. An op appears once
+/// per transport it has not yet reached.
+pub fn list_pending_outbound() -> Vec<PendingOutbound> {
+    store::block_on(async move {
+        let Some(core) = store::get_core() else {
+            return vec![];
+        };
+        db::list_undelivered_outbox(&core.read_pool)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|r| PendingOutbound {
+                op_hash: r.op_hash,
+                transport: r.transport,
+                room_id: r.room_id,
+                dm_thread_id: r.dm_thread_id,
+                state: r.state,
+                attempts: r.attempts as u32,
+                last_error: r.last_error,
+                created_at: r.created_at,
+                next_attempt_at: r.next_attempt_at,
+            })
+            .collect()
+    })
+}
+
 // ── Network / Iroh P2P ───────────────────────────────────────────────────────
 
-pub use network::{NetworkError, OnionPacket};
+pub use network::{DiscoveryMode, NetworkError, OnionPacket};
 
 /// Initialize the Iroh P2P network stack.
 /// Must be called after `init_core()`. Returns the node ID as a string.
+///
+/// Uses `relay_url` as the only relay if given, else n0's relays, with the
+/// default discovery. See [`init_network_with_config`] for the rest.
 pub fn init_network(relay_url: Option<String>) -> Result<String, NetworkError> {
+    init_network_with_config(relay_url.into_iter().collect(), DiscoveryMode::Default)
+}
+
+/// Initialize the Iroh P2P network stack with explicit relays and discovery.
+/// Must be called after `init_core()`. Returns the node ID as a string.
+///
+/// `relays` is empty for n0's relays, one of `"staging"` / `"disabled"`, or
+/// a list of relay URLs (e.g. a self-hosted iroh-relay).
+pub fn init_network_with_config(relays: Vec<String>, discovery: DiscoveryMode) -> Result<String, NetworkError> {
     store::block_on(async move {
         let core = store::get_core().ok_or(NetworkError::NotInitialized)?;
-        let node_id = network::init_network(&core.db_path, relay_url.as_deref()).await?;
-        if let Err(e) = join_existing_gossip_topics(&core).await {
+        let node_id = network::init_network(&core.db_path, &relays, discovery).await?;
+        if let Err(e) = join_existing_gossip_topics(core).await {
             log::warn!("[gossip] failed to join existing topics: {}", e);
         }
         Ok(node_id)
@@ -3939,6 +4439,14 @@ pub fn is_network_initialized() -> bool {
     })
 }
 
+/// Sync logs directly with a connected peer over the sync ALPN.
+/// Returns the number of ops received.
+pub fn sync_with_peer(peer_node_id: String) -> Result<u64, NetworkError> {
+    store::block_on(async move {
+        network::sync_with_peer(&peer_node_id).await
+    })
+}
+
 /// Send an onion-routed packet to the next hop.
 /// `next_hop` is the Iroh node ID of the next relay/destination.
 pub fn send_onion_packet(next_hop: String, encrypted_payload: Vec<u8>) -> Result<(), NetworkError> {
@@ -3947,6 +4455,17 @@ pub fn send_onion_packet(next_hop: String, encrypted_payload: Vec<u8>) -> Result
     })
 }
 
+/// Opt in to onion relay mode: packets arriving over the onion ALPN are
+/// peeled with the node key and forwarded or delivered by the core instead
+/// of being queued for `receive_onion_packet`.
+pub fn set_onion_relay_enabled(enabled: bool) {
+    network::set_onion_relay_enabled(enabled);
+}
+
+pub fn is_onion_relay_enabled() -> bool {
+    network::onion_relay_enabled()
+}
+
 /// Receive the next available onion packet (non-blocking check).
 /// Returns None if no packet is available.
 pub fn receive_onion_packet() -> Option<OnionPacket> {
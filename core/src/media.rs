@@ -0,0 +1,470 @@
+//! Image handling for uploads: location stripping, thumbnails and blurhash
+//! placeholders.
+//!
+//! Before an image is stored its GPS metadata is removed: the GPS IFD of
+//! JPEG and WebP EXIF blocks is emptied in place (orientation, camera and
+//! other tags survive), and XMP packets, PNG `eXIf` chunks and PNG text
+//! chunks carrying raw EXIF are dropped. Stripping fails closed: images we
+//! cannot parse (HEIC, malformed files, anything over [`MAX_IMAGE_SIZE`])
+//! are refused rather than stored with their location intact.
+//!
+//! Decodable images also get an [`ImageSummary`]: their dimensions, a
+//! blurhash to paint while loading, and a small JPEG thumbnail that
+//! `blobs::upload_blob` stores as a blob of its own.
+
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+
+/// Longest side of a thumbnail, in pixels.
+pub const THUMBNAIL_MAX_DIM: u32 = 320;
+
+/// Images larger than this are refused.
+pub const MAX_IMAGE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Largest width or height we decode for a summary.
+const MAX_DECODE_DIM: u32 = 16_384;
+
+/// Most memory a summary's decoder may allocate.
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Blurhash components along x and y.
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Longest side the blurhash is computed from; it only encodes low
+/// frequencies, so a tiny copy gives the same hash much faster.
+const BLURHASH_SOURCE_DIM: u32 = 32;
+
+/// Why an image can't be stored.
+#[derive(Debug, thiserror::Error)]
+pub enum MediaError {
+    #[error("image is larger than {MAX_IMAGE_SIZE} bytes")]
+    TooLarge,
+    #[error("can't remove location data from this image format")]
+    UnsupportedFormat,
+    #[error("malformed image metadata")]
+    Malformed,
+}
+
+/// What clients need to lay out and paint an image before fetching it.
+#[derive(Debug, Clone)]
+pub struct ImageSummary {
+    /// Dimensions after applying the EXIF orientation.
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    /// JPEG, at most [`THUMBNAIL_MAX_DIM`] on its longest side.
+    pub thumbnail: Vec<u8>,
+}
+
+/// Strip location data from an uploaded image and summarize it. Returns the
+/// bytes to store and, if the image could be decoded, its summary.
+pub fn prepare_image(data: Vec<u8>) -> Result<(Vec<u8>, Option<ImageSummary>), MediaError> {
+    if data.len() as u64 > MAX_IMAGE_SIZE {
+        return Err(MediaError::TooLarge);
+    }
+    let data = strip_gps(data)?;
+    let summary = match summarize(&data) {
+        Ok(summary) => Some(summary),
+        Err(e) => {
+            log::debug!("[media] no preview for image: {}", e);
+            None
+        }
+    };
+    Ok((data, summary))
+}
+
+/// Decode `data`, upright it per its EXIF orientation, and build its summary.
+pub fn summarize(data: &[u8]) -> Result<ImageSummary, image::ImageError> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DECODE_DIM);
+    limits.max_image_height = Some(MAX_DECODE_DIM);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+
+    let small = img.thumbnail(BLURHASH_SOURCE_DIM, BLURHASH_SOURCE_DIM).to_rgba8();
+    let blurhash = blurhash::encode(
+        BLURHASH_COMPONENTS.0,
+        BLURHASH_COMPONENTS.1,
+        small.width(),
+        small.height(),
+        small.as_raw(),
+    )
+    .map_err(|e| image::ImageError::IoError(std::io::Error::other(e.to_string())))?;
+
+    let mut thumbnail = Vec::new();
+    DynamicImage::ImageRgb8(img.thumbnail(THUMBNAIL_MAX_DIM, THUMBNAIL_MAX_DIM).to_rgb8())
+        .write_to(&mut Cursor::new(&mut thumbnail), ImageFormat::Jpeg)?;
+
+    Ok(ImageSummary { width: img.width(), height: img.height(), blurhash, thumbnail })
+}
+
+// ─── GPS stripping ───────────────────────────────────────────────────────────
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_EXTENSION_HEADER: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG text keywords that carry XMP or raw EXIF (the latter as written by
+/// ImageMagick and exiftool).
+const PNG_METADATA_KEYWORDS: &[&[u8]] = &[b"XML:com.adobe.xmp", b"Raw profile type exif", b"Raw profile type xmp"];
+
+/// Application extension identifier of XMP in a GIF.
+const GIF_XMP_APP: &[u8] = b"XMP DataXMP";
+
+/// VP8X flag bit announcing an `XMP ` chunk.
+const VP8X_XMP_FLAG: u8 = 0x04;
+
+/// EXIF tag pointing from IFD0 to the GPS IFD.
+const GPS_IFD_TAG: u16 = 0x8825;
+
+/// Remove GPS metadata from a JPEG, PNG or WebP image. GIFs carry no EXIF
+/// and are accepted unless they embed XMP; anything else, and anything
+/// malformed, is refused.
+pub fn strip_gps(data: Vec<u8>) -> Result<Vec<u8>, MediaError> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        strip_jpeg(data)
+    } else if data.starts_with(PNG_SIGNATURE) {
+        strip_png(data)
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        strip_webp(data)
+    } else if data.starts_with(b"GIF8") {
+        if data.windows(GIF_XMP_APP.len()).any(|w| w == GIF_XMP_APP) {
+            return Err(MediaError::UnsupportedFormat);
+        }
+        Ok(data)
+    } else {
+        Err(MediaError::UnsupportedFormat)
+    }
+}
+
+/// Empty the GPS IFD of every EXIF APP1 segment and drop XMP segments.
+/// Segments are walked up to the start of the scan; the rest is copied.
+fn strip_jpeg(data: Vec<u8>) -> Result<Vec<u8>, MediaError> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            return Err(MediaError::Malformed);
+        }
+        let payload = &data[pos + 4..end];
+        if marker == 0xE1 && (payload.starts_with(XMP_HEADER) || payload.starts_with(XMP_EXTENSION_HEADER)) {
+            pos = end;
+            continue;
+        }
+        let start = out.len();
+        out.extend_from_slice(&data[pos..end]);
+        if marker == 0xE1 && payload.starts_with(EXIF_HEADER) {
+            scrub_tiff_gps(&mut out[start + 4 + EXIF_HEADER.len()..]);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&data[pos..]);
+    Ok(out)
+}
+
+/// Drop `eXIf` chunks and text chunks carrying XMP or raw EXIF. Chunks are
+/// length ‖ type ‖ data ‖ CRC; text chunks start with a NUL-terminated
+/// keyword.
+fn strip_png(data: Vec<u8>) -> Result<Vec<u8>, MediaError> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    let mut pos = PNG_SIGNATURE.len();
+    while pos + 12 <= data.len() {
+        let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let Some(end) = (pos + 12).checked_add(len).filter(|end| *end <= data.len()) else {
+            return Err(MediaError::Malformed);
+        };
+        let kind = &data[pos + 4..pos + 8];
+        let chunk = &data[pos + 8..end - 4];
+        let is_metadata = kind == b"eXIf"
+            || (matches!(kind, b"iTXt" | b"tEXt" | b"zTXt")
+                && PNG_METADATA_KEYWORDS
+                    .iter()
+                    .any(|keyword| chunk.starts_with(keyword) && chunk.get(keyword.len()) == Some(&0)));
+        if !is_metadata {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&data[pos..]);
+    Ok(out)
+}
+
+/// Empty the GPS IFD of the `EXIF` chunk in place and drop `XMP ` chunks.
+/// Chunks are fourcc ‖ size (LE) ‖ data, padded to an even length.
+fn strip_webp(data: Vec<u8>) -> Result<Vec<u8>, MediaError> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..12]);
+    let mut vp8x = None;
+    let mut dropped_xmp = false;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        let start = pos + 8;
+        let Some(end) = start.checked_add(size).filter(|end| *end <= data.len()) else {
+            return Err(MediaError::Malformed);
+        };
+        let padded = (end + (size & 1)).min(data.len());
+        let fourcc = &data[pos..pos + 4];
+        if fourcc == b"XMP " {
+            dropped_xmp = true;
+        } else {
+            let at = out.len();
+            out.extend_from_slice(&data[pos..padded]);
+            if fourcc == b"VP8X" {
+                vp8x = Some(at + 8);
+            } else if fourcc == b"EXIF" {
+                let chunk = &mut out[at + 8..at + 8 + size];
+                let tiff = if chunk.starts_with(EXIF_HEADER) { &mut chunk[EXIF_HEADER.len()..] } else { chunk };
+                scrub_tiff_gps(tiff);
+            }
+        }
+        pos = padded;
+    }
+    out.extend_from_slice(&data[pos..]);
+
+    if dropped_xmp {
+        if let Some(flags) = vp8x.and_then(|at| out.get_mut(at)) {
+            *flags &= !VP8X_XMP_FLAG;
+        }
+    }
+    let riff_size = u32::try_from(out.len() - 8).map_err(|_| MediaError::Malformed)?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+/// Empty the GPS IFD of a TIFF/EXIF block: zero its entries and the values
+/// they point to, and set its entry count to 0. The IFD0 pointer to it is
+/// left in place, now pointing at an empty IFD, so every offset stays valid.
+fn scrub_tiff_gps(tiff: &mut [u8]) {
+    let big_endian = match tiff.get(..4) {
+        Some(b"MM\0*") => true,
+        Some(b"II*\0") => false,
+        _ => return,
+    };
+    let tiff_view = Tiff { data: tiff, big_endian };
+    let Some(ifd0) = tiff_view.u32(4) else { return };
+    let Some(count) = tiff_view.u16(ifd0) else { return };
+
+    let mut gps_ifd = None;
+    for i in 0..count as usize {
+        let entry = ifd0 + 2 + i * 12;
+        if tiff_view.u16(entry) == Some(GPS_IFD_TAG) {
+            gps_ifd = tiff_view.u32(entry + 8);
+        }
+    }
+    let Some(gps) = gps_ifd else { return };
+    let Some(gps_count) = tiff_view.u16(gps) else { return };
+
+    let mut spans = Vec::new();
+    for i in 0..gps_count as usize {
+        let entry = gps + 2 + i * 12;
+        let (Some(kind), Some(n)) = (tiff_view.u16(entry + 2), tiff_view.u32(entry + 4)) else { break };
+        let size = value_size(kind).saturating_mul(n);
+        if size > 4 {
+            if let Some(offset) = tiff_view.u32(entry + 8) {
+                spans.push((offset, size));
+            }
+        }
+    }
+    spans.push((gps + 2, gps_count as usize * 12));
+
+    for (start, len) in spans {
+        if let Some(bytes) = start.checked_add(len).and_then(|end| tiff.get_mut(start..end)) {
+            bytes.fill(0);
+        }
+    }
+    if let Some(bytes) = tiff.get_mut(gps..gps + 2) {
+        bytes.fill(0);
+    }
+}
+
+/// Bytes per value of a TIFF field type; 0 for unknown types.
+fn value_size(kind: u16) -> usize {
+    match kind {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    fn u32(&self, at: usize) -> Option<usize> {
+        let b: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) } as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// Little-endian TIFF: IFD0 with Orientation and the GPS pointer, and a
+    /// GPS IFD with GPSLatitudeRef and GPSLatitude (three rationals).
+    fn tiff_with_gps() -> Vec<u8> {
+        let mut t = b"II*\0".to_vec();
+        t.extend_from_slice(&8u32.to_le_bytes());
+        // IFD0 at 8: 2 entries, next IFD 0
+        t.extend_from_slice(&2u16.to_le_bytes());
+        t.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]); // Orientation = 6
+        t.extend_from_slice(&[0x25, 0x88, 4, 0, 1, 0, 0, 0]);
+        t.extend_from_slice(&38u32.to_le_bytes()); // GPS IFD offset
+        t.extend_from_slice(&0u32.to_le_bytes());
+        // GPS IFD at 38: 2 entries, next IFD 0
+        t.extend_from_slice(&2u16.to_le_bytes());
+        t.extend_from_slice(&[1, 0, 2, 0, 2, 0, 0, 0, b'N', 0, 0, 0]);
+        t.extend_from_slice(&[2, 0, 5, 0, 3, 0, 0, 0]);
+        t.extend_from_slice(&68u32.to_le_bytes()); // latitude values
+        t.extend_from_slice(&0u32.to_le_bytes());
+        // Latitude at 68: 52/1, 31/1, 12/1
+        for v in [52u32, 1, 31, 1, 12, 1] {
+            t.extend_from_slice(&v.to_le_bytes());
+        }
+        t
+    }
+
+    fn jpeg_with_exif(tiff: &[u8]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((2 + EXIF_HEADER.len() + tiff.len()) as u16).to_be_bytes());
+        jpeg.extend_from_slice(EXIF_HEADER);
+        jpeg.extend_from_slice(tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2, 0xAB, 0xCD, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn jpeg_gps_is_emptied_and_other_tags_kept() {
+        let tiff = tiff_with_gps();
+        let stripped = strip_gps(jpeg_with_exif(&tiff)).unwrap();
+        let offset = 4 + 2 + EXIF_HEADER.len();
+        let out = &stripped[offset..offset + tiff.len()];
+
+        assert_eq!(out[..38], tiff[..38], "IFD0 incl. orientation must survive");
+        assert_eq!(&out[38..40], &[0, 0], "GPS IFD must be empty");
+        assert!(out[38..].iter().all(|b| *b == 0), "GPS values must be zeroed");
+        assert!(stripped.ends_with(&[0xFF, 0xDA, 0, 2, 0xAB, 0xCD, 0xFF, 0xD9]));
+    }
+
+    #[test]
+    fn png_exif_chunks_are_dropped() {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([10, 20, 30])))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        // Insert an eXIf chunk right after IHDR (8 + 25 bytes)
+        let tiff = tiff_with_gps();
+        let mut chunk = (tiff.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(b"eXIf");
+        chunk.extend_from_slice(&tiff);
+        chunk.extend_from_slice(&[0; 4]);
+        png.splice(33..33, chunk);
+
+        let stripped = strip_gps(png).unwrap();
+        assert!(!stripped.windows(4).any(|w| w == b"eXIf"));
+        assert!(image::load_from_memory(&stripped).is_ok());
+    }
+
+    #[test]
+    fn png_xmp_text_chunks_are_dropped() {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([10, 20, 30])))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let mut text = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+        text.extend_from_slice(b"<exif:GPSLatitude>52,31.2N</exif:GPSLatitude>");
+        let mut chunk = (text.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(b"iTXt");
+        chunk.extend_from_slice(&text);
+        chunk.extend_from_slice(&[0; 4]);
+        png.splice(33..33, chunk);
+
+        let stripped = strip_gps(png).unwrap();
+        assert!(!stripped.windows(11).any(|w| w == b"GPSLatitude"));
+        assert!(image::load_from_memory(&stripped).is_ok());
+    }
+
+    #[test]
+    fn webp_xmp_chunks_are_dropped() {
+        let xmp = b"<exif:GPSLatitude>52,31.2N</exif:GPSLatitude>";
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend_from_slice(b"VP8X");
+        webp.extend_from_slice(&10u32.to_le_bytes());
+        webp.extend_from_slice(&[VP8X_XMP_FLAG, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        webp.extend_from_slice(b"XMP ");
+        webp.extend_from_slice(&(xmp.len() as u32).to_le_bytes());
+        webp.extend_from_slice(xmp);
+        webp.push(0); // pad to even
+        webp.extend_from_slice(b"VP8L");
+        webp.extend_from_slice(&2u32.to_le_bytes());
+        webp.extend_from_slice(&[0xAB, 0xCD]);
+        let size = (webp.len() - 8) as u32;
+        webp[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let stripped = strip_gps(webp).unwrap();
+        assert!(!stripped.windows(11).any(|w| w == b"GPSLatitude"));
+        assert_eq!(stripped[20] & VP8X_XMP_FLAG, 0, "the VP8X flag must not announce XMP");
+        assert_eq!(u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize, stripped.len() - 8);
+        assert!(stripped.ends_with(&[0xAB, 0xCD]));
+    }
+
+    #[test]
+    fn images_we_cannot_strip_are_refused() {
+        let heic = [&[0, 0, 0, 24][..], b"ftypheic", &[0; 12]].concat();
+        assert!(matches!(prepare_image(heic), Err(MediaError::UnsupportedFormat)));
+        assert!(matches!(prepare_image(b"not an image".to_vec()), Err(MediaError::UnsupportedFormat)));
+
+        let truncated = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x40, 0x00, b'E'];
+        assert!(matches!(strip_gps(truncated), Err(MediaError::Malformed)));
+
+        let huge = [&[0xFF, 0xD8][..], &vec![0; MAX_IMAGE_SIZE as usize]].concat();
+        assert!(matches!(prepare_image(huge), Err(MediaError::TooLarge)));
+    }
+
+    #[test]
+    fn summaries_keep_aspect_and_fit_thumbnails() {
+        let img = RgbImage::from_fn(640, 480, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 128]));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(img).write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+
+        let summary = summarize(&png).unwrap();
+        assert_eq!((summary.width, summary.height), (640, 480));
+        assert_eq!(summary.blurhash.len(), 6 + 2 * (BLURHASH_COMPONENTS.0 * BLURHASH_COMPONENTS.1 - 1) as usize);
+
+        let thumb = image::load_from_memory(&summary.thumbnail).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (THUMBNAIL_MAX_DIM, 240));
+
+        let mut too_wide = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(MAX_DECODE_DIM + 1, 1))
+            .write_to(&mut Cursor::new(&mut too_wide), ImageFormat::Png)
+            .unwrap();
+        assert!(summarize(&too_wide).is_err(), "decoding is bounded");
+    }
+}
@@ -2,25 +2,30 @@
 //!
 //! This module provides:
 //! - Iroh endpoint for P2P connectivity (NAT traversal, hole punching)
-//! - Custom ALPN protocol for onion routing
+//! - Custom ALPN protocol for onion routing, with an opt-in relay mode that
+//!   peels and forwards packets itself
 //! - iroh-blobs integration for content-addressed blob storage
 //! - iroh-gossip for message broadcasting
+//! - Direct peer-to-peer log sync (see [`crate::peer_sync`])
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
 use std::time::Duration;
 
-use iroh::{Endpoint, EndpointId, SecretKey};
-use iroh::endpoint::{Connection, RecvStream, SendStream};
+use iroh::{Endpoint, EndpointId, RelayMap, RelayMode, RelayUrl, SecretKey, Watcher as _};
+use iroh::discovery::{dns::DnsDiscovery, mdns::MdnsDiscovery, pkarr::PkarrPublisher};
+use iroh::endpoint::{Connection, ConnectionType, RecvStream, SendStream};
 use iroh_gossip::api::{Event as GossipEvent, GossipSender};
 use iroh_gossip::net::Gossip;
 use tokio::sync::{mpsc, Mutex, RwLock};
 use tokio::task::JoinHandle;
 use futures_util::StreamExt;
-use p2panda_core::{Body, Header};
-use p2panda_store::OperationStore;
 
-use crate::{blobs, ops, sealed_sender, store};
+use crate::onion::{self, OnionError, OnionPayload};
+use crate::ops::{decode_cbor, GossipEnvelope};
+use crate::sealed_sender::SealedSenderError;
+use crate::{blobs, peer_sync, sealed_sender, store};
 
 /// ALPN protocol identifier for Gardens's onion routing protocol.
 pub const ONION_ALPN: &[u8] = b"/gardens/onion/1.0.0";
@@ -28,6 +33,9 @@ pub const ONION_ALPN: &[u8] = b"/gardens/onion/1.0.0";
 /// ALPN protocol identifier for blob requests.
 pub const BLOB_ALPN: &[u8] = b"/gardens/blob/1.0.0";
 
+/// ALPN protocol identifier for direct log sync between peers.
+pub const SYNC_ALPN: &[u8] = b"/gardens/sync/1.0.0";
+
 /// Maximum size for onion packets (64KB).
 pub const MAX_ONION_PACKET_SIZE: usize = 64 * 1024;
 
@@ -46,6 +54,46 @@ pub enum NetworkError {
     StreamError(String),
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
+    #[error("Invalid network config: {0}")]
+    InvalidConfig(String),
+}
+
+/// How the endpoint finds the addresses of peers it only knows by node id.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiscoveryMode {
+    /// n0's DNS and pkarr discovery servers.
+    #[default]
+    Default,
+    /// mDNS on the local network only. Works without internet access.
+    LocalNetwork,
+    /// Both of the above.
+    DefaultAndLocalNetwork,
+    /// No discovery: peers are reachable only through addresses we already hold.
+    Disabled,
+}
+
+/// Build the relay mode for `relays`.
+///
+/// No entries means n0's production relays. A single `"default"`, `"staging"`
+/// or `"disabled"` selects that mode; anything else is a list of relay URLs
+/// to use instead of n0's, e.g. a self-hosted iroh-relay.
+pub fn relay_mode(relays: &[String]) -> Result<RelayMode, NetworkError> {
+    match relays {
+        [] => Ok(RelayMode::Default),
+        [one] if one.eq_ignore_ascii_case("default") => Ok(RelayMode::Default),
+        [one] if one.eq_ignore_ascii_case("staging") => Ok(RelayMode::Staging),
+        [one] if one.eq_ignore_ascii_case("disabled") => Ok(RelayMode::Disabled),
+        urls => {
+            let urls = urls
+                .iter()
+                .map(|url| {
+                    url.parse::<RelayUrl>()
+                        .map_err(|e| NetworkError::InvalidConfig(format!("invalid relay url {url}: {e}")))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(RelayMode::Custom(RelayMap::from_iter(urls)))
+        }
+    }
 }
 
 /// Network state containing all Iroh components.
@@ -63,6 +111,40 @@ pub struct NetworkState {
     pub gossip: Gossip,
     /// Active gossip topics by topic id
     pub gossip_topics: HashMap<[u8; 32], GossipSender>,
+    /// Neighbours and errors observed by background tasks
+    pub health: Arc<StdMutex<NetworkHealth>>,
+}
+
+/// What the network tasks have observed, for connection status reporting.
+#[derive(Default)]
+pub struct NetworkHealth {
+    /// Current gossip neighbours per topic.
+    pub topic_neighbors: HashMap<[u8; 32], (GossipTopicKind, HashSet<EndpointId>)>,
+    /// Most recent error and when it happened (micros).
+    pub last_error: Option<(String, i64)>,
+}
+
+impl NetworkHealth {
+    fn record_error(health: &StdMutex<NetworkHealth>, error: impl std::fmt::Display) {
+        let mut h = health.lock().unwrap_or_else(|e| e.into_inner());
+        h.last_error = Some((error.to_string(), crate::now_micros()));
+    }
+}
+
+/// Point-in-time view of the network layer, for `get_connection_status`.
+pub struct NetworkSnapshot {
+    pub node_id: String,
+    /// The endpoint is bound and not closed.
+    pub bound: bool,
+    /// Relay we are reachable through, if connected to one.
+    pub home_relay: Option<String>,
+    /// (topic id, kind, neighbour count) per joined gossip topic.
+    pub topics: Vec<([u8; 32], GossipTopicKind, usize)>,
+    /// Gossip neighbours reached over a direct path.
+    pub direct_peers: usize,
+    /// Gossip neighbours reached only through a relay.
+    pub relayed_peers: usize,
+    pub last_error: Option<(String, i64)>,
 }
 
 /// An onion-routed packet received by this node.
@@ -73,13 +155,26 @@ pub struct OnionPacket {
     pub from_node_id: String,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum GossipTopicKind {
     Room,
     DmInbox,
     Org,
 }
 
+/// Whether this node peels and forwards onion packets itself (relay mode)
+/// instead of queueing them for the app.
+static ONION_RELAY: AtomicBool = AtomicBool::new(false);
+
+/// Turn onion relay mode on or off.
+pub fn set_onion_relay_enabled(enabled: bool) {
+    ONION_RELAY.store(enabled, Ordering::Relaxed);
+}
+
+pub fn onion_relay_enabled() -> bool {
+    ONION_RELAY.load(Ordering::Relaxed)
+}
+
 /// Global network state - initialized once on startup.
 static NETWORK: RwLock<Option<Arc<Mutex<NetworkState>>>> = RwLock::const_new(None);
 
@@ -95,9 +190,12 @@ pub async fn is_initialized() -> bool {
 }
 
 /// Initialize the Iroh networking stack.
+///
+/// `relays` is interpreted by [`relay_mode`].
 pub async fn init_network(
     _db_dir: &str,
-    relay_url: Option<&str>,
+    relays: &[String],
+    discovery: DiscoveryMode,
 ) -> Result<String, NetworkError> {
     // Check if already initialized
     {
@@ -111,14 +209,28 @@ pub async fn init_network(
     // Get or create the secret key from the core's private key
     let secret_key = get_or_create_secret_key().await?;
 
+    let relay_mode = relay_mode(relays)?;
+    log::info!("[network] Relay mode: {:?}, discovery: {:?}", relay_mode, discovery);
+
     // Create the endpoint
     let builder = Endpoint::builder()
-        .secret_key(secret_key);
-
-    if let Some(url) = relay_url {
-        log::info!("[network] Using relay: {}", url);
-        // Relay configuration would go here
-    }
+        .secret_key(secret_key)
+        .alpns(vec![
+            ONION_ALPN.to_vec(),
+            BLOB_ALPN.to_vec(),
+            SYNC_ALPN.to_vec(),
+            iroh_gossip::net::GOSSIP_ALPN.to_vec(),
+        ])
+        .relay_mode(relay_mode)
+        .clear_discovery();
+    // n0's DNS discovery, without the preset's relay map (relays are ours)
+    let n0 = |b: iroh::endpoint::Builder| b.discovery(PkarrPublisher::n0_dns()).discovery(DnsDiscovery::n0_dns());
+    let builder = match discovery {
+        DiscoveryMode::Default => n0(builder),
+        DiscoveryMode::LocalNetwork => builder.discovery(MdnsDiscovery::builder()),
+        DiscoveryMode::DefaultAndLocalNetwork => n0(builder).discovery(MdnsDiscovery::builder()),
+        DiscoveryMode::Disabled => builder,
+    };
 
     let endpoint = builder.bind()
         .await
@@ -135,16 +247,20 @@ pub async fn init_network(
     
     // Create the blobs directory if it doesn't exist
     if let Err(e) = std::fs::create_dir_all(&blob_store_path) {
-        return Err(NetworkError::IoError(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(NetworkError::IoError(std::io::Error::other(
             format!("Failed to create blobs directory: {}", e),
         )));
     }
     
-    let blob_store = iroh_blobs::store::fs::FsStore::load(&blob_store_path)
+    // Let the store delete blobs once `blob_gc` has removed their tags.
+    let mut blob_options = iroh_blobs::store::fs::options::Options::new(&blob_store_path);
+    blob_options.gc = Some(iroh_blobs::store::fs::options::GcConfig {
+        interval: std::time::Duration::from_secs(5 * 60),
+        add_protected: None,
+    });
+    let blob_store = iroh_blobs::store::fs::FsStore::load_with_opts(blob_store_path.join("blobs.db"), blob_options)
         .await
-        .map_err(|e| NetworkError::IoError(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        .map_err(|e| NetworkError::IoError(std::io::Error::other(
             format!("Failed to load blob store: {}", e),
         )))?;
     let blob_store = Arc::new(blob_store);
@@ -156,9 +272,10 @@ pub async fn init_network(
     let gossip = Gossip::builder().spawn(endpoint.clone());
 
     // Spawn connection handler
+    let health = Arc::new(StdMutex::new(NetworkHealth::default()));
     let endpoint_clone = endpoint.clone();
     let gossip_clone = gossip.clone();
-    let handler = tokio::spawn(connection_handler(endpoint_clone, gossip_clone, onion_tx));
+    let handler = tokio::spawn(connection_handler(endpoint_clone, gossip_clone, onion_tx, health.clone()));
 
     let state = NetworkState {
         endpoint,
@@ -167,6 +284,7 @@ pub async fn init_network(
         blob_store,
         gossip,
         gossip_topics: HashMap::new(),
+        health,
     };
 
     // Store the network state
@@ -184,22 +302,26 @@ async fn connection_handler(
     endpoint: Endpoint,
     gossip: Gossip,
     onion_tx: mpsc::UnboundedSender<OnionPacket>,
+    health: Arc<StdMutex<NetworkHealth>>,
 ) {
     log::info!("[network] Connection handler started");
 
     while let Some(incoming) = endpoint.accept().await {
         let onion_tx = onion_tx.clone();
         let gossip = gossip.clone();
+        let health = health.clone();
         
         tokio::spawn(async move {
             match incoming.await {
                 Ok(conn) => {
                     if let Err(e) = handle_connection(conn, &gossip, onion_tx).await {
                         log::warn!("[network] Connection handling error: {}", e);
+                        NetworkHealth::record_error(&health, &e);
                     }
                 }
                 Err(e) => {
                     log::warn!("[network] Failed to accept connection: {}", e);
+                    NetworkHealth::record_error(&health, &e);
                 }
             }
         });
@@ -220,7 +342,8 @@ async fn handle_connection(
 
     match alpn.as_deref() {
         Some(a) if a == ONION_ALPN => handle_onion_connection(conn, onion_tx).await,
-        Some(a) if a == BLOB_ALPN => handle_blob_connection(conn).await,
+        Some(a) if a == BLOB_ALPN => blobs::handle_blob_connection(conn).await,
+        Some(a) if a == SYNC_ALPN => peer_sync::handle_sync_connection(conn).await,
         Some(a) if a == iroh_gossip::net::GOSSIP_ALPN => {
             gossip
                 .handle_connection(conn)
@@ -296,6 +419,10 @@ async fn handle_onion_stream(
     recv.read_exact(&mut payload).await
         .map_err(|e| NetworkError::StreamError(e.to_string()))?;
 
+    if onion_relay_enabled() {
+        return relay_onion_packet(payload, from, onion_tx).await;
+    }
+
     // Send to processing channel
     let packet = OnionPacket {
         payload,
@@ -307,10 +434,93 @@ async fn handle_onion_stream(
     Ok(())
 }
 
-/// Handle connections for blob transfers.
-async fn handle_blob_connection(_conn: Connection) -> Result<(), NetworkError> {
-    log::debug!("[network] Blob connection established");
-    Ok(())
+/// Relay mode: peel the layer addressed to us and act on it.
+///
+/// `Forward` to an iroh node id (bare, or as `iroh:<node id>`) goes out over
+/// [`ONION_ALPN`]; a `Forward` to an HTTP hop is left to the app as before.
+/// `Deliver` is gossiped on `topic_id`, see [`deliver_onion_op`].
+async fn relay_onion_packet(
+    packet: Vec<u8>,
+    from: EndpointId,
+    onion_tx: mpsc::UnboundedSender<OnionPacket>,
+) -> Result<(), NetworkError> {
+    let core = store::get_core().ok_or(NetworkError::NotInitialized)?;
+    let seed = *core.private_key.as_bytes();
+    let payload = match onion::peel_layer(&core.read_pool, &packet, &seed).await {
+        Ok(payload) => payload,
+        Err(OnionError::Replayed) => {
+            log::debug!("[network] Dropping replayed onion packet from {}", from);
+            return Ok(());
+        }
+        Err(e) => return Err(NetworkError::ProtocolError(e.to_string())),
+    };
+
+    match payload {
+        OnionPayload::Forward { next_hop_url, inner_packet } => match forward_target(&next_hop_url) {
+            Some(next_hop) => {
+                log::debug!("[network] Relaying onion packet to {}", next_hop);
+                send_onion_packet(&next_hop.to_string(), inner_packet).await
+            }
+            None => onion_tx
+                .send(OnionPacket { payload: packet, from_node_id: from.to_string() })
+                .map_err(|_| NetworkError::ProtocolError("Failed to queue packet".to_string())),
+        },
+        OnionPayload::Deliver { topic_id, op } => deliver_onion_op(topic_id, op).await,
+        OnionPayload::Drop => Ok(()),
+        OnionPayload::ReplyHop { .. } | OnionPayload::ReplyExit { .. } => {
+            Err(NetworkError::ProtocolError("unexpected reply-block instruction".to_string()))
+        }
+    }
+}
+
+/// The iroh node a `Forward` hop names, if it names one.
+fn forward_target(next_hop: &str) -> Option<EndpointId> {
+    next_hop.strip_prefix("iroh:").unwrap_or(next_hop).parse().ok()
+}
+
+/// Exit hop: hand a delivered op to the topic's gossip swarm. Ops for topics
+/// we have joined are ingested on the way; we needn't have joined the topic
+/// to deliver to it (see [`exit_delivery`]).
+async fn deliver_onion_op(topic_id: [u8; 32], op: Vec<u8>) -> Result<(), NetworkError> {
+    let network = get_network().await.ok_or(NetworkError::NotInitialized)?;
+    let joined = {
+        let net = network.lock().await;
+        let health = net.health.lock().unwrap_or_else(|e| e.into_inner());
+        health.topic_neighbors.get(&topic_id).map(|(kind, _)| *kind)
+    };
+
+    let (kind, bootstrap) = match joined {
+        Some(kind) => {
+            if let Err(e) = handle_gossip_message(kind, op.clone()).await {
+                // A sealed DM op for another recipient cannot be opened here but is
+                // still theirs to receive; room ops must be valid to be passed on.
+                if matches!(kind, GossipTopicKind::Room) {
+                    return Err(e);
+                }
+            }
+            (kind, vec![])
+        }
+        None => exit_delivery(&topic_id, &op)?,
+    };
+    gossip_publish(topic_id, kind, bootstrap, op).await
+}
+
+/// Topic kind and bootstrap peer for delivering `op` to a topic we haven't
+/// joined. A sealed op is for a DM inbox, whose topic id is the recipient's
+/// key; anything else must be a validly signed op and goes to its room topic,
+/// bootstrapped through the op's author.
+fn exit_delivery(topic_id: &[u8; 32], op: &[u8]) -> Result<(GossipTopicKind, Vec<EndpointId>), NetworkError> {
+    if sealed_sender::is_sealed(op) {
+        let recipient = EndpointId::from_bytes(topic_id)
+            .map_err(|e| NetworkError::ProtocolError(format!("invalid inbox topic: {}", e)))?;
+        return Ok((GossipTopicKind::DmInbox, vec![recipient]));
+    }
+
+    let env = decode_cbor::<GossipEnvelope>(op).map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    let (header, _) = crate::ingest::validate_op(&env).map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    let author = EndpointId::from_bytes(header.public_key.as_bytes())
+        .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    Ok((GossipTopicKind::Room, vec![author]))
 }
 
 /// Send an onion packet to the next hop.
@@ -360,14 +570,23 @@ pub async fn gossip_publish(
 ) -> Result<(), NetworkError> {
     let network = get_network().await.ok_or(NetworkError::NotInitialized)?;
     let mut net = network.lock().await;
-    let sender = ensure_gossip_topic(&mut *net, topic_id, kind, bootstrap).await?;
-    sender
-        .broadcast(bytes.into())
-        .await
-        .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    let sender = ensure_gossip_topic(&mut net, topic_id, kind, bootstrap).await?;
+    if let Err(e) = sender.broadcast(bytes.into()).await {
+        NetworkHealth::record_error(&net.health, &e);
+        return Err(NetworkError::ProtocolError(e.to_string()));
+    }
     Ok(())
 }
 
+/// Gossip neighbours we currently have on `topic_id`; 0 for topics we
+/// haven't joined.
+pub async fn topic_neighbor_count(topic_id: &[u8; 32]) -> usize {
+    let Some(network) = get_network().await else { return 0 };
+    let net = network.lock().await;
+    let health = net.health.lock().unwrap_or_else(|e| e.into_inner());
+    health.topic_neighbors.get(topic_id).map_or(0, |(_, peers)| peers.len())
+}
+
 pub async fn gossip_join(
     topic_id: [u8; 32],
     kind: GossipTopicKind,
@@ -375,7 +594,7 @@ pub async fn gossip_join(
 ) -> Result<(), NetworkError> {
     let network = get_network().await.ok_or(NetworkError::NotInitialized)?;
     let mut net = network.lock().await;
-    let _ = ensure_gossip_topic(&mut *net, topic_id, kind, bootstrap).await?;
+    let _ = ensure_gossip_topic(&mut net, topic_id, kind, bootstrap).await?;
     Ok(())
 }
 
@@ -396,17 +615,47 @@ async fn ensure_gossip_topic(
         .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
     let (sender, mut receiver) = topic.split();
     let kind_copy = kind;
+    let endpoint = net.endpoint.clone();
+    let health = net.health.clone();
+    health
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .topic_neighbors
+        .insert(topic_id, (kind, HashSet::new()));
     tokio::spawn(async move {
         while let Some(event) = receiver.next().await {
             match event {
                 Ok(GossipEvent::Received(msg)) => {
                     if let Err(e) = handle_gossip_message(kind_copy, msg.content.to_vec()).await {
                         log::warn!("[network] Gossip ingest failed: {}", e);
+                        NetworkHealth::record_error(&health, &e);
+                    }
+                }
+                Ok(GossipEvent::NeighborUp(peer)) => {
+                    if let Some((_, peers)) = health
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .topic_neighbors
+                        .get_mut(&topic_id)
+                    {
+                        peers.insert(peer);
+                    }
+                    peer_sync::on_neighbor_up(endpoint.clone(), peer);
+                }
+                Ok(GossipEvent::NeighborDown(peer)) => {
+                    if let Some((_, peers)) = health
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .topic_neighbors
+                        .get_mut(&topic_id)
+                    {
+                        peers.remove(&peer);
                     }
                 }
                 Ok(_) => {}
                 Err(e) => {
                     log::warn!("[network] Gossip receive error: {}", e);
+                    NetworkHealth::record_error(&health, &e);
                 }
             }
         }
@@ -419,25 +668,19 @@ async fn ensure_gossip_topic(
 async fn handle_gossip_message(kind: GossipTopicKind, bytes: Vec<u8>) -> Result<(), NetworkError> {
     let payload = match kind {
         GossipTopicKind::Room => bytes,
-        GossipTopicKind::DmInbox => {
+        // Org gossip uses sealed sender for encrypted messages, like DM inboxes
+        GossipTopicKind::DmInbox | GossipTopicKind::Org => {
             if sealed_sender::is_sealed(&bytes) {
                 let core = store::get_core().ok_or(NetworkError::NotInitialized)?;
                 let seed = *core.private_key.as_bytes();
-                let (_sender_pk, op_bytes) = sealed_sender::open(&bytes, &seed)
-                    .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
-                op_bytes
-            } else {
-                bytes
-            }
-        }
-        GossipTopicKind::Org => {
-            // Org gossip uses sealed sender for encrypted messages
-            if sealed_sender::is_sealed(&bytes) {
-                let core = store::get_core().ok_or(NetworkError::NotInitialized)?;
-                let seed = *core.private_key.as_bytes();
-                let (_sender_pk, op_bytes) = sealed_sender::open(&bytes, &seed)
-                    .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
-                op_bytes
+                match sealed_sender::open(&bytes, &seed) {
+                    Ok((_sender_pk, op_bytes)) => op_bytes,
+                    Err(SealedSenderError::Replayed) => {
+                        log::debug!("[network] Dropping replayed sealed envelope");
+                        return Ok(());
+                    }
+                    Err(e) => return Err(NetworkError::ProtocolError(e.to_string())),
+                }
             } else {
                 bytes
             }
@@ -449,21 +692,71 @@ async fn handle_gossip_message(kind: GossipTopicKind, bytes: Vec<u8>) -> Result<
 
 async fn ingest_gossip_envelope(bytes: &[u8]) -> Result<(), NetworkError> {
     let core = store::get_core().ok_or(NetworkError::NotInitialized)?;
-    let env = ops::decode_cbor::<ops::GossipEnvelope>(bytes)
-        .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
-    let header = Header::try_from(env.header_bytes.as_slice())
-        .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
-    let body = Body::new(&env.body_bytes);
-    let op_hash = header.hash();
-
-    let mut store = core.op_store.lock().await;
-    store
-        .insert_operation(op_hash, &header, Some(&body), &env.header_bytes, &env.log_id)
+    let op = crate::ingest::ingest_envelope(core, bytes)
         .await
         .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+
+    crate::projector::notify_op(op.author, &op.log_id);
     Ok(())
 }
 
+/// Run a direct log sync session with `peer_node_id`.
+/// Returns the number of ops received.
+pub async fn sync_with_peer(peer_node_id: &str) -> Result<u64, NetworkError> {
+    let network = get_network().await.ok_or(NetworkError::NotInitialized)?;
+    let endpoint = network.lock().await.endpoint.clone();
+
+    let peer: EndpointId = peer_node_id.parse()
+        .map_err(|e| NetworkError::ProtocolError(format!("Invalid node ID: {}", e)))?;
+    let stats = peer_sync::sync_with_peer(&endpoint, peer).await?;
+    Ok(stats.ops_received)
+}
+
+/// Snapshot of endpoint, relay and gossip state, or `None` before
+/// `init_network`.
+pub async fn snapshot() -> Option<NetworkSnapshot> {
+    let network = get_network().await?;
+    let net = network.lock().await;
+    let health = net.health.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut peers = HashSet::new();
+    let topics = health
+        .topic_neighbors
+        .iter()
+        .map(|(topic_id, (kind, neighbors))| {
+            peers.extend(neighbors.iter().copied());
+            (*topic_id, *kind, neighbors.len())
+        })
+        .collect();
+
+    let (mut direct_peers, mut relayed_peers) = (0, 0);
+    for peer in peers {
+        match net.endpoint.conn_type(peer).map(|mut w| w.get()) {
+            Some(ConnectionType::Direct(_)) => direct_peers += 1,
+            Some(ConnectionType::Relay(_)) | Some(ConnectionType::Mixed(..)) => relayed_peers += 1,
+            _ => {}
+        }
+    }
+
+    Some(NetworkSnapshot {
+        node_id: net.endpoint.id().to_string(),
+        bound: !net.endpoint.is_closed(),
+        home_relay: net.endpoint.addr().relay_urls().next().map(|url| url.to_string()),
+        topics,
+        direct_peers,
+        relayed_peers,
+        last_error: health.last_error.clone(),
+    })
+}
+
+/// Record an error from outside the network tasks (e.g. a failed peer sync)
+/// for diagnostics.
+pub async fn record_error(error: impl std::fmt::Display) {
+    if let Some(network) = get_network().await {
+        NetworkHealth::record_error(&network.lock().await.health, error);
+    }
+}
+
 /// Get our node ID as a string.
 pub async fn get_node_id() -> Result<String, NetworkError> {
     let network = get_network().await
@@ -516,4 +809,97 @@ mod tests {
         let result: Result<EndpointId, _> = id_str.parse();
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn forward_targets_are_node_ids() {
+        let id = "ae58c67e4e034e4ae26f17c13c25b6b7c5a7cc7b7d78380d1f5e1c1a1b1c1d1e";
+        assert!(forward_target(id).is_some());
+        assert_eq!(forward_target(&format!("iroh:{id}")), forward_target(id));
+        assert!(forward_target("https://relay.usegardens.com/hop").is_none());
+    }
+
+    #[test]
+    fn onion_dm_reaches_the_recipient_inbox_through_core_hops() {
+        use crate::db::OutboxRow;
+        use p2panda_core::PrivateKey;
+
+        let (sender, relay, exit, recipient) =
+            (PrivateKey::new(), PrivateKey::new(), PrivateKey::new(), PrivateKey::new());
+        let hop = |key: &PrivateKey| onion::OnionHop {
+            pubkey_bytes: *key.public_key().as_bytes(),
+            next_url: format!("iroh:{}", key.public_key().to_hex()),
+        };
+        let hops = [hop(&relay), hop(&exit)];
+        let row = OutboxRow {
+            op_hash: "00".repeat(32),
+            transport: crate::outbox::TRANSPORT_ONION.to_string(),
+            log_id: "message".to_string(),
+            room_id: None,
+            dm_thread_id: Some("11".repeat(32)),
+            recipient_key: Some(recipient.public_key().to_hex()),
+            topic_hex: "11".repeat(32),
+            envelope: b"envelope".to_vec(),
+            state: "pending".to_string(),
+            attempts: 0,
+            next_attempt_at: 0,
+            last_error: None,
+            created_at: 0,
+            delivered_at: None,
+        };
+
+        // Build
+        let (topic_id, payload) = crate::outbox::onion_delivery(&sender, &row, &hops[1]).unwrap();
+        let packet = onion::build_onion_packet(&hops, &topic_id, &payload).unwrap();
+
+        // Relay
+        let inner = match onion::decrypt_layer(&packet, relay.as_bytes()).unwrap() {
+            OnionPayload::Forward { next_hop_url, inner_packet } => {
+                let exit_id = EndpointId::from_bytes(exit.public_key().as_bytes()).unwrap();
+                assert_eq!(forward_target(&next_hop_url), Some(exit_id));
+                inner_packet
+            }
+            _ => panic!("the relay hop should forward"),
+        };
+
+        // Exit
+        let (topic_id, op) = match onion::decrypt_layer(&inner, exit.as_bytes()).unwrap() {
+            OnionPayload::Deliver { topic_id, op } => (topic_id, op),
+            _ => panic!("the exit hop should deliver"),
+        };
+        let recipient_id = EndpointId::from_bytes(recipient.public_key().as_bytes()).unwrap();
+        assert_eq!(topic_id, *recipient.public_key().as_bytes(), "the inbox topic the recipient joins");
+        assert_eq!(
+            exit_delivery(&topic_id, &op).unwrap(),
+            (GossipTopicKind::DmInbox, vec![recipient_id])
+        );
+        assert!(sealed_sender::open(&op, exit.as_bytes()).is_err(), "the exit can't read it");
+
+        // Deliver
+        let (sender_pk, envelope) = sealed_sender::open(&op, recipient.as_bytes()).unwrap();
+        assert_eq!(sender_pk, *sender.public_key().as_bytes());
+        assert_eq!(envelope, b"envelope");
+    }
+
+    #[test]
+    fn exits_refuse_unsigned_room_ops() {
+        assert!(exit_delivery(&[7; 32], b"not an op").is_err());
+    }
+
+    #[test]
+    fn relay_mode_from_config() {
+        assert!(matches!(relay_mode(&[]), Ok(RelayMode::Default)));
+        assert!(matches!(relay_mode(&["staging".into()]), Ok(RelayMode::Staging)));
+        assert!(matches!(relay_mode(&["Disabled".into()]), Ok(RelayMode::Disabled)));
+
+        let custom = relay_mode(&[
+            "https://relay.example.com".into(),
+            "https://relay2.example.com".into(),
+        ]);
+        match custom {
+            Ok(RelayMode::Custom(map)) => assert_eq!(map.len(), 2),
+            other => panic!("expected custom relay map, got {:?}", other.map(|_| ())),
+        }
+
+        assert!(matches!(relay_mode(&["not a url".into()]), Err(NetworkError::InvalidConfig(_))));
+    }
 }
@@ -1,24 +1,80 @@
 //! Onion routing packet builder and peeler.
 //!
-//! Each layer: VERSION[1] | EPK[32] | NONCE[24] | CIPHERTEXT[N]
+//! v3 layer (built by [`build_onion_packet`]), always [`CELL_SIZE`] bytes on
+//! the wire:
+//!   VERSION[1] | EPK[32] | NONCE[24] | LEN_CT[4+16] | BODY_CT[len+16] | FILL
+//! `LEN_CT` seals the payload length, so neither the op size nor the number
+//! of hops left is visible outside the AEAD. A hop that peels a `Forward`
+//! pads the inner layer back up to a full cell with random fill before
+//! passing it on. The peeling hop itself still learns the inner length.
+//! The body plaintext is `TIMESTAMP:u64 | payload`; see [`crate::replay`].
+//!
+//! v2 layer (still peeled, no longer built):
+//!   VERSION[1] | EPK[32] | NONCE[24] | CIPHERTEXT[N]
+//!
+//! The worker relay peels the same formats in `relay/src/onion.ts`; any
+//! change here has to land there too, or routes through it break.
+//!
 //! Payload:    TYPE[1] | ...
-//!   Forward:  url_len:u16 | url | inner_packet
-//!   Deliver:  topic_id[32] | op
-
-use chacha20poly1305::{AeadCore, KeyInit, XChaCha20Poly1305, XNonce, aead::Aead};
-use rand::rngs::OsRng;
+//!   Forward:   url_len:u16 | url | inner_packet
+//!   Deliver:   topic_id[32] | op
+//!   Drop:      (nothing — cover traffic, discarded by the exit)
+//!   ReplyHop:  url_len:u16 | url | body_key[32] | inner_header
+//!   ReplyExit: topic_id[32] | body_key[32]
+//!
+//! # Reply blocks
+//!
+//! A reply block lets someone answer without learning where the answer goes.
+//! The original sender builds the route's layers ahead of time (the
+//! *header*, ending in `ReplyExit` for a topic they watch) and hands the
+//! header, the first hop's URL and a fresh `reply_key` to the replier, who
+//! appends a fixed-size body sealed under `reply_key` ([`ReplyBlock::seal_reply`]).
+//! Each hop peels its header layer and XORs the bytes after it with a
+//! keystream from its `body_key`, so the body looks different on every link;
+//! the exit delivers the body to the topic. Only the sender, holding every
+//! `body_key` in the [`ReplySecret`], can strip the keystreams and open it
+//! ([`open_reply`]). Hops handle reply packets exactly like forward packets:
+//! `decrypt_layer` returns `Forward`/`Deliver` for them. A reply block is
+//! valid for [`REPLY_BLOCK_TTL_SECS`], so [`peel_layer`] remembers the
+//! reply-block layers it has peeled in SQLite for that long.
+
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20poly1305::{AeadCore, KeyInit, XChaCha20Poly1305, XNonce, aead::{Aead, Payload}};
+use rand::{rngs::OsRng, RngCore};
+use sqlx::SqlitePool;
 use thiserror::Error;
 use x25519_dalek::{EphemeralSecret, PublicKey as X25519Public};
 
 use crate::crypto::{derive_aead_key, ed25519_pubkey_to_x25519, ed25519_seed_to_x25519};
+use crate::replay;
 
 // ── Constants ─────────────────────────────────────────────────────────────────
 
 const VERSION: u8      = 0x02;
+const VERSION_V3: u8   = 0x03;
 const EPK_LEN: usize   = 32;
 const NONCE_LEN: usize = 24;
-const MIN_LEN: usize   = 1 + EPK_LEN + NONCE_LEN + 16;
+const TAG_LEN: usize   = 16;
+const HEADER_LEN: usize = 1 + EPK_LEN + NONCE_LEN;
+const LEN_CT_LEN: usize = 4 + TAG_LEN;
+const MIN_LEN: usize   = HEADER_LEN + TAG_LEN;
+const MIN_LEN_V3: usize = HEADER_LEN + LEN_CT_LEN + TAG_LEN;
 const HKDF_INFO: &[u8] = b"gardens:onion:v1";
+const HKDF_INFO_V3_LEN: &[u8]  = b"gardens:onion:v3:len";
+const HKDF_INFO_V3_BODY: &[u8] = b"gardens:onion:v3:body";
+
+/// Size of every v3 onion packet on the wire.
+pub const CELL_SIZE: usize = 32 * 1024;
+
+/// Size of the reply body carried behind a reply block's header.
+pub const REPLY_BODY_LEN: usize = 16 * 1024;
+
+/// Largest op a reply body can hold: nonce, tag and length prefix come off.
+pub const MAX_REPLY_LEN: usize = REPLY_BODY_LEN - NONCE_LEN - TAG_LEN - 4;
+
+/// How long a reply block stays usable after it is built.
+pub const REPLY_BLOCK_TTL_SECS: u64 = 7 * 24 * 60 * 60;
 
 // ── Error ─────────────────────────────────────────────────────────────────────
 
@@ -38,6 +94,16 @@ pub enum OnionError {
     InvalidPayload,
     #[error("invalid key bytes: {0}")]
     InvalidKey(String),
+    #[error("packet needs {size} bytes, more than one {max}-byte cell")]
+    TooLarge { size: usize, max: usize },
+    #[error("packet already seen")]
+    Replayed,
+    #[error("packet timestamp outside the accepted window")]
+    Stale,
+    #[error("replay store error: {0}")]
+    Storage(String),
+    #[error("relay directory offers only {available} distinct hops, {needed} needed")]
+    InsufficientRelays { available: usize, needed: usize },
 }
 
 // ── Payload ───────────────────────────────────────────────────────────────────
@@ -55,6 +121,22 @@ pub enum OnionPayload {
         topic_id: [u8; 32],
         op: Vec<u8>,
     },
+    /// Cover traffic — the exit discards it.
+    Drop,
+    /// Reply-block hop: forward `inner_header` to `next_hop_url`, carrying
+    /// the reply body behind this layer re-keyed with `body_key`. Only ever
+    /// built into layers; `decrypt_layer` hands it back as a `Forward`.
+    ReplyHop {
+        next_hop_url: String,
+        body_key: [u8; 32],
+        inner_header: Vec<u8>,
+    },
+    /// Reply-block exit: re-key the reply body with `body_key` and deliver it
+    /// to `topic_id`. `decrypt_layer` hands it back as a `Deliver`.
+    ReplyExit {
+        topic_id: [u8; 32],
+        body_key: [u8; 32],
+    },
 }
 
 // ── Payload encode / decode ───────────────────────────────────────────────────
@@ -78,6 +160,24 @@ fn encode_payload(p: &OnionPayload) -> Vec<u8> {
             out.extend_from_slice(op);
             out
         }
+        OnionPayload::Drop => vec![0x03],
+        OnionPayload::ReplyHop { next_hop_url, body_key, inner_header } => {
+            let url_bytes = next_hop_url.as_bytes();
+            let mut out = Vec::with_capacity(3 + url_bytes.len() + 32 + inner_header.len());
+            out.push(0x04);
+            out.extend_from_slice(&(url_bytes.len() as u16).to_be_bytes());
+            out.extend_from_slice(url_bytes);
+            out.extend_from_slice(body_key);
+            out.extend_from_slice(inner_header);
+            out
+        }
+        OnionPayload::ReplyExit { topic_id, body_key } => {
+            let mut out = Vec::with_capacity(1 + 32 + 32);
+            out.push(0x05);
+            out.extend_from_slice(topic_id);
+            out.extend_from_slice(body_key);
+            out
+        }
     }
 }
 
@@ -108,6 +208,29 @@ fn decode_payload(bytes: &[u8]) -> Result<OnionPayload, OnionError> {
             let op = bytes[33..].to_vec();
             Ok(OnionPayload::Deliver { topic_id, op })
         }
+        0x03 => Ok(OnionPayload::Drop),
+        0x04 => {
+            if bytes.len() < 3 {
+                return Err(OnionError::InvalidPayload);
+            }
+            let url_len = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+            if bytes.len() < 3 + url_len + 32 {
+                return Err(OnionError::InvalidPayload);
+            }
+            let url = String::from_utf8(bytes[3..3 + url_len].to_vec())
+                .map_err(|_| OnionError::InvalidPayload)?;
+            let body_key: [u8; 32] = bytes[3 + url_len..3 + url_len + 32].try_into().unwrap();
+            let inner_header = bytes[3 + url_len + 32..].to_vec();
+            Ok(OnionPayload::ReplyHop { next_hop_url: url, body_key, inner_header })
+        }
+        0x05 => {
+            if bytes.len() != 1 + 32 + 32 {
+                return Err(OnionError::InvalidPayload);
+            }
+            let topic_id: [u8; 32] = bytes[1..33].try_into().unwrap();
+            let body_key: [u8; 32] = bytes[33..65].try_into().unwrap();
+            Ok(OnionPayload::ReplyExit { topic_id, body_key })
+        }
         _ => Err(OnionError::InvalidPayload),
     }
 }
@@ -147,15 +270,124 @@ pub fn encrypt_layer(payload: &OnionPayload, hop_pubkey_bytes: &[u8; 32]) -> Res
     Ok(out)
 }
 
+/// Encrypt `payload` for `hop_pubkey_bytes` as a v3 layer, without fill.
+///
+/// The result is at most [`CELL_SIZE`] bytes; [`pad_cell`] brings it up to
+/// exactly that.
+pub fn encrypt_layer_v3(payload: &OnionPayload, hop_pubkey_bytes: &[u8; 32]) -> Result<Vec<u8>, OnionError> {
+    let mut plaintext = replay::now_secs().to_be_bytes().to_vec();
+    plaintext.extend_from_slice(&encode_payload(payload));
+    let size = HEADER_LEN + LEN_CT_LEN + plaintext.len() + TAG_LEN;
+    if size > CELL_SIZE {
+        return Err(OnionError::TooLarge { size, max: CELL_SIZE });
+    }
+
+    let recipient_x25519 = ed25519_pubkey_to_x25519(hop_pubkey_bytes);
+    let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = X25519Public::from(&ephemeral_secret);
+    let shared = ephemeral_secret.diffie_hellman(&recipient_x25519);
+    let (len_cipher, body_cipher) = v3_ciphers(shared.as_bytes(), ephemeral_public.as_bytes())?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let mut out = Vec::with_capacity(size);
+    out.push(VERSION_V3);
+    out.extend_from_slice(ephemeral_public.as_bytes());
+    out.extend_from_slice(&nonce);
+    let len_ct = len_cipher
+        .encrypt(&nonce, (plaintext.len() as u32).to_be_bytes().as_slice())
+        .map_err(|_| OnionError::Encrypt)?;
+    out.extend_from_slice(&len_ct);
+
+    // The body is bound to everything before it, including the sealed length.
+    let body_ct = body_cipher
+        .encrypt(&nonce, Payload { msg: &plaintext, aad: &out })
+        .map_err(|_| OnionError::Encrypt)?;
+    out.extend_from_slice(&body_ct);
+    Ok(out)
+}
+
+/// Pad a v3 layer with random fill to exactly [`CELL_SIZE`] bytes.
+pub fn pad_cell(mut layer: Vec<u8>) -> Result<Vec<u8>, OnionError> {
+    if layer.len() > CELL_SIZE {
+        return Err(OnionError::TooLarge { size: layer.len(), max: CELL_SIZE });
+    }
+    let start = layer.len();
+    layer.resize(CELL_SIZE, 0);
+    OsRng.fill_bytes(&mut layer[start..]);
+    Ok(layer)
+}
+
+fn v3_ciphers(shared: &[u8; 32], epk: &[u8; 32]) -> Result<(XChaCha20Poly1305, XChaCha20Poly1305), OnionError> {
+    let len_key = derive_aead_key(shared, epk, HKDF_INFO_V3_LEN);
+    let body_key = derive_aead_key(shared, epk, HKDF_INFO_V3_BODY);
+    Ok((
+        XChaCha20Poly1305::new_from_slice(&len_key).map_err(|_| OnionError::Encrypt)?,
+        XChaCha20Poly1305::new_from_slice(&body_key).map_err(|_| OnionError::Encrypt)?,
+    ))
+}
+
 /// Decrypt one onion layer using the recipient's 32-byte Ed25519 seed.
+///
+/// For a v3 `Forward`, `inner_packet` comes back already padded to a full
+/// cell, ready to send on. A layer this process has already peeled fails
+/// with [`OnionError::Replayed`] before any decryption is attempted.
+///
+/// The in-memory cache only covers [`replay::MAX_AGE_SECS`]; nodes with a
+/// read model peel through [`peel_layer`], which keeps reply blocks
+/// single-use for their whole lifetime.
 pub fn decrypt_layer(envelope: &[u8], recipient_seed_bytes: &[u8; 32]) -> Result<OnionPayload, OnionError> {
+    decrypt_layer_tracked(envelope, recipient_seed_bytes).map(|(payload, _)| payload)
+}
+
+/// [`decrypt_layer`], recording reply-block layers in `used_reply_blocks`
+/// until they would be stale, so a reply block can't be replayed after the
+/// in-memory cache forgets it or across restarts.
+pub async fn peel_layer(
+    pool: &SqlitePool,
+    envelope: &[u8],
+    recipient_seed_bytes: &[u8; 32],
+) -> Result<OnionPayload, OnionError> {
+    let (payload, reply_id) = decrypt_layer_tracked(envelope, recipient_seed_bytes)?;
+    if let Some(reply_id) = reply_id {
+        let now = replay::now_secs();
+        let expires_at = now + REPLY_BLOCK_TTL_SECS + replay::MAX_SKEW_SECS;
+        let fresh = crate::db::claim_reply_block(pool, &reply_id, now as i64, expires_at as i64)
+            .await
+            .map_err(|e| OnionError::Storage(e.to_string()))?;
+        if !fresh {
+            return Err(OnionError::Replayed);
+        }
+    }
+    Ok(payload)
+}
+
+/// Peel a layer; the replay id comes back too if it was a reply-block layer.
+fn decrypt_layer_tracked(
+    envelope: &[u8],
+    recipient_seed_bytes: &[u8; 32],
+) -> Result<(OnionPayload, Option<replay::ReplayKey>), OnionError> {
     if envelope.len() < MIN_LEN {
         return Err(OnionError::InvalidEnvelope);
     }
-    if envelope[0] != VERSION {
-        return Err(OnionError::UnsupportedVersion(envelope[0]));
+    let replay_key = replay::key_of(envelope).ok_or(OnionError::InvalidEnvelope)?;
+    let now = replay::now_secs();
+    if replay::onion().seen(&replay_key, now) {
+        return Err(OnionError::Replayed);
     }
 
+    let (payload, is_reply_block) = match envelope[0] {
+        VERSION_V3 => decrypt_layer_v3(envelope, recipient_seed_bytes, now)?,
+        VERSION => (decrypt_layer_v2(envelope, recipient_seed_bytes)?, false),
+        other => return Err(OnionError::UnsupportedVersion(other)),
+    };
+
+    if !replay::onion().insert(replay_key, now) {
+        return Err(OnionError::Replayed);
+    }
+    Ok((payload, is_reply_block.then_some(replay_key)))
+}
+
+fn decrypt_layer_v2(envelope: &[u8], recipient_seed_bytes: &[u8; 32]) -> Result<OnionPayload, OnionError> {
     let epk_bytes: [u8; 32]   = envelope[1..33].try_into().unwrap();
     let nonce_bytes: [u8; 24] = envelope[33..57].try_into().unwrap();
     let ciphertext = &envelope[57..];
@@ -169,7 +401,86 @@ pub fn decrypt_layer(envelope: &[u8], recipient_seed_bytes: &[u8; 32]) -> Result
     let nonce     = XNonce::from_slice(&nonce_bytes);
     let plaintext = cipher.decrypt(nonce, ciphertext).map_err(|_| OnionError::Decrypt)?;
 
-    decode_payload(&plaintext)
+    match decode_payload(&plaintext)? {
+        // Reply blocks are v3 only.
+        OnionPayload::ReplyHop { .. } | OnionPayload::ReplyExit { .. } => Err(OnionError::InvalidPayload),
+        other => Ok(other),
+    }
+}
+
+/// Returns the payload and whether the layer was a reply-block layer.
+fn decrypt_layer_v3(
+    envelope: &[u8],
+    recipient_seed_bytes: &[u8; 32],
+    now: u64,
+) -> Result<(OnionPayload, bool), OnionError> {
+    if envelope.len() < MIN_LEN_V3 || envelope.len() > CELL_SIZE {
+        return Err(OnionError::InvalidEnvelope);
+    }
+
+    let epk_bytes: [u8; 32] = envelope[1..33].try_into().unwrap();
+    let nonce = XNonce::from_slice(&envelope[33..HEADER_LEN]);
+    let ephemeral_public = X25519Public::from(epk_bytes);
+    let recipient_x25519 = ed25519_seed_to_x25519(recipient_seed_bytes);
+    let shared = recipient_x25519.diffie_hellman(&ephemeral_public);
+    let (len_cipher, body_cipher) =
+        v3_ciphers(shared.as_bytes(), &epk_bytes).map_err(|_| OnionError::Decrypt)?;
+
+    let body_start = HEADER_LEN + LEN_CT_LEN;
+    let len_bytes = len_cipher
+        .decrypt(nonce, &envelope[HEADER_LEN..body_start])
+        .map_err(|_| OnionError::Decrypt)?;
+    let len = u32::from_be_bytes(len_bytes.try_into().map_err(|_| OnionError::InvalidEnvelope)?) as usize;
+    let body_end = body_start
+        .checked_add(len)
+        .and_then(|n| n.checked_add(TAG_LEN))
+        .filter(|&n| n <= envelope.len())
+        .ok_or(OnionError::InvalidEnvelope)?;
+
+    let plaintext = body_cipher
+        .decrypt(nonce, Payload { msg: &envelope[body_start..body_end], aad: &envelope[..body_start] })
+        .map_err(|_| OnionError::Decrypt)?;
+
+    if plaintext.len() < 8 {
+        return Err(OnionError::InvalidPayload);
+    }
+    let (timestamp, payload) = plaintext.split_at(8);
+    let timestamp = u64::from_be_bytes(timestamp.try_into().unwrap());
+    let payload = decode_payload(payload)?;
+    let max_age = match payload {
+        OnionPayload::ReplyHop { .. } | OnionPayload::ReplyExit { .. } => REPLY_BLOCK_TTL_SECS,
+        _ => replay::MAX_AGE_SECS,
+    };
+    if !replay::check_timestamp_within(timestamp, now, max_age) {
+        return Err(OnionError::Stale);
+    }
+
+    // Everything after this layer: fill, or a reply body behind a header.
+    let trailing = &envelope[body_end..];
+    match payload {
+        OnionPayload::Forward { next_hop_url, inner_packet } => Ok((
+            OnionPayload::Forward { next_hop_url, inner_packet: pad_cell(inner_packet)? },
+            false,
+        )),
+        OnionPayload::ReplyHop { next_hop_url, body_key, mut inner_header } => {
+            let mut body = trailing.to_vec();
+            apply_body_key(&body_key, &mut body);
+            inner_header.extend_from_slice(&body);
+            Ok((OnionPayload::Forward { next_hop_url, inner_packet: pad_cell(inner_header)? }, true))
+        }
+        OnionPayload::ReplyExit { topic_id, body_key } => {
+            let mut body = trailing.get(..REPLY_BODY_LEN).ok_or(OnionError::InvalidEnvelope)?.to_vec();
+            apply_body_key(&body_key, &mut body);
+            Ok((OnionPayload::Deliver { topic_id, op: body }, true))
+        }
+        other => Ok((other, false)),
+    }
+}
+
+/// XOR `body` with the ChaCha20 keystream of a single-use `body_key`.
+fn apply_body_key(body_key: &[u8; 32], body: &mut [u8]) {
+    let mut cipher = ChaCha20::new(body_key.into(), &[0u8; 12].into());
+    cipher.apply_keystream(body);
 }
 
 // ── Multi-layer packet builder ────────────────────────────────────────────────
@@ -178,22 +489,32 @@ pub fn decrypt_layer(envelope: &[u8], recipient_seed_bytes: &[u8; 32]) -> Result
 ///
 /// Route: hops[0] → hops[1] → ... → hops[N-1] → deliver `op` to `topic_id`.
 ///
-/// The sender posts the returned bytes to `hops[0].next_url`.
+/// The sender posts the returned [`CELL_SIZE`] bytes to `hops[0].next_url`.
 pub fn build_onion_packet(
     hops: &[OnionHop],
     topic_id: &[u8; 32],
     op: &[u8],
 ) -> Result<Vec<u8>, OnionError> {
-    if hops.is_empty() {
-        return Err(OnionError::EmptyRoute);
-    }
-
-    // Innermost layer: Deliver instruction encrypted to the last hop's key.
     let deliver = OnionPayload::Deliver {
         topic_id: *topic_id,
         op: op.to_vec(),
     };
-    let mut current = encrypt_layer(&deliver, &hops[hops.len() - 1].pubkey_bytes)?;
+    wrap_route(hops, deliver)
+}
+
+/// Build a cover-traffic packet: it travels the whole route like a real one
+/// and the exit drops it.
+pub fn build_cover_packet(hops: &[OnionHop]) -> Result<Vec<u8>, OnionError> {
+    wrap_route(hops, OnionPayload::Drop)
+}
+
+fn wrap_route(hops: &[OnionHop], exit: OnionPayload) -> Result<Vec<u8>, OnionError> {
+    if hops.is_empty() {
+        return Err(OnionError::EmptyRoute);
+    }
+
+    // Innermost layer: the exit instruction encrypted to the last hop's key.
+    let mut current = encrypt_layer_v3(&exit, &hops[hops.len() - 1].pubkey_bytes)?;
 
     // Wrap remaining hops outside-in.
     // For hops[i], the Forward payload tells it to POST the inner packet
@@ -203,10 +524,158 @@ pub fn build_onion_packet(
             next_hop_url: hops[i + 1].next_url.clone(),
             inner_packet: current,
         };
-        current = encrypt_layer(&forward, &hops[i].pubkey_bytes)?;
+        current = encrypt_layer_v3(&forward, &hops[i].pubkey_bytes)?;
     }
 
-    Ok(current)
+    pad_cell(current)
+}
+
+// ── Reply blocks ──────────────────────────────────────────────────────────────
+
+/// What the replier gets: enough to send one reply, nothing about where it
+/// ends up.
+pub struct ReplyBlock {
+    /// Where the reply packet is posted.
+    pub first_hop_url: String,
+    /// The pre-built route layers, unpadded.
+    pub header: Vec<u8>,
+    /// Key the reply body is sealed under.
+    pub reply_key: [u8; 32],
+}
+
+/// What the sender keeps to open the reply: the body key and every hop's
+/// keystream key.
+pub struct ReplySecret {
+    pub reply_key: [u8; 32],
+    pub body_keys: Vec<[u8; 32]>,
+}
+
+/// Build a reply block whose route runs `hops[0] → … → hops[N-1]` and
+/// delivers to `topic_id`.
+pub fn build_reply_block(
+    hops: &[OnionHop],
+    topic_id: &[u8; 32],
+) -> Result<(ReplyBlock, ReplySecret), OnionError> {
+    if hops.is_empty() {
+        return Err(OnionError::EmptyRoute);
+    }
+
+    let body_keys: Vec<[u8; 32]> = hops.iter().map(|_| random_key()).collect();
+    let last = hops.len() - 1;
+    let exit = OnionPayload::ReplyExit { topic_id: *topic_id, body_key: body_keys[last] };
+    let mut header = encrypt_layer_v3(&exit, &hops[last].pubkey_bytes)?;
+    for i in (0..last).rev() {
+        let hop = OnionPayload::ReplyHop {
+            next_hop_url: hops[i + 1].next_url.clone(),
+            body_key: body_keys[i],
+            inner_header: header,
+        };
+        header = encrypt_layer_v3(&hop, &hops[i].pubkey_bytes)?;
+    }
+
+    let size = header.len() + REPLY_BODY_LEN;
+    if size > CELL_SIZE {
+        return Err(OnionError::TooLarge { size, max: CELL_SIZE });
+    }
+
+    let reply_key = random_key();
+    Ok((
+        ReplyBlock { first_hop_url: hops[0].next_url.clone(), header, reply_key },
+        ReplySecret { reply_key, body_keys },
+    ))
+}
+
+impl ReplyBlock {
+    /// Seal `op` into a reply packet, to be posted to `first_hop_url`.
+    pub fn seal_reply(&self, op: &[u8]) -> Result<Vec<u8>, OnionError> {
+        if op.len() > MAX_REPLY_LEN {
+            return Err(OnionError::TooLarge { size: op.len(), max: MAX_REPLY_LEN });
+        }
+        let mut plaintext = Vec::with_capacity(REPLY_BODY_LEN - NONCE_LEN - TAG_LEN);
+        plaintext.extend_from_slice(&(op.len() as u32).to_be_bytes());
+        plaintext.extend_from_slice(op);
+        plaintext.resize(REPLY_BODY_LEN - NONCE_LEN - TAG_LEN, 0);
+
+        let cipher = XChaCha20Poly1305::new_from_slice(&self.reply_key).map_err(|_| OnionError::Encrypt)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, plaintext.as_slice()).map_err(|_| OnionError::Encrypt)?;
+
+        let mut packet = self.header.clone();
+        packet.extend_from_slice(&nonce);
+        packet.extend_from_slice(&ciphertext);
+        pad_cell(packet)
+    }
+
+    /// `url_len:u16 | url | reply_key[32] | header`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let url = self.first_hop_url.as_bytes();
+        let mut out = Vec::with_capacity(2 + url.len() + 32 + self.header.len());
+        out.extend_from_slice(&(url.len() as u16).to_be_bytes());
+        out.extend_from_slice(url);
+        out.extend_from_slice(&self.reply_key);
+        out.extend_from_slice(&self.header);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OnionError> {
+        if bytes.len() < 2 {
+            return Err(OnionError::InvalidPayload);
+        }
+        let url_len = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+        if bytes.len() < 2 + url_len + 32 + MIN_LEN_V3 {
+            return Err(OnionError::InvalidPayload);
+        }
+        let first_hop_url = String::from_utf8(bytes[2..2 + url_len].to_vec())
+            .map_err(|_| OnionError::InvalidPayload)?;
+        let reply_key: [u8; 32] = bytes[2 + url_len..2 + url_len + 32].try_into().unwrap();
+        let header = bytes[2 + url_len + 32..].to_vec();
+        if header.len() + REPLY_BODY_LEN > CELL_SIZE {
+            return Err(OnionError::InvalidPayload);
+        }
+        Ok(ReplyBlock { first_hop_url, header, reply_key })
+    }
+}
+
+impl ReplySecret {
+    /// `reply_key[32] | body_key[32]…`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(32 * (1 + self.body_keys.len()));
+        out.extend_from_slice(&self.reply_key);
+        for key in &self.body_keys {
+            out.extend_from_slice(key);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OnionError> {
+        if bytes.len() < 64 || !bytes.len().is_multiple_of(32) {
+            return Err(OnionError::InvalidKey("reply secret must be 32-byte keys".to_string()));
+        }
+        let reply_key: [u8; 32] = bytes[..32].try_into().unwrap();
+        let body_keys = bytes[32..].chunks_exact(32).map(|c| c.try_into().unwrap()).collect();
+        Ok(ReplySecret { reply_key, body_keys })
+    }
+}
+
+/// Open a reply body delivered to the sender's topic.
+pub fn open_reply(secret: &ReplySecret, delivered: &[u8]) -> Result<Vec<u8>, OnionError> {
+    let mut body = delivered.get(..REPLY_BODY_LEN).ok_or(OnionError::InvalidEnvelope)?.to_vec();
+    for key in &secret.body_keys {
+        apply_body_key(key, &mut body);
+    }
+
+    let cipher = XChaCha20Poly1305::new_from_slice(&secret.reply_key).map_err(|_| OnionError::Decrypt)?;
+    let nonce = XNonce::from_slice(&body[..NONCE_LEN]);
+    let plaintext = cipher.decrypt(nonce, &body[NONCE_LEN..]).map_err(|_| OnionError::Decrypt)?;
+
+    let len = u32::from_be_bytes(plaintext[..4].try_into().unwrap()) as usize;
+    plaintext.get(4..4 + len).map(<[u8]>::to_vec).ok_or(OnionError::InvalidPayload)
+}
+
+fn random_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
 }
 
 #[cfg(test)]
@@ -362,6 +831,165 @@ mod tests {
         }
     }
 
+    #[test]
+    fn every_hop_sees_a_full_cell() {
+        let (hop1_seed, hop1_pk) = random_keypair();
+        let (hop2_seed, hop2_pk) = random_keypair();
+        let hops = vec![
+            OnionHop { pubkey_bytes: hop1_pk, next_url: "https://hop1.example.com/hop".to_string() },
+            OnionHop { pubkey_bytes: hop2_pk, next_url: "https://hop2.example.com/hop".to_string() },
+        ];
+
+        let small = build_onion_packet(&hops, &[1u8; 32], b"hi").unwrap();
+        let large = build_onion_packet(&hops, &[1u8; 32], &vec![7u8; 20_000]).unwrap();
+        assert_eq!(small.len(), CELL_SIZE);
+        assert_eq!(large.len(), CELL_SIZE);
+
+        let inner = match decrypt_layer(&large, &hop1_seed).unwrap() {
+            OnionPayload::Forward { inner_packet, .. } => inner_packet,
+            _ => panic!("hop1 should see Forward"),
+        };
+        assert_eq!(inner.len(), CELL_SIZE);
+        match decrypt_layer(&inner, &hop2_seed).unwrap() {
+            OnionPayload::Deliver { op, .. } => assert_eq!(op, vec![7u8; 20_000]),
+            _ => panic!("hop2 should see Deliver"),
+        }
+    }
+
+    #[test]
+    fn oversized_op_is_rejected() {
+        let (_, pk) = random_keypair();
+        let hops = vec![OnionHop { pubkey_bytes: pk, next_url: "https://hop.example.com".to_string() }];
+        assert!(matches!(
+            build_onion_packet(&hops, &[0u8; 32], &vec![0u8; CELL_SIZE]),
+            Err(OnionError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn tampered_fill_is_ignored_but_tampered_length_is_not() {
+        let (seed, pk) = random_keypair();
+        let hops = vec![OnionHop { pubkey_bytes: pk, next_url: "https://hop.example.com".to_string() }];
+
+        let mut packet = build_onion_packet(&hops, &[0u8; 32], b"msg").unwrap();
+        packet[CELL_SIZE - 1] ^= 0xff;
+        assert!(decrypt_layer(&packet, &seed).is_ok());
+
+        let mut packet = build_onion_packet(&hops, &[0u8; 32], b"msg").unwrap();
+        packet[HEADER_LEN] ^= 0xff;
+        assert!(matches!(decrypt_layer(&packet, &seed), Err(OnionError::Decrypt)));
+    }
+
+    #[test]
+    fn replayed_layer_is_dropped() {
+        let (seed, pk) = random_keypair();
+        let hops = vec![OnionHop { pubkey_bytes: pk, next_url: "https://hop.example.com".to_string() }];
+        let packet = build_onion_packet(&hops, &[0u8; 32], b"msg").unwrap();
+
+        assert!(decrypt_layer(&packet, &seed).is_ok());
+        assert!(matches!(decrypt_layer(&packet, &seed), Err(OnionError::Replayed)));
+    }
+
+    #[test]
+    fn forged_copy_does_not_burn_the_genuine_packet() {
+        let (seed, pk) = random_keypair();
+        let hops = vec![OnionHop { pubkey_bytes: pk, next_url: "https://hop.example.com".to_string() }];
+        let packet = build_onion_packet(&hops, &[0u8; 32], b"msg").unwrap();
+
+        let mut forged = packet.clone();
+        forged[HEADER_LEN + LEN_CT_LEN] ^= 0xff;
+        assert!(decrypt_layer(&forged, &seed).is_err());
+        assert!(decrypt_layer(&packet, &seed).is_ok());
+    }
+
+    #[test]
+    fn cover_packet_is_dropped_at_the_exit() {
+        let (seed, pk) = random_keypair();
+        let hops = vec![OnionHop { pubkey_bytes: pk, next_url: "https://hop.example.com".to_string() }];
+        let packet = build_cover_packet(&hops).unwrap();
+        assert_eq!(packet.len(), CELL_SIZE);
+        assert!(matches!(decrypt_layer(&packet, &seed).unwrap(), OnionPayload::Drop));
+    }
+
+    #[test]
+    fn reply_travels_the_block_route_and_opens_for_the_sender() {
+        let (hop1_seed, hop1_pk) = random_keypair();
+        let (hop2_seed, hop2_pk) = random_keypair();
+        let hops = vec![
+            OnionHop { pubkey_bytes: hop1_pk, next_url: "https://hop1.example.com/hop".to_string() },
+            OnionHop { pubkey_bytes: hop2_pk, next_url: "https://hop2.example.com/hop".to_string() },
+        ];
+        let tid = [0x5au8; 32];
+
+        let (block, secret) = build_reply_block(&hops, &tid).unwrap();
+        let block = ReplyBlock::from_bytes(&block.to_bytes()).unwrap();
+        let secret = ReplySecret::from_bytes(&secret.to_bytes()).unwrap();
+        assert_eq!(block.first_hop_url, "https://hop1.example.com/hop");
+
+        let packet = block.seal_reply(b"anonymous reply").unwrap();
+        assert_eq!(packet.len(), CELL_SIZE);
+
+        let inner = match decrypt_layer(&packet, &hop1_seed).unwrap() {
+            OnionPayload::Forward { next_hop_url, inner_packet } => {
+                assert_eq!(next_hop_url, "https://hop2.example.com/hop");
+                inner_packet
+            }
+            _ => panic!("hop1 should see Forward"),
+        };
+        assert_eq!(inner.len(), CELL_SIZE);
+
+        let delivered = match decrypt_layer(&inner, &hop2_seed).unwrap() {
+            OnionPayload::Deliver { topic_id, op } => {
+                assert_eq!(topic_id, tid);
+                op
+            }
+            _ => panic!("hop2 should see Deliver"),
+        };
+
+        // The body is re-keyed on the way, so only the block's secret opens it.
+        assert_eq!(open_reply(&secret, &delivered).unwrap(), b"anonymous reply");
+        let (_, other_secret) = build_reply_block(&hops, &tid).unwrap();
+        assert!(open_reply(&other_secret, &delivered).is_err());
+    }
+
+    #[test]
+    fn reply_block_is_single_use_at_the_first_hop() {
+        let (seed, pk) = random_keypair();
+        let hops = vec![OnionHop { pubkey_bytes: pk, next_url: "https://hop.example.com".to_string() }];
+        let (block, _) = build_reply_block(&hops, &[0u8; 32]).unwrap();
+
+        assert!(decrypt_layer(&block.seal_reply(b"one").unwrap(), &seed).is_ok());
+        assert!(matches!(
+            decrypt_layer(&block.seal_reply(b"two").unwrap(), &seed),
+            Err(OnionError::Replayed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn peeled_reply_blocks_are_remembered_for_their_lifetime() {
+        let core = crate::store::test_core().await;
+        let (seed, pk) = random_keypair();
+        let hops = vec![OnionHop { pubkey_bytes: pk, next_url: "https://hop.example.com".to_string() }];
+        let (block, _) = build_reply_block(&hops, &[0u8; 32]).unwrap();
+        let reply = block.seal_reply(b"one").unwrap();
+        let forward = build_onion_packet(&hops, &[0u8; 32], b"op").unwrap();
+
+        assert!(peel_layer(&core.read_pool, &reply, &seed).await.is_ok());
+        assert!(peel_layer(&core.read_pool, &forward, &seed).await.is_ok());
+
+        // After a restart the in-memory cache is empty; the table still knows.
+        let now = replay::now_secs() as i64;
+        let later = now + REPLY_BLOCK_TTL_SECS as i64 - 60;
+        let reply_id = replay::key_of(&reply).unwrap();
+        assert!(!crate::db::claim_reply_block(&core.read_pool, &reply_id, later, later + 60).await.unwrap());
+        let forward_id = replay::key_of(&forward).unwrap();
+        assert!(crate::db::claim_reply_block(&core.read_pool, &forward_id, now, now + 60).await.unwrap());
+
+        // Once stale, the block would be refused anyway and is forgotten.
+        let expired = now + (REPLY_BLOCK_TTL_SECS + replay::MAX_SKEW_SECS) as i64 + 1;
+        assert!(crate::db::claim_reply_block(&core.read_pool, &reply_id, expired, expired + 60).await.unwrap());
+    }
+
     #[test]
     fn empty_route_returns_error() {
         let hops: Vec<OnionHop> = vec![];
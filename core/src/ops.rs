@@ -7,7 +7,7 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use ciborium::{from_reader, into_writer};
-use p2panda_core::{Body, Hash, Header, PrivateKey};
+use p2panda_core::{Body, Hash, Header, PrivateKey, PublicKey};
 use p2panda_store::{LogStore, OperationStore};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -174,9 +174,56 @@ pub struct MessageOp {
     pub content_type: String,     // "text" | "audio" | "image" | "gif" | "video" | "embed"
     pub text_content: Option<String>,
     pub blob_id: Option<String>,
+    /// Content key and nonce prefix of `blob_id` (see `blobs::seal_blob`).
+    /// Travels inside the encrypted op, so whoever can read the message can
+    /// read the attachment.
+    #[serde(default)]
+    pub blob_key: Option<Vec<u8>>,
+    #[serde(default)]
+    pub blob_nonce: Option<Vec<u8>>,
+    /// Placeholder for an image `blob_id`, rendered before it is fetched.
+    #[serde(default)]
+    pub preview: Option<ImagePreview>,
     pub embed_url: Option<String>,
     pub mentions: Vec<String>,    // hex public keys
     pub reply_to: Option<String>, // hex op hash
+    #[serde(default)]
+    pub target_id: Option<String>, // hex op hash of the message an "edit"/"delete" applies to
+}
+
+/// Dimensions, blurhash and thumbnail of an image attachment (see `media`).
+/// The thumbnail is a blob of its own, sealed with its own content key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImagePreview {
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    pub thumbnail_id: Option<String>,
+    pub thumbnail_key: Option<Vec<u8>>,
+    pub thumbnail_nonce: Option<Vec<u8>>,
+}
+
+/// Op body for end-to-end encrypted room payloads (messages, reactions, edits).
+///
+/// `encrypted` is a CBOR-encoded `encryption::EncryptedBody` whose plaintext is
+/// the CBOR of the original payload (`MessageOp` or `ReactionOp`).  `room_id`
+/// stays in the clear so receivers know which group state to decrypt with.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncryptedRoomOp {
+    pub op_type: String, // "encrypted"
+    pub room_id: String,
+    pub encrypted: Vec<u8>,
+}
+
+/// Op body for end-to-end encrypted DM payloads.
+///
+/// `encrypted` is a CBOR-encoded `encryption::EncryptedDmBody` produced by the
+/// thread's forward-secure message-scheme group.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncryptedDmOp {
+    pub op_type: String, // "encrypted_dm"
+    pub dm_thread_id: String,
+    pub encrypted: Vec<u8>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -249,6 +296,16 @@ pub struct GossipEnvelope {
     pub body_bytes: Vec<u8>,
 }
 
+impl GossipEnvelope {
+    /// Signed header timestamp (micros) of the op, the time every peer
+    /// projects it at.
+    pub fn timestamp(&self) -> Result<i64, OpsError> {
+        let header = Header::<()>::try_from(self.header_bytes.as_slice())
+            .map_err(|e| OpsError::CborDecode(e.to_string()))?;
+        Ok(header.timestamp as i64)
+    }
+}
+
 // ─── CBOR helpers ────────────────────────────────────────────────────────────
 
 pub fn encode_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, OpsError> {
@@ -274,6 +331,19 @@ pub async fn sign_and_store_op(
     private_key: &PrivateKey,
     log_id: &str,
     body_bytes: Vec<u8>,
+) -> Result<(Hash, Vec<u8>), OpsError> {
+    sign_and_store_op_with_previous(store, private_key, log_id, body_bytes, vec![]).await
+}
+
+/// Like [`sign_and_store_op`], with the hashes of the ops this one refers to
+/// in the header's `previous` field. The projector holds the op back until
+/// every one of them has been projected.
+pub async fn sign_and_store_op_with_previous(
+    store: &mut GardensStore,
+    private_key: &PrivateKey,
+    log_id: &str,
+    body_bytes: Vec<u8>,
+    previous: Vec<Hash>,
 ) -> Result<(Hash, Vec<u8>), OpsError> {
     let public_key = private_key.public_key();
     let log_id_str = log_id.to_string();
@@ -305,7 +375,7 @@ pub async fn sign_and_store_op(
         timestamp,
         seq_num,
         backlink,
-        previous: vec![],
+        previous,
         extensions: (),
     };
 
@@ -319,6 +389,8 @@ pub async fn sign_and_store_op(
         .await
         .map_err(|e| OpsError::Store(e.to_string()))?;
 
+    crate::projector::notify_op(public_key, &log_id_str);
+
     // Build the gossip envelope the caller can use for real-time delivery.
     let gossip_bytes = encode_cbor(&GossipEnvelope {
         log_id: log_id_str,
@@ -342,11 +414,49 @@ pub async fn publish<T: Serialize>(
     sign_and_store_op(store, private_key, log_id, body_bytes).await
 }
 
+/// Convenience: encode payload to CBOR and store it with causal `previous` links.
+pub async fn publish_with_previous<T: Serialize>(
+    store: &mut GardensStore,
+    private_key: &PrivateKey,
+    log_id: &str,
+    payload: &T,
+    previous: Vec<Hash>,
+) -> Result<(Hash, Vec<u8>), OpsError> {
+    let body_bytes = encode_cbor(payload)?;
+    sign_and_store_op_with_previous(store, private_key, log_id, body_bytes, previous).await
+}
+
+/// Turn the ids of referenced ops (rooms, orgs and messages are keyed by the
+/// hex hash of the op that created them) into `previous` links. Ids that
+/// aren't op hashes are skipped.
+pub fn previous_from_ids<S: AsRef<str>>(ids: impl IntoIterator<Item = S>) -> Vec<Hash> {
+    ids.into_iter()
+        .filter_map(|id| hex::decode(id.as_ref()).ok())
+        .filter_map(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+        .map(Hash::from_bytes)
+        .collect()
+}
+
+/// Parse a hex-encoded author key, as stored in the read model.
+pub(crate) fn parse_public_key(key_hex: &str) -> Option<PublicKey> {
+    hex::decode(key_hex)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .and_then(|arr| PublicKey::from_bytes(&arr).ok())
+}
+
 // ─── Tests for Phase 4 encryption ops ───────────────────────────────────────
 #[cfg(test)]
 mod enc_ops_tests {
     use super::*;
 
+    #[test]
+    fn previous_from_ids_skips_non_hash_ids() {
+        let hash = Hash::new(b"room");
+        let previous = previous_from_ids([hash.to_hex().as_str(), "not-a-hash", "abcd"]);
+        assert_eq!(previous, vec![hash]);
+    }
+
     #[test]
     fn key_bundle_op_cbor_roundtrip() {
         let op = KeyBundleOp { bundle_type: "long_term".into(), bundle_data: vec![1, 2, 3] };
@@ -376,4 +486,50 @@ mod enc_ops_tests {
         let decoded: EncDirectOp = decode_cbor(&bytes).unwrap();
         assert_eq!(decoded.recipient_key, "deadbeef");
     }
+
+    #[test]
+    fn encrypted_room_op_is_distinct_from_plain_payloads() {
+        let op = EncryptedRoomOp {
+            op_type: "encrypted".into(),
+            room_id: "room1".into(),
+            encrypted: vec![1, 2, 3],
+        };
+        let bytes = encode_cbor(&op).unwrap();
+        let decoded: EncryptedRoomOp = decode_cbor(&bytes).unwrap();
+        assert_eq!(decoded.room_id, "room1");
+        assert_eq!(decoded.encrypted, vec![1, 2, 3]);
+
+        // A plaintext MessageOp must not be mistaken for an encrypted envelope.
+        let plain = encode_cbor(&MessageOp {
+            op_type: "send".into(),
+            room_id: Some("room1".into()),
+            dm_thread_id: None,
+            content_type: "text".into(),
+            text_content: Some("hi".into()),
+            blob_id: None,
+            blob_key: None,
+            blob_nonce: None,
+            preview: None,
+            embed_url: None,
+            mentions: vec![],
+            reply_to: None,
+            target_id: None,
+        })
+        .unwrap();
+        assert!(decode_cbor::<EncryptedRoomOp>(&plain).is_err());
+    }
+
+    #[test]
+    fn encrypted_dm_op_cbor_roundtrip() {
+        let op = EncryptedDmOp {
+            op_type: "encrypted_dm".into(),
+            dm_thread_id: "thread1".into(),
+            encrypted: vec![4, 5, 6],
+        };
+        let bytes = encode_cbor(&op).unwrap();
+        let decoded: EncryptedDmOp = decode_cbor(&bytes).unwrap();
+        assert_eq!(decoded.op_type, "encrypted_dm");
+        assert_eq!(decoded.dm_thread_id, "thread1");
+        assert!(decode_cbor::<EncryptedRoomOp>(&bytes).is_err());
+    }
 }
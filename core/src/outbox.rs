@@ -0,0 +1,364 @@
+//! Durable delivery of locally authored ops.
+//!
+//! Gossip and the sync worker are both best-effort: a publish fails while
+//! the network is down, and nothing retries it. Every op the user sends to a
+//! room, DM thread or inbox is therefore queued in the `outbox` table, once
+//! per transport:
+//!
+//! - `gossip` — the room topic, or the recipient's DM inbox (sealed sender),
+//!   counted as delivered only once the topic has a neighbour to take it;
+//! - `onion`  — through the configured relay hops, to the sync topic or, via
+//!   a core exit, sealed to the recipient's DM inbox;
+//! - `sync`   — the sync topic posted straight to the sync worker, when no
+//!   relay hops are configured.
+//!
+//! [`run_outbox`] attempts due deliveries and reschedules failures with
+//! exponential backoff, giving up after [`MAX_ATTEMPTS`]. The queue is what
+//! backs the UI's "not yet delivered" markers (`list_pending_outbound`).
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use base64::{engine::general_purpose, Engine as _};
+use p2panda_core::{Hash, PrivateKey};
+use sqlx::SqlitePool;
+use tokio::sync::Notify;
+
+use crate::db::{self, OutboxRow};
+use crate::network::{self, GossipTopicKind};
+use crate::store::{self, GardensCore};
+use crate::{onion, relay_directory, sealed_sender, sync_config, CoreError};
+
+pub const TRANSPORT_GOSSIP: &str = "gossip";
+pub const TRANSPORT_ONION: &str = "onion";
+pub const TRANSPORT_SYNC: &str = "sync";
+
+/// How often due deliveries are checked when nothing new is queued.
+const OUTBOX_TICK: Duration = Duration::from_secs(5);
+
+/// Deliveries attempted per pass.
+const OUTBOX_BATCH: i64 = 50;
+
+/// First retry delay; doubles per attempt up to [`BACKOFF_MAX_SECS`].
+const BACKOFF_BASE_SECS: i64 = 5;
+const BACKOFF_MAX_SECS: i64 = 10 * 60;
+
+/// Attempts before a delivery is marked failed.
+pub const MAX_ATTEMPTS: i64 = 30;
+
+/// Timeout for HTTP deliveries to the sync worker and relays.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+
+static WAKE: OnceLock<Notify> = OnceLock::new();
+static HTTP: OnceLock<reqwest::Client> = OnceLock::new();
+
+fn wake() -> &'static Notify {
+    WAKE.get_or_init(Notify::new)
+}
+
+fn http() -> &'static reqwest::Client {
+    HTTP.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+// ─── Queueing ────────────────────────────────────────────────────────────────
+
+/// Where an op is headed.
+pub enum Destination<'a> {
+    Room(&'a str),
+    /// An existing DM thread, by thread id.
+    Dm(&'a str),
+    /// A recipient's DM inbox, for ops that open a thread.
+    Inbox(&'a str),
+}
+
+impl<'a> Destination<'a> {
+    /// Destination of a message-log op: its room, else its DM thread.
+    pub fn for_message(room_id: Option<&'a str>, dm_thread_id: Option<&'a str>) -> Option<Self> {
+        room_id.map(Destination::Room).or(dm_thread_id.map(Destination::Dm))
+    }
+}
+
+/// Queue `envelope` (GossipEnvelope CBOR) for delivery to `dest` and wake the
+/// delivery task.
+pub async fn enqueue(
+    core: &GardensCore,
+    op_hash: Hash,
+    log_id: &str,
+    dest: Destination<'_>,
+    envelope: &[u8],
+) -> Result<(), CoreError> {
+    let (room_id, dm_thread_id, recipient_key, topic_hex) = match dest {
+        Destination::Room(room_id) => (Some(room_id.to_string()), None, None, room_id.to_string()),
+        Destination::Dm(thread_id) => {
+            let (_, _, recipient) = crate::dm_gossip_context(core, thread_id).await?;
+            (None, Some(thread_id.to_string()), Some(recipient), thread_id.to_string())
+        }
+        Destination::Inbox(recipient_key) => (
+            None,
+            None,
+            Some(recipient_key.to_string()),
+            inbox_topic_hex(recipient_key)?,
+        ),
+    };
+
+    let mut transports = vec![TRANSPORT_GOSSIP];
+    if sync_config::has_onion_route() {
+        transports.push(TRANSPORT_ONION);
+    } else if !sync_config::get_url().is_empty() {
+        transports.push(TRANSPORT_SYNC);
+    }
+
+    let now = crate::now_micros();
+    for transport in transports {
+        db::insert_outbox(
+            &core.read_pool,
+            &OutboxRow {
+                op_hash: op_hash.to_hex(),
+                transport: transport.to_string(),
+                log_id: log_id.to_string(),
+                room_id: room_id.clone(),
+                dm_thread_id: dm_thread_id.clone(),
+                recipient_key: recipient_key.clone(),
+                topic_hex: topic_hex.clone(),
+                envelope: envelope.to_vec(),
+                state: "pending".to_string(),
+                attempts: 0,
+                next_attempt_at: now,
+                last_error: None,
+                created_at: now,
+                delivered_at: None,
+            },
+        )
+        .await?;
+    }
+
+    wake().notify_one();
+    Ok(())
+}
+
+/// Sync worker topic of a user's DM inbox: blake3(pubkey ‖ "gardens:inbox:v1").
+pub fn inbox_topic_hex(public_key_hex: &str) -> Result<String, CoreError> {
+    let mut input = crate::hex_to_bytes_32(public_key_hex)?.to_vec();
+    input.extend_from_slice(b"gardens:inbox:v1");
+    Ok(Hash::new(&input).to_hex())
+}
+
+/// Delay before attempt number `attempts + 1`.
+fn backoff_micros(attempts: i64) -> i64 {
+    let secs = BACKOFF_BASE_SECS
+        .saturating_mul(1 << attempts.clamp(0, 20))
+        .min(BACKOFF_MAX_SECS);
+    secs * 1_000_000
+}
+
+// ─── Delivery ────────────────────────────────────────────────────────────────
+
+/// Background task: deliver queued ops as they are queued and as their
+/// retries fall due.
+pub async fn run_outbox(read_pool: SqlitePool) {
+    let mut ticker = tokio::time::interval(OUTBOX_TICK);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = wake().notified() => {}
+        }
+        if let Err(e) = deliver_due(&read_pool).await {
+            log::warn!("[outbox] delivery pass failed: {}", e);
+        }
+    }
+}
+
+async fn deliver_due(read_pool: &SqlitePool) -> Result<(), CoreError> {
+    let Some(core) = store::get_core() else {
+        return Ok(());
+    };
+
+    let now = crate::now_micros();
+    for row in db::list_due_outbox(read_pool, now, OUTBOX_BATCH).await? {
+        match attempt(core, &row).await {
+            Ok(()) => {
+                db::mark_outbox_delivered(read_pool, &row.op_hash, &row.transport, crate::now_micros()).await?;
+            }
+            Err(e) => {
+                let attempts = row.attempts + 1;
+                let next = (attempts < MAX_ATTEMPTS).then(|| crate::now_micros() + backoff_micros(row.attempts));
+                if next.is_none() {
+                    log::warn!("[outbox] giving up on {} via {}: {}", row.op_hash, row.transport, e);
+                }
+                db::mark_outbox_attempt_failed(read_pool, &row.op_hash, &row.transport, &e, next).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn attempt(core: &GardensCore, row: &OutboxRow) -> Result<(), String> {
+    match row.transport.as_str() {
+        TRANSPORT_GOSSIP => deliver_gossip(core, row).await,
+        TRANSPORT_ONION => deliver_onion(core, row).await,
+        TRANSPORT_SYNC => deliver_sync(row).await,
+        other => Err(format!("unknown transport {other}")),
+    }
+}
+
+async fn deliver_gossip(core: &GardensCore, row: &OutboxRow) -> Result<(), String> {
+    if !network::is_initialized().await {
+        return Err("network not initialized".to_string());
+    }
+
+    let (topic_id, kind, bootstrap, bytes) = if let Some(room_id) = &row.room_id {
+        let (topic_id, bootstrap) = crate::room_gossip_context(core, room_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        (topic_id, GossipTopicKind::Room, bootstrap, row.envelope.clone())
+    } else {
+        let recipient = row
+            .recipient_key
+            .as_deref()
+            .ok_or_else(|| "no gossip destination".to_string())?;
+        let topic_id = crate::topic_id_from_hex(recipient).map_err(|e| e.to_string())?;
+        let bootstrap = [recipient, core.public_key_hex.as_str()]
+            .into_iter()
+            .filter_map(|key| crate::endpoint_id_from_hex(key).ok())
+            .collect();
+        let sealed = seal_for(&core.private_key, recipient, &row.envelope)?;
+        (topic_id, GossipTopicKind::DmInbox, bootstrap, sealed)
+    };
+
+    // A broadcast with nobody on the topic reaches no one, so it doesn't
+    // count as delivered; joining now lets neighbours turn up for the retry.
+    network::gossip_join(topic_id, kind, bootstrap)
+        .await
+        .map_err(|e| e.to_string())?;
+    if network::topic_neighbor_count(&topic_id).await == 0 {
+        return Err("no gossip neighbours on the topic yet".to_string());
+    }
+    network::gossip_publish(topic_id, kind, Vec::new(), bytes)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Seal `envelope` from `sender` to `recipient`'s DM inbox.
+fn seal_for(sender: &PrivateKey, recipient: &str, envelope: &[u8]) -> Result<Vec<u8>, String> {
+    let recipient_bytes = crate::hex_to_bytes_32(recipient).map_err(|e| e.to_string())?;
+    sealed_sender::seal(envelope, sender.public_key().as_bytes(), &recipient_bytes).map_err(|e| e.to_string())
+}
+
+async fn deliver_onion(core: &GardensCore, row: &OutboxRow) -> Result<(), String> {
+    let hops = relay_hops()?;
+    let (topic_id, payload) = onion_delivery(&core.private_key, row, &hops[hops.len() - 1])?;
+    let packet = onion::build_onion_packet(&hops, &topic_id, &payload).map_err(|e| e.to_string())?;
+    post_onion_packet(&hops[0].next_url, packet).await
+}
+
+/// Topic and payload of the `Deliver` instruction for `row` through `exit`.
+///
+/// A core exit (`iroh:` hop) gossips what it delivers, so DM and inbox ops
+/// go sealed to the recipient's inbox topic, the one their device joins. An
+/// HTTP exit hands the op to the sync worker under its sync topic.
+pub(crate) fn onion_delivery(
+    sender: &PrivateKey,
+    row: &OutboxRow,
+    exit: &onion::OnionHop,
+) -> Result<([u8; 32], Vec<u8>), String> {
+    match row.recipient_key.as_deref() {
+        Some(recipient) if exit.next_url.starts_with("iroh:") => {
+            let topic_id = crate::topic_id_from_hex(recipient).map_err(|e| e.to_string())?;
+            Ok((topic_id, seal_for(sender, recipient, &row.envelope)?))
+        }
+        _ => {
+            let topic_id = crate::topic_id_from_hex(&row.topic_hex).map_err(|e| e.to_string())?;
+            Ok((topic_id, row.envelope.clone()))
+        }
+    }
+}
+
+/// The route for the next packet: a fresh random path from the relay
+/// directory when one is loaded, else the hops from `init_sync`.
+pub(crate) fn relay_hops() -> Result<Vec<onion::OnionHop>, String> {
+    let directory = sync_config::get_directory();
+    if !directory.is_empty() {
+        return relay_directory::select_path(&directory, relay_directory::DEFAULT_PATH_LEN).map_err(|e| e.to_string());
+    }
+
+    let hops = sync_config::get_hops()
+        .into_iter()
+        .map(|(pubkey_hex, next_url)| {
+            let pubkey_bytes = crate::hex_to_bytes_32(&pubkey_hex).map_err(|e| e.to_string())?;
+            Ok(onion::OnionHop { pubkey_bytes, next_url })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if hops.is_empty() {
+        return Err("no relay hops configured".to_string());
+    }
+    Ok(hops)
+}
+
+/// Send an onion packet to the first hop of the route: over iroh for an
+/// `iroh:<node id>` hop, else as an HTTP POST.
+pub(crate) async fn post_onion_packet(first_hop_url: &str, packet: Vec<u8>) -> Result<(), String> {
+    if let Some(node_id) = first_hop_url.strip_prefix("iroh:") {
+        return network::send_onion_packet(node_id, packet).await.map_err(|e| e.to_string());
+    }
+
+    let resp = http()
+        .post(first_hop_url)
+        .header("Content-Type", "application/octet-stream")
+        .body(packet)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("relay returned {}", resp.status()));
+    }
+    Ok(())
+}
+
+async fn deliver_sync(row: &OutboxRow) -> Result<(), String> {
+    let sync_url = sync_config::get_url();
+    if sync_url.is_empty() {
+        return Err("sync url not configured".to_string());
+    }
+
+    let resp = http()
+        .post(format!("{}/deliver", sync_url.trim_end_matches('/')))
+        .json(&serde_json::json!({
+            "topic_hex": row.topic_hex,
+            "op_base64": general_purpose::STANDARD.encode(&row.envelope),
+        }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("sync worker returned {}", resp.status()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff_micros(0), 5_000_000);
+        assert_eq!(backoff_micros(1), 10_000_000);
+        assert_eq!(backoff_micros(3), 40_000_000);
+        assert_eq!(backoff_micros(10), BACKOFF_MAX_SECS * 1_000_000);
+        assert_eq!(backoff_micros(MAX_ATTEMPTS), BACKOFF_MAX_SECS * 1_000_000);
+    }
+
+    #[test]
+    fn inbox_topic_is_per_recipient() {
+        let alice = inbox_topic_hex(&"aa".repeat(32)).unwrap();
+        let bob = inbox_topic_hex(&"bb".repeat(32)).unwrap();
+        assert_eq!(alice.len(), 64);
+        assert_ne!(alice, bob);
+        assert!(inbox_topic_hex("not hex").is_err());
+    }
+}
@@ -0,0 +1,532 @@
+//! Direct log sync between two peers over [`SYNC_ALPN`].
+//!
+//! Catch-up otherwise goes through the sync worker. When two members are
+//! connected to each other — a gossip neighbour coming up, or an explicit
+//! `sync_with_peer` call — they swap the heights of the logs they share and
+//! stream each other whatever the other side lacks, so members who are
+//! online together converge without any server.
+//!
+//! The logs exchanged are those of both peers plus every member of an org
+//! they share (see [`db::list_sync_authors`]); ops by anyone else are
+//! dropped. Peers with no DM thread or shared org are turned away. Message
+//! and reaction ops only go to a peer who can read their room or DM thread.
+//!
+//! Wire format: one bi-directional stream of length-prefixed CBOR
+//! [`SyncFrame`]s. Each side sends `Heights`, reads the other's, then sends
+//! the missing ops as `Op` frames followed by `Done`. Received ops go through
+//! [`crate::ingest::ingest_envelope`] like ops from any other source.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant};
+
+use iroh::endpoint::{Connection, RecvStream, SendStream};
+use iroh::{Endpoint, EndpointAddr, EndpointId};
+use p2panda_core::Header;
+use p2panda_store::LogStore;
+use serde::{Deserialize, Serialize};
+
+use crate::db;
+use crate::network::{NetworkError, DEFAULT_TIMEOUT, SYNC_ALPN};
+use crate::ops::{decode_cbor, encode_cbor, log_ids, parse_public_key, GossipEnvelope};
+use crate::store::{self, GardensCore};
+
+/// Largest frame we accept. A `Heights` frame is the biggest by far; an `Op`
+/// frame is bounded by the ingest limits.
+pub const MAX_SYNC_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Minimum time between two automatic sync sessions with the same peer.
+pub const RESYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Last automatic session per peer, for [`RESYNC_INTERVAL`].
+static LAST_SYNC: StdMutex<Option<HashMap<EndpointId, Instant>>> = StdMutex::new(None);
+
+// ─── Wire format ─────────────────────────────────────────────────────────────
+
+/// Tip of one author's log as the sender holds it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogHeight {
+    pub author: String,
+    pub log_id: String,
+    pub seq_num: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SyncFrame {
+    Heights(Vec<LogHeight>),
+    /// `GossipEnvelope` CBOR bytes.
+    Op(Vec<u8>),
+    Done,
+}
+
+/// Outcome of one sync session.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SyncStats {
+    pub ops_sent: u64,
+    pub ops_received: u64,
+}
+
+// ─── Sessions ────────────────────────────────────────────────────────────────
+
+/// Dial `peer` and run a sync session.
+pub async fn sync_with_peer(endpoint: &Endpoint, peer: EndpointId) -> Result<SyncStats, NetworkError> {
+    let core = store::get_core().ok_or(NetworkError::NotInitialized)?;
+    dial_session(core, endpoint, peer.into()).await
+}
+
+/// Serve a sync session dialled by a peer.
+pub async fn handle_sync_connection(conn: Connection) -> Result<(), NetworkError> {
+    let core = store::get_core().ok_or(NetworkError::NotInitialized)?;
+    serve_session(core, conn).await
+}
+
+async fn dial_session(
+    core: &GardensCore,
+    endpoint: &Endpoint,
+    addr: EndpointAddr,
+) -> Result<SyncStats, NetworkError> {
+    let peer = addr.id;
+    let conn = tokio::time::timeout(DEFAULT_TIMEOUT, endpoint.connect(addr, SYNC_ALPN))
+        .await
+        .map_err(|_| NetworkError::ConnectionFailed("sync connect timed out".to_string()))?
+        .map_err(|e| NetworkError::ConnectionFailed(e.to_string()))?;
+    let (send, recv) = conn
+        .open_bi()
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+
+    let stats = run_session(core, send, recv, peer).await?;
+    conn.close(0u32.into(), b"done");
+    Ok(stats)
+}
+
+async fn serve_session(core: &GardensCore, conn: Connection) -> Result<(), NetworkError> {
+    let peer = conn
+        .remote_id()
+        .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    let (send, recv) = conn
+        .accept_bi()
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+
+    let stats = run_session(core, send, recv, peer).await?;
+    log::debug!(
+        "[sync] session with {} done: sent {} ops, received {}",
+        peer, stats.ops_sent, stats.ops_received
+    );
+    // Let the dialler close once it has read our `Done`.
+    let _ = tokio::time::timeout(DEFAULT_TIMEOUT, conn.closed()).await;
+    Ok(())
+}
+
+/// Kick off a session with a gossip neighbour that just came up.
+///
+/// Both ends see the neighbour event, so only the peer with the smaller id
+/// dials, and at most once per [`RESYNC_INTERVAL`].
+pub fn on_neighbor_up(endpoint: Endpoint, peer: EndpointId) {
+    if endpoint.id().as_bytes() >= peer.as_bytes() {
+        return;
+    }
+    {
+        let mut last = LAST_SYNC.lock().unwrap_or_else(|e| e.into_inner());
+        let last = last.get_or_insert_with(HashMap::new);
+        if last.get(&peer).is_some_and(|at| at.elapsed() < RESYNC_INTERVAL) {
+            return;
+        }
+        last.insert(peer, Instant::now());
+    }
+
+    tokio::spawn(async move {
+        match sync_with_peer(&endpoint, peer).await {
+            Ok(stats) => log::debug!(
+                "[sync] session with {} done: sent {} ops, received {}",
+                peer, stats.ops_sent, stats.ops_received
+            ),
+            Err(e) => {
+                log::warn!("[sync] session with {} failed: {}", peer, e);
+                crate::network::record_error(format!("sync with {}: {}", peer, e)).await;
+            }
+        }
+    });
+}
+
+async fn run_session(
+    core: &GardensCore,
+    mut send: SendStream,
+    mut recv: RecvStream,
+    peer: EndpointId,
+) -> Result<SyncStats, NetworkError> {
+    let peer_hex = hex::encode(peer.as_bytes());
+    let authors = db::list_sync_authors(&core.read_pool, &core.public_key_hex, &peer_hex)
+        .await
+        .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    if authors.is_empty() {
+        return Err(NetworkError::ProtocolError(format!(
+            "{} shares no org or DM thread with us",
+            peer
+        )));
+    }
+    let scopes = PeerScopes::load(core, &peer_hex).await?;
+
+    let ours = local_heights(core, &authors).await?;
+    write_frame(&mut send, &SyncFrame::Heights(ours.clone())).await?;
+    let theirs = match read_frame(&mut recv).await? {
+        SyncFrame::Heights(h) => h,
+        _ => return Err(NetworkError::ProtocolError("expected heights".to_string())),
+    };
+
+    // Ops by anyone outside the shared set are dropped, the peer's own
+    // included: they reach us once we have seen the membership op.
+    let accepted: HashSet<String> = authors.into_iter().collect();
+
+    let (ops_sent, ops_received) = tokio::try_join!(
+        send_missing(core, &mut send, &ours, &theirs, &scopes),
+        receive_ops(core, &mut recv, &accepted),
+    )?;
+    send.finish().map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    // Wait for the peer to finish its side too, so the dialler doesn't close
+    // the connection while the server is still reading our `Done`.
+    let _ = tokio::time::timeout(DEFAULT_TIMEOUT, recv.read_to_end(0)).await;
+
+    Ok(SyncStats { ops_sent, ops_received })
+}
+
+/// Heights of every log we hold for `authors`.
+async fn local_heights(core: &GardensCore, authors: &[String]) -> Result<Vec<LogHeight>, NetworkError> {
+    let authors: HashSet<&str> = authors.iter().map(String::as_str).collect();
+    let op_store = core.op_store.lock().await;
+
+    let mut heights = vec![];
+    for &log_id in log_ids::ALL {
+        let tips = op_store
+            .get_log_heights(&log_id.to_string())
+            .await
+            .map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+        for (public_key, seq_num) in tips {
+            let author = public_key.to_hex();
+            if authors.contains(author.as_str()) {
+                heights.push(LogHeight { author, log_id: log_id.to_string(), seq_num });
+            }
+        }
+    }
+    Ok(heights)
+}
+
+/// Stream every op in `ours` past the peer's height for that log, except
+/// messages and reactions the peer may not read.
+async fn send_missing(
+    core: &GardensCore,
+    send: &mut SendStream,
+    ours: &[LogHeight],
+    theirs: &[LogHeight],
+    scopes: &PeerScopes,
+) -> Result<u64, NetworkError> {
+    let theirs: HashMap<(&str, &str), u64> = theirs
+        .iter()
+        .map(|h| ((h.author.as_str(), h.log_id.as_str()), h.seq_num))
+        .collect();
+
+    let mut sent = 0;
+    for height in ours {
+        let from = match theirs.get(&(height.author.as_str(), height.log_id.as_str())) {
+            Some(&seq) if seq >= height.seq_num => continue,
+            Some(&seq) => seq + 1,
+            None => 0,
+        };
+        let Some(public_key) = parse_public_key(&height.author) else { continue };
+
+        // Copy the ops out so the store is not locked while we write.
+        let ops = {
+            let op_store = core.op_store.lock().await;
+            op_store
+                .get_raw_log(&public_key, &height.log_id, Some(from))
+                .await
+                .map_err(|e| NetworkError::ProtocolError(e.to_string()))?
+                .unwrap_or_default()
+        };
+        for (header_bytes, body_bytes) in ops {
+            let body_bytes = body_bytes.unwrap_or_default();
+            if !scopes.allows(&height.log_id, &body_bytes) {
+                continue;
+            }
+            let env = GossipEnvelope {
+                log_id: height.log_id.clone(),
+                header_bytes,
+                body_bytes,
+            };
+            let bytes = encode_cbor(&env).map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+            write_frame(send, &SyncFrame::Op(bytes)).await?;
+            sent += 1;
+        }
+    }
+
+    write_frame(send, &SyncFrame::Done).await?;
+    Ok(sent)
+}
+
+/// Ingest ops until the peer sends `Done`. Ops by authors outside `accepted`
+/// are dropped; invalid ops are logged and skipped.
+async fn receive_ops(
+    core: &GardensCore,
+    recv: &mut RecvStream,
+    accepted: &HashSet<String>,
+) -> Result<u64, NetworkError> {
+    let mut received = 0;
+    loop {
+        let bytes = match read_frame(recv).await? {
+            SyncFrame::Op(bytes) => bytes,
+            SyncFrame::Done => return Ok(received),
+            SyncFrame::Heights(_) => {
+                return Err(NetworkError::ProtocolError("unexpected heights".to_string()));
+            }
+        };
+
+        if !accepted.contains(&envelope_author(&bytes).unwrap_or_default()) {
+            continue;
+        }
+        match crate::ingest::ingest_envelope(core, &bytes).await {
+            Ok(op) => {
+                crate::projector::notify_op(op.author, &op.log_id);
+                crate::sync::record_ingest();
+                received += 1;
+            }
+            Err(e) => log::warn!("[sync] dropped op from peer: {}", e),
+        }
+    }
+}
+
+/// Rooms and DM threads a peer can read.
+struct PeerScopes {
+    rooms: HashSet<String>,
+    dm_threads: HashSet<String>,
+}
+
+/// Where a message or reaction belongs. Every such body names its room or
+/// DM thread in the clear, encrypted or not.
+#[derive(Deserialize)]
+struct OpScope {
+    #[serde(default)]
+    room_id: Option<String>,
+    #[serde(default)]
+    dm_thread_id: Option<String>,
+}
+
+impl PeerScopes {
+    async fn load(core: &GardensCore, peer_hex: &str) -> Result<Self, NetworkError> {
+        let db_err = |e: db::DbError| NetworkError::ProtocolError(e.to_string());
+        let rooms = db::list_member_room_ids(&core.read_pool, peer_hex).await.map_err(db_err)?;
+        let dm_threads = db::list_dm_threads(&core.read_pool, peer_hex).await.map_err(db_err)?;
+        Ok(Self {
+            rooms: rooms.into_iter().collect(),
+            dm_threads: dm_threads.into_iter().map(|t| t.thread_id).collect(),
+        })
+    }
+
+    /// Whether an op body may go to the peer. Messages and reactions that
+    /// name no room or thread are withheld.
+    fn allows(&self, log_id: &str, body: &[u8]) -> bool {
+        if log_id != log_ids::MESSAGE && log_id != log_ids::REACTION {
+            return true;
+        }
+        match decode_cbor::<OpScope>(body) {
+            Ok(OpScope { room_id: Some(room_id), .. }) => self.rooms.contains(&room_id),
+            Ok(OpScope { dm_thread_id: Some(thread_id), .. }) => self.dm_threads.contains(&thread_id),
+            _ => false,
+        }
+    }
+}
+
+/// Hex author key of an encoded envelope, without validating it.
+fn envelope_author(bytes: &[u8]) -> Option<String> {
+    let env = decode_cbor::<GossipEnvelope>(bytes).ok()?;
+    let header = Header::<()>::try_from(env.header_bytes.as_slice()).ok()?;
+    Some(header.public_key.to_hex())
+}
+
+// ─── Framing ─────────────────────────────────────────────────────────────────
+
+async fn write_frame(send: &mut SendStream, frame: &SyncFrame) -> Result<(), NetworkError> {
+    let bytes = encode_cbor(frame).map_err(|e| NetworkError::ProtocolError(e.to_string()))?;
+    send.write_all(&(bytes.len() as u32).to_be_bytes())
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    send.write_all(&bytes)
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    Ok(())
+}
+
+async fn read_frame(recv: &mut RecvStream) -> Result<SyncFrame, NetworkError> {
+    let mut size_buf = [0u8; 4];
+    recv.read_exact(&mut size_buf)
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    let size = u32::from_be_bytes(size_buf) as usize;
+    if size > MAX_SYNC_FRAME_SIZE {
+        return Err(NetworkError::ProtocolError(format!("Frame too large: {} bytes", size)));
+    }
+
+    let mut bytes = vec![0u8; size];
+    recv.read_exact(&mut bytes)
+        .await
+        .map_err(|e| NetworkError::StreamError(e.to_string()))?;
+    decode_cbor(&bytes).map_err(|e| NetworkError::ProtocolError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p2panda_core::{Body, PrivateKey};
+
+    #[test]
+    fn frames_roundtrip_through_cbor() {
+        let frame = SyncFrame::Heights(vec![LogHeight {
+            author: "ab".repeat(32),
+            log_id: log_ids::MESSAGE.to_string(),
+            seq_num: 7,
+        }]);
+        match decode_cbor::<SyncFrame>(&encode_cbor(&frame).unwrap()).unwrap() {
+            SyncFrame::Heights(h) => {
+                assert_eq!(h.len(), 1);
+                assert_eq!(h[0].seq_num, 7);
+            }
+            _ => panic!("expected heights"),
+        }
+    }
+
+    #[test]
+    fn envelope_author_reads_the_header_key() {
+        let key = PrivateKey::new();
+        let body = Body::new(b"hi");
+        let mut header: Header<()> = Header {
+            version: 1,
+            public_key: key.public_key(),
+            signature: None,
+            payload_size: body.size(),
+            payload_hash: Some(body.hash()),
+            timestamp: 1,
+            seq_num: 0,
+            backlink: None,
+            previous: vec![],
+            extensions: (),
+        };
+        header.sign(&key);
+        let env = GossipEnvelope {
+            log_id: log_ids::MESSAGE.to_string(),
+            header_bytes: header.to_bytes(),
+            body_bytes: body.to_bytes(),
+        };
+
+        let bytes = encode_cbor(&env).unwrap();
+        assert_eq!(envelope_author(&bytes), Some(key.public_key().to_hex()));
+        assert_eq!(envelope_author(b"junk"), None);
+    }
+
+    // ── Two endpoints in one process ──
+
+    /// An endpoint with `key`'s identity, as `network::init_network` binds.
+    async fn bind(key: &PrivateKey) -> Endpoint {
+        Endpoint::builder()
+            .secret_key(iroh::SecretKey::from_bytes(key.as_bytes()))
+            .alpns(vec![SYNC_ALPN.to_vec()])
+            .relay_mode(iroh::RelayMode::Disabled)
+            .clear_discovery()
+            .bind()
+            .await
+            .unwrap()
+    }
+
+    fn loopback_addr(endpoint: &Endpoint) -> EndpointAddr {
+        let port = endpoint.bound_sockets().into_iter().find(|a| a.is_ipv4()).unwrap().port();
+        EndpointAddr::new(endpoint.id()).with_ip_addr(([127, 0, 0, 1], port).into())
+    }
+
+    /// A core whose op store and read model belong to `key`.
+    async fn core_for(key: &PrivateKey) -> &'static GardensCore {
+        let mut core = store::test_core().await;
+        core.private_key = key.clone();
+        core.public_key_hex = key.public_key().to_hex();
+        Box::leak(Box::new(core))
+    }
+
+    async fn join(core: &GardensCore, org_id: &str, room_id: &str, members: &[&PrivateKey]) {
+        for member in members {
+            db::upsert_membership(&core.read_pool, org_id, &member.public_key().to_hex(), "write", 1)
+                .await
+                .unwrap();
+        }
+        sqlx::query("INSERT OR IGNORE INTO rooms (room_id, org_id, name, created_by, created_at) VALUES (?, ?, 'general', 'x', 1)")
+            .bind(room_id)
+            .bind(org_id)
+            .execute(&core.read_pool)
+            .await
+            .unwrap();
+    }
+
+    /// Store a room message by `author` in `core`'s op store.
+    async fn post(core: &GardensCore, author: &PrivateKey, room_id: &str) {
+        let body = encode_cbor(&crate::ops::EncryptedRoomOp {
+            op_type: "encrypted".into(),
+            room_id: room_id.to_string(),
+            encrypted: vec![],
+        })
+        .unwrap();
+        let mut op_store = core.op_store.lock().await;
+        crate::ops::sign_and_store_op_with_previous(&mut op_store, author, log_ids::MESSAGE, body, vec![])
+            .await
+            .unwrap();
+    }
+
+    async fn message_count(core: &GardensCore, author: &PrivateKey) -> usize {
+        let op_store = core.op_store.lock().await;
+        op_store
+            .get_log(&author.public_key(), &log_ids::MESSAGE.to_string(), None)
+            .await
+            .unwrap()
+            .map_or(0, |log| log.len())
+    }
+
+    #[tokio::test]
+    async fn sessions_only_carry_ops_both_sides_may_see() {
+        let (alice, bob, carol, dave, eve) =
+            (PrivateKey::new(), PrivateKey::new(), PrivateKey::new(), PrivateKey::new(), PrivateKey::new());
+        let (a, b, c) = (core_for(&alice).await, core_for(&bob).await, core_for(&carol).await);
+
+        // Alice and Bob share org1; org2 is Alice and Dave's. Bob believes
+        // Eve is in org1, which Alice hasn't seen.
+        join(a, "org1", "room1", &[&alice, &bob]).await;
+        join(a, "org2", "room2", &[&alice, &dave]).await;
+        join(b, "org1", "room1", &[&alice, &bob, &eve]).await;
+        // Carol claims org1 too, but Alice has never heard of her.
+        join(c, "org1", "room1", &[&alice, &carol]).await;
+
+        post(a, &alice, "room1").await;
+        post(a, &alice, "room2").await;
+        post(b, &bob, "room1").await;
+        post(b, &eve, "room1").await;
+        post(c, &carol, "room1").await;
+
+        let (a_ep, b_ep, c_ep) = (bind(&alice).await, bind(&bob).await, bind(&carol).await);
+        let server = {
+            let a_ep = a_ep.clone();
+            tokio::spawn(async move {
+                let mut results = vec![];
+                for _ in 0..2 {
+                    let conn = a_ep.accept().await.unwrap().await.unwrap();
+                    results.push(serve_session(a, conn).await.is_ok());
+                }
+                results
+            })
+        };
+
+        let stats = dial_session(b, &b_ep, loopback_addr(&a_ep)).await.unwrap();
+        assert_eq!(stats.ops_received, 1, "only the room1 message reaches Bob");
+        assert_eq!(message_count(b, &alice).await, 1);
+        assert!(dial_session(c, &c_ep, loopback_addr(&a_ep)).await.is_err());
+
+        assert_eq!(server.await.unwrap(), vec![true, false]);
+        assert_eq!(message_count(a, &bob).await, 1);
+        assert_eq!(message_count(a, &eve).await, 0, "Eve is outside the set Alice shares");
+        assert_eq!(message_count(a, &carol).await, 0, "strangers are turned away");
+    }
+}
@@ -262,7 +262,7 @@ pub async fn publish_tombstone(private_key_hex: &str) -> Result<(), String> {
 /// Publish a tombstone for an org using its z32-encoded public key.
 /// This signals that the org has been deleted.
 pub async fn publish_tombstone_for_org(org_pubkey_z32: &str) -> Result<(), String> {
-    let public_key = pkarr::PublicKey::try_from(org_pubkey_z32)
+    let _public_key = pkarr::PublicKey::try_from(org_pubkey_z32)
         .map_err(|e| format!("invalid z32 key: {}", e))?;
     
     // We can't sign without the private key, so we just log this
@@ -369,6 +369,7 @@ pub async fn start_republish_loop(read_pool: SqlitePool) {
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use super::*;
 
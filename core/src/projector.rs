@@ -1,15 +1,32 @@
 //! Projector — reads new ops from the p2panda store and materialises them
 //! into the SQLite read model.
 //!
-//! Design: a simple polling loop (500 ms) that:
-//!  1. Iterates over all known log types.
-//!  2. For each type, gets all (author, latest_seq_num) pairs from the op store.
-//!  3. Compares against the stored cursor.
-//!  4. Fetches new ops and dispatches to the appropriate db helper.
+//! Design: event-driven. Every path that inserts ops into the store
+//! (`ops::sign_and_store_op`, `sync::ingest_op`, gossip ingest) calls
+//! [`notify_op`] with the (author, log_id) pair, and the projector:
+//!  1. Drains queued notifications into a set of pairs.
+//!  2. For each pair, compares the author's log against the stored cursor.
+//!  3. Fetches new ops and dispatches to the appropriate db helper.
 //!
-//! Real-time delivery (Phase 3) will add push notifications via p2panda-net
-//! Gossip on top of this foundation.
+//! Ops name the ops they refer to (the message a reaction or edit targets,
+//! the room of a message, the org of a room) in their header's `previous`
+//! field. An op that arrives before those have been projected is held in
+//! `pending_deps` and released once they are, since gossip and sync deliver
+//! out of order. Authorization checks defer the same way: an op whose org,
+//! room or event isn't projected yet, or whose author has no grant in the
+//! org yet, waits for it, and is only rejected once the check can be decided.
+//!
+//! A slow fallback sweep (every 30 s) walks all logs and authors in case a
+//! notification was missed, e.g. for ops stored before the projector started.
+//!
+//! [`rebuild_read_model`] throws the projection away and replays every log
+//! from the start, for when a projection bug has left the read model wrong.
+//! It also runs on startup when a schema migration asks for it.
 
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use p2panda_core::PublicKey;
@@ -17,11 +34,13 @@ use p2panda_encryption::key_registry::KeyRegistry;
 use p2panda_encryption::key_bundle::LongTermKeyBundle;
 use p2panda_store::LogStore;
 use sqlx::SqlitePool;
+use tokio::sync::mpsc;
 
-use crate::db::{self, MessageRow, OrgRow, ProfileRow, RoomRow, DmThreadRow, EventRow};
-use crate::encryption::{Id, get_encryption, remove_member_from_org_groups, add_member_to_org_groups, publish_enc_ctrl_op};
-use crate::ops::{decode_cbor, log_ids, MessageOp, OrgOp, OrgUpdateOp, ProfileOp, ReactionOp, RoomOp, RoomDeleteOp, RoomUpdateOp, DmThreadOp, DeleteConversationOp, EventOp, EventUpdateOp, EventDeleteOp, EventRsvpOp, OrgAdminThreadOp};
-use crate::store::get_core;
+use crate::db::{self, MessageRow, OrgRow, ProfileRow, RejectedOpRow, RoomRow, DmThreadRow, EventRow};
+use crate::encryption::{Id, get_encryption, decrypt_for_room_with_pool, decrypt_for_dm_with_pool, receive_dm_ctrl_with_pool, register_key_bundle_with_pool, remove_member_from_org_groups, add_member_to_org_groups, publish_enc_ctrl_op};
+use crate::ops::{decode_cbor, log_ids, parse_public_key, EncCtrlOp, EncryptedDmOp, EncryptedRoomOp, KeyBundleOp, MessageOp, OrgOp, OrgUpdateOp, ProfileOp, ReactionOp, RoomOp, RoomDeleteOp, RoomUpdateOp, DmThreadOp, DeleteConversationOp, EventOp, EventUpdateOp, EventDeleteOp, EventRsvpOp, OrgAdminThreadOp};
+use crate::events::{self, GardensEvent};
+use crate::store::{get_core, GardensStore};
 use crate::auth::{self, AccessLevel};
 
 fn now_micros() -> i64 {
@@ -31,16 +50,65 @@ fn now_micros() -> i64 {
         .as_micros() as i64
 }
 
+/// How often the fallback sweep walks every log, in case a notification was missed.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// An (author, log) pair with new ops to project.
+type LogNotice = (PublicKey, String);
+
+static NOTIFY_TX: OnceLock<mpsc::UnboundedSender<LogNotice>> = OnceLock::new();
+
+/// Tell the projector that `author` has new ops in `log_id`.
+///
+/// Called wherever ops enter the op store. Before the projector is running
+/// this is a no-op; its first sweep picks those ops up.
+pub fn notify_op(author: PublicKey, log_id: &str) {
+    if let Some(tx) = NOTIFY_TX.get() {
+        let _ = tx.send((author, log_id.to_string()));
+    }
+}
+
 pub async fn run_projector(read_pool: SqlitePool) {
-    let mut interval = tokio::time::interval(Duration::from_millis(500));
+    let (tx, mut rx) = mpsc::unbounded_channel::<LogNotice>();
+    if NOTIFY_TX.set(tx).is_err() {
+        eprintln!("[projector] already running");
+        return;
+    }
+
+    // A schema migration may have changed what we project.
+    match db::rebuild_pending(&read_pool).await {
+        Ok(true) => {
+            if let Err(e) = rebuild_read_model(&read_pool).await {
+                eprintln!("[projector] rebuild after migration failed: {e}");
+            }
+        }
+        Ok(false) => {}
+        Err(e) => eprintln!("[projector] error: {e}"),
+    }
+
+    let mut sweep = tokio::time::interval(SWEEP_INTERVAL);
     loop {
-        interval.tick().await;
-        if let Err(e) = project_tick(&read_pool).await {
-            eprintln!("[projector] error: {e}");
+        tokio::select! {
+            Some(first) = rx.recv() => {
+                // Coalesce everything queued so a burst of ops is one pass.
+                let mut pending = HashSet::from([first]);
+                while let Ok(notice) = rx.try_recv() {
+                    pending.insert(notice);
+                }
+                if let Err(e) = project_logs(&read_pool, &pending).await {
+                    eprintln!("[projector] error: {e}");
+                }
+            }
+            _ = sweep.tick() => {
+                if let Err(e) = project_tick(&read_pool).await {
+                    eprintln!("[projector] error: {e}");
+                }
+            }
         }
     }
 }
 
+/// Full sweep: project new ops for every author of every known log.
 pub async fn project_tick(read_pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let core = match get_core() {
         Some(c) => c,
@@ -50,113 +118,594 @@ pub async fn project_tick(read_pool: &SqlitePool) -> Result<(), Box<dyn std::err
     let op_store = core.op_store.lock().await;
 
     for &log_id in log_ids::ALL {
-        let log_id_str = log_id.to_string();
-
         // Heights: Vec<(PublicKey, seq_num)> — all authors who have ops of this type.
-        let heights = op_store.get_log_heights(&log_id_str).await?;
+        let heights = op_store.get_log_heights(&log_id.to_string()).await?;
 
         for (public_key, _tip_seq) in heights {
-            let pk_hex = public_key.to_hex();
+            project_log(read_pool, &op_store, &public_key, log_id).await?;
+        }
+    }
+
+    retry_pending_decrypts(read_pool).await?;
+    retry_pending_deps(read_pool).await?;
+
+    Ok(())
+}
+
+/// Project only the given (author, log) pairs.
+pub async fn project_logs(
+    read_pool: &SqlitePool,
+    pairs: &HashSet<LogNotice>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let core = match get_core() {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+
+    let op_store = core.op_store.lock().await;
+
+    for (public_key, log_name) in pairs {
+        let Some(&log_id) = log_ids::ALL.iter().find(|&&l| l == log_name) else {
+            continue;
+        };
+        project_log(read_pool, &op_store, public_key, log_id).await?;
+    }
+
+    retry_pending_decrypts(read_pool).await?;
+    retry_pending_deps(read_pool).await?;
+
+    Ok(())
+}
+
+/// Project one author's ops in one log, from the stored cursor onward.
+///
+/// Callers hold the op store lock, which also keeps two passes from
+/// projecting the same ops concurrently.
+async fn project_log(
+    read_pool: &SqlitePool,
+    op_store: &GardensStore,
+    public_key: &PublicKey,
+    log_id: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let pk_hex = public_key.to_hex();
+
+    // Where did we last stop?
+    let cursor = db::get_cursor(read_pool, log_id, &pk_hex).await?;
+
+    // Fetch ops from cursor+1 onward.
+    let from = if cursor == 0 { None } else { Some(cursor + 1) };
+    let Some(ops) = op_store.get_log(public_key, &log_id.to_string(), from).await? else {
+        return Ok(());
+    };
+
+    for (header, body_opt) in ops {
+        let Some(body) = body_opt else { continue };
+        let entry = LogEntry {
+            seq: header.seq_num,
+            op_hash: header.hash().to_hex(),
+            timestamp: header.timestamp as i64,
+            previous: header.previous.iter().map(|h| h.to_hex()).collect(),
+            body: body.to_bytes(),
+        };
+        project_entry(read_pool, log_id, &pk_hex, entry).await?;
+    }
+
+    Ok(())
+}
+
+/// One op read from an author's log.
+struct LogEntry {
+    seq: u64,
+    op_hash: String,
+    timestamp: i64,
+    previous: Vec<String>,
+    body: Vec<u8>,
+}
+
+/// Project a single op and advance its log's cursor past it.
+async fn project_entry(
+    read_pool: &SqlitePool,
+    log_id: &str,
+    pk_hex: &str,
+    entry: LogEntry,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Replaying key bundles and ctrl messages would re-apply them to the
+    // encryption state, which a rebuild keeps as it is.
+    if replaying() && (log_id == log_ids::KEY_BUNDLE || log_id == log_ids::ENC_CTRL) {
+        db::set_cursor(read_pool, log_id, pk_hex, entry.seq).await?;
+        return Ok(());
+    }
+
+    if db::unprojected_ops(read_pool, &entry.previous).await?.is_empty() {
+        project_ready_op(read_pool, log_id, pk_hex, &entry.op_hash, entry.timestamp, entry.body).await?;
+    } else {
+        let row = db::PendingDepRow {
+            op_hash: entry.op_hash,
+            log_id: log_id.to_string(),
+            author_key: pk_hex.to_string(),
+            previous: entry.previous,
+            body: entry.body,
+            timestamp: entry.timestamp,
+        };
+        hold_back(read_pool, &row).await?;
+    }
+
+    db::set_cursor(read_pool, log_id, pk_hex, entry.seq).await?;
+    Ok(())
+}
+
+/// Decrypt (if needed) and dispatch an op whose dependencies are projected.
+///
+/// Returns false if an authorization check held the op back again, in
+/// `pending_deps`, until what it waits for is projected.
+async fn project_ready_op(
+    read_pool: &SqlitePool,
+    log_id: &str,
+    pk_hex: &str,
+    op_hash_hex: &str,
+    timestamp: i64,
+    body_bytes: Vec<u8>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    // Room and DM messages and reactions arrive encrypted. Ops we
+    // can't decrypt yet are parked in pending_decrypt and retried later.
+    let body_bytes = if log_id == log_ids::MESSAGE || log_id == log_ids::REACTION {
+        match open_encrypted_payload(read_pool, log_id, pk_hex, op_hash_hex, body_bytes, timestamp).await? {
+            OpenedPayload::Plain(plaintext) => plaintext,
+            OpenedPayload::Skipped => {
+                db::mark_op_projected(read_pool, op_hash_hex).await?;
+                return Ok(true);
+            }
+            OpenedPayload::Queued => return Ok(true),
+        }
+    } else {
+        body_bytes
+    };
+
+    let result =
+        dispatch_op(read_pool, log_id, pk_hex, op_hash_hex, &body_bytes, timestamp).await;
+
+    if let Err(e) = result {
+        if let Some(Deferred(dep)) = e.downcast_ref::<Deferred>() {
+            let row = db::PendingDepRow {
+                op_hash: op_hash_hex.to_string(),
+                log_id: log_id.to_string(),
+                author_key: pk_hex.to_string(),
+                previous: vec![dep.clone()],
+                body: body_bytes,
+                timestamp,
+            };
+            hold_back(read_pool, &row).await?;
+            return Ok(false);
+        }
+        eprintln!("[projector] failed to project {log_id} op {op_hash_hex}: {e}");
+    }
+
+    db::mark_op_projected(read_pool, op_hash_hex).await?;
+    Ok(true)
+}
+
+/// Max held-back ops released per query; the loop runs until none are ready.
+const PENDING_DEPS_BATCH: u32 = 100;
+
+/// How long an op may be held back before it is rejected (7 days).
+const PENDING_DEPS_TTL_MICROS: i64 = 7 * 24 * 3600 * 1_000_000;
+
+/// Most ops one author may have held back at once; more are rejected, so
+/// ops naming dependencies that never arrive can't fill the disk.
+const PENDING_DEPS_PER_AUTHOR: i64 = 10_000;
+
+/// Hold an op back in `pending_deps` until what `row.previous` names is
+/// projected, or reject it if its author is over the limit.
+async fn hold_back(pool: &SqlitePool, row: &db::PendingDepRow) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if db::count_pending_deps_by_author(pool, &row.author_key, &row.op_hash).await? >= PENDING_DEPS_PER_AUTHOR {
+        db::delete_pending_dep(pool, &row.op_hash).await?;
+        let reason = format!("author already has {PENDING_DEPS_PER_AUTHOR} ops held back");
+        return reject_held_back(pool, row, &reason).await;
+    }
+    db::requeue_pending_dep(pool, row, now_micros()).await?;
+    Ok(())
+}
+
+/// Reject an op that will no longer be held back. Like other rejected ops
+/// it counts as projected, so ops after it are decided on their own.
+async fn reject_held_back(
+    pool: &SqlitePool,
+    row: &db::PendingDepRow,
+    reason: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let org_id = row.previous.iter().find_map(|dep| grant_marker_org(dep));
+    reject_op(pool, &row.op_hash, &row.log_id, &row.author_key, &row.log_id, org_id, reason).await?;
+    db::mark_op_projected(pool, &row.op_hash).await?;
+    Ok(())
+}
+
+/// Reject held-back ops whose dependencies did not arrive within the TTL.
+async fn expire_pending_deps(pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let received_before = now_micros() - PENDING_DEPS_TTL_MICROS;
+    for row in db::list_expired_pending_deps(pool, received_before, PENDING_DEPS_BATCH).await? {
+        let missing = db::unprojected_ops(pool, &row.previous).await?;
+        let reason = format!("dependencies never projected: {}", missing.join(", "));
+        db::delete_pending_dep(pool, &row.op_hash).await?;
+        reject_held_back(pool, &row, &reason).await?;
+    }
+    Ok(())
+}
+
+/// Project held-back ops whose dependencies have since been projected.
+/// Releasing one op can unblock others, so keep going until nothing is ready.
+async fn retry_pending_deps(pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    expire_pending_deps(pool).await?;
+    loop {
+        let ready = db::list_ready_pending_deps(pool, PENDING_DEPS_BATCH).await?;
+        if ready.is_empty() {
+            return Ok(());
+        }
+        for row in ready {
+            if project_ready_op(pool, &row.log_id, &row.author_key, &row.op_hash, row.timestamp, row.body).await? {
+                db::delete_pending_dep(pool, &row.op_hash).await?;
+            }
+        }
+    }
+}
 
-            // Where did we last stop?
-            let cursor = db::get_cursor(read_pool, log_id, &pk_hex).await?;
+// ─── Read-model rebuild ──────────────────────────────────────────────────────
 
-            // Fetch ops from cursor+1 onward.
-            let from = if cursor == 0 { None } else { Some(cursor + 1) };
-            let Some(ops) = op_store.get_log(&public_key, &log_id_str, from).await? else {
+/// Set while [`rebuild_read_model`] replays the op store. Handlers skip side
+/// effects on encryption state, which the rebuild preserves.
+static REPLAYING: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn replaying() -> bool {
+    REPLAYING.load(Ordering::SeqCst)
+}
+
+/// Emit a progress event every this many replayed ops.
+const REBUILD_PROGRESS_EVERY: u64 = 100;
+
+/// Clears the replay flags however the rebuild exits.
+struct ReplayGuard;
+
+impl ReplayGuard {
+    fn start() -> Self {
+        REPLAYING.store(true, Ordering::SeqCst);
+        events::set_suspended(true);
+        ReplayGuard
+    }
+}
+
+impl Drop for ReplayGuard {
+    fn drop(&mut self) {
+        REPLAYING.store(false, Ordering::SeqCst);
+        events::set_suspended(false);
+    }
+}
+
+/// Empty the projection tables and re-project every log from the op store.
+///
+/// Ops are replayed across all logs in timestamp order (each log stays in
+/// seq order), so orgs and memberships exist before the ops they authorize.
+/// Encryption state, pending decrypts and DM history are kept; see
+/// [`db::begin_read_model_rebuild`]. Live projection and publishing wait on
+/// the op store lock until the rebuild finishes. Progress is reported as
+/// [`GardensEvent::RebuildProgress`].
+pub async fn rebuild_read_model(
+    read_pool: &SqlitePool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let core = get_core().ok_or("core not initialised")?;
+    let op_store = core.op_store.lock().await;
+
+    // Load everything first so a store error leaves the read model untouched.
+    let mut logs: Vec<(&'static str, String, VecDeque<LogEntry>)> = Vec::new();
+    for &log_id in log_ids::ALL {
+        let heights = op_store.get_log_heights(&log_id.to_string()).await?;
+        for (public_key, _tip_seq) in heights {
+            let Some(ops) = op_store.get_log(&public_key, &log_id.to_string(), None).await? else {
                 continue;
             };
+            let entries: VecDeque<LogEntry> = ops
+                .into_iter()
+                .filter_map(|(header, body)| {
+                    body.map(|b| LogEntry {
+                        seq: header.seq_num,
+                        op_hash: header.hash().to_hex(),
+                        timestamp: header.timestamp as i64,
+                        previous: header.previous.iter().map(|h| h.to_hex()).collect(),
+                        body: b.to_bytes(),
+                    })
+                })
+                .collect();
+            if !entries.is_empty() {
+                logs.push((log_id, public_key.to_hex(), entries));
+            }
+        }
+    }
+    let ops_total: u64 = logs.iter().map(|(_, _, entries)| entries.len() as u64).sum();
 
-            for (header, body_opt) in ops {
-                let seq = header.seq_num;
-                let op_hash_hex = header.hash().to_hex();
+    let guard = ReplayGuard::start();
+    events::emit(GardensEvent::RebuildProgress { ops_done: 0, ops_total, finished: false });
 
-                let body_bytes = match body_opt {
-                    Some(b) => b.to_bytes(),
-                    None => continue,
-                };
+    db::begin_read_model_rebuild(read_pool).await?;
 
-                // Dispatch to the right handler.
-                let result = match log_id {
-                    log_ids::PROFILE => {
-                        project_profile(read_pool, &pk_hex, &body_bytes, now_micros()).await
-                    }
-                    log_ids::ORG => {
-                        project_org(read_pool, &pk_hex, &op_hash_hex, &body_bytes, now_micros()).await
-                    }
-                    log_ids::ROOM => {
-                        project_room(read_pool, &pk_hex, &op_hash_hex, &body_bytes, now_micros()).await
-                    }
-                    log_ids::MESSAGE => {
-                        project_message(
-                            read_pool,
-                            &pk_hex,
-                            &op_hash_hex,
-                            &body_bytes,
-                            header.timestamp as i64,
-                        )
-                        .await
-                    }
-                    log_ids::REACTION => {
-                        project_reaction(read_pool, &pk_hex, &body_bytes).await
-                    }
-                    log_ids::DM_THREAD => {
-                        project_dm_thread(
-                            read_pool,
-                            &pk_hex,
-                            &op_hash_hex,
-                            &body_bytes,
-                            now_micros(),
-                        )
-                        .await
-                    }
-                    log_ids::ORG_ADMIN_THREAD => {
-                        project_org_admin_thread(
-                            read_pool,
-                            &pk_hex,
-                            &op_hash_hex,
-                            &body_bytes,
-                            now_micros(),
-                        )
-                        .await
-                    }
-                    log_ids::EVENT => {
-                        project_event(
-                            read_pool,
-                            &pk_hex,
-                            &op_hash_hex,
-                            &body_bytes,
-                            now_micros(),
-                        )
-                        .await
-                    }
-                    log_ids::EVENT_RSVP => {
-                        project_event_rsvp(
-                            read_pool,
-                            &pk_hex,
-                            &body_bytes,
-                            now_micros(),
-                        )
-                        .await
-                    }
-                    log_ids::MEMBERSHIP => {
-                        project_membership(read_pool, &pk_hex, &body_bytes, now_micros()).await
-                    }
-                    _ => Ok(()),
-                };
+    // Merge the logs by the timestamp of each one's next op.
+    let mut heads: BinaryHeap<Reverse<(i64, usize)>> = logs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (_, _, entries))| entries.front().map(|e| Reverse((e.timestamp, i))))
+        .collect();
+    let mut ops_done = 0u64;
+    while let Some(Reverse((_, i))) = heads.pop() {
+        let (log_id, pk_hex, entries) = &mut logs[i];
+        let Some(entry) = entries.pop_front() else { continue };
+        project_entry(read_pool, log_id, pk_hex, entry).await?;
+        if let Some(next) = entries.front() {
+            heads.push(Reverse((next.timestamp, i)));
+        }
+
+        ops_done += 1;
+        if ops_done.is_multiple_of(REBUILD_PROGRESS_EVERY) {
+            events::emit(GardensEvent::RebuildProgress { ops_done, ops_total, finished: false });
+        }
+    }
+
+    retry_pending_deps(read_pool).await?;
+    db::finish_read_model_rebuild(read_pool).await?;
+    drop(guard);
+    drop(op_store);
+
+    retry_pending_decrypts(read_pool).await?;
+    retry_pending_deps(read_pool).await?;
+    events::emit(GardensEvent::RebuildProgress { ops_done, ops_total, finished: true });
+    Ok(())
+}
+
+// ─── Encrypted room and DM payloads ──────────────────────────────────────────
+
+/// Max pending ops retried per tick so a backlog can't stall the projector.
+const PENDING_DECRYPT_BATCH: u32 = 50;
 
-                if let Err(e) = result {
-                    eprintln!("[projector] failed to project {log_id} op {op_hash_hex}: {e}");
+/// Failed decryption attempts after which a parked op is given up on.
+const PENDING_DECRYPT_MAX_ATTEMPTS: i64 = 100;
+
+/// How long an op may stay parked before it is given up on (7 days).
+const PENDING_DECRYPT_TTL_MICROS: i64 = 7 * 24 * 3600 * 1_000_000;
+
+/// Backoff before retrying an undecryptable op: 1 s doubling up to 5 min.
+fn pending_decrypt_backoff(attempts: i64, now: i64) -> i64 {
+    let secs = 1i64 << attempts.clamp(0, 8);
+    now + secs.min(300) * 1_000_000
+}
+
+/// Outcome of [`open_encrypted_payload`].
+enum OpenedPayload {
+    /// The plaintext payload, or the body unchanged if it wasn't encrypted.
+    Plain(Vec<u8>),
+    /// Nothing to project: the op's effects are already in the read model.
+    Skipped,
+    /// Not decryptable yet; parked in `pending_decrypt`.
+    Queued,
+}
+
+/// Unwrap an `EncryptedRoomOp` / `EncryptedDmOp` body into the plaintext payload.
+///
+/// Plaintext payloads for a room that has an encryption group are rejected:
+/// accepting them would let anyone downgrade the room to cleartext.
+async fn open_encrypted_payload(
+    pool: &SqlitePool,
+    log_id: &str,
+    author_key: &str,
+    op_hash: &str,
+    body: Vec<u8>,
+    timestamp: i64,
+) -> Result<OpenedPayload, Box<dyn std::error::Error + Send + Sync>> {
+    let (group_id, group_type, encrypted) = match decode_cbor::<EncryptedRoomOp>(&body) {
+        Ok(op) if op.op_type == "encrypted" => (op.room_id, "room", op.encrypted),
+        _ => match decode_cbor::<EncryptedDmOp>(&body) {
+            Ok(op) if op.op_type == "encrypted_dm" => {
+                // Our own DM ops were materialised when sent; the sending
+                // ratchet can't decrypt them again.
+                let is_mine = get_core().map(|c| c.public_key_hex == author_key).unwrap_or(false);
+                // The same goes for received DMs on replay: their keys are
+                // gone, and the rebuild carries their messages over.
+                if is_mine || replaying() {
+                    return Ok(OpenedPayload::Skipped);
+                }
+                (op.dm_thread_id, "dm", op.encrypted)
+            }
+            _ => {
+                if let Some((op_type, room_id)) = plaintext_room_payload(pool, log_id, &body).await? {
+                    if db::load_enc_group_state(pool, &room_id).await?.is_some() {
+                        let org_id = room_org_id(pool, &room_id).await?;
+                        let reason = format!("plaintext payload in encrypted room {room_id}");
+                        reject_op(pool, op_hash, log_id, author_key, &op_type, org_id.as_deref(), &reason).await?;
+                        return Ok(OpenedPayload::Skipped);
+                    }
                 }
+                return Ok(OpenedPayload::Plain(body));
+            }
+        },
+    };
 
-                db::set_cursor(read_pool, log_id, &pk_hex, seq).await?;
+    Ok(match decrypt_group_payload(pool, &group_id, group_type, &encrypted).await {
+        Ok(plaintext) => OpenedPayload::Plain(plaintext),
+        Err(e) => {
+            eprintln!("[projector] queueing {log_id} op {op_hash} for {group_type} {group_id}: {e}");
+            let row = db::PendingDecryptRow {
+                op_hash: op_hash.to_string(),
+                log_id: log_id.to_string(),
+                author_key: author_key.to_string(),
+                group_id,
+                group_type: group_type.to_string(),
+                encrypted,
+                timestamp,
+                attempts: 1,
+            };
+            let now = now_micros();
+            if let Err(e) =
+                db::insert_pending_decrypt(pool, &row, &e.to_string(), now, pending_decrypt_backoff(1, now)).await
+            {
+                eprintln!("[projector] failed to queue {op_hash} for decryption: {e}");
             }
+            OpenedPayload::Queued
         }
+    })
+}
+
+/// Op type and room of a plaintext room message or reaction, if `body` is one.
+async fn plaintext_room_payload(
+    pool: &SqlitePool,
+    log_id: &str,
+    body: &[u8],
+) -> Result<Option<(String, String)>, db::DbError> {
+    if log_id == log_ids::REACTION {
+        let Ok(op) = decode_cbor::<ReactionOp>(body) else { return Ok(None) };
+        let room_id = db::get_message_room_id(pool, &op.message_id).await?;
+        return Ok(room_id.map(|room_id| (op.op_type, room_id)));
+    }
+    Ok(decode_cbor::<MessageOp>(body).ok().and_then(|op| Some((op.op_type, op.room_id?))))
+}
+
+async fn room_org_id(pool: &SqlitePool, room_id: &str) -> Result<Option<String>, db::DbError> {
+    Ok(db::get_room(pool, room_id).await?.map(|room| room.org_id))
+}
+
+async fn decrypt_group_payload(
+    pool: &SqlitePool,
+    group_id: &str,
+    group_type: &str,
+    encrypted: &[u8],
+) -> Result<Vec<u8>, crate::encryption::EncryptionError> {
+    match group_type {
+        "dm" => decrypt_for_dm_with_pool(group_id, encrypted, pool).await,
+        _ => decrypt_for_room_with_pool(group_id, encrypted, pool).await,
     }
+}
 
+/// Retry queued encrypted ops whose backoff has elapsed, after giving up on
+/// those that hit the attempt cap or outlived the TTL.
+async fn retry_pending_decrypts(pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let now = now_micros();
+    let queued_before = now - PENDING_DECRYPT_TTL_MICROS;
+    for row in
+        db::list_expired_pending_decrypts(pool, PENDING_DECRYPT_MAX_ATTEMPTS, queued_before, PENDING_DECRYPT_BATCH).await?
+    {
+        let org_id = match row.group_type.as_str() {
+            "room" => room_org_id(pool, &row.group_id).await?,
+            _ => None,
+        };
+        let reason = format!("undecryptable after {} attempts: gave up", row.attempts);
+        reject_op(pool, &row.op_hash, &row.log_id, &row.author_key, "encrypted", org_id.as_deref(), &reason).await?;
+        db::mark_op_projected(pool, &row.op_hash).await?;
+        db::delete_pending_decrypt(pool, &row.op_hash).await?;
+    }
+
+    for row in db::list_due_pending_decrypts(pool, now, PENDING_DECRYPT_BATCH).await? {
+        match decrypt_group_payload(pool, &row.group_id, &row.group_type, &row.encrypted).await {
+            Ok(plaintext) => {
+                if let Err(e) =
+                    dispatch_op(pool, &row.log_id, &row.author_key, &row.op_hash, &plaintext, row.timestamp).await
+                {
+                    eprintln!("[projector] failed to project {} op {}: {e}", row.log_id, row.op_hash);
+                }
+                db::mark_op_projected(pool, &row.op_hash).await?;
+                db::delete_pending_decrypt(pool, &row.op_hash).await?;
+            }
+            Err(e) => {
+                db::reschedule_pending_decrypt(
+                    pool,
+                    &row.op_hash,
+                    &e.to_string(),
+                    pending_decrypt_backoff(row.attempts + 1, now),
+                )
+                .await?;
+            }
+        }
+    }
     Ok(())
 }
 
+/// Dispatch a (decrypted) op body to the handler for its log.
+async fn dispatch_op(
+    read_pool: &SqlitePool,
+    log_id: &str,
+    pk_hex: &str,
+    op_hash: &str,
+    body: &[u8],
+    timestamp: i64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match log_id {
+        log_ids::PROFILE => {
+            project_profile(read_pool, pk_hex, body, now_micros()).await
+        }
+        log_ids::ORG => {
+            project_org(read_pool, pk_hex, op_hash, body, now_micros()).await
+        }
+        log_ids::ROOM => {
+            project_room(read_pool, pk_hex, op_hash, body, now_micros()).await
+        }
+        log_ids::MESSAGE => {
+            project_message(
+                read_pool,
+                pk_hex,
+                op_hash,
+                body,
+                timestamp,
+            )
+            .await
+        }
+        log_ids::REACTION => {
+            project_reaction(read_pool, pk_hex, body).await
+        }
+        log_ids::DM_THREAD => {
+            project_dm_thread(
+                read_pool,
+                pk_hex,
+                op_hash,
+                body,
+                now_micros(),
+            )
+            .await
+        }
+        log_ids::ORG_ADMIN_THREAD => {
+            project_org_admin_thread(
+                read_pool,
+                pk_hex,
+                op_hash,
+                body,
+                now_micros(),
+            )
+            .await
+        }
+        log_ids::EVENT => {
+            project_event(
+                read_pool,
+                pk_hex,
+                op_hash,
+                body,
+                now_micros(),
+            )
+            .await
+        }
+        log_ids::EVENT_RSVP => {
+            project_event_rsvp(
+                read_pool,
+                pk_hex,
+                body,
+                now_micros(),
+            )
+            .await
+        }
+        log_ids::MEMBERSHIP => {
+            project_membership(read_pool, pk_hex, op_hash, body, now_micros()).await
+        }
+        log_ids::KEY_BUNDLE => {
+            project_key_bundle(read_pool, pk_hex, body).await
+        }
+        log_ids::ENC_CTRL => {
+            project_enc_ctrl(read_pool, pk_hex, body).await
+        }
+        _ => Ok(()),
+    }
+}
+
 async fn project_profile(
     pool: &SqlitePool,
     author_key: &str,
@@ -184,7 +733,7 @@ async fn project_profile(
 
     // Register the sender's pre-key bundle in our KeyRegistry so we can
     // later call GroupState::add(member) without MissingPreKeys errors.
-    if let Some(bundle_bytes) = op.pre_key_bundle {
+    if let Some(bundle_bytes) = op.pre_key_bundle.filter(|_| !replaying()) {
         if let Some(enc) = get_encryption() {
             // Decode author public key.
             if let Ok(pk_bytes) = hex::decode(author_key) {
@@ -218,6 +767,56 @@ async fn project_profile(
     Ok(())
 }
 
+async fn project_key_bundle(
+    pool: &SqlitePool,
+    author_key: &str,
+    body: &[u8],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let op: KeyBundleOp = decode_cbor(body)?;
+    let pk_bytes: [u8; 32] = hex::decode(author_key)?
+        .try_into()
+        .map_err(|_| "author key must be 32 bytes")?;
+    let author_pk = PublicKey::from_bytes(&pk_bytes)?;
+    register_key_bundle_with_pool(author_pk, &op.bundle_type, &op.bundle_data, pool).await?;
+    Ok(())
+}
+
+/// Feed a DM control message from the other participant into the thread's
+/// forward-secure group. Room ctrl messages are handled by the room flows.
+async fn project_enc_ctrl(
+    pool: &SqlitePool,
+    author_key: &str,
+    body: &[u8],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let op: EncCtrlOp = decode_cbor(body)?;
+    let is_mine = get_core().map(|c| c.public_key_hex == author_key).unwrap_or(false);
+    if is_mine || db::get_room(pool, &op.group_id).await?.is_some() {
+        return Ok(());
+    }
+
+    let replies = receive_dm_ctrl_with_pool(&op.group_id, &op.ctrl_data, pool).await?;
+    db::wake_pending_decrypts(pool, &op.group_id).await?;
+
+    // The op store is locked for the whole tick, so acks (and pre-keys to
+    // replace one a welcome used up) are published afterwards.
+    if !replies.is_empty() {
+        let group_id = op.group_id.clone();
+        tokio::spawn(async move {
+            for ctrl_bytes in replies {
+                if let Err(e) = publish_enc_ctrl_op(&group_id, ctrl_bytes).await {
+                    log::warn!("[projector] failed to publish DM ack for {}: {}", group_id, e);
+                }
+            }
+            if let Some(core) = get_core() {
+                if let Err(e) = crate::top_up_onetime_key_bundles(core).await {
+                    log::warn!("[projector] failed to top up one-time key bundles: {}", e);
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
 async fn project_org(
     pool: &SqlitePool,
     author_key: &str,
@@ -227,6 +826,12 @@ async fn project_org(
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Try to decode as OrgUpdateOp first
     if let Ok(update_op) = decode_cbor::<OrgUpdateOp>(body) {
+        if update_op.op_type == "update_org" || update_op.op_type == "delete_org" {
+            let verdict = check_org_admin(pool, &update_op.org_id, author_key).await?;
+            if !authorize(pool, verdict, op_hash, log_ids::ORG, author_key, &update_op.op_type, Some(&update_op.org_id)).await? {
+                return Ok(());
+            }
+        }
         if update_op.op_type == "update_org" {
             db::update_org(
                 pool,
@@ -242,6 +847,7 @@ async fn project_org(
                 update_op.is_public,
                 None, // email_enabled not carried in ops; updated only via direct call
             ).await?;
+            events::emit(GardensEvent::OrgUpdated { org_id: update_op.org_id });
             return Ok(());
         }
         if update_op.op_type == "delete_org" {
@@ -308,6 +914,7 @@ async fn project_org(
                 .bind(&update_op.org_id)
                 .execute(pool)
                 .await?;
+            events::emit(GardensEvent::OrgUpdated { org_id: update_op.org_id });
             return Ok(());
         }
     }
@@ -338,6 +945,8 @@ async fn project_org(
     .await?;
     // Auto-enroll creator as "manage"-level member.
     db::upsert_membership(pool, op_hash, author_key, "manage", now).await?;
+    note_grant(pool, op_hash, author_key).await?;
+    events::emit(GardensEvent::OrgUpdated { org_id: op_hash.to_string() });
     Ok(())
 }
 
@@ -351,25 +960,38 @@ async fn project_room(
     // Try to decode as RoomUpdateOp first
     if let Ok(update_op) = decode_cbor::<RoomUpdateOp>(body) {
         if update_op.op_type == "update_room" {
+            let verdict = check_room_access(pool, &update_op.room_id, author_key).await?;
+            if !authorize(pool, verdict, op_hash, log_ids::ROOM, author_key, &update_op.op_type, Some(&update_op.org_id)).await? {
+                return Ok(());
+            }
             db::update_room(
                 pool,
                 &update_op.room_id,
                 update_op.name.as_deref(),
                 update_op.room_cooldown_secs,
             ).await?;
+            events::emit(GardensEvent::RoomUpdated { room_id: update_op.room_id });
             return Ok(());
         }
     }
 
     // Try to decode as RoomDeleteOp (for delete/archive operations)
     if let Ok(delete_op) = decode_cbor::<RoomDeleteOp>(body) {
+        if delete_op.op_type == "delete_room" || delete_op.op_type == "archive_room" {
+            let verdict = check_room_access(pool, &delete_op.room_id, author_key).await?;
+            if !authorize(pool, verdict, op_hash, log_ids::ROOM, author_key, &delete_op.op_type, Some(&delete_op.org_id)).await? {
+                return Ok(());
+            }
+        }
         match delete_op.op_type.as_str() {
             "delete_room" => {
                 db::delete_room(pool, &delete_op.room_id).await?;
+                events::emit(GardensEvent::RoomUpdated { room_id: delete_op.room_id });
                 return Ok(());
             }
             "archive_room" => {
                 db::archive_room(pool, &delete_op.room_id, now).await?;
+                events::emit(GardensEvent::RoomUpdated { room_id: delete_op.room_id });
                 return Ok(());
             }
             _ => {}
@@ -378,6 +1000,10 @@ async fn project_room(
 
     // Otherwise decode as regular RoomOp
     let op: RoomOp = decode_cbor(body)?;
+    let verdict = check_org_access(pool, &op.org_id, author_key, AccessLevel::Write).await?;
+    if !authorize(pool, verdict, op_hash, log_ids::ROOM, author_key, "create_room", Some(&op.org_id)).await? {
+        return Ok(());
+    }
     db::insert_room(
         pool,
         &RoomRow {
@@ -397,6 +1023,7 @@ async fn project_room(
         },
     )
     .await?;
+    events::emit(GardensEvent::RoomUpdated { room_id: op_hash.to_string() });
     Ok(())
 }
 
@@ -409,6 +1036,10 @@ async fn project_event(
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     if let Ok(update_op) = decode_cbor::<EventUpdateOp>(body) {
         if update_op.op_type == "update_event" {
+            let verdict = check_event_access(pool, &update_op.event_id, author_key).await?;
+            if !authorize(pool, verdict, op_hash, log_ids::EVENT, author_key, &update_op.op_type, Some(&update_op.org_id)).await? {
+                return Ok(());
+            }
             db::update_event(
                 pool,
                 &update_op.event_id,
@@ -427,12 +1058,20 @@ async fn project_event(
 
     if let Ok(delete_op) = decode_cbor::<EventDeleteOp>(body) {
         if delete_op.op_type == "delete_event" {
+            let verdict = check_event_access(pool, &delete_op.event_id, author_key).await?;
+            if !authorize(pool, verdict, op_hash, log_ids::EVENT, author_key, &delete_op.op_type, Some(&delete_op.org_id)).await? {
+                return Ok(());
+            }
             db::delete_event(pool, &delete_op.event_id).await?;
             return Ok(());
         }
     }
 
     let op: EventOp = decode_cbor(body)?;
+    let verdict = check_org_access(pool, &op.org_id, author_key, AccessLevel::Write).await?;
+    if !authorize(pool, verdict, op_hash, log_ids::EVENT, author_key, "create_event", Some(&op.org_id)).await? {
+        return Ok(());
+    }
     db::insert_event(
         pool,
         &EventRow {
@@ -483,23 +1122,134 @@ async fn project_message(
     timestamp: i64,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let op: MessageOp = decode_cbor(body)?;
+
+    // Edits and deletes reference the original message; only its author may
+    // apply them, except that members with Manage access in the message's
+    // room may delete it.
+    if let Some(target_id) = op.target_id.as_deref() {
+        match op.op_type.as_str() {
+            "edit" => {
+                let applied = db::apply_message_edit(
+                    pool,
+                    op_hash,
+                    target_id,
+                    author_key,
+                    op.text_content.as_deref(),
+                    &op.mentions,
+                    timestamp,
+                )
+                .await?;
+                if applied {
+                    events::emit(GardensEvent::MessageEdited { message_id: target_id.to_string() });
+                }
+            }
+            "delete" => {
+                let mut deleted = db::mark_message_deleted(pool, target_id, Some(author_key)).await?;
+                if !deleted {
+                    if let Some(room_id) = db::get_message_room_id(pool, target_id).await? {
+                        if matches!(check_room_access(pool, &room_id, author_key).await?, Verdict::Allowed) {
+                            deleted = db::mark_message_deleted(pool, target_id, None).await?;
+                        }
+                    }
+                }
+                if deleted {
+                    events::emit(GardensEvent::MessageDeleted { message_id: target_id.to_string() });
+                }
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+    if op.op_type == "edit" {
+        return Ok(());
+    }
+
     let is_deleted = op.op_type == "delete";
-    db::insert_message(
+
+    if !is_deleted {
+        if let Some(hash) = op.blob_id.as_deref() {
+            project_attachment(pool, author_key, op_hash, &op, hash).await?;
+        }
+    }
+
+    let row = MessageRow {
+        message_id: op_hash.to_string(),
+        room_id: op.room_id,
+        dm_thread_id: op.dm_thread_id,
+        author_key: author_key.to_string(),
+        content_type: op.content_type,
+        text_content: op.text_content,
+        blob_id: op.blob_id,
+        embed_url: op.embed_url,
+        mentions: op.mentions,
+        reply_to: op.reply_to,
+        timestamp,
+        edited_at: None,
+        is_deleted,
+    };
+    db::insert_message(pool, &row).await?;
+    if !is_deleted {
+        events::emit(GardensEvent::MessageInserted { message: crate::message_from_row(row) });
+    }
+    Ok(())
+}
+
+/// Remember an attachment's content key and image preview. Keys are kept
+/// per message and only adopted once one opens the blob (see
+/// `blobs::prepare_read`), so a message naming someone else's blob cannot
+/// swap in a bogus key. The first message to describe the blob fixes its
+/// preview.
+async fn project_attachment(
+    pool: &SqlitePool,
+    author_key: &str,
+    message_id: &str,
+    op: &MessageOp,
+    hash: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let preview = op.preview.as_ref();
+    if let (Some(key), Some(nonce)) = (&op.blob_key, &op.blob_nonce) {
+        db::insert_blob_key(pool, hash, message_id, key, nonce).await?;
+    }
+    if let Some(p) = preview {
+        if let (Some(thumb), Some(key), Some(nonce)) = (&p.thumbnail_id, &p.thumbnail_key, &p.thumbnail_nonce) {
+            db::insert_blob_key(pool, thumb, message_id, key, nonce).await?;
+        }
+    }
+    if op.blob_key.is_none() && op.preview.is_none() {
+        return Ok(());
+    }
+    if db::get_blob_meta(pool, hash).await?.is_some() {
+        return Ok(());
+    }
+
+    if let Some(thumb) = preview.and_then(|p| p.thumbnail_id.as_deref()) {
+        if db::get_blob_meta(pool, thumb).await?.is_none() {
+            db::insert_blob_meta(
+                pool,
+                &db::BlobMeta {
+                    blob_hash: thumb.to_string(),
+                    mime_type: "image/jpeg".to_string(),
+                    room_id: op.room_id.clone(),
+                    sender_key: Some(author_key.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+    }
+
+    db::insert_blob_meta(
         pool,
-        &MessageRow {
-            message_id: op_hash.to_string(),
-            room_id: op.room_id,
-            dm_thread_id: op.dm_thread_id,
-            author_key: author_key.to_string(),
-            content_type: op.content_type,
-            text_content: op.text_content,
-            blob_id: op.blob_id,
-            embed_url: op.embed_url,
-            mentions: op.mentions,
-            reply_to: op.reply_to,
-            timestamp,
-            edited_at: None,
-            is_deleted,
+        &db::BlobMeta {
+            blob_hash: hash.to_string(),
+            mime_type: op.content_type.clone(),
+            room_id: op.room_id.clone(),
+            sender_key: Some(author_key.to_string()),
+            thumbnail_hash: preview.and_then(|p| p.thumbnail_id.clone()),
+            width: preview.map(|p| p.width),
+            height: preview.map(|p| p.height),
+            blurhash: preview.map(|p| p.blurhash.clone()),
+            ..Default::default()
         },
     )
     .await?;
@@ -512,13 +1262,23 @@ async fn project_reaction(
     body: &[u8],
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let op: ReactionOp = decode_cbor(body)?;
-    match op.op_type.as_str() {
-        "add_reaction" => db::upsert_reaction(pool, &op.message_id, &op.emoji, author_key).await?,
+    let added = match op.op_type.as_str() {
+        "add_reaction" => {
+            db::upsert_reaction(pool, &op.message_id, &op.emoji, author_key).await?;
+            true
+        }
         "remove_reaction" => {
-            db::delete_reaction(pool, &op.message_id, &op.emoji, author_key).await?
+            db::delete_reaction(pool, &op.message_id, &op.emoji, author_key).await?;
+            false
         }
-        _ => {}
-    }
+        _ => return Ok(()),
+    };
+    events::emit(GardensEvent::ReactionChanged {
+        message_id: op.message_id,
+        emoji: op.emoji,
+        reactor_key: author_key.to_string(),
+        added,
+    });
     Ok(())
 }
 
@@ -571,6 +1331,12 @@ async fn project_dm_thread(
         },
     )
     .await?;
+    if is_request {
+        events::emit(GardensEvent::DmRequestReceived {
+            thread_id: op_hash.to_string(),
+            initiator_key: author_key.to_string(),
+        });
+    }
     Ok(())
 }
 
@@ -633,6 +1399,168 @@ async fn load_org_membership_state(
     Ok(state)
 }
 
+/// Outcome of an authorization check.
+enum Verdict {
+    Allowed,
+    /// The op fails for good, for this reason.
+    Denied(String),
+    /// Can't be decided until this op (or [`grant_marker`]) is projected.
+    Waiting(String),
+}
+
+/// Raised by a projection to hold its op back until the named op is
+/// projected; see [`project_ready_op`].
+#[derive(Debug)]
+struct Deferred(String);
+
+impl std::fmt::Display for Deferred {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "waiting for {}", self.0)
+    }
+}
+
+impl std::error::Error for Deferred {}
+
+/// Act on `verdict` for an op: returns true if the op may be applied. Denied
+/// ops are recorded in `rejected_ops`; waiting ones are held back.
+async fn authorize(
+    pool: &SqlitePool,
+    verdict: Verdict,
+    op_hash: &str,
+    log_id: &str,
+    author_key: &str,
+    op_type: &str,
+    org_id: Option<&str>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    match verdict {
+        Verdict::Allowed => Ok(true),
+        Verdict::Denied(reason) => {
+            reject_op(pool, op_hash, log_id, author_key, op_type, org_id, &reason).await?;
+            Ok(false)
+        }
+        Verdict::Waiting(dep) => Err(Box::new(Deferred(dep))),
+    }
+}
+
+/// Stand-in entry in `projected_ops` for the first grant `member_key` got in
+/// `org_id`, so ops waiting on it are released by the usual dependency pass.
+fn grant_marker(org_id: &str, member_key: &str) -> String {
+    format!("grant:{org_id}:{member_key}")
+}
+
+/// The org of a [`grant_marker`], if `dep` is one.
+fn grant_marker_org(dep: &str) -> Option<&str> {
+    dep.strip_prefix("grant:")?.split(':').next()
+}
+
+async fn note_grant(pool: &SqlitePool, org_id: &str, member_key: &str) -> Result<(), db::DbError> {
+    db::mark_op_projected(pool, &grant_marker(org_id, member_key)).await
+}
+
+/// Check that `author_key` holds at least `required` in `org_id`. Waits for
+/// the org, and for the author's first grant in it, before denying.
+async fn check_org_access(
+    pool: &SqlitePool,
+    org_id: &str,
+    author_key: &str,
+    required: AccessLevel,
+) -> Result<Verdict, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(author_pk) = parse_public_key(author_key) else {
+        return Ok(Verdict::Denied("invalid author key".into()));
+    };
+    if db::get_org(pool, org_id).await?.is_none() {
+        return Ok(Verdict::Waiting(org_id.to_string()));
+    }
+    let state = load_org_membership_state(pool, org_id).await?;
+    if state.has_permission(&author_pk, required) {
+        return Ok(Verdict::Allowed);
+    }
+    let marker = grant_marker(org_id, author_key);
+    if !state.is_member(&author_pk) && !db::unprojected_ops(pool, std::slice::from_ref(&marker)).await?.is_empty() {
+        return Ok(Verdict::Waiting(marker));
+    }
+    Ok(Verdict::Denied(format!("author lacks {} access in org", required.as_str())))
+}
+
+/// Org updates and deletes need Manage access. Ops signed by the org's own
+/// key (how older clients published them) are accepted when we know that key.
+async fn check_org_admin(
+    pool: &SqlitePool,
+    org_id: &str,
+    author_key: &str,
+) -> Result<Verdict, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(org) = db::get_org(pool, org_id).await? else {
+        return Ok(Verdict::Waiting(org_id.to_string()));
+    };
+    if let (Some(org_z32), Ok(author_bytes)) = (org.org_pubkey.as_deref(), hex::decode(author_key)) {
+        if z32::encode(&author_bytes) == org_z32 {
+            return Ok(Verdict::Allowed);
+        }
+    }
+    check_org_access(pool, org_id, author_key, AccessLevel::Manage).await
+}
+
+/// Room updates, deletes and archives need Manage access in the org that
+/// owns the room, not the org the op claims.
+async fn check_room_access(
+    pool: &SqlitePool,
+    room_id: &str,
+    author_key: &str,
+) -> Result<Verdict, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(room) = db::get_room(pool, room_id).await? else {
+        return Ok(Verdict::Waiting(room_id.to_string()));
+    };
+    check_org_access(pool, &room.org_id, author_key, AccessLevel::Manage).await
+}
+
+/// Event updates and deletes need Manage access, or Write access for the
+/// member who created the event.
+async fn check_event_access(
+    pool: &SqlitePool,
+    event_id: &str,
+    author_key: &str,
+) -> Result<Verdict, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(event) = db::get_event(pool, event_id).await? else {
+        return Ok(Verdict::Waiting(event_id.to_string()));
+    };
+    let required = if event.created_by == author_key {
+        AccessLevel::Write
+    } else {
+        AccessLevel::Manage
+    };
+    check_org_access(pool, &event.org_id, author_key, required).await
+}
+
+/// Record an op that failed authorization instead of applying it.
+async fn reject_op(
+    pool: &SqlitePool,
+    op_hash: &str,
+    log_id: &str,
+    author_key: &str,
+    op_type: &str,
+    org_id: Option<&str>,
+    reason: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    log::warn!(
+        "[projector] Rejected {} op {} from {}: {}",
+        op_type, op_hash, author_key, reason
+    );
+    db::insert_rejected_op(
+        pool,
+        &RejectedOpRow {
+            op_hash: op_hash.to_string(),
+            log_id: log_id.to_string(),
+            author_key: author_key.to_string(),
+            op_type: op_type.to_string(),
+            org_id: org_id.map(str::to_string),
+            reason: reason.to_string(),
+            rejected_at: now_micros(),
+        },
+    )
+    .await?;
+    Ok(())
+}
+
 /// Operations that require Manage-level permission
 const MODERATION_OPS: &[&str] = &[
     "kick_member",
@@ -650,6 +1578,7 @@ const MODERATION_OPS: &[&str] = &[
 async fn project_membership(
     pool: &SqlitePool,
     author_key: &str,
+    op_hash: &str,
     body: &[u8],
     now: i64,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -661,23 +1590,8 @@ async fn project_membership(
     let requires_manage = MODERATION_OPS.contains(&op.op_type.as_str());
     
     if requires_manage {
-        let state = load_org_membership_state(pool, &op.org_id).await?;
-        let author_pubkey = match hex::decode(author_key)
-            .ok()
-            .and_then(|b| b.try_into().ok())
-            .and_then(|arr| p2panda_core::PublicKey::from_bytes(&arr).ok()) {
-            Some(pk) => pk,
-            None => {
-                log::warn!("[projector] Invalid author key in membership op: {}", author_key);
-                return Ok(());
-            }
-        };
-        
-        if !state.has_permission(&author_pubkey, AccessLevel::Manage) {
-            log::warn!(
-                "[projector] Rejected {} op from unauthorized author {} in org {}",
-                op.op_type, author_key, op.org_id
-            );
+        let verdict = check_org_access(pool, &op.org_id, author_key, AccessLevel::Manage).await?;
+        if !authorize(pool, verdict, op_hash, log_ids::MEMBERSHIP, author_key, &op.op_type, Some(&op.org_id)).await? {
             return Ok(());
         }
     }
@@ -700,26 +1614,24 @@ async fn project_membership(
                     now,
                 )
                 .await?;
+                note_grant(pool, &op.org_id, &op.member_key).await?;
                 
                 // Add member to encryption groups for all rooms in the org
-                if let Ok(pk_bytes) = hex::decode(&op.member_key) {
-                    if let Ok(pk_arr) = <[u8; 32]>::try_from(pk_bytes.as_slice()) {
-                        if let Ok(member_pk) = p2panda_core::PublicKey::from_bytes(&pk_arr) {
-                            // Try to add member to all room groups
-                            match add_member_to_org_groups(&op.org_id, member_pk).await {
-                                Ok(ctrl_messages) => {
-                                    for (room_id, _ctrl_bytes) in ctrl_messages {
-                                        log::info!("[projector] added member to room encryption group: {} for room {}", 
-                                            &op.member_key, room_id);
-                                        // In a full implementation, these ctrl_bytes would be 
-                                        // gossiped to room members
-                                    }
-                                }
-                                Err(e) => {
-                                    log::warn!("[projector] failed to add member to org encryption groups: {}", e);
-                                }
+                // (already done if we're replaying).
+                if let Some(member_pk) = parse_public_key(&op.member_key).filter(|_| !replaying()) {
+                    // Try to add member to all room groups
+                    match add_member_to_org_groups(&op.org_id, member_pk).await {
+                        Ok(ctrl_messages) => {
+                            for (room_id, _ctrl_bytes) in ctrl_messages {
+                                log::info!("[projector] added member to room encryption group: {} for room {}", 
+                                    &op.member_key, room_id);
+                                // In a full implementation, these ctrl_bytes would be 
+                                // gossiped to room members
                             }
                         }
+                        Err(e) => {
+                            log::warn!("[projector] failed to add member to org encryption groups: {}", e);
+                        }
                     }
                 }
             }
@@ -746,7 +1658,7 @@ async fn project_membership(
                 .await?;
             
             // Remove from encryption groups if we have a valid public key
-            if let Some(pk) = member_pk {
+            if let Some(pk) = member_pk.filter(|_| !replaying()) {
                 if let Err(e) = remove_member_from_org_groups(&op.org_id, pk).await {
                     log::warn!("[projector] failed to remove member from encryption groups: {}", e);
                 }
@@ -789,7 +1701,7 @@ async fn project_membership(
             db::bump_room_epochs_for_org(pool, &op.org_id).await.ok();
             
             // Remove from encryption groups
-            if let Some(pk) = member_pk {
+            if let Some(pk) = member_pk.filter(|_| !replaying()) {
                 if let Err(e) = remove_member_from_org_groups(&op.org_id, pk).await {
                     log::warn!("[projector] failed to remove banned member from encryption groups: {}", e);
                 }
@@ -826,7 +1738,7 @@ async fn project_membership(
                 &op.member_key,
                 &moderator_key,
                 now,
-                expires_at.unwrap_or(now + 3600_000_000), // default 1 hour
+                expires_at.unwrap_or(now + 3_600_000_000), // default 1 hour
                 None,
             ).await?;
             
@@ -863,6 +1775,7 @@ async fn project_membership(
                     now,
                 )
                 .await?;
+                note_grant(pool, &op.org_id, &op.member_key).await?;
                 
                 db::insert_audit_log(
                     pool,
@@ -888,8 +1801,230 @@ async fn project_membership(
         "unice_member" => {
             db::clear_ice(pool, &op.org_id, &op.member_key).await?;
         }
-        _ => {}
+        _ => return Ok(()),
     }
-    
+
+    events::emit(GardensEvent::MembershipChanged { org_id: op.org_id, member_key: op.member_key });
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::{encode_cbor, MembershipOp};
+    use crate::store::test_core;
+    use p2panda_core::PrivateKey;
+
+    fn entry(seq: u64, op_hash: &str, body: Vec<u8>) -> LogEntry {
+        LogEntry { seq, op_hash: op_hash.to_string(), timestamp: 0, previous: Vec::new(), body }
+    }
+
+    fn org_op() -> Vec<u8> {
+        encode_cbor(&OrgOp {
+            op_type: "create_org".into(),
+            name: "Garden".into(),
+            type_label: "community".into(),
+            description: None,
+            avatar_blob_id: None,
+            cover_blob_id: None,
+            welcome_text: None,
+            custom_emoji_json: None,
+            is_public: false,
+        })
+        .unwrap()
+    }
+
+    fn add_member(org_id: &str, member: &str, admin: &str, level: &str) -> Vec<u8> {
+        encode_cbor(&MembershipOp {
+            op_type: "add_member".into(),
+            org_id: org_id.into(),
+            member_key: member.into(),
+            moderator_key: admin.into(),
+            access_level: Some(level.into()),
+            cooldown_secs: None,
+            iced_until: None,
+        })
+        .unwrap()
+    }
+
+    fn room_op(org_id: &str) -> Vec<u8> {
+        encode_cbor(&RoomOp {
+            op_type: "create_room".into(),
+            org_id: org_id.into(),
+            name: "general".into(),
+            enc_key_epoch: 0,
+            room_type: "text".into(),
+        })
+        .unwrap()
+    }
+
+    fn rename_room(room_id: &str, org_id: &str) -> Vec<u8> {
+        encode_cbor(&RoomUpdateOp {
+            op_type: "update_room".into(),
+            room_id: room_id.into(),
+            org_id: org_id.into(),
+            name: Some("renamed".into()),
+            room_cooldown_secs: None,
+        })
+        .unwrap()
+    }
+
+    fn plaintext_message(room_id: &str) -> Vec<u8> {
+        encode_cbor(&MessageOp {
+            op_type: "send".into(),
+            room_id: Some(room_id.into()),
+            dm_thread_id: None,
+            content_type: "text".into(),
+            text_content: Some("in the clear".into()),
+            blob_id: None,
+            blob_key: None,
+            blob_nonce: None,
+            preview: None,
+            embed_url: None,
+            mentions: Vec::new(),
+            reply_to: None,
+            target_id: None,
+        })
+        .unwrap()
+    }
+
+    fn author() -> String {
+        PrivateKey::new().public_key().to_hex()
+    }
+
+    async fn project_org(pool: &SqlitePool, founder: &str, org_id: &str) {
+        project_entry(pool, log_ids::ORG, founder, entry(0, org_id, org_op())).await.unwrap();
+    }
+
+    async fn project_room(pool: &SqlitePool, author: &str, org_id: &str, room_id: &str) {
+        project_entry(pool, log_ids::ROOM, author, entry(0, room_id, room_op(org_id))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ops_arriving_before_their_grant_are_deferred_not_rejected() {
+        let core = test_core().await;
+        let pool = &core.read_pool;
+        let alice = author();
+        let bob = author();
+        let org_id = "aa".repeat(32);
+        let room_id = "bb".repeat(32);
+
+        // Bob's room and its rename reach us before the org and his grant.
+        project_room(pool, &bob, &org_id, &room_id).await;
+        project_entry(pool, log_ids::ROOM, &bob, entry(1, &"cc".repeat(32), rename_room(&room_id, &org_id))).await.unwrap();
+        project_org(pool, &alice, &org_id).await;
+        retry_pending_deps(pool).await.unwrap();
+        assert!(db::get_room(pool, &room_id).await.unwrap().is_none());
+        assert!(db::list_rejected_ops(pool, &org_id).await.unwrap().is_empty());
+
+        let grant = add_member(&org_id, &bob, &alice, "manage");
+        project_entry(pool, log_ids::MEMBERSHIP, &alice, entry(0, &"dd".repeat(32), grant)).await.unwrap();
+        retry_pending_deps(pool).await.unwrap();
+
+        let room = db::get_room(pool, &room_id).await.unwrap().expect("room projected");
+        assert_eq!(room.name, "renamed");
+        assert!(db::list_rejected_ops(pool, &org_id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn members_without_the_level_are_rejected_once_deps_are_present() {
+        let core = test_core().await;
+        let pool = &core.read_pool;
+        let alice = author();
+        let bob = author();
+        let carol = author();
+        let org_id = "aa".repeat(32);
+
+        project_org(pool, &alice, &org_id).await;
+        let grant = add_member(&org_id, &bob, &alice, "read");
+        project_entry(pool, log_ids::MEMBERSHIP, &alice, entry(0, &"dd".repeat(32), grant)).await.unwrap();
+
+        // Bob is a reader: decided now. Carol has no grant yet: held back.
+        project_room(pool, &bob, &org_id, &"bb".repeat(32)).await;
+        project_room(pool, &carol, &org_id, &"cc".repeat(32)).await;
+        retry_pending_deps(pool).await.unwrap();
+
+        let rejected = db::list_rejected_ops(pool, &org_id).await.unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].author_key, bob);
+        assert!(db::get_room(pool, &"cc".repeat(32)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn ops_waiting_past_the_ttl_are_rejected() {
+        let core = test_core().await;
+        let pool = &core.read_pool;
+        let alice = author();
+        let carol = author();
+        let org_id = "aa".repeat(32);
+
+        // Carol's room waits for a grant that never comes.
+        project_org(pool, &alice, &org_id).await;
+        project_room(pool, &carol, &org_id, &"cc".repeat(32)).await;
+        retry_pending_deps(pool).await.unwrap();
+        assert!(db::list_rejected_ops(pool, &org_id).await.unwrap().is_empty());
+
+        sqlx::query("UPDATE pending_deps SET received_at = ?")
+            .bind(now_micros() - PENDING_DEPS_TTL_MICROS - 1)
+            .execute(pool)
+            .await
+            .unwrap();
+        retry_pending_deps(pool).await.unwrap();
+
+        let rejected = db::list_rejected_ops(pool, &org_id).await.unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].author_key, carol);
+        assert!(db::list_expired_pending_deps(pool, i64::MAX, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn plaintext_messages_in_encrypted_rooms_are_rejected() {
+        let core = test_core().await;
+        let pool = &core.read_pool;
+        let alice = author();
+        let org_id = "aa".repeat(32);
+        let room_id = "bb".repeat(32);
+
+        project_org(pool, &alice, &org_id).await;
+        project_room(pool, &alice, &org_id, &room_id).await;
+        db::save_enc_group_state(pool, &room_id, "room", b"group").await.unwrap();
+
+        let msg_hash = "cc".repeat(32);
+        project_entry(pool, log_ids::MESSAGE, &alice, entry(0, &msg_hash, plaintext_message(&room_id))).await.unwrap();
+
+        assert!(db::get_message_room_id(pool, &msg_hash).await.unwrap().is_none());
+        let rejected = db::list_rejected_ops(pool, &org_id).await.unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].op_hash, msg_hash);
+    }
+
+    #[tokio::test]
+    async fn undecryptable_ops_are_given_up_after_the_attempt_cap() {
+        let core = test_core().await;
+        let pool = &core.read_pool;
+        let alice = author();
+        let org_id = "aa".repeat(32);
+        let room_id = "bb".repeat(32);
+        project_org(pool, &alice, &org_id).await;
+        project_room(pool, &alice, &org_id, &room_id).await;
+
+        let row = db::PendingDecryptRow {
+            op_hash: "cc".repeat(32),
+            log_id: log_ids::MESSAGE.into(),
+            author_key: alice,
+            group_id: room_id,
+            group_type: "room".into(),
+            encrypted: vec![7u8; 16],
+            timestamp: 0,
+            attempts: PENDING_DECRYPT_MAX_ATTEMPTS,
+        };
+        let now = now_micros();
+        db::insert_pending_decrypt(pool, &row, "no group state", now, now + 1_000_000).await.unwrap();
+        retry_pending_decrypts(pool).await.unwrap();
+
+        assert!(db::list_expired_pending_decrypts(pool, 0, i64::MAX, 10).await.unwrap().is_empty());
+        let rejected = db::list_rejected_ops(pool, &org_id).await.unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].op_hash, row.op_hash);
+    }
+}
@@ -0,0 +1,374 @@
+//! Relay directory — the list of onion relays, published via pkarr.
+//!
+//! A directory operator signs a pkarr packet under their key with one TXT
+//! record per relay at `_relays`:
+//!
+//!   `v=gardens1;t=relay;k=<relay pubkey z32>;u=<hop url>;i=<iroh node id z32>;c=<caps>`
+//!
+//! `u` or `i` may be omitted (but not both). `c` is a comma-separated list of
+//! capabilities: `hop` (forwards packets) and `exit` (delivers to topics);
+//! without it a relay is assumed to do both. The pkarr signature is what
+//! makes the list trustworthy: clients only accept it from the directory
+//! key they asked for. A signed packet is capped at 1000 bytes, which fits
+//! about four relays.
+//!
+//! [`refresh_relay_directory`] resolves a directory into `sync_config`, the
+//! last one resolved is cached in SQLite for the next start, and
+//! [`select_path`] picks a fresh, diverse route from it for every packet
+//! built by the core.
+
+use std::collections::HashSet;
+
+use pkarr::{Keypair, SignedPacket};
+use rand::seq::SliceRandom;
+use sqlx::SqlitePool;
+
+use crate::onion::{OnionError, OnionHop};
+use crate::{db, sync_config};
+
+/// Hops in a route picked from the directory.
+pub const DEFAULT_PATH_LEN: usize = 3;
+
+/// Fewest hops a route may have: with one, that relay sees both ends.
+pub const MIN_PATH_LEN: usize = 2;
+
+const DIRECTORY_NAME: &str = "_relays";
+const DNS_TTL: u32 = 7200; // 2 hours
+
+pub const CAP_HOP: &str = "hop";
+pub const CAP_EXIT: &str = "exit";
+
+/// One relay as listed in a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayEntry {
+    /// Ed25519 key onion layers are encrypted to.
+    pub pubkey: [u8; 32],
+    /// HTTP URL the relay accepts onion packets on.
+    pub url: Option<String>,
+    /// Iroh node id, hex, for relays that take packets over the onion ALPN.
+    pub node_id: Option<String>,
+    pub capabilities: Vec<String>,
+}
+
+impl RelayEntry {
+    pub fn can(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Where previous hops send packets for this relay: its URL, else its
+    /// iroh node as `iroh:<node id>`.
+    pub fn address(&self) -> Option<String> {
+        self.url
+            .clone()
+            .or_else(|| self.node_id.as_ref().map(|id| format!("iroh:{id}")))
+    }
+
+    /// Who runs this relay, as far as we can tell: the URL's host, else the
+    /// node id. Routes never use two relays with the same operator.
+    fn operator(&self) -> String {
+        self.url
+            .as_deref()
+            .and_then(|u| reqwest::Url::parse(u).ok())
+            .and_then(|u| u.host_str().map(str::to_string))
+            .or_else(|| self.node_id.clone())
+            .unwrap_or_else(|| hex::encode(self.pubkey))
+    }
+}
+
+// ─── Record format ───────────────────────────────────────────────────────────
+
+fn encode_entry(entry: &RelayEntry) -> String {
+    let mut parts = vec![
+        "v=gardens1".to_string(),
+        "t=relay".to_string(),
+        format!("k={}", z32::encode(&entry.pubkey)),
+    ];
+    if let Some(url) = &entry.url {
+        parts.push(format!("u={url}"));
+    }
+    if let Some(node_id) = entry.node_id.as_deref().and_then(|id| hex::decode(id).ok()) {
+        parts.push(format!("i={}", z32::encode(&node_id)));
+    }
+    if !entry.capabilities.is_empty() {
+        parts.push(format!("c={}", entry.capabilities.join(",")));
+    }
+    parts.join(";")
+}
+
+fn parse_entry(txt: &str) -> Option<RelayEntry> {
+    let mut fields = txt.split(';').filter_map(|p| p.split_once('='));
+    if fields.next()? != ("v", "gardens1") {
+        return None;
+    }
+
+    let mut is_relay = false;
+    let mut pubkey = None;
+    let mut url = None;
+    let mut node_id = None;
+    let mut capabilities = vec![CAP_HOP.to_string(), CAP_EXIT.to_string()];
+    for (key, value) in fields {
+        match key {
+            "t" => is_relay = value == "relay",
+            "k" => pubkey = z32::decode(value.as_bytes()).ok()?.try_into().ok(),
+            "u" => url = Some(value.to_string()),
+            "i" => node_id = Some(hex::encode(z32::decode(value.as_bytes()).ok()?)),
+            "c" => capabilities = value.split(',').filter(|c| !c.is_empty()).map(str::to_string).collect(),
+            _ => {}
+        }
+    }
+
+    if !is_relay || (url.is_none() && node_id.is_none()) {
+        return None;
+    }
+    Some(RelayEntry { pubkey: pubkey?, url, node_id, capabilities })
+}
+
+// ─── Publish / resolve ───────────────────────────────────────────────────────
+
+/// Sign and publish a relay directory under `private_key_hex`.
+pub async fn publish_relay_directory(private_key_hex: &str, entries: &[RelayEntry]) -> Result<(), String> {
+    let pk_bytes = hex::decode(private_key_hex).map_err(|e| format!("invalid hex: {}", e))?;
+    let pk_arr: [u8; 32] = pk_bytes.as_slice().try_into()
+        .map_err(|_| "invalid key length".to_string())?;
+    let keypair = Keypair::from_secret_key(&pk_arr);
+
+    let mut builder = SignedPacket::builder();
+    for entry in entries {
+        let txt_value = encode_entry(entry);
+        let txt = pkarr::dns::rdata::TXT::try_from(txt_value.as_str())
+            .map_err(|e| format!("invalid txt: {}", e))?
+            .into_owned();
+        let name = pkarr::dns::Name::new(DIRECTORY_NAME)
+            .map_err(|e| format!("invalid name: {}", e))?;
+        builder = builder.txt(name, txt, DNS_TTL);
+    }
+    let signed_packet = builder
+        .sign(&keypair)
+        .map_err(|e| format!("failed to sign packet: {}", e))?;
+
+    let client = pkarr::Client::builder()
+        .build()
+        .map_err(|e| format!("failed to create pkarr client: {}", e))?;
+    client
+        .publish(&signed_packet, None)
+        .await
+        .map_err(|e| format!("failed to publish relay directory: {}", e))
+}
+
+/// Resolve the directory published under `z32_key` and make it the one
+/// routes are picked from. Returns the number of relays listed.
+pub async fn refresh_relay_directory(z32_key: &str) -> Result<usize, String> {
+    let public_key = pkarr::PublicKey::try_from(z32_key)
+        .map_err(|e| format!("invalid z32 key: {}", e))?;
+
+    let client = pkarr::Client::builder()
+        .build()
+        .map_err(|e| format!("failed to create pkarr client: {}", e))?;
+    let signed_packet = client
+        .resolve(&public_key)
+        .await
+        .ok_or_else(|| format!("no relay directory published under {}", z32_key))?;
+
+    let mut entries = Vec::new();
+    for rr in signed_packet.all_resource_records() {
+        if let pkarr::dns::rdata::RData::TXT(txt) = &rr.rdata {
+            if let Ok(txt_str) = String::try_from(txt.clone()) {
+                entries.extend(parse_entry(&txt_str));
+            }
+        }
+    }
+    if entries.is_empty() {
+        return Err(format!("relay directory {} lists no usable relays", z32_key));
+    }
+
+    log::info!("[relays] directory {} lists {} relays", z32_key, entries.len());
+    let count = entries.len();
+    sync_config::set_directory(entries);
+    Ok(count)
+}
+
+/// Store the directory currently in `sync_config`, resolved from `z32_key`.
+pub async fn cache_relay_directory(pool: &SqlitePool, z32_key: &str) -> Result<(), db::DbError> {
+    let records: Vec<String> = sync_config::get_directory().iter().map(encode_entry).collect();
+    db::save_relay_directory(pool, z32_key, &records, crate::now_micros()).await
+}
+
+/// Load the cached directory into `sync_config` unless one is already set.
+/// Returns the number of relays loaded.
+pub async fn load_cached_relay_directory(pool: &SqlitePool) -> Result<usize, db::DbError> {
+    if !sync_config::get_directory().is_empty() {
+        return Ok(0);
+    }
+    let entries: Vec<RelayEntry> = db::load_relay_directory(pool)
+        .await?
+        .iter()
+        .filter_map(|record| parse_entry(record))
+        .collect();
+    let count = entries.len();
+    if count > 0 {
+        sync_config::set_directory(entries);
+    }
+    Ok(count)
+}
+
+// ─── Path selection ──────────────────────────────────────────────────────────
+
+/// Pick a random route of up to `len` hops from `entries`: an exit last,
+/// forwarding relays before it, and no two relays sharing an operator.
+/// `len` is raised to [`MIN_PATH_LEN`]; shorter routes are returned when
+/// the directory is not diverse enough, but never shorter than that.
+pub fn select_path(entries: &[RelayEntry], len: usize) -> Result<Vec<OnionHop>, OnionError> {
+    let mut rng = rand::thread_rng();
+    let needed = len.max(MIN_PATH_LEN);
+
+    let mut exits: Vec<&RelayEntry> = entries
+        .iter()
+        .filter(|e| e.can(CAP_EXIT) && e.address().is_some())
+        .collect();
+    exits.shuffle(&mut rng);
+    let exit = *exits.first().ok_or(OnionError::InsufficientRelays { available: 0, needed })?;
+
+    let mut operators = HashSet::from([exit.operator()]);
+    let mut candidates: Vec<&RelayEntry> = entries
+        .iter()
+        .filter(|e| e.can(CAP_HOP) && e.address().is_some())
+        .collect();
+    candidates.shuffle(&mut rng);
+
+    let mut path = Vec::with_capacity(needed);
+    for entry in candidates {
+        if path.len() + 1 >= needed {
+            break;
+        }
+        if operators.insert(entry.operator()) {
+            path.push(entry);
+        }
+    }
+    path.push(exit);
+    if path.len() < MIN_PATH_LEN {
+        return Err(OnionError::InsufficientRelays { available: path.len(), needed });
+    }
+
+    Ok(path
+        .into_iter()
+        .map(|e| OnionHop {
+            pubkey_bytes: e.pubkey,
+            next_url: e.address().unwrap_or_default(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(byte: u8, url: &str, caps: &[&str]) -> RelayEntry {
+        RelayEntry {
+            pubkey: [byte; 32],
+            url: Some(url.to_string()),
+            node_id: Some(hex::encode([byte; 32])),
+            capabilities: caps.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn entries_roundtrip_through_txt() {
+        let e = entry(1, "https://r1.example.com/hop", &[CAP_HOP]);
+        let txt = encode_entry(&e);
+        assert!(txt.starts_with("v=gardens1;t=relay;"));
+        assert_eq!(parse_entry(&txt), Some(e));
+
+        let iroh_only = RelayEntry { url: None, ..entry(2, "", &[CAP_EXIT]) };
+        let parsed = parse_entry(&encode_entry(&iroh_only)).unwrap();
+        assert_eq!(parsed.address(), Some(format!("iroh:{}", hex::encode([2u8; 32]))));
+    }
+
+    #[test]
+    fn other_records_are_not_relays() {
+        assert_eq!(parse_entry("v=gardens1;t=user;u=alice"), None);
+        assert_eq!(parse_entry(&format!("v=gardens1;t=relay;k={}", z32::encode(&[3u8; 32]))), None);
+        let caps = parse_entry(&format!("v=gardens1;t=relay;k={};u=https://x.example", z32::encode(&[3u8; 32])))
+            .unwrap()
+            .capabilities;
+        assert_eq!(caps, vec![CAP_HOP, CAP_EXIT]);
+    }
+
+    #[test]
+    fn paths_end_at_an_exit_and_never_repeat_an_operator() {
+        let entries = vec![
+            entry(1, "https://a.example.com/hop", &[CAP_HOP]),
+            entry(2, "https://a.example.com/hop2", &[CAP_HOP]),
+            entry(3, "https://b.example.com/hop", &[CAP_HOP]),
+            entry(4, "https://c.example.com/hop", &[CAP_EXIT]),
+        ];
+
+        for _ in 0..50 {
+            let path = select_path(&entries, DEFAULT_PATH_LEN).unwrap();
+            assert_eq!(path.len(), 3);
+            assert_eq!(path[2].pubkey_bytes, [4u8; 32]);
+            let hosts: HashSet<_> = path
+                .iter()
+                .map(|h| reqwest::Url::parse(&h.next_url).unwrap().host_str().unwrap().to_string())
+                .collect();
+            assert_eq!(hosts.len(), 3);
+        }
+    }
+
+    #[test]
+    fn path_needs_an_exit() {
+        let entries = vec![entry(1, "https://a.example.com/hop", &[CAP_HOP])];
+        assert!(matches!(
+            select_path(&entries, DEFAULT_PATH_LEN),
+            Err(OnionError::InsufficientRelays { available: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn paths_never_shrink_to_a_single_operator() {
+        let entries = vec![
+            entry(1, "https://a.example.com/hop", &[CAP_HOP]),
+            entry(2, "https://a.example.com/exit", &[CAP_EXIT]),
+        ];
+        for _ in 0..10 {
+            assert!(matches!(
+                select_path(&entries, DEFAULT_PATH_LEN),
+                Err(OnionError::InsufficientRelays { available: 1, needed: 3 })
+            ));
+        }
+
+        let entries = [entries, vec![entry(3, "https://b.example.com/hop", &[CAP_HOP])]].concat();
+        assert_eq!(select_path(&entries, DEFAULT_PATH_LEN).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn short_requested_paths_are_raised_to_the_minimum() {
+        let entries = vec![
+            entry(1, "https://a.example.com/hop", &[CAP_HOP]),
+            entry(2, "https://b.example.com/hop", &[CAP_HOP]),
+            entry(3, "https://c.example.com/exit", &[CAP_EXIT]),
+        ];
+        for len in 0..MIN_PATH_LEN {
+            let path = select_path(&entries, len).unwrap();
+            assert_eq!(path.len(), MIN_PATH_LEN);
+            assert_eq!(path[MIN_PATH_LEN - 1].pubkey_bytes, [3u8; 32]);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_directory_is_cached_across_restarts() {
+        let core = crate::store::test_core().await;
+        let entries = vec![
+            entry(1, "https://a.example.com/hop", &[CAP_HOP]),
+            entry(2, "https://b.example.com/exit", &[CAP_EXIT]),
+        ];
+        let records: Vec<String> = entries.iter().map(encode_entry).collect();
+        db::save_relay_directory(&core.read_pool, "directory", &records, 1).await.unwrap();
+
+        let cached: Vec<RelayEntry> =
+            db::load_relay_directory(&core.read_pool).await.unwrap().iter().filter_map(|r| parse_entry(r)).collect();
+        assert_eq!(cached, entries);
+
+        db::save_relay_directory(&core.read_pool, "directory", &records[..1], 2).await.unwrap();
+        assert_eq!(db::load_relay_directory(&core.read_pool).await.unwrap(), records[..1]);
+    }
+}
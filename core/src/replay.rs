@@ -0,0 +1,162 @@
+//! Replay protection for onion layers and sealed-sender envelopes.
+//!
+//! Both formats start with a fresh ephemeral public key and a random nonce,
+//! so `EPK ‖ NONCE` identifies an envelope. A [`ReplayCache`] remembers the
+//! ids it has accepted in one-minute buckets and forgets a bucket once every
+//! envelope in it would be rejected as stale anyway: the sender puts a
+//! timestamp inside the encrypted payload, and [`check_timestamp`] refuses
+//! anything older than [`MAX_AGE_SECS`].
+//!
+//! Callers look an id up before doing any crypto and record it only after
+//! the envelope authenticates, so a forged copy cannot get a genuine
+//! envelope dropped.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Mutex as StdMutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Oldest envelope we accept, by its inner timestamp.
+pub const MAX_AGE_SECS: u64 = 15 * 60;
+
+/// How far ahead of our clock a sender's timestamp may be.
+pub const MAX_SKEW_SECS: u64 = 2 * 60;
+
+/// Ids in the cache. `EPK[32] ‖ NONCE[24]`.
+pub type ReplayKey = [u8; 56];
+
+const BUCKET_SECS: u64 = 60;
+
+/// Buckets kept: enough to outlive the staleness window.
+const RETAINED_BUCKETS: u64 = (MAX_AGE_SECS + MAX_SKEW_SECS) / BUCKET_SECS + 1;
+
+/// Upper bound on remembered ids. Past it the oldest bucket is dropped early.
+const MAX_ENTRIES: usize = 200_000;
+
+static ONION: OnceLock<ReplayCache> = OnceLock::new();
+static SEALED_SENDER: OnceLock<ReplayCache> = OnceLock::new();
+
+/// Cache for onion layers peeled by this process.
+pub fn onion() -> &'static ReplayCache {
+    ONION.get_or_init(ReplayCache::default)
+}
+
+/// Cache for sealed-sender envelopes opened by this process.
+pub fn sealed_sender() -> &'static ReplayCache {
+    SEALED_SENDER.get_or_init(ReplayCache::default)
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Whether an envelope stamped `timestamp` is still within the window at `now`.
+pub fn check_timestamp(timestamp: u64, now: u64) -> bool {
+    check_timestamp_within(timestamp, now, MAX_AGE_SECS)
+}
+
+/// [`check_timestamp`] with a longer window, for envelopes built ahead of
+/// time such as onion reply blocks. The cache only catches duplicates within
+/// [`MAX_AGE_SECS`] of each other.
+pub fn check_timestamp_within(timestamp: u64, now: u64, max_age_secs: u64) -> bool {
+    timestamp <= now.saturating_add(MAX_SKEW_SECS) && now.saturating_sub(timestamp) <= max_age_secs
+}
+
+/// The replay id of an envelope laid out as `VERSION | EPK[32] | NONCE[24] | …`.
+pub fn key_of(envelope: &[u8]) -> Option<ReplayKey> {
+    envelope.get(1..57)?.try_into().ok()
+}
+
+// ─── Cache ───────────────────────────────────────────────────────────────────
+
+#[derive(Default)]
+pub struct ReplayCache {
+    inner: StdMutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    /// (bucket index, ids first seen in it), oldest first.
+    buckets: VecDeque<(u64, HashSet<ReplayKey>)>,
+    len: usize,
+}
+
+impl ReplayCache {
+    /// Whether `key` has already been accepted.
+    pub fn seen(&self, key: &ReplayKey, now: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.expire(now);
+        inner.buckets.iter().any(|(_, ids)| ids.contains(key))
+    }
+
+    /// Record `key` as accepted. Returns false if it already was, i.e. the
+    /// envelope is a replay that raced the first copy.
+    pub fn insert(&self, key: ReplayKey, now: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.expire(now);
+        if inner.buckets.iter().any(|(_, ids)| ids.contains(&key)) {
+            return false;
+        }
+
+        while inner.len >= MAX_ENTRIES {
+            match inner.buckets.pop_front() {
+                Some((_, ids)) => inner.len -= ids.len(),
+                None => break,
+            }
+        }
+
+        let bucket = now / BUCKET_SECS;
+        if inner.buckets.back().map(|(b, _)| *b) != Some(bucket) {
+            inner.buckets.push_back((bucket, HashSet::new()));
+        }
+        if let Some((_, ids)) = inner.buckets.back_mut() {
+            ids.insert(key);
+        }
+        inner.len += 1;
+        true
+    }
+}
+
+impl Buckets {
+    fn expire(&mut self, now: u64) {
+        let oldest_kept = (now / BUCKET_SECS).saturating_sub(RETAINED_BUCKETS);
+        while let Some((bucket, _)) = self.buckets.front() {
+            if *bucket >= oldest_kept {
+                break;
+            }
+            if let Some((_, ids)) = self.buckets.pop_front() {
+                self.len -= ids.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates_are_caught_until_they_expire() {
+        let cache = ReplayCache::default();
+        let key = [7u8; 56];
+        let t0 = 1_700_000_000;
+
+        assert!(!cache.seen(&key, t0));
+        assert!(cache.insert(key, t0));
+        assert!(cache.seen(&key, t0 + 1));
+        assert!(!cache.insert(key, t0 + MAX_AGE_SECS));
+
+        let later = t0 + (RETAINED_BUCKETS + 1) * BUCKET_SECS;
+        assert!(!cache.seen(&key, later));
+        assert_eq!(cache.inner.lock().unwrap().len, 0);
+    }
+
+    #[test]
+    fn timestamps_outside_the_window_are_stale() {
+        let now = 1_700_000_000;
+        assert!(check_timestamp(now, now));
+        assert!(check_timestamp(now - MAX_AGE_SECS, now));
+        assert!(!check_timestamp(now - MAX_AGE_SECS - 1, now));
+        assert!(check_timestamp(now + MAX_SKEW_SECS, now));
+        assert!(!check_timestamp(now + MAX_SKEW_SECS + 1, now));
+    }
+}
@@ -8,12 +8,20 @@
 //! 1. Sender generates an ephemeral X25519 keypair.
 //! 2. ECDH(ephemeral_secret, recipient_x25519_pk) → shared secret.
 //! 3. HKDF(shared_secret, ephemeral_pk) → 32-byte AEAD key.
-//! 4. Encrypt:  XChaCha20-Poly1305(sender_pk[32] || op_bytes)
-//! 5. Wire envelope:  VERSION[1] | ephemeral_pk[32] | nonce[24] | ciphertext
+//! 4. Encrypt:  XChaCha20-Poly1305(sender_pk[32] || op_bytes), with the
+//!    header `VERSION | ephemeral_pk | nonce | timestamp` as associated data
+//! 5. Wire envelope:  VERSION[1] | ephemeral_pk[32] | nonce[24] | timestamp:u64[8] | ciphertext
 //!
 //! The relay only ever sees the recipient's topic hash and opaque ciphertext.
 //! The recipient decrypts and learns the authenticated sender public key.
 //!
+//! `open` drops envelopes it has already opened and, for v2, envelopes whose
+//! timestamp is outside the window in [`crate::replay`]. Because the version
+//! byte and timestamp are authenticated, a v2 envelope can't be relabelled
+//! as v1 or re-dated to dodge that check. v1 envelopes (no timestamp, no
+//! associated data) from older clients are still opened, with the duplicate
+//! check only.
+//!
 //! # Ed25519 → X25519 conversion
 //!
 //! Both key types live on Curve25519 — Ed25519 uses the Edwards form, X25519
@@ -25,21 +33,26 @@
 
 use chacha20poly1305::{
     AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
-    aead::Aead,
+    aead::{Aead, Payload},
 };
 use rand::rngs::OsRng;
 use thiserror::Error;
 use x25519_dalek::{EphemeralSecret, PublicKey as X25519Public};
 
 use crate::crypto::{derive_aead_key, ed25519_pubkey_to_x25519, ed25519_seed_to_x25519};
+use crate::replay;
 
 // ─── Constants ────────────────────────────────────────────────────────────────
 
-const VERSION: u8  = 0x01;
+const VERSION: u8  = 0x02;
+const VERSION_V1: u8 = 0x01;
 const EPK_LEN: usize   = 32;
 const NONCE_LEN: usize = 24;
+const TIMESTAMP_LEN: usize = 8;
 /// Minimum valid envelope length (version + epk + nonce + 1-byte poly1305 tag minimum).
 const MIN_LEN: usize = 1 + EPK_LEN + NONCE_LEN + 16;
+/// Length of the v2 header bound into the AEAD as associated data.
+const HEADER_LEN: usize = 1 + EPK_LEN + NONCE_LEN + TIMESTAMP_LEN;
 
 // ─── Errors ───────────────────────────────────────────────────────────────────
 
@@ -53,6 +66,10 @@ pub enum SealedSenderError {
     Encrypt,
     #[error("AEAD decryption failed — wrong key or tampered ciphertext")]
     Decrypt,
+    #[error("envelope already seen")]
+    Replayed,
+    #[error("envelope timestamp outside the accepted window")]
+    Stale,
 }
 
 // ─── Public API ───────────────────────────────────────────────────────────────
@@ -84,16 +101,19 @@ pub fn seal(
     plaintext.extend_from_slice(sender_pk_bytes);
     plaintext.extend_from_slice(op_bytes);
 
-    // Encrypt with a random nonce
-    let cipher = XChaCha20Poly1305::new_from_slice(&aead_key).map_err(|_| SealedSenderError::Encrypt)?;
+    // Header, authenticated as associated data: version || epk || nonce || timestamp
     let nonce  = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-    let ciphertext = cipher.encrypt(&nonce, plaintext.as_slice()).map_err(|_| SealedSenderError::Encrypt)?;
-
-    // Build wire envelope
-    let mut out = Vec::with_capacity(1 + EPK_LEN + NONCE_LEN + ciphertext.len());
+    let mut out = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
     out.push(VERSION);
     out.extend_from_slice(ephemeral_public.as_bytes());
     out.extend_from_slice(&nonce);
+    out.extend_from_slice(&replay::now_secs().to_be_bytes());
+
+    // Encrypt and append the ciphertext
+    let cipher = XChaCha20Poly1305::new_from_slice(&aead_key).map_err(|_| SealedSenderError::Encrypt)?;
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: &plaintext, aad: &out })
+        .map_err(|_| SealedSenderError::Encrypt)?;
     out.extend_from_slice(&ciphertext);
 
     Ok(out)
@@ -111,13 +131,34 @@ pub fn open(
     if envelope.len() < MIN_LEN {
         return Err(SealedSenderError::InvalidEnvelope);
     }
-    if envelope[0] != VERSION {
+    if envelope[0] != VERSION && envelope[0] != VERSION_V1 {
         return Err(SealedSenderError::UnsupportedVersion(envelope[0]));
     }
 
+    // Duplicates are dropped before any key agreement or decryption.
+    let replay_key = replay::key_of(envelope).ok_or(SealedSenderError::InvalidEnvelope)?;
+    let now = replay::now_secs();
+    if replay::sealed_sender().seen(&replay_key, now) {
+        return Err(SealedSenderError::Replayed);
+    }
+
+    // v2 carries an authenticated timestamp after the nonce; stale
+    // envelopes are dropped before any key agreement.
+    let (aad, ciphertext) = if envelope[0] == VERSION {
+        if envelope.len() < HEADER_LEN + 16 {
+            return Err(SealedSenderError::InvalidEnvelope);
+        }
+        let timestamp = u64::from_be_bytes(envelope[57..HEADER_LEN].try_into().unwrap());
+        if !replay::check_timestamp(timestamp, now) {
+            return Err(SealedSenderError::Stale);
+        }
+        envelope.split_at(HEADER_LEN)
+    } else {
+        (&[][..], &envelope[57..])
+    };
+
     let epk_bytes:   [u8; 32] = envelope[1..33].try_into().unwrap();
     let nonce_bytes: [u8; 24] = envelope[33..57].try_into().unwrap();
-    let ciphertext = &envelope[57..];
 
     // Recover X25519 keys
     let ephemeral_public   = X25519Public::from(epk_bytes);
@@ -128,14 +169,20 @@ pub fn open(
     // Decrypt
     let cipher = XChaCha20Poly1305::new_from_slice(&aead_key).map_err(|_| SealedSenderError::Decrypt)?;
     let nonce  = XNonce::from_slice(&nonce_bytes);
-    let plaintext = cipher.decrypt(nonce, ciphertext).map_err(|_| SealedSenderError::Decrypt)?;
+    let body = cipher
+        .decrypt(nonce, Payload { msg: ciphertext, aad })
+        .map_err(|_| SealedSenderError::Decrypt)?;
 
-    if plaintext.len() < 32 {
+    if body.len() < 32 {
         return Err(SealedSenderError::InvalidEnvelope);
     }
 
-    let sender_pk: [u8; 32] = plaintext[..32].try_into().unwrap();
-    let op_bytes = plaintext[32..].to_vec();
+    if !replay::sealed_sender().insert(replay_key, now) {
+        return Err(SealedSenderError::Replayed);
+    }
+
+    let sender_pk: [u8; 32] = body[..32].try_into().unwrap();
+    let op_bytes = body[32..].to_vec();
 
     Ok((sender_pk, op_bytes))
 }
@@ -143,7 +190,7 @@ pub fn open(
 /// Returns true if `bytes` looks like a sealed-sender envelope (version byte
 /// check only — use `open()` to fully verify).
 pub fn is_sealed(bytes: &[u8]) -> bool {
-    bytes.len() >= MIN_LEN && bytes[0] == VERSION
+    bytes.len() >= MIN_LEN && (bytes[0] == VERSION || bytes[0] == VERSION_V1)
 }
 
 // ─── Tests ────────────────────────────────────────────────────────────────────
@@ -198,6 +245,46 @@ mod tests {
         assert!(open(&envelope, &recipient_seed).is_err());
     }
 
+    #[test]
+    fn replayed_envelope_is_dropped() {
+        let (_, sender_pk)                 = random_ed25519_keypair();
+        let (recipient_seed, recipient_pk) = random_ed25519_keypair();
+
+        let envelope = seal(b"once", &sender_pk, &recipient_pk).unwrap();
+        assert!(open(&envelope, &recipient_seed).is_ok());
+        assert!(matches!(open(&envelope, &recipient_seed), Err(SealedSenderError::Replayed)));
+    }
+
+    #[test]
+    fn stale_envelope_is_rejected() {
+        let (_, sender_pk)                 = random_ed25519_keypair();
+        let (recipient_seed, recipient_pk) = random_ed25519_keypair();
+
+        let mut envelope = seal(b"old", &sender_pk, &recipient_pk).unwrap();
+        let old = replay::now_secs() - replay::MAX_AGE_SECS - 60;
+        envelope[57..HEADER_LEN].copy_from_slice(&old.to_be_bytes());
+        assert!(matches!(open(&envelope, &recipient_seed), Err(SealedSenderError::Stale)));
+    }
+
+    #[test]
+    fn version_and_timestamp_are_authenticated() {
+        let (_, sender_pk)                 = random_ed25519_keypair();
+        let (recipient_seed, recipient_pk) = random_ed25519_keypair();
+
+        // Relabelled as v1, with the timestamp stripped so it parses as one.
+        let envelope = seal(b"v2", &sender_pk, &recipient_pk).unwrap();
+        let mut relabelled = vec![VERSION_V1];
+        relabelled.extend_from_slice(&envelope[1..57]);
+        relabelled.extend_from_slice(&envelope[HEADER_LEN..]);
+        assert!(matches!(open(&relabelled, &recipient_seed), Err(SealedSenderError::Decrypt)));
+
+        // Re-dated within the accepted window.
+        let mut redated = seal(b"v2", &sender_pk, &recipient_pk).unwrap();
+        let earlier = replay::now_secs() - 60;
+        redated[57..HEADER_LEN].copy_from_slice(&earlier.to_be_bytes());
+        assert!(matches!(open(&redated, &recipient_seed), Err(SealedSenderError::Decrypt)));
+    }
+
     #[test]
     fn is_sealed_detects_version_byte() {
         let (_, sender_pk)       = random_ed25519_keypair();
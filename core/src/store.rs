@@ -31,6 +31,15 @@ where
 {
     get_runtime().block_on(f)
 }
+
+/// Spawn a background task on the global multi-thread runtime.
+pub fn spawn<F>(f: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: std::future::Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    get_runtime().spawn(f)
+}
 use p2panda_store::sqlite::store::{
     create_database, connection_pool, run_pending_migrations, SqliteStore,
 };
@@ -76,6 +85,27 @@ pub fn get_core() -> Option<&'static GardensCore> {
     CORE.get()
 }
 
+/// A standalone core in a fresh temp directory, for tests that run more
+/// than one peer in the same process.
+#[cfg(test)]
+pub(crate) async fn test_core() -> GardensCore {
+    let dir = std::env::temp_dir().join(format!("gardens-test-{:016x}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let db_dir = dir.to_str().unwrap();
+    let read_pool = init_read_pool(db_dir).await.unwrap();
+    crate::db::run_migrations(&read_pool).await.unwrap();
+    let private_key = PrivateKey::new();
+    let op_store = init_op_store(db_dir).await.unwrap();
+    GardensCore {
+        public_key_hex: private_key.public_key().to_hex(),
+        private_key,
+        op_store: Mutex::new(op_store),
+        read_pool,
+        blob_store: crate::blobs::blob_store_path(db_dir),
+        db_path: db_dir.to_string(),
+    }
+}
+
 // ─── Initialisation ──────────────────────────────────────────────────────────
 
 /// Initialise the p2panda operation store at `{db_dir}/ops.db`.
@@ -147,6 +177,12 @@ pub async fn bootstrap(
 
     CORE.set(core).map_err(|_| StoreError::AlreadyInit)?;
 
+    // Route onion packets through the last known relay directory until
+    // it is next refreshed.
+    if let Err(e) = crate::relay_directory::load_cached_relay_directory(&read_pool).await {
+        log::warn!("[relays] failed to load cached directory: {}", e);
+    }
+
     // Spawn the projector.
     tokio::spawn(crate::projector::run_projector(read_pool.clone()));
 
@@ -155,6 +191,12 @@ pub async fn bootstrap(
         .await
         .map_err(|e| StoreError::Other(e.to_string()))?;
 
+    // Deliver queued outbound ops, retrying failures.
+    tokio::spawn(crate::outbox::run_outbox(read_pool.clone()));
+
+    // Drop unreferenced blobs and keep the blob cache within its quotas.
+    tokio::spawn(crate::blob_gc::run_blob_gc(read_pool.clone()));
+
     // Start pkarr republish loop for public profiles/orgs
     tokio::spawn(crate::pkarr_publish::start_republish_loop(read_pool.clone()));
 
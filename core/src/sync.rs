@@ -1,14 +1,17 @@
 //! Incoming op ingestion from the Sync Worker WebSocket.
 //!
 //! React Native manages the WebSocket connection. When an op arrives,
-//! RN calls `ingest_op(topic_hex, seq, op_bytes)` which inserts it into
-//! the GardensStore and immediately runs a projector tick so the read
-//! model is up-to-date before JS increments opTick and calls list_messages.
+//! RN calls `ingest_op(topic_hex, seq, op_bytes)` which validates it (see
+//! [`crate::ingest`]), inserts it into the GardensStore and immediately
+//! projects that author's log so the read model is up-to-date before JS
+//! increments opTick and calls list_messages.
+
+use std::sync::atomic::{AtomicI64, Ordering};
 
-use crate::ops::{decode_cbor, GossipEnvelope};
 use crate::store::get_core;
-use p2panda_core::{Body, Header};
-use p2panda_store::OperationStore;
+
+/// When an op last arrived through sync (worker or peer), in micros; 0 if never.
+static LAST_INGEST_AT: AtomicI64 = AtomicI64::new(0);
 
 #[derive(Debug)]
 pub struct SyncError(pub String);
@@ -26,6 +29,19 @@ pub fn bytes_to_hex(bytes: &[u8; 32]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
+/// Note that an op just arrived through sync.
+pub fn record_ingest() {
+    LAST_INGEST_AT.store(crate::now_micros(), Ordering::Relaxed);
+}
+
+/// When an op last arrived through sync, if ever.
+pub fn last_ingest_at() -> Option<i64> {
+    match LAST_INGEST_AT.load(Ordering::Relaxed) {
+        0 => None,
+        at => Some(at),
+    }
+}
+
 /// Ingest a raw op received from the DO WebSocket.
 ///
 /// `topic_hex` — 64-char hex topic ID (for seq tracking)
@@ -34,23 +50,11 @@ pub fn bytes_to_hex(bytes: &[u8; 32]) -> String {
 pub async fn ingest_op(topic_hex: &str, seq: i64, op_bytes: &[u8]) -> Result<(), SyncError> {
     let core = get_core().ok_or_else(|| SyncError("core not initialised".into()))?;
 
-    // Decode GossipEnvelope CBOR
-    let env = decode_cbor::<GossipEnvelope>(op_bytes)
-        .map_err(|e| SyncError(format!("decode: {e}")))?;
-
-    let header = Header::try_from(env.header_bytes.as_slice())
-        .map_err(|e| SyncError(format!("header: {e}")))?;
-    let body = Body::new(&env.body_bytes);
-    let op_hash = header.hash();
-
-    // Insert into store — duplicate inserts are silently ignored
-    {
-        let mut store = core.op_store.lock().await;
-        store
-            .insert_operation(op_hash, &header, Some(&body), &env.header_bytes, &env.log_id)
-            .await
-            .map_err(|e| SyncError(format!("insert: {e}")))?;
-    }
+    // Validate and insert into store — duplicate inserts are silently ignored
+    let op = crate::ingest::ingest_envelope(core, op_bytes)
+        .await
+        .map_err(|e| SyncError(format!("ingest: {e}")))?;
+    record_ingest();
 
     // Update last-seen seq for this topic
     crate::db::set_topic_seq(&core.read_pool, topic_hex, seq)
@@ -61,15 +65,18 @@ pub async fn ingest_op(topic_hex: &str, seq: i64, op_bytes: &[u8]) -> Result<(),
         "[sync] ingest_op OK topic={} seq={} log_id={} author={}",
         &topic_hex[..topic_hex.len().min(16)],
         seq,
-        env.log_id,
-        header.public_key.to_hex().chars().take(16).collect::<String>(),
+        op.log_id,
+        op.author.to_hex().chars().take(16).collect::<String>(),
     );
 
-    // Eagerly project the ingested op into the read model so that JS
+    crate::projector::notify_op(op.author, &op.log_id);
+
+    // Eagerly project the ingested op's log into the read model so that JS
     // can call list_messages / list_dm_threads immediately after opTick
-    // increments without waiting for the 500ms polling cycle.
-    if let Err(e) = crate::projector::project_tick(&core.read_pool).await {
-        eprintln!("[sync] eager project_tick failed: {e}");
+    // increments without waiting for the projector task.
+    let pair = std::collections::HashSet::from([(op.author, op.log_id)]);
+    if let Err(e) = crate::projector::project_logs(&core.read_pool, &pair).await {
+        eprintln!("[sync] eager projection failed: {e}");
     }
 
     Ok(())
@@ -1,4 +1,5 @@
-//! Sync configuration — stores relay hops and sync URL set by `init_sync`.
+//! Sync configuration — stores relay hops and sync URL set by `init_sync`,
+//! and the relay directory loaded by `refresh_relay_directory`.
 //!
 //! Uses plain (String, String) tuples for hop storage so this module has no
 //! dependency on `lib.rs` types (avoiding a circular import).  The `lib.rs`
@@ -9,9 +10,12 @@ use std::sync::OnceLock;
 use hex;
 use z32;
 
+use crate::relay_directory::RelayEntry;
+
 struct Inner {
     hops: Vec<(String, String)>, // (pubkey_hex, next_url)
     sync_url: String,
+    directory: Vec<RelayEntry>,
 }
 
 static CONFIG: OnceLock<std::sync::RwLock<Inner>> = OnceLock::new();
@@ -21,6 +25,7 @@ fn lock() -> &'static std::sync::RwLock<Inner> {
         std::sync::RwLock::new(Inner {
             hops: vec![],
             sync_url: String::new(),
+            directory: vec![],
         })
     })
 }
@@ -38,12 +43,34 @@ pub fn get_hops() -> Vec<(String, String)> {
     lock().read().expect("sync config lock poisoned").hops.clone()
 }
 
-/// Return the z32-encoded pubkey of the first relay hop, or None if not configured.
+/// Return the z32-encoded pubkey of the first relay hop, falling back to the
+/// first exit in the relay directory, or None if neither is configured.
 pub fn get_relay_z32() -> Option<String> {
-    let hops = lock().read().expect("sync config lock poisoned").hops.clone();
-    let (pubkey_hex, _) = hops.into_iter().next()?;
-    let bytes = hex::decode(&pubkey_hex).ok()?;
-    Some(z32::encode(&bytes))
+    let g = lock().read().expect("sync config lock poisoned");
+    if let Some((pubkey_hex, _)) = g.hops.first() {
+        let bytes = hex::decode(pubkey_hex).ok()?;
+        return Some(z32::encode(&bytes));
+    }
+    g.directory
+        .iter()
+        .find(|e| e.can(crate::relay_directory::CAP_EXIT))
+        .map(|e| z32::encode(&e.pubkey))
+}
+
+/// Replace the relay directory routes are picked from.
+pub fn set_directory(entries: Vec<RelayEntry>) {
+    lock().write().expect("sync config lock poisoned").directory = entries;
+}
+
+/// Return the relays listed in the current directory.
+pub fn get_directory() -> Vec<RelayEntry> {
+    lock().read().expect("sync config lock poisoned").directory.clone()
+}
+
+/// Whether onion packets can be routed: a directory or fixed hops are set.
+pub fn has_onion_route() -> bool {
+    let g = lock().read().expect("sync config lock poisoned");
+    !g.directory.is_empty() || !g.hops.is_empty()
 }
 
 /// Return the stored sync WebSocket URL, or an empty string if not yet set.
@@ -336,6 +336,11 @@ export default {
         return new Response(null, { status: resp.ok ? 200 : 502 });
       }
 
+      if (payload.type === 'drop') {
+        // Cover traffic ends here.
+        return new Response(null, { status: 200 });
+      }
+
       if (payload.type === 'deliver') {
         // Forward op to sync worker TopicDO for persistence and fan-out
         const topicHex = bytesToHex(payload.topicId);
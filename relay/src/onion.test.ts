@@ -1,5 +1,5 @@
 import { describe, it, expect } from 'vitest';
-import { buildTestPacket, peelLayer } from './onion';
+import { CELL_SIZE, MAX_AGE_SECS, REPLY_BLOCK_TTL_SECS, buildTestPacket, buildTestPacketV3, peelLayer } from './onion';
 import { bytesToHex } from './crypto';
 
 describe('peelLayer', () => {
@@ -39,3 +39,65 @@ describe('peelLayer', () => {
     expect(() => peelLayer(new Uint8Array(10), 'a'.repeat(64))).toThrow();
   });
 });
+
+describe('peelLayer v3', () => {
+  it('decrypts a Forward and pads the inner packet to a full cell', () => {
+    const { packet, seedHex, expectedNextUrl, expectedInner } = buildTestPacketV3('forward');
+    expect(packet.length).toBe(CELL_SIZE);
+    const result = peelLayer(packet, seedHex);
+    if (result.type !== 'forward') throw new Error(`got ${result.type}`);
+    expect(result.nextHopUrl).toBe(expectedNextUrl);
+    expect(result.innerPacket.length).toBe(CELL_SIZE);
+    expect(result.innerPacket.slice(0, expectedInner!.length)).toEqual(expectedInner);
+  });
+
+  it('decrypts a Deliver payload', () => {
+    const { packet, seedHex, expectedTopicId, expectedOp } = buildTestPacketV3('deliver');
+    const result = peelLayer(packet, seedHex);
+    if (result.type !== 'deliver') throw new Error(`got ${result.type}`);
+    expect(result.topicId).toEqual(expectedTopicId);
+    expect(result.op).toEqual(expectedOp);
+  });
+
+  it('decrypts a Drop payload', () => {
+    const { packet, seedHex } = buildTestPacketV3('drop');
+    expect(peelLayer(packet, seedHex).type).toBe('drop');
+  });
+
+  it('rejects a stale packet', () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { packet, seedHex } = buildTestPacketV3('deliver', now - MAX_AGE_SECS - 60);
+    expect(() => peelLayer(packet, seedHex, now)).toThrow('stale');
+  });
+
+  it('forwards a ReplyHop with the reply body re-keyed behind the inner header', () => {
+    const { packet, seedHex, expectedNextUrl, expectedInner } = buildTestPacketV3('replyHop');
+    const result = peelLayer(packet, seedHex);
+    if (result.type !== 'forward') throw new Error(`got ${result.type}`);
+    expect(result.nextHopUrl).toBe(expectedNextUrl);
+    expect(result.innerPacket.length).toBe(CELL_SIZE);
+    expect(result.innerPacket.slice(0, expectedInner!.length)).toEqual(expectedInner);
+  });
+
+  it('delivers a ReplyExit body re-keyed', () => {
+    const { packet, seedHex, expectedTopicId, expectedOp } = buildTestPacketV3('replyExit');
+    const result = peelLayer(packet, seedHex);
+    if (result.type !== 'deliver') throw new Error(`got ${result.type}`);
+    expect(result.topicId).toEqual(expectedTopicId);
+    expect(result.op).toEqual(expectedOp);
+  });
+
+  it('accepts reply blocks for their whole lifetime, but no longer', () => {
+    const now = Math.floor(Date.now() / 1000);
+    const fresh = buildTestPacketV3('replyExit', now - MAX_AGE_SECS - 60);
+    expect(peelLayer(fresh.packet, fresh.seedHex, now).type).toBe('deliver');
+    const old = buildTestPacketV3('replyExit', now - REPLY_BLOCK_TTL_SECS - 60);
+    expect(() => peelLayer(old.packet, old.seedHex, now)).toThrow('stale');
+  });
+
+  it('throws on a tampered length', () => {
+    const { packet, seedHex } = buildTestPacketV3('deliver');
+    packet[60] ^= 0xff;
+    expect(() => peelLayer(packet, seedHex)).toThrow();
+  });
+});
@@ -1,19 +1,49 @@
 /**
  * Onion layer peeling — TypeScript port of core/src/onion.rs.
  *
- * Wire format: VERSION[1] | EPK[32] | NONCE[24] | CIPHERTEXT[N]
+ * v3 layer (what current cores build), always CELL_SIZE bytes:
+ *   VERSION[1] | EPK[32] | NONCE[24] | LEN_CT[4+16] | BODY_CT[len+16] | FILL
+ * The body plaintext is TIMESTAMP:u64 | payload. A peeled Forward is padded
+ * back up to a full cell with random fill before it is sent on.
+ *
+ * Reply-block layers (v3 only) carry a reply body behind the layer instead
+ * of fill. ReplyHop re-keys that body with its body_key and forwards it
+ * behind the inner header; ReplyExit re-keys it and delivers it. Callers see
+ * them as an ordinary Forward / Deliver.
+ *
+ * v2 layer (older cores): VERSION[1] | EPK[32] | NONCE[24] | CIPHERTEXT[N]
  * Min valid length: 1 + 32 + 24 + 16 (Poly1305 tag) = 73 bytes
  */
 
-import { xchacha20poly1305 } from '@noble/ciphers/chacha';
+import { chacha20, xchacha20poly1305 } from '@noble/ciphers/chacha';
 import { x25519 } from '@noble/curves/ed25519';
 import { hkdf } from '@noble/hashes/hkdf';
 import { sha256, sha512 } from '@noble/hashes/sha2';
+import { randomBytes } from '@noble/hashes/utils';
 import { bytesToHex, hexToBytes } from './crypto';
 
 const VERSION = 0x02;
+const VERSION_V3 = 0x03;
 const HKDF_INFO = new TextEncoder().encode('gardens:onion:v1');
-const MIN_LEN = 1 + 32 + 24 + 16;
+const HKDF_INFO_V3_LEN = new TextEncoder().encode('gardens:onion:v3:len');
+const HKDF_INFO_V3_BODY = new TextEncoder().encode('gardens:onion:v3:body');
+const HEADER_LEN = 1 + 32 + 24;
+const TAG_LEN = 16;
+const LEN_CT_LEN = 4 + TAG_LEN;
+const MIN_LEN = HEADER_LEN + TAG_LEN;
+const MIN_LEN_V3 = HEADER_LEN + LEN_CT_LEN + TAG_LEN;
+
+/** Size of every v3 onion packet on the wire. Matches `onion::CELL_SIZE`. */
+export const CELL_SIZE = 32 * 1024;
+
+/** Oldest v3 layer accepted, by its inner timestamp. Matches `replay::MAX_AGE_SECS`. */
+export const MAX_AGE_SECS = 15 * 60;
+/** How far ahead of our clock a sender's timestamp may be. */
+export const MAX_SKEW_SECS = 2 * 60;
+/** Oldest reply-block layer accepted. Matches `onion::REPLY_BLOCK_TTL_SECS`. */
+export const REPLY_BLOCK_TTL_SECS = 7 * 24 * 60 * 60;
+/** Size of a reply body. Matches `onion::REPLY_BODY_LEN`. */
+export const REPLY_BODY_LEN = 16 * 1024;
 
 // ── Key conversion ────────────────────────────────────────────────────────────
 
@@ -34,9 +64,16 @@ export function seedToX25519Priv(seed: Uint8Array): Uint8Array {
 
 export type OnionPayload =
   | { type: 'forward'; nextHopUrl: string; innerPacket: Uint8Array }
-  | { type: 'deliver'; topicId: Uint8Array; op: Uint8Array };
+  | { type: 'deliver'; topicId: Uint8Array; op: Uint8Array }
+  | { type: 'drop' };
+
+/** A decoded layer, before reply-block instructions are resolved. */
+type LayerPayload =
+  | OnionPayload
+  | { type: 'replyHop'; nextHopUrl: string; bodyKey: Uint8Array; innerHeader: Uint8Array }
+  | { type: 'replyExit'; topicId: Uint8Array; bodyKey: Uint8Array };
 
-function decodePayload(bytes: Uint8Array): OnionPayload {
+function decodePayload(bytes: Uint8Array): LayerPayload {
   if (bytes.length === 0) throw new Error('empty payload');
   const type = bytes[0];
 
@@ -56,6 +93,24 @@ function decodePayload(bytes: Uint8Array): OnionPayload {
     return { type: 'deliver', topicId, op };
   }
 
+  // Cover traffic: travels the route like a real packet, the exit drops it.
+  if (type === 0x03) return { type: 'drop' };
+
+  if (type === 0x04) {
+    if (bytes.length < 3) throw new Error('ReplyHop payload too short');
+    const urlLen = (bytes[1] << 8) | bytes[2];
+    if (bytes.length < 3 + urlLen + 32) throw new Error('ReplyHop payload truncated');
+    const url = new TextDecoder().decode(bytes.slice(3, 3 + urlLen));
+    const bodyKey = bytes.slice(3 + urlLen, 3 + urlLen + 32);
+    const innerHeader = bytes.slice(3 + urlLen + 32);
+    return { type: 'replyHop', nextHopUrl: url, bodyKey, innerHeader };
+  }
+
+  if (type === 0x05) {
+    if (bytes.length !== 1 + 32 + 32) throw new Error('bad ReplyExit payload');
+    return { type: 'replyExit', topicId: bytes.slice(1, 33), bodyKey: bytes.slice(33, 65) };
+  }
+
   throw new Error(`unknown payload type 0x${type.toString(16)}`);
 }
 
@@ -64,77 +119,193 @@ function decodePayload(bytes: Uint8Array): OnionPayload {
 /**
  * Peel one onion layer.
  *
+ * For a v3 Forward, `innerPacket` comes back padded to a full cell, ready to
+ * send on.
+ *
  * @param envelope        Raw onion packet bytes.
  * @param recipientSeedHex 64 hex chars — Ed25519 seed of this hop's keypair.
+ * @param now             Current time in seconds, for the v3 timestamp check.
  */
-export function peelLayer(envelope: Uint8Array, recipientSeedHex: string): OnionPayload {
+export function peelLayer(
+  envelope: Uint8Array,
+  recipientSeedHex: string,
+  now: number = Math.floor(Date.now() / 1000),
+): OnionPayload {
   if (envelope.length < MIN_LEN) throw new Error('envelope too short');
+  if (envelope[0] === VERSION_V3) return peelLayerV3(envelope, recipientSeedHex, now);
   if (envelope[0] !== VERSION) throw new Error(`unsupported version 0x${envelope[0].toString(16)}`);
 
   const epk = envelope.slice(1, 33);
   const nonce = envelope.slice(33, 57);
   const ciphertext = envelope.slice(57);
 
-  const seed = hexToBytes(recipientSeedHex);
-  if (seed.length !== 32) throw new Error('seed must be 32 bytes');
-
-  const x25519Priv = seedToX25519Priv(seed);
-  const shared = x25519.getSharedSecret(x25519Priv, epk);
+  const shared = sharedSecret(recipientSeedHex, epk);
 
   // HKDF-SHA256(ikm=shared, salt=epk, info="gardens:onion:v1") → 32-byte key
   const aesKey = hkdf(sha256, shared, epk, HKDF_INFO, 32);
 
   const plaintext = xchacha20poly1305(aesKey, nonce).decrypt(ciphertext);
-  return decodePayload(plaintext);
+  const payload = decodePayload(plaintext);
+  // Reply blocks are v3 only.
+  if (payload.type === 'replyHop' || payload.type === 'replyExit') throw new Error('reply block in a v2 layer');
+  return payload;
+}
+
+function peelLayerV3(envelope: Uint8Array, recipientSeedHex: string, now: number): OnionPayload {
+  if (envelope.length < MIN_LEN_V3 || envelope.length > CELL_SIZE) throw new Error('bad v3 envelope length');
+
+  const epk = envelope.slice(1, 33);
+  const nonce = envelope.slice(33, HEADER_LEN);
+  const shared = sharedSecret(recipientSeedHex, epk);
+  const lenKey = hkdf(sha256, shared, epk, HKDF_INFO_V3_LEN, 32);
+  const bodyKey = hkdf(sha256, shared, epk, HKDF_INFO_V3_BODY, 32);
+
+  const bodyStart = HEADER_LEN + LEN_CT_LEN;
+  const lenBytes = xchacha20poly1305(lenKey, nonce).decrypt(envelope.slice(HEADER_LEN, bodyStart));
+  const len = new DataView(lenBytes.buffer, lenBytes.byteOffset, 4).getUint32(0);
+  const bodyEnd = bodyStart + len + TAG_LEN;
+  if (bodyEnd > envelope.length) throw new Error('v3 body truncated');
+
+  // The body is bound to everything before it, including the sealed length.
+  const aad = envelope.slice(0, bodyStart);
+  const plaintext = xchacha20poly1305(bodyKey, nonce, aad).decrypt(envelope.slice(bodyStart, bodyEnd));
+  if (plaintext.length < 8) throw new Error('v3 payload too short');
+
+  const timestamp = Number(new DataView(plaintext.buffer, plaintext.byteOffset, 8).getBigUint64(0));
+  const payload = decodePayload(plaintext.slice(8));
+  const isReply = payload.type === 'replyHop' || payload.type === 'replyExit';
+  const maxAge = isReply ? REPLY_BLOCK_TTL_SECS : MAX_AGE_SECS;
+  if (timestamp > now + MAX_SKEW_SECS || now - timestamp > maxAge) throw new Error('stale packet');
+
+  // Everything after this layer: fill, or a reply body behind a header.
+  const trailing = envelope.slice(bodyEnd);
+  switch (payload.type) {
+    case 'forward':
+      return { ...payload, innerPacket: padCell(payload.innerPacket) };
+    case 'replyHop': {
+      const body = applyBodyKey(payload.bodyKey, trailing);
+      const inner = new Uint8Array(payload.innerHeader.length + body.length);
+      inner.set(payload.innerHeader);
+      inner.set(body, payload.innerHeader.length);
+      return { type: 'forward', nextHopUrl: payload.nextHopUrl, innerPacket: padCell(inner) };
+    }
+    case 'replyExit': {
+      if (trailing.length < REPLY_BODY_LEN) throw new Error('reply body truncated');
+      const op = applyBodyKey(payload.bodyKey, trailing.slice(0, REPLY_BODY_LEN));
+      return { type: 'deliver', topicId: payload.topicId, op };
+    }
+    default:
+      return payload;
+  }
+}
+
+/** XOR `body` with the ChaCha20 keystream of a single-use body key. Matches `onion::apply_body_key`. */
+function applyBodyKey(bodyKey: Uint8Array, body: Uint8Array): Uint8Array {
+  return chacha20(bodyKey, new Uint8Array(12), body);
+}
+
+function sharedSecret(recipientSeedHex: string, epk: Uint8Array): Uint8Array {
+  const seed = hexToBytes(recipientSeedHex);
+  if (seed.length !== 32) throw new Error('seed must be 32 bytes');
+  return x25519.getSharedSecret(seedToX25519Priv(seed), epk);
+}
+
+/** Pad a v3 layer with random fill to exactly CELL_SIZE bytes. */
+function padCell(layer: Uint8Array): Uint8Array {
+  if (layer.length > CELL_SIZE) throw new Error('inner packet larger than a cell');
+  const out = new Uint8Array(CELL_SIZE);
+  out.set(layer);
+  out.set(randomBytes(CELL_SIZE - layer.length), layer.length);
+  return out;
 }
 
 // ── Test helpers (only used in unit tests) ────────────────────────────────────
 
-/** Build an encrypted test packet using @noble crypto — mirrors Rust encrypt_layer. */
-export async function buildTestPacket(kind: 'forward' | 'deliver'): Promise<{
+type TestPacket = {
   packet: Uint8Array;
   seedHex: string;
   expectedNextUrl?: string;
   expectedInner?: Uint8Array;
   expectedTopicId?: Uint8Array;
   expectedOp?: Uint8Array;
-}> {
-  const { randomBytes } = await import('@noble/hashes/utils');
-  const { xchacha20poly1305 } = await import('@noble/ciphers/chacha');
-
-  const seed = randomBytes(32);
-  const seedHex = bytesToHex(seed);
-  const privKey = seedToX25519Priv(seed);
-  const pubKey = x25519.getPublicKey(privKey);
+};
 
-  const ephemeralPriv = randomBytes(32);
-  const ephemeralPub = x25519.getPublicKey(ephemeralPriv);
-  const shared = x25519.getSharedSecret(ephemeralPriv, pubKey);
-  const aesKey = hkdf(sha256, shared, ephemeralPub, HKDF_INFO, 32);
-  const nonce = randomBytes(24);
+type TestKind = 'forward' | 'deliver' | 'drop' | 'replyHop' | 'replyExit';
 
-  let plaintext: Uint8Array;
-  let extras: object;
+function testPayload(kind: TestKind): { plaintext: Uint8Array; extras: object } {
+  if (kind === 'replyHop') {
+    const url = 'https://hop2.example.com/hop';
+    const urlBytes = new TextEncoder().encode(url);
+    const bodyKey = randomBytes(32);
+    const innerHeader = new Uint8Array([9, 8, 7]);
+    const plaintext = new Uint8Array(3 + urlBytes.length + 32 + innerHeader.length);
+    plaintext[0] = 0x04;
+    plaintext[1] = (urlBytes.length >> 8) & 0xff;
+    plaintext[2] = urlBytes.length & 0xff;
+    plaintext.set(urlBytes, 3);
+    plaintext.set(bodyKey, 3 + urlBytes.length);
+    plaintext.set(innerHeader, 3 + urlBytes.length + 32);
+    const body = replyBody();
+    const inner = new Uint8Array(innerHeader.length + body.length);
+    inner.set(innerHeader);
+    inner.set(applyBodyKey(bodyKey, body), innerHeader.length);
+    return { plaintext, extras: { expectedNextUrl: url, expectedInner: inner, trailing: body } };
+  }
+  if (kind === 'replyExit') {
+    const topicId = randomBytes(32);
+    const bodyKey = randomBytes(32);
+    const plaintext = new Uint8Array(1 + 32 + 32);
+    plaintext[0] = 0x05;
+    plaintext.set(topicId, 1);
+    plaintext.set(bodyKey, 33);
+    const body = replyBody();
+    return { plaintext, extras: { expectedTopicId: topicId, expectedOp: applyBodyKey(bodyKey, body), trailing: body } };
+  }
   if (kind === 'forward') {
     const url = 'https://hop2.example.com/hop';
     const inner = new Uint8Array([1, 2, 3, 4]);
     const urlBytes = new TextEncoder().encode(url);
-    plaintext = new Uint8Array(3 + urlBytes.length + inner.length);
+    const plaintext = new Uint8Array(3 + urlBytes.length + inner.length);
     plaintext[0] = 0x01;
     plaintext[1] = (urlBytes.length >> 8) & 0xff;
     plaintext[2] = urlBytes.length & 0xff;
     plaintext.set(urlBytes, 3);
     plaintext.set(inner, 3 + urlBytes.length);
-    extras = { expectedNextUrl: url, expectedInner: inner };
-  } else {
-    const topicId = randomBytes(32);
-    const op = new TextEncoder().encode('hello gardens');
-    plaintext = new Uint8Array(1 + 32 + op.length);
-    plaintext[0] = 0x02;
-    plaintext.set(topicId, 1);
-    plaintext.set(op, 33);
-    extras = { expectedTopicId: topicId, expectedOp: op };
+    return { plaintext, extras: { expectedNextUrl: url, expectedInner: inner } };
+  }
+  if (kind === 'drop') {
+    return { plaintext: new Uint8Array([0x03]), extras: {} };
   }
+  const topicId = randomBytes(32);
+  const op = new TextEncoder().encode('hello gardens');
+  const plaintext = new Uint8Array(1 + 32 + op.length);
+  plaintext[0] = 0x02;
+  plaintext.set(topicId, 1);
+  plaintext.set(op, 33);
+  return { plaintext, extras: { expectedTopicId: topicId, expectedOp: op } };
+}
+
+function replyBody(): Uint8Array {
+  const body = new Uint8Array(REPLY_BODY_LEN);
+  body.set(new TextEncoder().encode('sealed reply'));
+  return body;
+}
+
+function testKeys() {
+  const seed = randomBytes(32);
+  const pubKey = x25519.getPublicKey(seedToX25519Priv(seed));
+  const ephemeralPriv = randomBytes(32);
+  const ephemeralPub = x25519.getPublicKey(ephemeralPriv);
+  const shared = x25519.getSharedSecret(ephemeralPriv, pubKey);
+  return { seedHex: bytesToHex(seed), ephemeralPub, shared };
+}
+
+/** Build an encrypted v2 test packet using @noble crypto — mirrors Rust encrypt_layer. */
+export async function buildTestPacket(kind: 'forward' | 'deliver'): Promise<TestPacket> {
+  const { seedHex, ephemeralPub, shared } = testKeys();
+  const aesKey = hkdf(sha256, shared, ephemeralPub, HKDF_INFO, 32);
+  const nonce = randomBytes(24);
+  const { plaintext, extras } = testPayload(kind);
 
   const ciphertext = xchacha20poly1305(aesKey, nonce).encrypt(plaintext);
   const packet = new Uint8Array(1 + 32 + 24 + ciphertext.length);
@@ -143,5 +314,40 @@ export async function buildTestPacket(kind: 'forward' | 'deliver'): Promise<{
   packet.set(nonce, 33);
   packet.set(ciphertext, 57);
 
-  return { packet, seedHex, ...extras } as any;
+  return { packet, seedHex, ...extras };
+}
+
+/**
+ * Build a padded v3 test cell — mirrors Rust encrypt_layer_v3 + pad_cell.
+ * Reply-block kinds carry a reply body behind the layer, as a reply does.
+ */
+export function buildTestPacketV3(
+  kind: TestKind,
+  timestamp: number = Math.floor(Date.now() / 1000),
+): TestPacket {
+  const { seedHex, ephemeralPub, shared } = testKeys();
+  const lenKey = hkdf(sha256, shared, ephemeralPub, HKDF_INFO_V3_LEN, 32);
+  const bodyKey = hkdf(sha256, shared, ephemeralPub, HKDF_INFO_V3_BODY, 32);
+  const nonce = randomBytes(24);
+  const { plaintext: payload, extras: allExtras } = testPayload(kind);
+  const { trailing = new Uint8Array(0), ...extras } = allExtras as { trailing?: Uint8Array };
+
+  const plaintext = new Uint8Array(8 + payload.length);
+  new DataView(plaintext.buffer).setBigUint64(0, BigInt(timestamp));
+  plaintext.set(payload, 8);
+
+  const lenBytes = new Uint8Array(4);
+  new DataView(lenBytes.buffer).setUint32(0, plaintext.length);
+  const header = new Uint8Array(HEADER_LEN + LEN_CT_LEN);
+  header[0] = VERSION_V3;
+  header.set(ephemeralPub, 1);
+  header.set(nonce, 33);
+  header.set(xchacha20poly1305(lenKey, nonce).encrypt(lenBytes), HEADER_LEN);
+  const body = xchacha20poly1305(bodyKey, nonce, header).encrypt(plaintext);
+
+  const layer = new Uint8Array(header.length + body.length + trailing.length);
+  layer.set(header);
+  layer.set(body, header.length);
+  layer.set(trailing, header.length + body.length);
+  return { packet: padCell(layer), seedHex, ...extras };
 }